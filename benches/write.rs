@@ -0,0 +1,98 @@
+//! Measures how long it takes to write multi-page documents to a file.
+//!
+//! Run using `cargo bench`. Writing into memory is measured as well; the closer the file results
+//! are to it, the less time is lost to system calls.
+
+
+use std::fs::File;
+use std::hint::black_box;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use jpeg2pdf::content::{self, Operator};
+use jpeg2pdf::pdf;
+
+
+const ITERATIONS: u32 = 10;
+
+
+fn build_document(page_count: usize, image_size: usize, signature: bool) -> pdf::Document {
+    let mut builder = pdf::DocumentBuilder::new(pdf::Compression::None);
+    for _ in 0..page_count {
+        // the writer does not look into the image data, so any bytes will do
+        let image_ref = builder.add(pdf::ImageXObject {
+            width: 2480,
+            height: 3508,
+            color_space: "/DeviceRGB",
+            bits_per_component: 8,
+            interpolate: false,
+            intent: None,
+            decode: None,
+            color_key_mask: None,
+            smask_id: None,
+            data_filters: vec!["/DCTDecode".to_owned()],
+            color_transform: None,
+            data: pdf::ImageData::Bytes(vec![0x55; image_size]),
+        });
+        builder.add_page(pdf::NewPage {
+            width: 595,
+            height: 842,
+            images: vec![image_ref],
+            operators: vec![
+                Operator::SaveState,
+                Operator::ConcatMatrix(content::scale_translate(595.0, 842.0, 0.0, 0.0)),
+                Operator::DrawXObject("Im0".to_owned()),
+                Operator::RestoreState,
+            ],
+            ..pdf::NewPage::default()
+        });
+    }
+    if signature {
+        let signature_ref = builder.add(pdf::Signature {
+            contents_size: 8192,
+        });
+        let signature_field_ref = builder.add(pdf::SignatureField {
+            name: "Signature1".to_owned(),
+            page_id: builder.pages()[0].id(),
+            signature_id: signature_ref.id(),
+        });
+        builder.catalog_mut().acro_form = Some(pdf::AcroForm {
+            field_ids: vec![signature_field_ref.id()],
+            sig_flags: 3,
+        });
+    }
+    builder.build().unwrap()
+}
+
+fn measure<F: FnMut()>(mut f: F) -> Duration {
+    // warm up
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn bench(name: &str, document: &pdf::Document) {
+    let path = std::env::temp_dir().join(format!("jpeg2pdf-bench-{}.pdf", std::process::id()));
+    let to_file = measure(|| {
+        let file = File::create(&path).unwrap();
+        document.write(file).unwrap();
+    });
+    std::fs::remove_file(&path).unwrap();
+
+    let to_memory = measure(|| {
+        let mut buf = Cursor::new(Vec::new());
+        document.write(&mut buf).unwrap();
+        black_box(buf);
+    });
+
+    println!("{:<40} file: {:>10.3} ms    memory: {:>10.3} ms", name, to_file.as_secs_f64() * 1000.0, to_memory.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    bench("500 pages, 1 KiB images", &build_document(500, 1024, false));
+    bench("500 pages, 256 KiB images", &build_document(500, 256 * 1024, false));
+    bench("500 pages, 1 KiB images, signature", &build_document(500, 1024, true));
+}
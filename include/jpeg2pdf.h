@@ -0,0 +1,57 @@
+/*
+ * C interface of the jpeg2pdf library (built with the "ffi" feature).
+ *
+ * Converts a JPEG image into a single-page PDF document without recompressing the image data.
+ */
+
+#ifndef JPEG2PDF_H
+#define JPEG2PDF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* the conversion succeeded */
+#define JPEG2PDF_OK 0
+/* a required pointer was null */
+#define JPEG2PDF_ERROR_INVALID_ARGUMENT 1
+/* the input is not a JPEG image that could be read */
+#define JPEG2PDF_ERROR_INVALID_JPEG 2
+/* the image cannot be embedded into a PDF document or its page size is unknown */
+#define JPEG2PDF_ERROR_UNSUPPORTED 3
+/* the document could not be assembled or written, or the conversion failed unexpectedly */
+#define JPEG2PDF_ERROR_INTERNAL 4
+
+typedef struct jpeg2pdf_options {
+    /* density in dots per inch to size the page with instead of the one stored in the image;
+     * 0 in either field to use the stored one */
+    uint16_t dpi_x;
+    uint16_t dpi_y;
+} jpeg2pdf_options;
+
+/*
+ * Converts the JPEG image in jpeg_data (jpeg_len bytes) into a PDF document.
+ *
+ * On success, stores the document and its length in *pdf_data and *pdf_len and returns
+ * JPEG2PDF_OK; the document must be released using jpeg2pdf_free. Otherwise, returns one of the
+ * error codes and leaves *pdf_data and *pdf_len untouched. options may be NULL.
+ */
+int32_t jpeg2pdf_convert(
+    const uint8_t *jpeg_data,
+    size_t jpeg_len,
+    uint8_t **pdf_data,
+    size_t *pdf_len,
+    const jpeg2pdf_options *options
+);
+
+/* Releases a document returned by jpeg2pdf_convert. Does nothing if pdf_data is NULL. */
+void jpeg2pdf_free(uint8_t *pdf_data, size_t pdf_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* JPEG2PDF_H */
@@ -0,0 +1,252 @@
+//! Reading of images from ZIP archives, including comic book archives (CBZ).
+//!
+//! Only the central directory is read when the archive is opened; the data of an entry is read
+//! when it is requested. Entries must be stored or compressed using Deflate; encrypted entries and
+//! ZIP64 archives are not supported.
+
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::inflate::{self, inflate};
+
+
+/// The signature of the end of central directory record.
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4B50;
+/// The signature of a central directory file header.
+const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0201_4B50;
+/// The signature of a local file header.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4B50;
+
+const END_OF_CENTRAL_DIRECTORY_LENGTH: usize = 22;
+const CENTRAL_DIRECTORY_HEADER_LENGTH: usize = 46;
+const LOCAL_HEADER_LENGTH: usize = 30;
+/// The end of central directory record may be followed by a comment of up to this length.
+const MAX_COMMENT_LENGTH: usize = 0xFFFF;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// The general purpose flag marking an encrypted entry.
+const FLAG_ENCRYPTED: u16 = 0x0001;
+/// The general purpose flag marking a name encoded as UTF-8 (instead of code page 437).
+const FLAG_UTF8: u16 = 0x0800;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NoEndOfCentralDirectory,
+    Zip64NotSupported,
+    InvalidCentralDirectory,
+    InvalidLocalHeader { name: String },
+    EntryNotFound { name: String },
+    Encrypted { name: String },
+    UnsupportedMethod { name: String, method: u16 },
+    Inflate { name: String, error: inflate::Error },
+    ChecksumMismatch { name: String },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+            Self::NoEndOfCentralDirectory
+                => write!(f, "not a ZIP archive (no end of central directory record found)"),
+            Self::Zip64NotSupported
+                => write!(f, "ZIP64 archives are not supported"),
+            Self::InvalidCentralDirectory
+                => write!(f, "invalid central directory"),
+            Self::InvalidLocalHeader { name }
+                => write!(f, "invalid local header for entry {:?}", name),
+            Self::EntryNotFound { name }
+                => write!(f, "no entry {:?} in archive", name),
+            Self::Encrypted { name }
+                => write!(f, "entry {:?} is encrypted", name),
+            Self::UnsupportedMethod { name, method }
+                => write!(f, "entry {:?} uses unsupported compression method {}; only 0 (stored) and 8 (Deflate)", name, method),
+            Self::Inflate { name, error }
+                => write!(f, "failed to decompress entry {:?}: {}", name, error),
+            Self::ChecksumMismatch { name }
+                => write!(f, "checksum of entry {:?} does not match its data", name),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Inflate { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Calculates the CRC-32 checksum (as used by ZIP) of the given data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+
+/// A file stored in an archive.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Entry {
+    /// The path of the file within the archive, with forward slashes as separators.
+    pub name: String,
+    pub method: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    local_header_offset: u64,
+}
+impl Entry {
+    /// Whether the entry is a directory instead of a file.
+    pub fn is_directory(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+
+/// A ZIP archive whose entries can be read.
+pub struct ZipArchive<R: Read + Seek> {
+    reader: R,
+    entries: Vec<Entry>,
+}
+impl<R: Read + Seek> ZipArchive<R> {
+    /// Opens an archive by reading its central directory.
+    pub fn open(mut reader: R) -> Result<Self, Error> {
+        // the end of central directory record is at the end, possibly followed by a comment
+        let archive_length = reader.seek(SeekFrom::End(0))?;
+        let tail_length = archive_length.min((END_OF_CENTRAL_DIRECTORY_LENGTH + MAX_COMMENT_LENGTH) as u64);
+        reader.seek(SeekFrom::Start(archive_length - tail_length))?;
+        let mut tail = vec![0u8; tail_length as usize];
+        reader.read_exact(&mut tail)?;
+        let end_pos = (0..=tail.len().saturating_sub(END_OF_CENTRAL_DIRECTORY_LENGTH))
+            .rev()
+            .find(|&pos| u32_at(&tail, pos) == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+            .ok_or(Error::NoEndOfCentralDirectory)?;
+        let end = &tail[end_pos..];
+        let entry_count = u16_at(end, 10);
+        let directory_length = u32_at(end, 12);
+        let directory_offset = u32_at(end, 16);
+        if entry_count == 0xFFFF || directory_length == 0xFFFF_FFFF || directory_offset == 0xFFFF_FFFF {
+            return Err(Error::Zip64NotSupported);
+        }
+
+        reader.seek(SeekFrom::Start(directory_offset.into()))?;
+        let mut directory = vec![0u8; directory_length as usize];
+        reader.read_exact(&mut directory)?;
+
+        let mut entries = Vec::with_capacity(entry_count.into());
+        let mut pos = 0;
+        for _ in 0..entry_count {
+            let header = directory.get(pos..pos + CENTRAL_DIRECTORY_HEADER_LENGTH)
+                .ok_or(Error::InvalidCentralDirectory)?;
+            if u32_at(header, 0) != CENTRAL_DIRECTORY_SIGNATURE {
+                return Err(Error::InvalidCentralDirectory);
+            }
+            let flags = u16_at(header, 8);
+            let method = u16_at(header, 10);
+            let crc32 = u32_at(header, 16);
+            let compressed_size = u32_at(header, 20);
+            let uncompressed_size = u32_at(header, 24);
+            let name_length = usize::from(u16_at(header, 28));
+            let extra_length = usize::from(u16_at(header, 30));
+            let comment_length = usize::from(u16_at(header, 32));
+            let local_header_offset = u32_at(header, 42);
+            if compressed_size == 0xFFFF_FFFF || uncompressed_size == 0xFFFF_FFFF || local_header_offset == 0xFFFF_FFFF {
+                return Err(Error::Zip64NotSupported);
+            }
+
+            let name_start = pos + CENTRAL_DIRECTORY_HEADER_LENGTH;
+            let name_bytes = directory.get(name_start..name_start + name_length)
+                .ok_or(Error::InvalidCentralDirectory)?;
+            // names that are not marked as UTF-8 are almost always ASCII in practice
+            let name = if flags & FLAG_UTF8 != 0 {
+                String::from_utf8_lossy(name_bytes).into_owned()
+            } else {
+                name_bytes.iter().map(|&b| char::from(b)).collect()
+            };
+            entries.push(Entry {
+                name,
+                method,
+                flags,
+                crc32,
+                compressed_size: compressed_size.into(),
+                uncompressed_size: uncompressed_size.into(),
+                local_header_offset: local_header_offset.into(),
+            });
+            pos = name_start + name_length + extra_length + comment_length;
+        }
+
+        Ok(Self {
+            reader,
+            entries,
+        })
+    }
+
+    /// The entries of the archive, in the order in which they are stored.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Reads and decompresses the data of the entry with the given name.
+    pub fn read(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let entry = self.entries.iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| Error::EntryNotFound { name: name.to_owned() })?;
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(Error::Encrypted { name: entry.name.clone() });
+        }
+        if entry.method != METHOD_STORED && entry.method != METHOD_DEFLATE {
+            return Err(Error::UnsupportedMethod { name: entry.name.clone(), method: entry.method });
+        }
+
+        // the local header repeats most of the central directory header, but its extra field may
+        // differ in length
+        self.reader.seek(SeekFrom::Start(entry.local_header_offset))?;
+        let mut local_header = [0u8; LOCAL_HEADER_LENGTH];
+        self.reader.read_exact(&mut local_header)?;
+        if u32_at(&local_header, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(Error::InvalidLocalHeader { name: entry.name.clone() });
+        }
+        let name_length = u16_at(&local_header, 26);
+        let extra_length = u16_at(&local_header, 28);
+        self.reader.seek(SeekFrom::Current(i64::from(name_length) + i64::from(extra_length)))?;
+
+        let mut compressed = vec![0u8; entry.compressed_size as usize];
+        self.reader.read_exact(&mut compressed)?;
+        let data = if entry.method == METHOD_DEFLATE {
+            // the central directory states the size, which also guards against decompression bombs
+            inflate(&compressed, usize::try_from(entry.uncompressed_size).unwrap_or(usize::MAX))
+                .map_err(|error| Error::Inflate { name: entry.name.clone(), error })?
+        } else {
+            compressed
+        };
+        if crc32(&data) != entry.crc32 {
+            return Err(Error::ChecksumMismatch { name: entry.name.clone() });
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,860 @@
+//! Assembly of PDF documents page by page from the images read and the document options.
+
+
+use std::collections::btree_map::Entry;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use jpeg2pdf::{caption, content, jpeg, nup, page_numbers, pdf, stamp, toc};
+use jpeg2pdf::content::Operator;
+use jpeg2pdf::stamp::Stamp;
+
+use crate::{batch, manifest, report, ImagePage, OutputPage};
+use crate::opts::{DocumentOpts, LinkSpecTarget, PixelRect};
+
+
+/// Calculates the position and size of the crop area of the given image in PDF points.
+///
+/// Returns `(left, bottom, width, height)`, measured from the bottom left corner of the uncropped page.
+pub fn crop_area_pt(jpeg: &jpeg::Image, full_size_pt: (u64, u64), crop: &PixelRect) -> (f64, f64, f64, f64) {
+    let (full_width_pt, full_height_pt) = full_size_pt;
+    let scale_x = (full_width_pt as f64) / f64::from(jpeg.width);
+    let scale_y = (full_height_pt as f64) / f64::from(jpeg.height);
+    let bottom_px = u64::from(jpeg.height) - crop.top - crop.height;
+    (
+        (crop.left as f64) * scale_x,
+        (bottom_px as f64) * scale_y,
+        (crop.width as f64) * scale_x,
+        (crop.height as f64) * scale_y,
+    )
+}
+
+/// What has to be known about the pages of a document before they are assembled.
+pub struct PageOverview {
+    pub page_count: usize,
+    pub has_toc: bool,
+    /// Whether any image has alternative text, which makes the document tagged.
+    pub has_alt_text: bool,
+}
+impl PageOverview {
+    pub fn of(output_pages: &[OutputPage]) -> Self {
+        Self {
+            page_count: output_pages.len(),
+            has_toc: output_pages.iter().any(|p| matches!(p, OutputPage::TableOfContents(_))),
+            has_alt_text: output_pages.iter().any(|p| match p {
+                OutputPage::Image(ImagePage { alt_text, .. }) => alt_text.is_some(),
+                OutputPage::Sheet { cells, .. } => cells.iter()
+                    .any(|(cell, _area)| matches!(cell, OutputPage::Image(ImagePage { alt_text: Some(_), .. }))),
+                _ => false,
+            }),
+        }
+    }
+}
+
+/// A table-of-contents entry whose link annotation is added once all pages are known.
+pub struct TocLink {
+    pub toc_page_ref: pdf::ObjectRef<pdf::Page>,
+    pub rect: pdf::Rectangle,
+    pub target_page_index: usize,
+}
+
+/// An image drawn underneath the image on each page.
+pub struct PageBackground {
+    pub image_ref: pdf::ObjectRef<pdf::ImageXObject>,
+    /// The graphics state the background is drawn with, unless it is opaque.
+    pub opacity_ext_g_state_name: Option<String>,
+    /// The graphics state the image is drawn onto the background with.
+    pub multiply_ext_g_state_name: String,
+}
+
+/// Reads the background image and adds it to the document, along with the graphics states it
+/// and the images are drawn with.
+pub fn add_background(path: &Path, opacity_percent: u8, builder: &mut pdf::DocumentBuilder, ext_g_states: &mut Vec<pdf::ObjectRef<pdf::ExtGState>>) -> Result<PageBackground, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("failed to open background JPEG file {}: {}", path.display(), e))?;
+    let jpeg = jpeg::Image::try_read(std::io::BufReader::new(file))
+        .map_err(|e| format!("failed to read background JPEG file {}: {}", path.display(), e))?;
+    jpeg2pdf::check_encoding(&jpeg)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let color_space = jpeg.color_space;
+    let image = pdf::ImageXObject::from_jpeg_image(Arc::new(jpeg), color_space)
+        .ok_or_else(|| format!("{}: unsupported color space {:?}", path.display(), color_space))?;
+    let image_ref = builder.add(image);
+
+    let opacity_ext_g_state_name = if opacity_percent < 100 {
+        let name = format!("GS{}", ext_g_states.len());
+        ext_g_states.push(builder.add(pdf::ExtGState {
+            opacity_percent: Some(opacity_percent),
+            blend_mode: None,
+        }));
+        Some(name)
+    } else {
+        None
+    };
+    let multiply_ext_g_state_name = format!("GS{}", ext_g_states.len());
+    ext_g_states.push(builder.add(pdf::ExtGState {
+        opacity_percent: None,
+        blend_mode: Some(pdf::BlendMode::Multiply),
+    }));
+
+    Ok(PageBackground {
+        image_ref,
+        opacity_ext_g_state_name,
+        multiply_ext_g_state_name,
+    })
+}
+
+/// Assembles a PDF document page by page.
+///
+/// The objects of each page can be written out as soon as the page has been added, so that only
+/// one image at a time has to be held in memory.
+pub struct DocumentAssembler<'a> {
+    pub document_opts: &'a DocumentOpts,
+    pub builder: pdf::DocumentBuilder,
+    pub page_count: usize,
+    pub signature_refs: Option<(pdf::ObjectRef<pdf::SignatureField>, pdf::ObjectRef<pdf::Signature>)>,
+    pub stamp: Option<Stamp>,
+    pub background: Option<PageBackground>,
+    pub page_numbers: Option<page_numbers::PageNumbers>,
+    pub page_number_font_name: String,
+    pub caption: Option<caption::Caption>,
+    pub caption_font_name: String,
+    pub fonts: Vec<pdf::ObjectRef<pdf::Type1Font>>,
+    pub ext_g_states: Vec<pdf::ObjectRef<pdf::ExtGState>>,
+    pub optional_content_groups: Vec<pdf::ObjectRef<pdf::OptionalContentGroup>>,
+    pub has_text: bool,
+    pub toc_fonts: Option<(pdf::ObjectRef<pdf::Type1Font>, pdf::ObjectRef<pdf::Type1Font>)>,
+    /// Added along with the first placeholder page.
+    pub placeholder_font: Option<pdf::ObjectRef<pdf::Type1Font>>,
+    /// The image XObjects of the previous page, which the next page reuses if it shows another
+    /// slice of the same image (see --paginate-height).
+    pub previous_images: Vec<(Arc<jpeg::Image>, pdf::ObjectRef<pdf::ImageXObject>)>,
+    /// Only collected if a manifest has been requested.
+    pub manifest_pages: Vec<manifest::PageManifest>,
+    pub toc_links: Vec<TocLink>,
+    pub tagged: bool,
+    pub structure_refs: Option<(pdf::ObjectRef<pdf::StructTreeRoot>, pdf::ObjectRef<pdf::StructElement>)>,
+    pub structure_element_ids: Vec<pdf::PdfObjectId>,
+    pub struct_parent_tree: Vec<Vec<pdf::PdfObjectId>>,
+}
+impl<'a> DocumentAssembler<'a> {
+    pub fn new(overview: &PageOverview, document_opts: &'a DocumentOpts) -> Result<Self, String> {
+        let compression = if document_opts.compress {
+            pdf::Compression::Flate
+        } else {
+            pdf::Compression::None
+        };
+        let mut builder = pdf::DocumentBuilder::new(compression);
+        builder.set_ascii_encoding(document_opts.ascii_encoding);
+        builder.set_write_threads(document_opts.write_threads);
+        builder.set_version(document_opts.pdf_version);
+        if let Some(encryption) = document_opts.encryption_opts.to_encryption() {
+            builder.set_encryption(encryption);
+        }
+
+        let signature_refs = if document_opts.prepare_signature {
+            let signature_field_ref: pdf::ObjectRef<pdf::SignatureField> = builder.reserve();
+            let signature_ref: pdf::ObjectRef<pdf::Signature> = builder.reserve();
+            Some((signature_field_ref, signature_ref))
+        } else {
+            None
+        };
+
+        let stamp = document_opts.stamp_opts.to_stamp();
+        let mut fonts = Vec::new();
+        let mut ext_g_states = Vec::new();
+        if stamp.is_some() {
+            fonts.push(builder.add(pdf::Type1Font {
+                font: stamp::STAMP_FONT,
+            }));
+            ext_g_states.push(builder.add(pdf::ExtGState {
+                opacity_percent: Some(document_opts.stamp_opts.stamp_opacity),
+                blend_mode: None,
+            }));
+        }
+
+        // the graphics states of the background follow the one of the stamp (if any)
+        let background_opts = &document_opts.background_opts;
+        let background = background_opts.background.as_ref()
+            .map(|path| add_background(path, background_opts.background_opacity, &mut builder, &mut ext_g_states))
+            .transpose()?;
+
+        // the page number font follows the stamp font (if any)
+        let page_numbers = document_opts.page_number_opts.to_page_numbers();
+        let page_number_font_name = format!("F{}", fonts.len());
+        if page_numbers.is_some() {
+            fonts.push(builder.add(pdf::Type1Font {
+                font: page_numbers::PAGE_NUMBER_FONT,
+            }));
+        }
+
+        // the caption font follows the page number font (if any)
+        let caption = document_opts.caption_opts.to_caption();
+        let caption_font_name = format!("F{}", fonts.len());
+        if caption.is_some() {
+            fonts.push(builder.add(pdf::Type1Font {
+                font: caption::CAPTION_FONT,
+            }));
+        }
+
+        // the image layer comes first, followed by the text layer if there is any text
+        let mut optional_content_groups = Vec::new();
+        let has_text = stamp.is_some() || page_numbers.is_some() || caption.is_some();
+        if document_opts.layers {
+            optional_content_groups.push(builder.add(pdf::OptionalContentGroup {
+                name: "Images".to_owned(),
+            }));
+            if has_text {
+                optional_content_groups.push(builder.add(pdf::OptionalContentGroup {
+                    name: "Text".to_owned(),
+                }));
+            }
+            builder.catalog_mut().optional_content_group_ids = optional_content_groups.iter()
+                .map(|group_ref| group_ref.id())
+                .collect();
+        }
+
+        // fonts for the table of contents, following the stamp, page number and caption fonts (if any)
+        let toc_fonts = if overview.has_toc {
+            let entry_font_ref = builder.add(pdf::Type1Font {
+                font: toc::ENTRY_FONT,
+            });
+            let title_font_ref = builder.add(pdf::Type1Font {
+                font: toc::TITLE_FONT,
+            });
+            Some((entry_font_ref, title_font_ref))
+        } else {
+            None
+        };
+
+        let tagged = document_opts.tagged || overview.has_alt_text;
+        let structure_refs = if tagged {
+            let struct_tree_root_ref: pdf::ObjectRef<pdf::StructTreeRoot> = builder.reserve();
+            let document_element_ref: pdf::ObjectRef<pdf::StructElement> = builder.reserve();
+            Some((struct_tree_root_ref, document_element_ref))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            document_opts,
+            builder,
+            page_count: overview.page_count,
+            signature_refs,
+            stamp,
+            background,
+            page_numbers,
+            page_number_font_name,
+            caption,
+            caption_font_name,
+            fonts,
+            ext_g_states,
+            optional_content_groups,
+            has_text,
+            toc_fonts,
+            placeholder_font: None,
+            previous_images: Vec::new(),
+            toc_links: Vec::new(),
+            tagged,
+            structure_refs,
+            structure_element_ids: Vec::new(),
+            struct_parent_tree: Vec::new(),
+            manifest_pages: Vec::new(),
+        })
+    }
+
+    /// Defines the named destination for the image from the given file, leading to the given page.
+    pub fn add_file_destination(&mut self, input_index: usize, path: &Path, page_ref: pdf::ObjectRef<pdf::Page>, report: &mut report::Report) {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = format!("file:{}", stem);
+        match self.builder.catalog_mut().named_destinations.entry(name) {
+            Entry::Occupied(entry) => report.warn(input_index, format!(
+                "named destination {} already leads to an earlier image; not redefined", entry.key(),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(pdf::Destination { page_id: page_ref.id() });
+            },
+        }
+    }
+
+    /// Returns the operators drawing the given image onto a page the size of its crop area,
+    /// adding its image XObjects to `images` (reusing those of the previous page if it shows
+    /// another slice of the same image) and to `page_images`.
+    ///
+    /// `page_scale` is the factor by which that page is scaled when it is drawn, which the
+    /// resolution recorded in the manifest takes into account.
+    pub fn image_operators(
+        &mut self,
+        image_page: &ImagePage,
+        page_scale: f64,
+        images: &mut Vec<pdf::ObjectRef<pdf::ImageXObject>>,
+        page_images: &mut Vec<(Arc<jpeg::Image>, pdf::ObjectRef<pdf::ImageXObject>)>,
+        manifest_images: &mut Vec<manifest::ImageManifest>,
+    ) -> Vec<Operator> {
+        let ImagePage { jpeg, color_space, decode_array, color_key_mask, interpolate, rendering_intent, full_size_pt, crop, tiles, .. } = image_page;
+
+        // the image is drawn whole or as tiles, each at its position within the image
+        let (full_width_pt, full_height_pt) = *full_size_pt;
+        let placements: Vec<(Arc<jpeg::Image>, f64, f64, f64, f64)> = if tiles.is_empty() {
+            vec![(Arc::clone(jpeg), 0.0, 0.0, full_width_pt as f64, full_height_pt as f64)]
+        } else {
+            let scale_x = (full_width_pt as f64) / f64::from(jpeg.width);
+            let scale_y = (full_height_pt as f64) / f64::from(jpeg.height);
+            tiles.iter()
+                .map(|t| {
+                    let bottom_px = jpeg.height - t.top - t.jpeg.height;
+                    (
+                        Arc::clone(&t.jpeg),
+                        f64::from(t.left) * scale_x,
+                        f64::from(bottom_px) * scale_y,
+                        f64::from(t.jpeg.width) * scale_x,
+                        f64::from(t.jpeg.height) * scale_y,
+                    )
+                })
+                .collect()
+        };
+
+        // when cropping, the image is shifted such that the crop area is at the origin
+        let (shift_x_pt, shift_y_pt) = if let Some(crop) = crop {
+            let (left_pt, bottom_pt, _width_pt, _height_pt) = crop_area_pt(jpeg, *full_size_pt, crop);
+            (-left_pt, -bottom_pt)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let mut operators = Vec::with_capacity(4 * placements.len());
+        for (image_jpeg, left_pt, bottom_pt, image_width_pt, image_height_pt) in placements {
+            let previous_image_ref = self.previous_images.iter()
+                .chain(page_images.iter())
+                .find(|(previous_jpeg, _image_ref)| Arc::ptr_eq(previous_jpeg, &image_jpeg))
+                .map(|(_previous_jpeg, image_ref)| *image_ref);
+            let image_ref = previous_image_ref.unwrap_or_else(|| {
+                let mut image = pdf::ImageXObject::from_jpeg_image(Arc::clone(&image_jpeg), *color_space).unwrap();
+                if let Some(decode_array) = decode_array {
+                    image.decode = Some(decode_array.to_pdf());
+                }
+                image.color_key_mask = color_key_mask.clone();
+                image.interpolate = *interpolate;
+                image.intent = *rendering_intent;
+                self.builder.add(image)
+            });
+            if self.document_opts.manifest.is_some() {
+                manifest_images.push(manifest::ImageManifest::of(
+                    image_ref.id(), &image_jpeg, image_width_pt * page_scale, image_height_pt * page_scale,
+                ));
+            }
+            page_images.push((image_jpeg, image_ref));
+            operators.extend([
+                Operator::SaveState,
+                Operator::ConcatMatrix(content::scale_translate(
+                    image_width_pt, image_height_pt, left_pt + shift_x_pt, bottom_pt + shift_y_pt,
+                )),
+                Operator::DrawXObject(format!("Im{}", images.len())),
+                Operator::RestoreState,
+            ]);
+            images.push(image_ref);
+        }
+        operators
+    }
+
+    /// If there is a page background, makes the given operators multiply onto it and returns the
+    /// operators drawing it onto a page of the given size, adding its image XObject to `images`.
+    pub fn background_operators(&self, operators: &mut Vec<Operator>, images: &mut Vec<pdf::ObjectRef<pdf::ImageXObject>>, width_pt: u64, height_pt: u64) -> Vec<Operator> {
+        let Some(background) = &self.background else { return Vec::new() };
+
+        // the image is multiplied onto the background
+        let mut multiplied_operators = vec![
+            Operator::SaveState,
+            Operator::SetExtGState(background.multiply_ext_g_state_name.clone()),
+        ];
+        multiplied_operators.append(operators);
+        multiplied_operators.push(Operator::RestoreState);
+        *operators = multiplied_operators;
+
+        let mut background_operators = vec![Operator::SaveState];
+        if let Some(ext_g_state_name) = &background.opacity_ext_g_state_name {
+            background_operators.push(Operator::SetExtGState(ext_g_state_name.clone()));
+        }
+        background_operators.extend([
+            Operator::ConcatMatrix(content::scale_translate(width_pt as f64, height_pt as f64, 0.0, 0.0)),
+            Operator::DrawXObject(format!("Im{}", images.len())),
+            Operator::RestoreState,
+        ]);
+        images.push(background.image_ref);
+        if self.tagged {
+            // the background is not part of the document's content
+            background_operators = content::mark("Artifact", None, background_operators);
+        }
+        background_operators
+    }
+
+    /// Adds the next page of the document.
+    pub fn add_page(&mut self, output_page: &OutputPage, report: &mut report::Report) {
+        let document_opts = self.document_opts;
+        let page_index = self.builder.page_count();
+        let (width_pt, height_pt) = output_page.size_pt();
+
+        let mut reserved_page_ref = None;
+        let mut page_fonts = self.fonts.clone();
+        let mut images = Vec::new();
+        let mut operators = Vec::new();
+        let mut crop_box = None;
+        let mut rotate = None;
+        let mut struct_parents = None;
+        let mut caption_operators = Vec::new();
+        let mut manifest_images = Vec::new();
+        if let OutputPage::Image(image_page) = output_page {
+            let ImagePage { input_index, path, crop, rotation, alt_text, date, .. } = image_page;
+            // the figure structure element references the page
+            let page_ref = self.builder.reserve_page();
+            reserved_page_ref = Some(page_ref);
+            if document_opts.file_destinations {
+                self.add_file_destination(*input_index, path, page_ref, report);
+            }
+            rotate = rotation
+                .map(|r| r.degrees)
+                .filter(|&degrees| degrees != 0);
+
+            // when cropping, the image is shifted such that the crop area is on the page
+            if crop.is_some() {
+                crop_box = Some(pdf::Rectangle {
+                    left: 0,
+                    bottom: 0,
+                    right: width_pt,
+                    top: height_pt,
+                });
+            }
+
+            let mut page_images = Vec::new();
+            operators = self.image_operators(image_page, 1.0, &mut images, &mut page_images, &mut manifest_images);
+            self.previous_images = page_images;
+
+            if let Some(caption) = &self.caption {
+                // the image makes room for the caption beneath it
+                let (transform, (left_pt, bottom_pt, area_width_pt, area_height_pt)) = caption.image_transform(
+                    width_pt as f64, height_pt as f64,
+                );
+                let mut shrunk_operators = vec![
+                    Operator::SaveState,
+                    Operator::ClipRectangle(left_pt, bottom_pt, area_width_pt, area_height_pt),
+                    Operator::ConcatMatrix(transform),
+                ];
+                shrunk_operators.append(&mut operators);
+                shrunk_operators.push(Operator::RestoreState);
+                operators = shrunk_operators;
+
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                let text = caption.text(&file_name, date.as_deref(), page_index + 1);
+                caption_operators = caption.operators(&text, width_pt as f64, &self.caption_font_name);
+            }
+
+            let mut background_operators = self.background_operators(&mut operators, &mut images, width_pt, height_pt);
+
+            if let Some((_, document_element_ref)) = self.structure_refs {
+                // mark the image as a figure
+                if alt_text.is_none() {
+                    report.warn(*input_index, "no alternative text for image in tagged PDF".to_owned());
+                }
+                let figure_ref = self.builder.add(pdf::StructElement {
+                    structure_type: "Figure",
+                    parent_id: document_element_ref.id(),
+                    page_id: Some(page_ref.id()),
+                    kids: vec![pdf::StructElementKid::MarkedContent(0)],
+                    alt_text: alt_text.clone(),
+                });
+                self.structure_element_ids.push(figure_ref.id());
+
+                operators = content::mark("Figure", Some(0), operators);
+                struct_parents = Some(u64::try_from(self.struct_parent_tree.len()).unwrap());
+                self.struct_parent_tree.push(vec![figure_ref.id()]);
+            }
+            background_operators.append(&mut operators);
+            operators = background_operators;
+            if document_opts.layers {
+                operators = content::mark_optional("OC0", operators);
+            }
+        } else if let OutputPage::Sheet { cells, .. } = output_page {
+            let page_ref = self.builder.reserve_page();
+            reserved_page_ref = Some(page_ref);
+
+            let mut page_images = Vec::new();
+            let mut figure_ids = Vec::new();
+            for (cell, cell_area) in cells {
+                // blank pages leave their cell empty
+                let OutputPage::Image(image_page) = cell else { continue };
+                let ImagePage { input_index, path, rotation, alt_text, .. } = image_page;
+                if document_opts.file_destinations {
+                    self.add_file_destination(*input_index, path, page_ref, report);
+                }
+
+                // the image is drawn as if onto its own page, which is then fitted into the cell
+                let (cell_page_width_pt, cell_page_height_pt) = cell.size_pt();
+                let rotation_degrees = rotation.map_or(0, |r| r.degrees);
+                let transform = nup::fit_transform(
+                    cell_page_width_pt as f64, cell_page_height_pt as f64, rotation_degrees, *cell_area,
+                );
+                let scale = transform[0].hypot(transform[1]);
+                let mut cell_operators = vec![
+                    Operator::SaveState,
+                    Operator::ConcatMatrix(transform),
+                    Operator::ClipRectangle(0.0, 0.0, cell_page_width_pt as f64, cell_page_height_pt as f64),
+                ];
+                cell_operators.extend(self.image_operators(image_page, scale, &mut images, &mut page_images, &mut manifest_images));
+                cell_operators.push(Operator::RestoreState);
+
+                if let Some((_, document_element_ref)) = self.structure_refs {
+                    // each image is a figure of its own
+                    if alt_text.is_none() {
+                        report.warn(*input_index, "no alternative text for image in tagged PDF".to_owned());
+                    }
+                    let mcid = u64::try_from(figure_ids.len()).unwrap();
+                    let figure_ref = self.builder.add(pdf::StructElement {
+                        structure_type: "Figure",
+                        parent_id: document_element_ref.id(),
+                        page_id: Some(page_ref.id()),
+                        kids: vec![pdf::StructElementKid::MarkedContent(mcid)],
+                        alt_text: alt_text.clone(),
+                    });
+                    self.structure_element_ids.push(figure_ref.id());
+                    figure_ids.push(figure_ref.id());
+                    cell_operators = content::mark("Figure", Some(mcid), cell_operators);
+                }
+                operators.extend(cell_operators);
+            }
+            self.previous_images = page_images;
+            if !figure_ids.is_empty() {
+                struct_parents = Some(u64::try_from(self.struct_parent_tree.len()).unwrap());
+                self.struct_parent_tree.push(figure_ids);
+            }
+
+            let mut background_operators = self.background_operators(&mut operators, &mut images, width_pt, height_pt);
+            background_operators.append(&mut operators);
+            operators = background_operators;
+            if document_opts.layers {
+                operators = content::mark_optional("OC0", operators);
+            }
+        } else if let OutputPage::TableOfContents(toc_page) = output_page {
+            // the entries link to their pages once all pages exist
+            let page_ref = self.builder.reserve_page();
+            reserved_page_ref = Some(page_ref);
+
+            let (entry_font_ref, title_font_ref) = self.toc_fonts.unwrap();
+            let entry_font_name = format!("F{}", page_fonts.len());
+            page_fonts.push(entry_font_ref);
+            let title_font_name = format!("F{}", page_fonts.len());
+            page_fonts.push(title_font_ref);
+
+            operators = toc_page.operators(width_pt as f64, height_pt as f64, &entry_font_name, &title_font_name);
+            let entry_rects_pt = toc_page.entry_rects_pt(width_pt as f64, height_pt as f64);
+            for (entry, (left_pt, bottom_pt, right_pt, top_pt)) in toc_page.entries.iter().zip(entry_rects_pt) {
+                self.toc_links.push(TocLink {
+                    toc_page_ref: page_ref,
+                    rect: pdf::Rectangle {
+                        left: left_pt.round() as u64,
+                        bottom: bottom_pt.round() as u64,
+                        right: right_pt.round() as u64,
+                        top: top_pt.round() as u64,
+                    },
+                    target_page_index: entry.page_index,
+                });
+            }
+
+            if let Some((_, document_element_ref)) = self.structure_refs {
+                let toc_element_ref = self.builder.add(pdf::StructElement {
+                    structure_type: "TOC",
+                    parent_id: document_element_ref.id(),
+                    page_id: Some(page_ref.id()),
+                    kids: vec![pdf::StructElementKid::MarkedContent(0)],
+                    alt_text: None,
+                });
+                self.structure_element_ids.push(toc_element_ref.id());
+
+                operators = content::mark("TOC", Some(0), operators);
+                struct_parents = Some(u64::try_from(self.struct_parent_tree.len()).unwrap());
+                self.struct_parent_tree.push(vec![toc_element_ref.id()]);
+            }
+        } else if let OutputPage::Placeholder { message } = output_page {
+            let page_ref = self.builder.reserve_page();
+            reserved_page_ref = Some(page_ref);
+
+            let font_ref = *self.placeholder_font.get_or_insert_with(|| self.builder.add(pdf::Type1Font {
+                font: batch::PLACEHOLDER_FONT,
+            }));
+            let font_name = format!("F{}", page_fonts.len());
+            page_fonts.push(font_ref);
+            operators = batch::placeholder_operators(message, width_pt as f64, height_pt as f64, &font_name);
+
+            if let Some((_, document_element_ref)) = self.structure_refs {
+                let paragraph_ref = self.builder.add(pdf::StructElement {
+                    structure_type: "P",
+                    parent_id: document_element_ref.id(),
+                    page_id: Some(page_ref.id()),
+                    kids: vec![pdf::StructElementKid::MarkedContent(0)],
+                    alt_text: None,
+                });
+                self.structure_element_ids.push(paragraph_ref.id());
+
+                operators = content::mark("P", Some(0), operators);
+                struct_parents = Some(u64::try_from(self.struct_parent_tree.len()).unwrap());
+                self.struct_parent_tree.push(vec![paragraph_ref.id()]);
+            }
+        }
+        let mut text_operators = Vec::new();
+        if !caption_operators.is_empty() {
+            if self.tagged {
+                text_operators.extend(content::mark("Artifact", None, caption_operators));
+            } else {
+                text_operators.extend(caption_operators);
+            }
+        }
+        if let Some(stamp) = &self.stamp {
+            let stamp_operators = stamp.operators(width_pt as f64, height_pt as f64, "F0", "GS0");
+            if self.tagged {
+                // the stamp is not part of the document's content
+                text_operators.extend(content::mark("Artifact", None, stamp_operators));
+            } else {
+                text_operators.extend(stamp_operators);
+            }
+        }
+        if let Some(page_numbers) = &self.page_numbers {
+            let page_number_operators = page_numbers.operators(
+                page_index + 1, self.page_count, width_pt as f64, height_pt as f64, &self.page_number_font_name,
+            );
+            if self.tagged {
+                text_operators.extend(content::mark("Artifact", None, page_number_operators));
+            } else {
+                text_operators.extend(page_number_operators);
+            }
+        }
+        if document_opts.layers && self.has_text {
+            text_operators = content::mark_optional("OC1", text_operators);
+        }
+        operators.extend(text_operators);
+
+        // pages that are too large are drawn in larger units
+        let user_unit = jpeg2pdf::user_unit_for(width_pt, height_pt);
+        let (width_units, height_units) = if let Some(user_unit) = user_unit {
+            let scale = 1.0 / (user_unit as f64);
+            let mut scaled_operators = vec![
+                Operator::SaveState,
+                Operator::ConcatMatrix(content::scale_translate(scale, scale, 0.0, 0.0)),
+            ];
+            scaled_operators.append(&mut operators);
+            scaled_operators.push(Operator::RestoreState);
+            operators = scaled_operators;
+            if let Some(crop_box) = &mut crop_box {
+                crop_box.right = crop_box.right.div_ceil(user_unit);
+                crop_box.top = crop_box.top.div_ceil(user_unit);
+            }
+            (width_pt.div_ceil(user_unit), height_pt.div_ceil(user_unit))
+        } else {
+            (width_pt, height_pt)
+        };
+
+        let (transition, display_duration_ms) = if document_opts.view_opts.presentation {
+            let view_opts = &document_opts.view_opts;
+            let transition = pdf::Transition {
+                style: view_opts.transition,
+                duration_ms: view_opts.transition_duration.milliseconds,
+            };
+            let display_duration_ms = Some(view_opts.slide_duration.milliseconds)
+                .filter(|&ms| ms > 0);
+            (Some(transition), display_duration_ms)
+        } else {
+            (None, None)
+        };
+
+        // the signature field is attached to the first page
+        let annotation_ids = match self.signature_refs {
+            Some((signature_field_ref, _)) if page_index == 0 => vec![signature_field_ref.id()],
+            _ => Vec::new(),
+        };
+
+        let new_page = pdf::NewPage {
+            width: width_units,
+            height: height_units,
+            user_unit,
+            crop_box,
+            rotate,
+            struct_parents,
+            annotation_ids,
+            transition,
+            display_duration_ms,
+            images,
+            fonts: page_fonts,
+            ext_g_states: self.ext_g_states.clone(),
+            optional_content_groups: self.optional_content_groups.clone(),
+            operators,
+        };
+        let page_ref = match reserved_page_ref {
+            Some(page_ref) => {
+                self.builder.fill_page(page_ref, new_page);
+                page_ref
+            },
+            None => self.builder.add_page(new_page),
+        };
+
+        if document_opts.manifest.is_some() {
+            let (kind, source) = match output_page {
+                OutputPage::Image(ImagePage { path, .. }) => ("image", Some(path.clone())),
+                OutputPage::Blank { .. } => ("blank", None),
+                OutputPage::TableOfContents(_) => ("table-of-contents", None),
+                OutputPage::Placeholder { .. } => ("placeholder", None),
+                OutputPage::Sheet { .. } => ("sheet", None),
+            };
+            self.manifest_pages.push(manifest::PageManifest {
+                object_id: page_ref.id(),
+                kind,
+                source,
+                size_pt: (width_pt, height_pt),
+                images: manifest_images,
+            });
+        }
+    }
+
+    /// Adds what refers to the pages (links, the signature field and the structure tree) and
+    /// the document-wide entries, returning the builder for the complete document.
+    pub fn finish(self) -> Result<pdf::DocumentBuilder, String> {
+        let Self { document_opts, mut builder, signature_refs, toc_links, structure_refs, structure_element_ids, struct_parent_tree, .. } = self;
+
+        let page_refs = builder.pages();
+        let get_page_ref = |page_number: usize, purpose: &str| {
+            page_refs.get(page_number - 1)
+                .copied()
+                .ok_or_else(|| format!("cannot {} page {}; the document has {} pages", purpose, page_number, page_refs.len()))
+        };
+
+        for toc_link in toc_links {
+            let annotation_ref = builder.add(pdf::LinkAnnotation {
+                rect: toc_link.rect,
+                target: pdf::LinkTarget::Destination(pdf::Destination {
+                    page_id: page_refs[toc_link.target_page_index].id(),
+                }),
+            });
+            builder.attach_annotation(toc_link.toc_page_ref, annotation_ref);
+        }
+
+        for link in &document_opts.link {
+            let page_ref = get_page_ref(link.page, "add link to")?;
+            let target = match &link.target {
+                LinkSpecTarget::Url(url) => pdf::LinkTarget::Uri(url.clone()),
+                LinkSpecTarget::Page(target_page) => pdf::LinkTarget::Destination(pdf::Destination {
+                    page_id: get_page_ref(*target_page, "link to")?.id(),
+                }),
+            };
+
+            // convert from points measured from the top to user space units measured from the bottom
+            let page = builder.page(page_ref);
+            let unit = page.user_unit.unwrap_or(1) as f64;
+            let height_pt = (page.height as f64) * unit;
+            let to_units = |pt: f64| (pt / unit).round().max(0.0) as u64;
+            let rect = pdf::Rectangle {
+                left: to_units(link.left_pt),
+                bottom: to_units(height_pt - link.top_pt - link.height_pt),
+                right: to_units(link.left_pt + link.width_pt),
+                top: to_units(height_pt - link.top_pt),
+            };
+
+            let annotation_ref = builder.add(pdf::LinkAnnotation {
+                rect,
+                target,
+            });
+            builder.attach_annotation(page_ref, annotation_ref);
+        }
+
+        for destination in &document_opts.destination {
+            let page_ref = get_page_ref(destination.page, "define a destination on")?;
+            builder.catalog_mut().named_destinations.insert(
+                destination.name.clone(),
+                pdf::Destination { page_id: page_ref.id() },
+            );
+        }
+
+        if let Some((signature_field_ref, signature_ref)) = signature_refs {
+            builder.fill(signature_field_ref, pdf::SignatureField {
+                name: "Signature1".to_owned(),
+                page_id: page_refs[0].id(),
+                signature_id: signature_ref.id(),
+            });
+            builder.fill(signature_ref, pdf::Signature {
+                contents_size: document_opts.signature_size,
+            });
+
+            builder.catalog_mut().acro_form = Some(pdf::AcroForm {
+                field_ids: vec![signature_field_ref.id()],
+                // SignaturesExist (1) | AppendOnly (2)
+                sig_flags: 3,
+            });
+        }
+
+        if let Some((struct_tree_root_ref, document_element_ref)) = structure_refs {
+            builder.fill(struct_tree_root_ref, pdf::StructTreeRoot {
+                kid_ids: vec![document_element_ref.id()],
+                parent_tree: struct_parent_tree,
+            });
+            builder.fill(document_element_ref, pdf::StructElement {
+                structure_type: "Document",
+                parent_id: struct_tree_root_ref.id(),
+                page_id: None,
+                kids: structure_element_ids.into_iter()
+                    .map(pdf::StructElementKid::Element)
+                    .collect(),
+                alt_text: None,
+            });
+            builder.catalog_mut().struct_tree_root_id = Some(struct_tree_root_ref.id());
+        }
+
+        let view_opts = &document_opts.view_opts;
+        let catalog = builder.catalog_mut();
+        if view_opts.fit_window || view_opts.hide_toolbar || view_opts.reading_direction != pdf::ReadingDirection::default() {
+            catalog.viewer_preferences = Some(pdf::ViewerPreferences {
+                fit_window: view_opts.fit_window,
+                hide_toolbar: view_opts.hide_toolbar,
+                direction: view_opts.reading_direction,
+            });
+        }
+        if view_opts.two_page_view {
+            catalog.page_layout = Some(pdf::PageLayout::TwoPageRight);
+        }
+        if view_opts.presentation {
+            catalog.page_mode = Some(pdf::PageMode::FullScreen);
+        }
+        if let Some(page_number) = view_opts.open_at_page {
+            let page_ref = get_page_ref(usize::try_from(page_number).unwrap_or(usize::MAX), "open at")?;
+            catalog.open_action = Some(pdf::Destination {
+                page_id: page_ref.id(),
+            });
+        }
+
+        let date = document_opts.date.to_date();
+        let info = pdf::Info {
+            producer: Some(document_opts.producer.clone()).filter(|p| !p.is_empty()),
+            creator: document_opts.creator.clone(),
+            creation_date: date,
+            mod_date: date,
+        };
+        if info.producer.is_some() || info.creator.is_some() || info.creation_date.is_some() {
+            builder.add(info);
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Assembles a PDF document from the given pages, along with the manifest entries of the pages
+/// if a manifest has been requested.
+pub fn assemble_document(output_pages: &[OutputPage], document_opts: &DocumentOpts, report: &mut report::Report) -> Result<(pdf::Document, Vec<manifest::PageManifest>), String> {
+    let start = Instant::now();
+    let mut assembler = DocumentAssembler::new(&PageOverview::of(output_pages), document_opts)?;
+    for output_page in output_pages {
+        assembler.add_page(output_page, report);
+    }
+    let manifest_pages = std::mem::take(&mut assembler.manifest_pages);
+    let pdf = assembler.finish()?
+        .build()
+        .map_err(|e| format!("failed to assemble PDF document: {}", e))?;
+    log::info!("assemble pages={} elapsed={:.3}s", output_pages.len(), start.elapsed().as_secs_f64());
+    Ok((pdf, manifest_pages))
+}
@@ -0,0 +1,244 @@
+//! Conversion of many images, each into its own PDF document, with progress output and the ability
+//! to resume an interrupted run.
+
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use jpeg2pdf::content::Operator;
+use jpeg2pdf::font::{encode_win_ansi, StandardFont};
+
+use crate::fetch::is_url;
+
+
+/// The name of the file in which progress is recorded if no other file is specified.
+pub const DEFAULT_STATE_FILE_NAME: &str = "jpeg2pdf-batch.state";
+
+
+/// The font in which the reason is set on placeholder pages.
+pub const PLACEHOLDER_FONT: StandardFont = StandardFont::Helvetica;
+
+const PLACEHOLDER_MARGIN_PT: f64 = 72.0;
+const PLACEHOLDER_SIZE_PT: f64 = 12.0;
+const PLACEHOLDER_LINE_HEIGHT_PT: f64 = 16.0;
+
+
+/// What to do when an image cannot be converted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum OnError {
+    /// Continue with the next image; no PDF file is written for the failed one.
+    Skip,
+
+    /// Stop converting images.
+    #[default]
+    Abort,
+
+    /// Continue with the next image; the PDF file of the failed one consists of a page stating
+    /// why the conversion failed.
+    Placeholder,
+}
+
+
+/// A single conversion of an input image into an output document.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Job {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+}
+impl Job {
+    /// Creates a job converting the given image into a PDF file of the same name (but with the
+    /// extension `.pdf`) in the given directory, or next to the image if no directory is given.
+    /// PDF files for images given as URLs are written into the current directory by default.
+    pub fn for_input(input_path: &Path, output_dir: Option<&Path>) -> Self {
+        let output_file_name = Path::new(input_path.file_name().unwrap_or_default())
+            .with_extension("pdf");
+        let output_path = match output_dir {
+            Some(dir) => dir.join(output_file_name),
+            None if is_url(input_path) => output_file_name,
+            None => input_path.with_file_name(output_file_name),
+        };
+        Self {
+            input_path: input_path.to_owned(),
+            output_path,
+        }
+    }
+}
+
+
+/// The record of which jobs have already been completed, stored in a text file with one input path
+/// per line.
+pub struct ResumeState {
+    completed: BTreeSet<String>,
+    file: File,
+}
+impl ResumeState {
+    /// Loads the state from the given file, creating it if it does not exist yet.
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let mut completed = BTreeSet::new();
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if !line.is_empty() {
+                        completed.insert(line);
+                    }
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            completed,
+            file,
+        })
+    }
+
+    pub fn is_completed(&self, job: &Job) -> bool {
+        self.completed.contains(job.input_path.to_string_lossy().as_ref())
+    }
+
+    /// Records that the given job has been completed. The state file is updated immediately.
+    pub fn mark_completed(&mut self, job: &Job) -> Result<(), io::Error> {
+        let key = job.input_path.to_string_lossy().into_owned();
+        write!(self.file, "{}\n", key)?;
+        self.file.flush()?;
+        self.completed.insert(key);
+        Ok(())
+    }
+}
+
+
+/// How a run of jobs ended.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Outcome {
+    /// The indexes of the jobs skipped because they had been completed by a previous run.
+    pub skipped: Vec<usize>,
+    pub converted: usize,
+    pub failed: usize,
+}
+impl Outcome {
+    /// The exit status of the process: 0 if no conversion failed, 1 if some failed and 2 if all
+    /// that were attempted failed.
+    pub fn exit_code(&self) -> i32 {
+        if self.failed == 0 {
+            0
+        } else if self.converted > 0 {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+
+/// Runs the given jobs in order using the given conversion function, outputting progress to
+/// standard error. The conversion function is passed the index of the job and the job itself and
+/// returns why the conversion failed, if it did; the remaining jobs are then run or not as
+/// `on_error` requires.
+///
+/// If a resume state is passed, jobs it records as completed are skipped and each job is recorded
+/// in it as soon as it is completed. Failed jobs are not recorded, so that they are retried.
+pub fn run_jobs<F: FnMut(usize, &Job) -> Result<(), String>>(jobs: &[Job], mut resume_state: Option<&mut ResumeState>, on_error: OnError, mut convert: F) -> Outcome {
+    let total = jobs.len();
+    let mut outcome = Outcome::default();
+    for (index, job) in jobs.iter().enumerate() {
+        let percent = 100.0 * ((index + 1) as f64) / (total as f64);
+        if let Some(state) = resume_state.as_deref() {
+            if state.is_completed(job) {
+                outcome.skipped.push(index);
+                continue;
+            }
+        }
+
+        eprintln!("[{}/{} {:5.1}%] {} -> {}", index + 1, total, percent, job.input_path.display(), job.output_path.display());
+        if let Err(reason) = convert(index, job) {
+            eprintln!("{}: conversion failed: {}", job.input_path.display(), reason);
+            outcome.failed += 1;
+            if on_error == OnError::Abort {
+                eprintln!("aborted; {} image(s) not attempted", total - index - 1);
+                break;
+            }
+            continue;
+        }
+        outcome.converted += 1;
+
+        if let Some(state) = resume_state.as_deref_mut() {
+            state.mark_completed(job)
+                .expect("failed to update resume state file");
+        }
+    }
+
+    if !outcome.skipped.is_empty() {
+        eprintln!("skipped {} already converted image(s)", outcome.skipped.len());
+    }
+    if outcome.failed > 0 {
+        eprintln!("failed to convert {} image(s)", outcome.failed);
+    }
+    outcome
+}
+
+
+/// Generates the content stream operators of a page stating the given message, wrapped to the
+/// width of the page.
+///
+/// `font_name` is the resource name (without the leading slash) of [`PLACEHOLDER_FONT`].
+pub fn placeholder_operators(message: &str, page_width_pt: f64, page_height_pt: f64, font_name: &str) -> Vec<Operator> {
+    let max_width_pt = page_width_pt - 2.0 * PLACEHOLDER_MARGIN_PT;
+    let mut operators = vec![
+        Operator::BeginText,
+        Operator::SetFont { name: font_name.to_owned(), size: PLACEHOLDER_SIZE_PT },
+    ];
+    let mut baseline_pt = page_height_pt - PLACEHOLDER_MARGIN_PT - PLACEHOLDER_SIZE_PT;
+    for line in wrap_to_width(&encode_win_ansi(message), max_width_pt) {
+        if baseline_pt < PLACEHOLDER_MARGIN_PT {
+            break;
+        }
+        operators.push(Operator::SetTextMatrix([1.0, 0.0, 0.0, 1.0, PLACEHOLDER_MARGIN_PT, baseline_pt]));
+        operators.push(Operator::ShowText(line));
+        baseline_pt -= PLACEHOLDER_LINE_HEIGHT_PT;
+    }
+    operators.push(Operator::EndText);
+    operators
+}
+
+/// Breaks the WinAnsi-encoded text into lines that fit into the given width when set in the
+/// placeholder font, preferably between words.
+fn wrap_to_width(text: &[u8], max_width_pt: f64) -> Vec<Vec<u8>> {
+    let fits = |line: &[u8]| PLACEHOLDER_FONT.text_width(line, PLACEHOLDER_SIZE_PT) <= max_width_pt;
+    let mut lines = Vec::new();
+    let mut line: Vec<u8> = Vec::new();
+    for word in text.split(|b| b.is_ascii_whitespace()).filter(|w| !w.is_empty()) {
+        let mut candidate = line.clone();
+        if !candidate.is_empty() {
+            candidate.push(b' ');
+        }
+        candidate.extend_from_slice(word);
+        if fits(&candidate) {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        // words that are too long by themselves are broken anywhere
+        for &byte in word {
+            line.push(byte);
+            if !fits(&line) && line.len() > 1 {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, vec![byte]));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
@@ -0,0 +1,80 @@
+//! Captions drawn into a strip beneath the image on each page.
+
+
+use crate::content::{self, Operator};
+use crate::font::{encode_win_ansi, StandardFont};
+
+
+/// The font in which captions are set.
+pub const CAPTION_FONT: StandardFont = StandardFont::Helvetica;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Caption {
+    /// The text drawn beneath each image, in which `{filename}` is replaced by the file name of
+    /// the image, `{date}` by the date the image was taken and `{page}` by the number of the
+    /// page.
+    pub format: String,
+    pub font_size: f64,
+}
+impl Caption {
+    /// Returns the caption of the image with the given file name and date on the page with the
+    /// given (one-based) number.
+    pub fn text(&self, file_name: &str, date: Option<&str>, page_number: usize) -> String {
+        self.format
+            .replace("{filename}", file_name)
+            .replace("{date}", date.unwrap_or(""))
+            .replace("{page}", &page_number.to_string())
+            // the placeholder of earlier versions, which clap's help cannot display
+            .replace("{n}", &page_number.to_string())
+    }
+
+    /// Returns the height of the strip reserved for the caption at the bottom of the page, in
+    /// points.
+    pub fn strip_height_pt(&self) -> f64 {
+        // the font size above and below the text
+        (2.0 * self.font_size).ceil()
+    }
+
+    /// Returns the transformation that shrinks the image on a page of the given size such that
+    /// it fits above the caption strip, centered horizontally, along with the area it then
+    /// occupies as `(left, bottom, width, height)`.
+    pub fn image_transform(&self, page_width_pt: f64, page_height_pt: f64) -> (content::Matrix, (f64, f64, f64, f64)) {
+        let strip_height_pt = self.strip_height_pt().min(page_height_pt);
+        let scale = (page_height_pt - strip_height_pt) / page_height_pt;
+        let left_pt = page_width_pt * (1.0 - scale) / 2.0;
+        let area = (left_pt, strip_height_pt, page_width_pt * scale, page_height_pt * scale);
+        (content::scale_translate(scale, scale, left_pt, strip_height_pt), area)
+    }
+
+    /// Generates the content stream operators to draw the given caption text centered in the strip
+    /// at the bottom of a page of the given width.
+    ///
+    /// Text wider than the page is set in a smaller font so that it fits. `font_name` is the
+    /// resource name (without the leading slash) of the caption font.
+    pub fn operators(&self, text: &str, page_width_pt: f64, font_name: &str) -> Vec<Operator> {
+        let encoded_text = encode_win_ansi(text);
+        let available_width = (page_width_pt - 2.0 * self.font_size).max(0.0);
+        let full_width = CAPTION_FONT.text_width(&encoded_text, self.font_size);
+        let font_size = if full_width > available_width {
+            self.font_size * available_width / full_width
+        } else {
+            self.font_size
+        };
+        let text_width = CAPTION_FONT.text_width(&encoded_text, font_size);
+        let text_height = f64::from(CAPTION_FONT.cap_height()) * font_size / 1000.0;
+        let origin_x = (page_width_pt - text_width) / 2.0;
+        let origin_y = (self.strip_height_pt() - text_height) / 2.0;
+
+        vec![
+            Operator::SaveState,
+            Operator::SetFillRgb(0.0, 0.0, 0.0),
+            Operator::BeginText,
+            Operator::SetFont { name: font_name.to_owned(), size: font_size },
+            Operator::SetTextMatrix([1.0, 0.0, 0.0, 1.0, origin_x, origin_y]),
+            Operator::ShowText(encoded_text),
+            Operator::EndText,
+            Operator::RestoreState,
+        ]
+    }
+}
@@ -0,0 +1,449 @@
+//! Configuration files presetting command-line options.
+//!
+//! The format is a subset of TOML: each line contains a `key = value` pair, a `[section]` header, a
+//! comment starting with `#` or nothing. Keys are the long names of command-line options. Values
+//! are strings (`"basic"` with escapes or `'literal'`), numbers, booleans or single-line arrays of
+//! these. Keys before the first section apply to every subcommand that accepts them; keys in a
+//! section named after a subcommand only apply to that subcommand.
+
+
+use std::ffi::OsString;
+use std::fmt;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Syntax { line: usize, message: String },
+    UnknownOption { line: usize, key: String },
+    InvalidValue { line: usize, key: String, message: String },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { line, message }
+                => write!(f, "line {}: {}", line, message),
+            Self::UnknownOption { line, key }
+                => write!(f, "line {}: unknown option {:?}", line, key),
+            Self::InvalidValue { line, key, message }
+                => write!(f, "line {}: invalid value for {:?}: {}", line, key, message),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    /// A number, kept in its textual form.
+    Number(String),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// The line on which the entry is defined, counted from 1.
+    pub line: usize,
+    /// The section in which the entry is defined, or `None` for the top level.
+    pub section: Option<String>,
+    /// The key, with underscores replaced by hyphens.
+    pub key: String,
+    pub value: Value,
+}
+
+/// How an option takes its value on the command line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OptionKind {
+    /// A flag without a value (`--compress`).
+    Flag,
+    /// An option with a value (`--stamp TEXT`).
+    Value,
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub entries: Vec<Entry>,
+}
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut entries = Vec::new();
+        let mut section = None;
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let mut parser = Parser { rest: raw_line, line };
+            parser.skip_whitespace();
+            if parser.at_end_of_line() {
+                continue;
+            }
+
+            if parser.eat('[') {
+                parser.skip_whitespace();
+                let name = parser.key()?;
+                parser.skip_whitespace();
+                if !parser.eat(']') {
+                    return Err(parser.error("expected ']' after section name"));
+                }
+                parser.expect_end_of_line()?;
+                section = Some(name);
+                continue;
+            }
+
+            let key = parser.key()?;
+            parser.skip_whitespace();
+            if !parser.eat('=') {
+                return Err(parser.error("expected '=' after key"));
+            }
+            parser.skip_whitespace();
+            let value = parser.value()?;
+            parser.expect_end_of_line()?;
+            entries.push(Entry {
+                line,
+                section: section.clone(),
+                key: key.replace('_', "-"),
+                value,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    /// Converts the entries that apply to the given subcommand into command-line arguments.
+    ///
+    /// `option_kind` returns how the subcommand takes the option with the given long name, or
+    /// `None` if the subcommand does not know it. `is_known` returns whether any subcommand knows
+    /// the option with the given long name.
+    pub fn to_args<K, A>(&self, subcommand: &str, option_kind: K, is_known: A) -> Result<Vec<OsString>, Error>
+        where
+            K: Fn(&str) -> Option<OptionKind>,
+            A: Fn(&str) -> bool {
+        let mut args = Vec::new();
+        for entry in &self.entries {
+            let applies = match &entry.section {
+                None => true,
+                Some(section) => section == subcommand,
+            };
+            let kind = option_kind(&entry.key);
+            let kind = match (applies, kind) {
+                (false, _) => continue,
+                (true, Some(kind)) => kind,
+                (true, None) => {
+                    if entry.section.is_none() && is_known(&entry.key) {
+                        // meant for another subcommand
+                        continue;
+                    }
+                    return Err(Error::UnknownOption { line: entry.line, key: entry.key.clone() });
+                },
+            };
+
+            let values = match &entry.value {
+                Value::Array(values) => values.as_slice(),
+                other => std::slice::from_ref(other),
+            };
+            for value in values {
+                let option = OsString::from(format!("--{}", entry.key));
+                match (kind, value) {
+                    (OptionKind::Flag, Value::Boolean(true)) => args.push(option),
+                    (OptionKind::Flag, Value::Boolean(false)) => {},
+                    (OptionKind::Flag, _) => return Err(Error::InvalidValue {
+                        line: entry.line,
+                        key: entry.key.clone(),
+                        message: "expected true or false".to_owned(),
+                    }),
+                    (OptionKind::Value, Value::String(s)|Value::Number(s)) => {
+                        args.push(option);
+                        args.push(OsString::from(s));
+                    },
+                    (OptionKind::Value, Value::Boolean(b)) => {
+                        args.push(option);
+                        args.push(OsString::from(b.to_string()));
+                    },
+                    (OptionKind::Value, Value::Array(_)) => return Err(Error::InvalidValue {
+                        line: entry.line,
+                        key: entry.key.clone(),
+                        message: "nested arrays are not supported".to_owned(),
+                    }),
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+
+struct Parser<'a> {
+    rest: &'a str,
+    line: usize,
+}
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::Syntax { line: self.line, message: message.to_owned() }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> &'a str {
+        let end = self.rest.find(|c: char| !predicate(c)).unwrap_or(self.rest.len());
+        let (taken, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        taken
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(|c| c == ' ' || c == '\t');
+    }
+
+    fn at_end_of_line(&self) -> bool {
+        self.rest.is_empty() || self.rest.starts_with('#')
+    }
+
+    fn expect_end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.at_end_of_line() {
+            Ok(())
+        } else {
+            Err(self.error("unexpected characters at end of line"))
+        }
+    }
+
+    fn key(&mut self) -> Result<String, Error> {
+        let key = self.take_while(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if key.is_empty() {
+            return Err(self.error("expected a key"));
+        }
+        Ok(key.to_owned())
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some(c) if c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.' => {
+                let word = self.take_while(|c| c.is_ascii_alphanumeric() || "+-._".contains(c));
+                match word {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    number if number.replace('_', "").parse::<f64>().is_ok() => Ok(Value::Number(number.replace('_', ""))),
+                    _ => Err(self.error("expected a string, number, boolean or array")),
+                }
+            },
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        self.eat('"');
+        let mut value = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((pos, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[pos + 1..];
+                    return Ok(value);
+                },
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'r')) => '\r',
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, 'u')) => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16).ok()
+                                .filter(|_| hex.len() == 4)
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?
+                        },
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    value.push(escaped);
+                },
+                other => value.push(other),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        self.eat('\'');
+        let end = self.rest.find('\'')
+            .ok_or_else(|| self.error("unterminated string"))?;
+        let value = self.rest[..end].to_owned();
+        self.rest = &self.rest[end + 1..];
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.eat('[');
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(',') {
+                continue;
+            }
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            return Err(self.error("expected ',' or ']' in array"));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a configuration consisting of the single given line and returns its value.
+    fn parse_value(line: &str) -> Value {
+        let config = Config::parse(line).unwrap();
+        assert_eq!(config.entries.len(), 1);
+        config.entries.into_iter().next().unwrap().value
+    }
+
+    /// Parses the given malformed configuration and returns the error message.
+    fn parse_error(text: &str) -> String {
+        Config::parse(text).unwrap_err().to_string()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_owned())
+    }
+
+    fn number(s: &str) -> Value {
+        Value::Number(s.to_owned())
+    }
+
+    #[test]
+    fn strings_with_escapes() {
+        assert_eq!(parse_value(r#"stamp = "plain""#), string("plain"));
+        assert_eq!(parse_value(r#"stamp = "a\tb\nc\r""#), string("a\tb\nc\r"));
+        assert_eq!(parse_value(r#"stamp = "\"quoted\" \\ back""#), string("\"quoted\" \\ back"));
+        assert_eq!(parse_value(r#"stamp = "\u00e9t\u00E9""#), string("\u{e9}t\u{e9}"));
+        assert_eq!(parse_value(r#"stamp = 'C:\no\escapes'"#), string(r"C:\no\escapes"));
+        assert_eq!(parse_value(r#"stamp = 'say "hi"'"#), string("say \"hi\""));
+        assert_eq!(parse_value(r##"stamp = "# not a comment""##), string("# not a comment"));
+    }
+
+    #[test]
+    fn numbers_and_booleans() {
+        assert_eq!(parse_value("dpi = 300"), number("300"));
+        assert_eq!(parse_value("dpi = +300"), number("+300"));
+        assert_eq!(parse_value("dpi = 1_200"), number("1200"));
+        assert_eq!(parse_value("margin = -2.5"), number("-2.5"));
+        assert_eq!(parse_value("margin = 1e3"), number("1e3"));
+        assert_eq!(parse_value("margin = .5"), number(".5"));
+        assert_eq!(parse_value("compress = true"), Value::Boolean(true));
+        assert_eq!(parse_value("compress = false"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(parse_value("include = []"), Value::Array(vec![]));
+        assert_eq!(
+            parse_value(r#"include = [ "a.jpg", 'b.jpg' , 3,true ]"#),
+            Value::Array(vec![string("a.jpg"), string("b.jpg"), number("3"), Value::Boolean(true)]),
+        );
+        assert_eq!(parse_value("include = [1, 2,]"), Value::Array(vec![number("1"), number("2")]));
+        assert_eq!(
+            parse_value("include = [[1], []]"),
+            Value::Array(vec![Value::Array(vec![number("1")]), Value::Array(vec![])]),
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let config = Config::parse("# leading comment\n\n   \t\ndpi = 300 # trailing comment\n  # indented comment\n").unwrap();
+        assert_eq!(
+            config.entries,
+            vec![Entry { line: 4, section: None, key: "dpi".to_owned(), value: number("300") }],
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn profile_tables() {
+        let config = Config::parse("dpi = 300\n[convert]\npage_size = 'a4'\n[ merge ] # comment\ncompress = true\n").unwrap();
+        assert_eq!(
+            config.entries,
+            vec![
+                Entry { line: 1, section: None, key: "dpi".to_owned(), value: number("300") },
+                Entry { line: 3, section: Some("convert".to_owned()), key: "page-size".to_owned(), value: string("a4") },
+                Entry { line: 5, section: Some("merge".to_owned()), key: "compress".to_owned(), value: Value::Boolean(true) },
+            ],
+        );
+
+        let option_kind = |key: &str| match key {
+            "compress" => Some(OptionKind::Flag),
+            "dpi"|"page-size" => Some(OptionKind::Value),
+            _ => None,
+        };
+        assert_eq!(
+            config.to_args("convert", option_kind, |_| true).unwrap(),
+            vec!["--dpi", "300", "--page-size", "a4"].into_iter().map(OsString::from).collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            config.to_args("merge", option_kind, |_| true).unwrap(),
+            vec!["--dpi", "300", "--compress"].into_iter().map(OsString::from).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn to_args_errors() {
+        let option_kind = |key: &str| match key {
+            "compress" => Some(OptionKind::Flag),
+            "dpi" => Some(OptionKind::Value),
+            _ => None,
+        };
+        let to_args_error = |text: &str| Config::parse(text).unwrap()
+            .to_args("convert", option_kind, |key| key == "other")
+            .unwrap_err()
+            .to_string();
+
+        // top-level options meant for other subcommands are skipped, unknown ones are not
+        assert!(Config::parse("other = 1").unwrap().to_args("convert", option_kind, |key| key == "other").unwrap().is_empty());
+        assert_eq!(to_args_error("\n[convert]\nother = 1"), "line 3: unknown option \"other\"");
+        assert_eq!(to_args_error("dpi = 1\nbogus = 2"), "line 2: unknown option \"bogus\"");
+        assert_eq!(to_args_error("compress = 'yes'"), "line 1: invalid value for \"compress\": expected true or false");
+        assert_eq!(to_args_error("dpi = [[1]]"), "line 1: invalid value for \"dpi\": nested arrays are not supported");
+    }
+
+    #[test]
+    fn syntax_errors_with_line_numbers() {
+        assert_eq!(parse_error("dpi = 300\nstamp = \"open"), "line 2: unterminated string");
+        assert_eq!(parse_error("\n\nstamp = 'open"), "line 3: unterminated string");
+        assert_eq!(parse_error(r#"stamp = "\q""#), "line 1: invalid escape sequence");
+        assert_eq!(parse_error(r#"stamp = "\u12""#), "line 1: invalid \\u escape");
+        assert_eq!(parse_error(r#"stamp = "\ud800""#), "line 1: invalid \\u escape");
+        assert_eq!(parse_error("# comment\ndpi 300"), "line 2: expected '=' after key");
+        assert_eq!(parse_error("= 300"), "line 1: expected a key");
+        assert_eq!(parse_error("dpi ="), "line 1: expected a value");
+        assert_eq!(parse_error("dpi = three"), "line 1: expected a string, number, boolean or array");
+        assert_eq!(parse_error("dpi = 300 400"), "line 1: unexpected characters at end of line");
+        assert_eq!(parse_error("dpi = [1 2]"), "line 1: expected ',' or ']' in array");
+        assert_eq!(parse_error("dpi = [1,"), "line 1: expected a value");
+        assert_eq!(parse_error("[convert\ndpi = 300"), "line 1: expected ']' after section name");
+        assert_eq!(parse_error("[convert] dpi = 300"), "line 1: unexpected characters at end of line");
+    }
+}
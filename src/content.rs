@@ -0,0 +1,177 @@
+//! A typed model of the operators in page content streams.
+
+
+use crate::pdf::literal_string;
+
+
+/// A transformation matrix `[a b c d e f]`, mapping `(x, y)` to `(a*x + c*y + e, b*x + d*y + f)`.
+pub type Matrix = [f64; 6];
+
+
+/// Returns a matrix that scales by the given factors and then translates by the given offsets.
+pub fn scale_translate(scale_x: f64, scale_y: f64, offset_x: f64, offset_y: f64) -> Matrix {
+    [scale_x, 0.0, 0.0, scale_y, offset_x, offset_y]
+}
+
+
+/// A content stream operator along with its operands.
+///
+/// Resource names (fonts, graphics states, XObjects) and tags are given without the leading slash.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operator {
+    /// `q`
+    SaveState,
+    /// `Q`
+    RestoreState,
+    /// `cm`
+    ConcatMatrix(Matrix),
+    /// `gs`
+    SetExtGState(String),
+    /// `re W n`, restricting drawing to the rectangle `(left, bottom, width, height)`.
+    ClipRectangle(f64, f64, f64, f64),
+    /// `rg`
+    SetFillRgb(f64, f64, f64),
+    /// `Do`
+    DrawXObject(String),
+    /// `BT`
+    BeginText,
+    /// `ET`
+    EndText,
+    /// `Tf`
+    SetFont { name: String, size: f64 },
+    /// `Tm`
+    SetTextMatrix(Matrix),
+    /// `Tj`, with the text already encoded for the current font.
+    ShowText(Vec<u8>),
+    /// `BMC`
+    BeginMarkedContent { tag: String },
+    /// `BDC` with a property list containing only a marked-content ID.
+    BeginMarkedContentWithId { tag: String, mcid: u64 },
+    /// `BDC` with the tag `/OC` and the optional content group of the given resource name, which
+    /// makes the content visible only while the group is switched on.
+    BeginOptionalContent { name: String },
+    /// `EMC`
+    EndMarkedContent,
+}
+impl Operator {
+    pub fn write_to_string(&self, output: &mut String) {
+        match self {
+            Self::SaveState => output.push('q'),
+            Self::RestoreState => output.push('Q'),
+            Self::ConcatMatrix(matrix) => {
+                write_numbers(output, matrix);
+                output.push_str(" cm");
+            },
+            Self::SetExtGState(name) => {
+                output.push('/');
+                output.push_str(name);
+                output.push_str(" gs");
+            },
+            Self::ClipRectangle(left, bottom, width, height) => {
+                write_numbers(output, &[*left, *bottom, *width, *height]);
+                output.push_str(" re W n");
+            },
+            Self::SetFillRgb(r, g, b) => {
+                write_numbers(output, &[*r, *g, *b]);
+                output.push_str(" rg");
+            },
+            Self::DrawXObject(name) => {
+                output.push('/');
+                output.push_str(name);
+                output.push_str(" Do");
+            },
+            Self::BeginText => output.push_str("BT"),
+            Self::EndText => output.push_str("ET"),
+            Self::SetFont { name, size } => {
+                output.push('/');
+                output.push_str(name);
+                output.push(' ');
+                output.push_str(&format_number(*size));
+                output.push_str(" Tf");
+            },
+            Self::SetTextMatrix(matrix) => {
+                write_numbers(output, matrix);
+                output.push_str(" Tm");
+            },
+            Self::ShowText(encoded_text) => {
+                output.push_str(&literal_string(encoded_text));
+                output.push_str(" Tj");
+            },
+            Self::BeginMarkedContent { tag } => {
+                output.push('/');
+                output.push_str(tag);
+                output.push_str(" BMC");
+            },
+            Self::BeginMarkedContentWithId { tag, mcid } => {
+                output.push('/');
+                output.push_str(tag);
+                output.push_str(&format!(" << /MCID {} >> BDC", mcid));
+            },
+            Self::BeginOptionalContent { name } => {
+                output.push_str("/OC /");
+                output.push_str(name);
+                output.push_str(" BDC");
+            },
+            Self::EndMarkedContent => output.push_str("EMC"),
+        }
+    }
+}
+
+
+/// Serializes the given operators into the text of a content stream.
+pub fn serialize(operators: &[Operator]) -> String {
+    let mut output = String::new();
+    for operator in operators {
+        if !output.is_empty() {
+            output.push(' ');
+        }
+        operator.write_to_string(&mut output);
+    }
+    output
+}
+
+/// Wraps the given operators in a marked-content sequence.
+pub fn mark(tag: &str, mcid: Option<u64>, operators: Vec<Operator>) -> Vec<Operator> {
+    let begin = match mcid {
+        Some(mcid) => Operator::BeginMarkedContentWithId { tag: tag.to_owned(), mcid },
+        None => Operator::BeginMarkedContent { tag: tag.to_owned() },
+    };
+    let mut ret = Vec::with_capacity(operators.len() + 2);
+    ret.push(begin);
+    ret.extend(operators);
+    ret.push(Operator::EndMarkedContent);
+    ret
+}
+
+/// Wraps the given operators in a marked-content sequence belonging to the optional content group
+/// of the given resource name.
+pub fn mark_optional(name: &str, operators: Vec<Operator>) -> Vec<Operator> {
+    let mut ret = Vec::with_capacity(operators.len() + 2);
+    ret.push(Operator::BeginOptionalContent { name: name.to_owned() });
+    ret.extend(operators);
+    ret.push(Operator::EndMarkedContent);
+    ret
+}
+
+/// Formats a number for a content stream: without exponent and with at most five decimal places,
+/// omitting trailing zeroes.
+pub fn format_number(number: f64) -> String {
+    let mut formatted = format!("{:.5}", number);
+    if formatted.contains('.') {
+        let trimmed_len = formatted.trim_end_matches('0').trim_end_matches('.').len();
+        formatted.truncate(trimmed_len);
+    }
+    if formatted == "-0" {
+        formatted = "0".to_owned();
+    }
+    formatted
+}
+
+fn write_numbers(output: &mut String, numbers: &[f64]) {
+    for (i, number) in numbers.iter().enumerate() {
+        if i > 0 {
+            output.push(' ');
+        }
+        output.push_str(&format_number(*number));
+    }
+}
@@ -0,0 +1,12 @@
+//! Compression into the zlib format (RFC 1950) using the Deflate algorithm (RFC 1951).
+
+
+/// The compression level passed to the encoder: zlib's default, which is good enough for the
+/// text-based streams of a PDF file.
+const COMPRESSION_LEVEL: u8 = 6;
+
+
+/// Compresses the given data into the zlib format, as expected by the PDF `/FlateDecode` filter.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, COMPRESSION_LEVEL)
+}
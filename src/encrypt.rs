@@ -0,0 +1,666 @@
+//! Encryption of documents using the AES-256 security handler of PDF 2.0 (revision 6 of the
+//! standard security handler, ISO 32000-2 section 7.6.4).
+//!
+//! The strings and streams of the document are encrypted with AES-256 in CBC mode using a random
+//! file key and initialization vectors derived from it (see [`ObjectEncryptor`]). The file key is
+//! stored in the document encrypted with keys derived from the user and owner passwords using the
+//! iterated SHA-256/384/512 hash of the standard. The hash functions and the block cipher are
+//! implemented here; the randomness has to be supplied by the caller, since the library cannot
+//! obtain it on every platform.
+
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::pdf::hex_string;
+
+
+/// The number of random bytes required by [`Encryption::new`].
+pub const RANDOM_LEN: usize = 68;
+
+/// The maximum length of a password in bytes; longer passwords are truncated.
+const MAX_PASSWORD_LEN: usize = 127;
+
+const AES_BLOCK_LEN: usize = 16;
+
+
+/// An operation that a user who opens the document with the user password is allowed to perform.
+///
+/// Viewers are expected, but cannot be forced, to honor the permissions; someone who opens the
+/// document with the owner password may do anything.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Permission {
+    /// Print the document, possibly only at a low resolution (see `PrintHighQuality`).
+    Print,
+    /// Modify the contents of the document.
+    Modify,
+    /// Copy text and graphics out of the document.
+    Copy,
+    /// Add and modify annotations, and fill in form fields.
+    Annotate,
+    /// Fill in form fields, even if annotations may not be modified.
+    FillForms,
+    /// Extract text and graphics for the purpose of accessibility.
+    ExtractForAccessibility,
+    /// Insert, rotate and delete pages and create bookmarks.
+    Assemble,
+    /// Print the document faithfully at full resolution.
+    PrintHighQuality,
+}
+impl Permission {
+    pub const ALL: [Self; 8] = [
+        Self::Print, Self::Modify, Self::Copy, Self::Annotate, Self::FillForms,
+        Self::ExtractForAccessibility, Self::Assemble, Self::PrintHighQuality,
+    ];
+
+    /// The bit of the permission in the `/P` entry of the encryption dictionary.
+    fn flag(&self) -> u32 {
+        match self {
+            Self::Print => 1 << 2,
+            Self::Modify => 1 << 3,
+            Self::Copy => 1 << 4,
+            Self::Annotate => 1 << 5,
+            Self::FillForms => 1 << 8,
+            Self::ExtractForAccessibility => 1 << 9,
+            Self::Assemble => 1 << 10,
+            Self::PrintHighQuality => 1 << 11,
+        }
+    }
+}
+
+
+/// The keys and password hashes of an encrypted document.
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Encryption {
+    cipher: Aes,
+    /// The `/O` entry: the owner password hash followed by its validation and key salts.
+    owner_hash: [u8; 48],
+    /// The `/OE` entry: the file key encrypted with the owner password.
+    owner_key: [u8; 32],
+    /// The `/U` entry: the user password hash followed by its validation and key salts.
+    user_hash: [u8; 48],
+    /// The `/UE` entry: the file key encrypted with the user password.
+    user_key: [u8; 32],
+    /// The `/P` entry.
+    permission_flags: u32,
+    /// The `/Perms` entry: the permissions encrypted with the file key.
+    encrypted_permissions: [u8; 16],
+}
+impl Encryption {
+    /// Sets up the encryption of a document which can be opened with either password, granting
+    /// the given permissions to users who open it with the user password.
+    ///
+    /// An empty user password allows anyone to open the document, subject to the permissions.
+    /// Passwords are used as UTF-8 without the SASLprep normalization of the standard, which
+    /// makes no difference for passwords consisting of printable ASCII characters.
+    ///
+    /// `random` must come from a cryptographically secure random number generator and must not be
+    /// reused for other documents.
+    pub fn new(user_password: &str, owner_password: &str, permissions: &[Permission], random: &[u8; RANDOM_LEN]) -> Self {
+        let user_password = prepare_password(user_password);
+        let owner_password = prepare_password(owner_password);
+        let file_key: [u8; 32] = random[0..32].try_into().unwrap();
+        let (user_validation_salt, user_key_salt) = (&random[32..40], &random[40..48]);
+        let (owner_validation_salt, owner_key_salt) = (&random[48..56], &random[56..64]);
+
+        // Algorithm 8
+        let mut user_hash = [0; 48];
+        user_hash[0..32].copy_from_slice(&password_hash(user_password, user_validation_salt, &[]));
+        user_hash[32..40].copy_from_slice(user_validation_salt);
+        user_hash[40..48].copy_from_slice(user_key_salt);
+        let user_key = Aes::new(&password_hash(user_password, user_key_salt, &[]))
+            .encrypt_cbc_unpadded(&[0; AES_BLOCK_LEN], &file_key)
+            .try_into().unwrap();
+
+        // Algorithm 9
+        let mut owner_hash = [0; 48];
+        owner_hash[0..32].copy_from_slice(&password_hash(owner_password, owner_validation_salt, &user_hash));
+        owner_hash[32..40].copy_from_slice(owner_validation_salt);
+        owner_hash[40..48].copy_from_slice(owner_key_salt);
+        let owner_key = Aes::new(&password_hash(owner_password, owner_key_salt, &user_hash))
+            .encrypt_cbc_unpadded(&[0; AES_BLOCK_LEN], &file_key)
+            .try_into().unwrap();
+
+        // Algorithm 10; the bits not assigned to a permission must be set
+        let permission_flags = permissions.iter()
+            .fold(0xFFFF_F0C0, |flags, permission| flags | permission.flag());
+        let cipher = Aes::new(&file_key);
+        let mut encrypted_permissions = [0; 16];
+        encrypted_permissions[0..4].copy_from_slice(&permission_flags.to_le_bytes());
+        encrypted_permissions[4..8].copy_from_slice(&[0xFF; 4]);
+        // the metadata is encrypted too
+        encrypted_permissions[8..12].copy_from_slice(b"Tadb");
+        encrypted_permissions[12..16].copy_from_slice(&random[64..68]);
+        cipher.encrypt_block(&mut encrypted_permissions);
+
+        Self {
+            cipher,
+            owner_hash,
+            owner_key,
+            user_hash,
+            user_key,
+            permission_flags,
+            encrypted_permissions,
+        }
+    }
+
+    /// Returns the encryptor for the strings and streams of the object with the given ID.
+    pub fn for_object(&self, object_id: u64) -> ObjectEncryptor<'_> {
+        ObjectEncryptor {
+            cipher: &self.cipher,
+            object_id,
+            next_index: 0,
+        }
+    }
+
+    /// Writes the encryption dictionary.
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<< /Filter /Standard /V 5 /R 6 /Length 256")?;
+        write!(writer, " /CF << /StdCF << /CFM /AESV3 /AuthEvent /DocOpen /Length 32 >> >>")?;
+        write!(writer, " /StmF /StdCF /StrF /StdCF")?;
+        write!(writer, " /O {} /U {}", hex_string(&self.owner_hash), hex_string(&self.user_hash))?;
+        write!(writer, " /OE {} /UE {}", hex_string(&self.owner_key), hex_string(&self.user_key))?;
+        // a 32-bit signed integer
+        write!(writer, " /P {}", self.permission_flags as i32)?;
+        write!(writer, " /Perms {}", hex_string(&self.encrypted_permissions))?;
+        write!(writer, " /EncryptMetadata true >>")
+    }
+}
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keep the keys out of log output
+        f.debug_struct("Encryption")
+            .field("permission_flags", &format_args!("{:#010X}", self.permission_flags))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypts the strings and streams of a single object.
+///
+/// Each string or stream is given its own initialization vector, derived deterministically by
+/// encrypting a block holding the object ID and the index of the string or stream within the object
+/// with the file key. This is the method recommended in NIST SP 800-38A, appendix C: CBC mode only
+/// requires initialization vectors to be unpredictable to anyone who does not know the key, which
+/// the encryption of a unique block under the random file key is, and since the cipher is a
+/// permutation, no two strings or streams of a document share an initialization vector. It also
+/// saves asking the caller for randomness for every string and stream.
+pub struct ObjectEncryptor<'a> {
+    cipher: &'a Aes,
+    object_id: u64,
+    next_index: u64,
+}
+impl ObjectEncryptor<'_> {
+    /// Encrypts the next string or stream of the object, returning the initialization vector
+    /// followed by the padded ciphertext.
+    pub fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut iv = [0; AES_BLOCK_LEN];
+        iv[0..8].copy_from_slice(&self.object_id.to_be_bytes());
+        iv[8..16].copy_from_slice(&self.next_index.to_be_bytes());
+        self.cipher.encrypt_block(&mut iv);
+        self.next_index += 1;
+
+        // PKCS #7 padding: always at least one byte, each holding the number of bytes added
+        let padding_len = AES_BLOCK_LEN - data.len() % AES_BLOCK_LEN;
+        let mut padded = Vec::with_capacity(data.len() + padding_len);
+        padded.extend_from_slice(data);
+        padded.resize(data.len() + padding_len, u8::try_from(padding_len).unwrap());
+
+        let mut ret = Vec::with_capacity(AES_BLOCK_LEN + padded.len());
+        ret.extend_from_slice(&iv);
+        ret.extend_from_slice(&self.cipher.encrypt_cbc_unpadded(&iv, &padded));
+        ret
+    }
+}
+
+
+/// Truncates a password to the maximum length.
+fn prepare_password(password: &str) -> &[u8] {
+    let bytes = password.as_bytes();
+    &bytes[..bytes.len().min(MAX_PASSWORD_LEN)]
+}
+
+/// Computes the hash of a password with the given salt (Algorithm 2.B). `user_hash` is the
+/// 48-byte `/U` entry when hashing the owner password and empty otherwise.
+fn password_hash(password: &[u8], salt: &[u8], user_hash: &[u8]) -> [u8; 32] {
+    let mut k = sha256(&[password, salt, user_hash].concat()).to_vec();
+    let mut round = 0;
+    loop {
+        let k1 = [password, &k, user_hash].concat().repeat(64);
+        let e = Aes::new(&k[0..16]).encrypt_cbc_unpadded(&k[16..32], &k1);
+        // the first 16 bytes taken as a big-endian number modulo 3, which equals the sum of the
+        // bytes modulo 3 since 256 % 3 == 1
+        let remainder = e[0..16].iter().map(|&b| u32::from(b)).sum::<u32>() % 3;
+        k = match remainder {
+            0 => sha256(&e).to_vec(),
+            1 => sha384(&e).to_vec(),
+            _ => sha512(&e).to_vec(),
+        };
+        round += 1;
+        if round >= 64 && u32::from(*e.last().unwrap()) + 32 <= round {
+            break;
+        }
+    }
+    k[0..32].try_into().unwrap()
+}
+
+
+/// The round constants of SHA-512, whose upper halves are those of SHA-256.
+const SHA512_ROUND_CONSTANTS: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+/// The initial hash value of SHA-512, whose upper halves are that of SHA-256.
+const SHA512_INITIAL_HASH: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const SHA384_INITIAL_HASH: [u64; 8] = [
+    0xcbbb9d5dc1059ed8, 0x629a292a367cd507, 0x9159015a3070dd17, 0x152fecd8f70e5939,
+    0x67332667ffc00b31, 0x8eb44a8768581511, 0xdb0c2e0d64f98fa7, 0x47b5481dbefa4fa4,
+];
+
+/// Appends the padding of the SHA-2 hash functions, a one bit, zeros and the length of the
+/// message in bits, such that the length becomes a multiple of the block length.
+fn sha2_pad(data: &[u8], block_len: usize) -> Vec<u8> {
+    let length_len = block_len / 8;
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % block_len != block_len - length_len {
+        padded.push(0x00);
+    }
+    let bit_len = u128::try_from(data.len()).unwrap() * 8;
+    padded.extend_from_slice(&bit_len.to_be_bytes()[16 - length_len..]);
+    padded
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Computes a SHA-256 digest of data passed in piece by piece, for data too large to be held in
+/// memory at once.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    hash: [u32; 8],
+    /// The beginning of the next block.
+    pending: Vec<u8>,
+    length: u64,
+}
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            hash: SHA512_INITIAL_HASH.map(|h| (h >> 32) as u32),
+            pending: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += u64::try_from(data.len()).unwrap();
+        if !self.pending.is_empty() {
+            let taken = data.len().min(64 - self.pending.len());
+            self.pending.extend_from_slice(&data[..taken]);
+            data = &data[taken..];
+            if self.pending.len() < 64 {
+                return;
+            }
+            sha256_compress(&mut self.hash, &self.pending);
+            self.pending.clear();
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            sha256_compress(&mut self.hash, block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        // pad the pending bytes, but with the length of all the data
+        let mut padded = sha2_pad(&self.pending, 64);
+        let bit_len = u128::from(self.length) * 8;
+        let padded_len = padded.len();
+        padded[padded_len - 8..].copy_from_slice(&bit_len.to_be_bytes()[8..]);
+        for block in padded.chunks(64) {
+            sha256_compress(&mut self.hash, block);
+        }
+
+        let mut ret = [0; 32];
+        for (chunk, word) in ret.chunks_mut(4).zip(self.hash) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        ret
+    }
+}
+impl Default for Sha256 {
+    fn default() -> Self { Self::new() }
+}
+
+/// Processes one 64-byte block of SHA-256.
+fn sha256_compress(hash: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks(4).enumerate() {
+        w[i] = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i-15].rotate_right(7) ^ w[i-15].rotate_right(18) ^ (w[i-15] >> 3);
+        let s1 = w[i-2].rotate_right(17) ^ w[i-2].rotate_right(19) ^ (w[i-2] >> 10);
+        w[i] = w[i-16].wrapping_add(s0).wrapping_add(w[i-7]).wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *hash;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let round_constant = (SHA512_ROUND_CONSTANTS[i] >> 32) as u32;
+        let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(round_constant).wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+        (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(temp1), c, b, a, temp1.wrapping_add(temp2));
+    }
+    for (hash_word, value) in hash.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *hash_word = hash_word.wrapping_add(value);
+    }
+}
+
+/// Computes the full SHA-512 state, of which SHA-384 and SHA-512 output different parts.
+fn sha512_hash(data: &[u8], initial_hash: [u64; 8]) -> [u64; 8] {
+    let mut hash = initial_hash;
+    for block in sha2_pad(data, 128).chunks(128) {
+        let mut w = [0u64; 80];
+        for (i, word) in block.chunks(8).enumerate() {
+            w[i] = u64::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i-15].rotate_right(1) ^ w[i-15].rotate_right(8) ^ (w[i-15] >> 7);
+            let s1 = w[i-2].rotate_right(19) ^ w[i-2].rotate_right(61) ^ (w[i-2] >> 6);
+            w[i] = w[i-16].wrapping_add(s0).wrapping_add(w[i-7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = hash;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA512_ROUND_CONSTANTS[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(temp1), c, b, a, temp1.wrapping_add(temp2));
+        }
+        for (hash_word, value) in hash.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *hash_word = hash_word.wrapping_add(value);
+        }
+    }
+    hash
+}
+
+pub fn sha384(data: &[u8]) -> [u8; 48] {
+    let mut ret = [0; 48];
+    for (chunk, word) in ret.chunks_mut(8).zip(sha512_hash(data, SHA384_INITIAL_HASH)) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    ret
+}
+
+pub fn sha512(data: &[u8]) -> [u8; 64] {
+    let mut ret = [0; 64];
+    for (chunk, word) in ret.chunks_mut(8).zip(sha512_hash(data, SHA512_INITIAL_HASH)) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    ret
+}
+
+
+/// Multiplies two elements of the field GF(2^8) used by AES.
+const fn gf_multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        // multiply a by x modulo x^8 + x^4 + x^3 + x + 1
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1B } else { 0x00 };
+        b >>= 1;
+    }
+    product
+}
+
+/// Computes the AES substitution box: the multiplicative inverse in GF(2^8) followed by an affine
+/// transformation.
+const fn aes_s_box() -> [u8; 256] {
+    let mut s_box = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let value = i as u8;
+        // the inverse is value^254 (and 0 for 0)
+        let mut inverse = 1;
+        let mut exponent = 0;
+        while exponent < 254 {
+            inverse = gf_multiply(inverse, value);
+            exponent += 1;
+        }
+        s_box[i] = inverse
+            ^ inverse.rotate_left(1) ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3) ^ inverse.rotate_left(4)
+            ^ 0x63;
+        i += 1;
+    }
+    s_box
+}
+
+const AES_S_BOX: [u8; 256] = aes_s_box();
+
+/// The AES block cipher (encryption only) with an expanded 128- or 256-bit key.
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct Aes {
+    round_keys: Vec<[u8; AES_BLOCK_LEN]>,
+}
+impl Aes {
+    /// Expands the given key, which must be 16 or 32 bytes long.
+    fn new(key: &[u8]) -> Self {
+        assert!(key.len() == 16 || key.len() == 32, "AES key must be 128 or 256 bits long");
+        let key_words = key.len() / 4;
+        let round_count = key_words + 6;
+        let mut words: Vec<[u8; 4]> = key.chunks(4)
+            .map(|word| word.try_into().unwrap())
+            .collect();
+        let mut round_constant = 0x01;
+        for i in key_words..4*(round_count + 1) {
+            let mut word = words[i - 1];
+            if i % key_words == 0 {
+                word.rotate_left(1);
+                word = word.map(|b| AES_S_BOX[usize::from(b)]);
+                word[0] ^= round_constant;
+                round_constant = gf_multiply(round_constant, 0x02);
+            } else if key_words > 6 && i % key_words == 4 {
+                word = word.map(|b| AES_S_BOX[usize::from(b)]);
+            }
+            let previous = words[i - key_words];
+            words.push([0, 1, 2, 3].map(|j| word[j] ^ previous[j]));
+        }
+        let round_keys = words.chunks(4)
+            .map(|round_words| {
+                let mut round_key = [0; AES_BLOCK_LEN];
+                for (chunk, word) in round_key.chunks_mut(4).zip(round_words) {
+                    chunk.copy_from_slice(word);
+                }
+                round_key
+            })
+            .collect();
+        Self { round_keys }
+    }
+
+    /// Encrypts a single block in place.
+    fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_LEN]) {
+        fn add_round_key(block: &mut [u8; AES_BLOCK_LEN], round_key: &[u8; AES_BLOCK_LEN]) {
+            for (b, k) in block.iter_mut().zip(round_key) {
+                *b ^= k;
+            }
+        }
+
+        let last_round = self.round_keys.len() - 1;
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..=last_round {
+            // the bytes are stored column by column; substitute and shift row r left by r
+            let state = *block;
+            for column in 0..4 {
+                for row in 0..4 {
+                    block[4*column + row] = AES_S_BOX[usize::from(state[4*((column + row) % 4) + row])];
+                }
+            }
+            if round != last_round {
+                for column in block.chunks_mut(4) {
+                    let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+                    column[0] = gf_multiply(a0, 2) ^ gf_multiply(a1, 3) ^ a2 ^ a3;
+                    column[1] = a0 ^ gf_multiply(a1, 2) ^ gf_multiply(a2, 3) ^ a3;
+                    column[2] = a0 ^ a1 ^ gf_multiply(a2, 2) ^ gf_multiply(a3, 3);
+                    column[3] = gf_multiply(a0, 3) ^ a1 ^ a2 ^ gf_multiply(a3, 2);
+                }
+            }
+            add_round_key(block, &self.round_keys[round]);
+        }
+    }
+
+    /// Encrypts data whose length is a multiple of the block length in CBC mode.
+    fn encrypt_cbc_unpadded(&self, iv: &[u8], data: &[u8]) -> Vec<u8> {
+        assert_eq!(data.len() % AES_BLOCK_LEN, 0);
+        let mut ret = Vec::with_capacity(data.len());
+        let mut previous: [u8; AES_BLOCK_LEN] = iv.try_into().unwrap();
+        for chunk in data.chunks(AES_BLOCK_LEN) {
+            for (p, d) in previous.iter_mut().zip(chunk) {
+                *p ^= d;
+            }
+            self.encrypt_block(&mut previous);
+            ret.extend_from_slice(&previous);
+        }
+        ret
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i+2], 16).unwrap())
+            .collect()
+    }
+
+    const TWO_BLOCK_MESSAGE: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    const LONG_TWO_BLOCK_MESSAGE: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+    // FIPS 180-4 example values
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(sha256(b"").to_vec(), hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        assert_eq!(sha256(b"abc").to_vec(), hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(sha256(TWO_BLOCK_MESSAGE).to_vec(), hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"));
+    }
+
+    #[test]
+    fn sha256_incremental() {
+        // a million times "a", fed in pieces that do not line up with the blocks
+        let mut hasher = Sha256::new();
+        let piece = [b'a'; 1000];
+        for i in 0..1000 {
+            let split = i % 100;
+            hasher.update(&piece[..split]);
+            hasher.update(&piece[split..]);
+        }
+        assert_eq!(hasher.finish().to_vec(), hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"));
+    }
+
+    #[test]
+    fn sha384_known_answers() {
+        assert_eq!(sha384(b"").to_vec(), hex("38b060a751ac96384cd9327eb1b1e36a21fdb71114be07434c0cc7bf63f6e1da274edebfe76f65fbd51ad2f14898b95b"));
+        assert_eq!(sha384(b"abc").to_vec(), hex("cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7"));
+        assert_eq!(sha384(LONG_TWO_BLOCK_MESSAGE).to_vec(), hex("09330c33f71147e83d192fc782cd1b4753111b173b3b05d22fa08086e3b0f712fcc7c71a557e2db966c3e9fa91746039"));
+    }
+
+    #[test]
+    fn sha512_known_answers() {
+        assert_eq!(sha512(b"").to_vec(), hex("cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"));
+        assert_eq!(sha512(b"abc").to_vec(), hex("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"));
+        assert_eq!(sha512(LONG_TWO_BLOCK_MESSAGE).to_vec(), hex("8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"));
+    }
+
+    // FIPS 197 appendix C
+    #[test]
+    fn aes_known_answers() {
+        let plaintext: [u8; AES_BLOCK_LEN] = hex("00112233445566778899aabbccddeeff").try_into().unwrap();
+
+        let mut block = plaintext;
+        Aes::new(&hex("000102030405060708090a0b0c0d0e0f")).encrypt_block(&mut block);
+        assert_eq!(block.to_vec(), hex("69c4e0d86a7b0430d8cdb78070b4c55a"));
+
+        let mut block = plaintext;
+        Aes::new(&hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")).encrypt_block(&mut block);
+        assert_eq!(block.to_vec(), hex("8ea2b7ca516745bfeafc49904b496089"));
+    }
+
+    // NIST SP 800-38A F.2.5, the first two blocks
+    #[test]
+    fn aes_cbc_known_answer() {
+        let cipher = Aes::new(&hex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4"));
+        let ciphertext = cipher.encrypt_cbc_unpadded(
+            &hex("000102030405060708090a0b0c0d0e0f"),
+            &hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51"),
+        );
+        assert_eq!(ciphertext, hex("f58c4c04d6e5f1ba779eabfb5f7bfbd69cfc4e967edb808d679f777bc6702c7d"));
+    }
+
+    // values computed independently from ISO 32000-2 algorithm 2.B
+    #[test]
+    fn password_hash_known_answers() {
+        let salt: Vec<u8> = (0..8).collect();
+        assert_eq!(password_hash(b"user", &salt, &[]).to_vec(), hex("731758c09c8b0160a34721d18bdd24220abada0070aa3f05b8103fd5b8d05f17"));
+        assert_eq!(password_hash(b"", &[0; 8], &[]).to_vec(), hex("439feba099a63d0d035a1e5fb67ff307329189584956425aff2d3bd3d15edc60"));
+
+        let salt: Vec<u8> = (8..16).collect();
+        let user_hash: Vec<u8> = (0..48).collect();
+        assert_eq!(password_hash(b"owner", &salt, &user_hash).to_vec(), hex("400c13628b144fe2fbb850b65729e9ecb63c00fbb817c685725f25de85af0521"));
+    }
+
+    #[test]
+    fn initialization_vectors_are_not_reused() {
+        let encryption = Encryption::new("user", "owner", &[], &[0x5A; RANDOM_LEN]);
+        let mut first = encryption.for_object(1);
+        let mut second = encryption.for_object(2);
+        let ivs = [
+            first.encrypt(b"a")[0..AES_BLOCK_LEN].to_vec(),
+            first.encrypt(b"a")[0..AES_BLOCK_LEN].to_vec(),
+            second.encrypt(b"a")[0..AES_BLOCK_LEN].to_vec(),
+        ];
+        assert_ne!(ivs[0], ivs[1]);
+        assert_ne!(ivs[0], ivs[2]);
+        assert_ne!(ivs[1], ivs[2]);
+
+        // the same object and index always yield the same initialization vector
+        assert_eq!(encryption.for_object(1).encrypt(b"b")[0..AES_BLOCK_LEN], ivs[0]);
+    }
+}
@@ -6,7 +6,7 @@ use std::io::{self, Cursor, Read, Seek, SeekFrom};
 
 use from_to_repr::from_to_other;
 
-use crate::jpeg::{DensityUnit, ImageBuilder};
+use crate::jpeg::{DensityUnit, Image, ImageBuilder};
 
 
 #[derive(Debug)]
@@ -156,6 +156,55 @@ impl<R: Read + Seek> Reader<R> {
         }
     }
 
+    /// Reads the entry count and the entries of the IFD at the current position, leaving the reader
+    /// positioned at the offset of the next IFD.
+    fn read_ifd_entries(&mut self) -> Result<Vec<ValueOrPointer>, Error> {
+        // how many entries in the IFD do we have?
+        let ifd_entry_count = self.read_ifd_entry_count()?;
+        let mut values = Vec::new();
+
+        // run through them, collecting the values
+        for _ in 0..ifd_entry_count {
+            let tag = self.read_u16()?;
+            let kind = self.read_type()?;
+            let count = self.read_u32()?;
+
+            let value_or_pointer = self.read_value_or_pointer(tag, kind, count)?;
+            values.push(value_or_pointer);
+        }
+        Ok(values)
+    }
+
+    /// Reads the IFD at the given offset, returning its entries (with the pointers among them
+    /// replaced by the values they point to) and the offset just past the last byte belonging to
+    /// it or its values.
+    fn read_ifd_at(&mut self, offset: u64) -> Result<(Vec<ValueOrPointer>, u64), Error> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut values = self.read_ifd_entries()?;
+        let offset_size = if self.ptr64 { 8 } else { 4 };
+        let mut end = self.reader.stream_position()? + offset_size;
+        for value in &values {
+            if let ValueOrPointer::Pointer { value_type, count, pointer, .. } = value {
+                let value_size = value_type.single_element_size().unwrap_or(0) * usize::try_from(*count).unwrap();
+                end = end.max(*pointer + u64::try_from(value_size).unwrap());
+            }
+        }
+        self.dereference(&mut values)?;
+        Ok((values, end))
+    }
+
+    /// Replaces the pointers among the given IFD entries with the values they point to.
+    fn dereference(&mut self, values: &mut [ValueOrPointer]) -> Result<(), Error> {
+        for value in values {
+            if let ValueOrPointer::Pointer { tag, value_type, count, pointer } = value {
+                self.reader.seek(SeekFrom::Start(*pointer))?;
+                let values = self.read_values(*value_type, *count)?;
+                *value = ValueOrPointer::Value { tag: *tag, values };
+            }
+        }
+        Ok(())
+    }
+
     fn read_type(&mut self) -> Result<ValueType, Error> {
         let base_value = self.read_u16()?;
         Ok(ValueType::from_base_type(base_value))
@@ -192,8 +241,8 @@ impl<R: Read + Seek> Reader<R> {
             let pointer_value: u64 = match (self.ptr64, self.big_endian) {
                 (false, false) => u32::from_le_bytes(inline_buf[0..4].try_into().unwrap()).into(),
                 (false, true) => u32::from_be_bytes(inline_buf[0..4].try_into().unwrap()).into(),
-                (true, false) => u64::from_le_bytes(inline_buf).into(),
-                (true, true) => u64::from_be_bytes(inline_buf).into(),
+                (true, false) => u64::from_le_bytes(inline_buf),
+                (true, true) => u64::from_be_bytes(inline_buf),
             };
             Ok(ValueOrPointer::Pointer {
                 tag,
@@ -219,6 +268,15 @@ impl<R: Read + Seek> Reader<R> {
 
     fn read_values(&mut self, kind: ValueType, count: u32) -> Result<Values, Error> {
         let count_usize: usize = count.try_into().unwrap();
+        if let Some(single_value_size) = kind.single_element_size() {
+            // a damaged count must not lead to a huge allocation
+            let position = self.reader.stream_position()?;
+            let end = self.reader.seek(SeekFrom::End(0))?;
+            self.reader.seek(SeekFrom::Start(position))?;
+            if u64::from(count) * u64::try_from(single_value_size).unwrap() > end.saturating_sub(position) {
+                return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
         match kind {
             ValueType::Byte|ValueType::Ascii|ValueType::Undefined => {
                 let mut buf = vec![0; count_usize];
@@ -429,20 +487,7 @@ pub(crate) fn process(app1_data: &[u8], builder: &mut ImageBuilder) -> Result<()
     let mut ifds_values = Vec::new();
 
     loop {
-        // how many entries in the IFD do we have?
-        let ifd_entry_count = tiff.read_ifd_entry_count()?;
-        let mut values = Vec::new();
-
-        // run through them, collecting the values
-        for _ in 0..ifd_entry_count {
-            let tag = tiff.read_u16()?;
-            let kind = tiff.read_type()?;
-            let count = tiff.read_u32()?;
-
-            let value_or_pointer = tiff.read_value_or_pointer(tag, kind, count)?;
-            values.push(value_or_pointer);
-        }
-
+        let values = tiff.read_ifd_entries()?;
         ifds_values.push(values);
 
         // the next value is the pointer to the next IFD
@@ -457,89 +502,179 @@ pub(crate) fn process(app1_data: &[u8], builder: &mut ImageBuilder) -> Result<()
 
     // dereference the pointers
     for values in &mut ifds_values {
-        for value in values {
-            if let ValueOrPointer::Pointer { tag, value_type, count, pointer } = value {
-                tiff.reader.seek(SeekFrom::Start(*pointer))?;
-                let values = tiff.read_values(*value_type, *count)?;
-                *value = ValueOrPointer::Value { tag: *tag, values };
-            }
-        }
+        tiff.dereference(values)?;
     }
 
     // process what we know
     // IFD0 = image itself, IFD1 = thumbnail
-    // => ignore IFD1
+    // => only note whether IFD1 exists
+    let ifd0 = &ifds_values[0];
+    builder.exif_has_thumbnail = ifds_values.len() > 1;
+
+    // do we have an X resolution? fall back to 72 (as mandated by the standard) if not
+    let x_resolution = find_single_rational(ifd0, 0x011A)
+        .unwrap_or(72);
+
+    // do we have a Y resolution? fall back to X resolution if not
+    let y_resolution = find_single_rational(ifd0, 0x011B)
+        .unwrap_or(x_resolution);
+
+    // find the unit (fall back to inches)
+    let unit = find_single_short(ifd0, 0x0128)
+        .unwrap_or(2);
+
+    builder.exif_density_x = Some(x_resolution);
+    builder.exif_density_y = Some(y_resolution);
+    builder.exif_density_unit = Some(match unit {
+        1 => DensityUnit::NoUnit,
+        2 => DensityUnit::DotsPerInch,
+        3 => DensityUnit::DotsPerCentimeter,
+        _ => DensityUnit::DotsPerInch,
+    });
+
+    builder.exif_orientation = find_single_short(ifd0, 0x0112);
+
+    Ok(())
+}
+
+/// Returns the date and time at which the image was taken (or, failing that, last changed)
+/// according to its Exif metadata, in the Exif format `YYYY:MM:DD HH:MM:SS`.
+///
+/// Returns `None` if the image has no Exif metadata, the metadata cannot be read or it records no
+/// date.
+pub fn date_time(image: &Image) -> Option<String> {
+    let block = image.leading_blocks.iter()
+        .find(|block| block.kind() == 0xE1 && block.data().starts_with(b"Exif\0\0"))?;
+    read_date_time(block.data()).ok()?
+}
 
-    // do we have an X resolution? fall back to 72 if not
-    let x_resolution_values_opt = ifds_values[0]
+fn read_date_time(app1_data: &[u8]) -> Result<Option<String>, Error> {
+    let exif_tiff = &app1_data[6..];
+    let mut tiff = Reader::new(Cursor::new(exif_tiff))?;
+    let mut ifd0 = tiff.read_ifd_entries()?;
+    tiff.dereference(&mut ifd0)?;
+
+    // prefer the date and time of the original image (Exif IFD) over that of the last change (IFD0)
+    if let Some(exif_ifd_offset) = find_single_offset(&ifd0, 0x8769) {
+        tiff.reader.seek(SeekFrom::Start(exif_ifd_offset))?;
+        let mut exif_ifd = tiff.read_ifd_entries()?;
+        tiff.dereference(&mut exif_ifd)?;
+        if let Some(date_time) = find_ascii(&exif_ifd, 0x9003) {
+            return Ok(Some(date_time));
+        }
+    }
+    Ok(find_ascii(&ifd0, 0x0132))
+}
+
+/// Removes the thumbnail, i.e. the second IFD along with the image data it points to, from the
+/// given Exif metadata (the data of an APP1 block).
+///
+/// Returns `None` if there is no thumbnail or if removing it would require moving other metadata,
+/// which is the case unless the thumbnail comes last.
+pub(crate) fn remove_thumbnail(app1_data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    assert!(app1_data.starts_with(b"Exif\0\0"));
+    let exif_tiff = &app1_data[6..];
+    let mut tiff = Reader::new(Cursor::new(exif_tiff))?;
+    let ifd0_offset = tiff.reader.position();
+    let (ifd0, ifd0_end) = tiff.read_ifd_at(ifd0_offset)?;
+
+    // find where IFD0 points to IFD1
+    tiff.reader.seek(SeekFrom::Start(ifd0_offset))?;
+    tiff.read_ifd_entries()?;
+    let next_pointer_offset = tiff.reader.position();
+    let ifd1_offset = tiff.read_offset()?;
+    if ifd1_offset == 0 {
+        return Ok(None);
+    }
+
+    // everything else must end before the thumbnail starts
+    // (IFD0, the Exif, GPS and interoperability IFDs, and their values)
+    let mut kept_end = ifd0_end;
+    let mut sub_ifd_offsets: Vec<u64> = [0x8769, 0x8825].iter()
+        .filter_map(|&tag| find_single_offset(&ifd0, tag))
+        .collect();
+    while let Some(sub_ifd_offset) = sub_ifd_offsets.pop() {
+        let (sub_ifd, sub_ifd_end) = tiff.read_ifd_at(sub_ifd_offset)?;
+        kept_end = kept_end.max(sub_ifd_end);
+        sub_ifd_offsets.extend(find_single_offset(&sub_ifd, 0xA005));
+    }
+
+    // the thumbnail consists of IFD1 and its JPEG data
+    let (ifd1, _ifd1_end) = tiff.read_ifd_at(ifd1_offset)?;
+    let mut thumbnail_start = ifd1_offset;
+    if let Some(jpeg_offset) = find_single_offset(&ifd1, 0x0201) {
+        thumbnail_start = thumbnail_start.min(jpeg_offset);
+    }
+    if thumbnail_start < kept_end || thumbnail_start > u64::try_from(exif_tiff.len()).unwrap() {
+        return Ok(None);
+    }
+
+    // cut off the thumbnail and unlink IFD1
+    let mut new_data = app1_data[..6 + usize::try_from(thumbnail_start).unwrap()].to_vec();
+    let pointer_start = 6 + usize::try_from(next_pointer_offset).unwrap();
+    let pointer_end = pointer_start + if tiff.ptr64 { 8 } else { 4 };
+    new_data[pointer_start..pointer_end].fill(0);
+    Ok(Some(new_data))
+}
+
+fn find_values(ifd_values: &[ValueOrPointer], tag: u16) -> Option<&Values> {
+    ifd_values
         .iter()
-        .filter(|v| v.tag() == 0x011A)
+        .filter(|v| v.tag() == tag)
         .filter_map(|v| v.value())
-        .nth(0);
-    let x_resolution_opt = if let Some(x_resolution_values) = x_resolution_values_opt {
-        if let Values::Rational(vals) = x_resolution_values {
-            if vals.len() == 1 {
-                Some(vals[0].0 / vals[0].1)
-            } else {
-                None
-            }
-        } else {
+        .next()
+}
+
+/// Finds a single rational value with the given tag and rounds it to a positive `u16`.
+fn find_single_rational(ifd_values: &[ValueOrPointer], tag: u16) -> Option<u16> {
+    if let Some(Values::Rational(vals)) = find_values(ifd_values, tag) {
+        if vals.len() != 1 || vals[0].1 == 0 {
+            return None;
+        }
+        let (numerator, denominator) = (u64::from(vals[0].0), u64::from(vals[0].1));
+        let value: u16 = ((numerator + denominator / 2) / denominator).try_into().ok()?;
+        if value == 0 {
             None
+        } else {
+            Some(value)
         }
     } else {
         None
-    };
-    let x_resolution = x_resolution_opt.unwrap_or(72);
+    }
+}
 
-    // do we have a Y resolution? fall back to X resolution if not
-    let y_resolution_values_opt = ifds_values[0]
-        .iter()
-        .filter(|v| v.tag() == 0x011B)
-        .filter_map(|v| v.value())
-        .nth(0);
-    let y_resolution_opt = if let Some(y_resolution_values) = y_resolution_values_opt {
-        if let Values::Rational(vals) = y_resolution_values {
-            if vals.len() == 1 {
-                Some(vals[0].0 / vals[0].1)
-            } else {
-                None
-            }
+fn find_single_short(ifd_values: &[ValueOrPointer], tag: u16) -> Option<u16> {
+    if let Some(Values::Short(vals)) = find_values(ifd_values, tag) {
+        if vals.len() == 1 {
+            Some(vals[0])
         } else {
             None
         }
     } else {
         None
-    };
-    let y_resolution = y_resolution_opt.unwrap_or(x_resolution);
+    }
+}
 
-    // find the unit (fall back to inches)
-    let unit_values_opt = ifds_values[0]
-        .iter()
-        .filter(|v| v.tag() == 0x0128)
-        .filter_map(|v| v.value())
-        .nth(0);
-    let unit_opt = if let Some(unit_values) = unit_values_opt {
-        if let Values::Short(vals) = unit_values {
-            if vals.len() == 1 {
-                Some(vals[0])
-            } else {
-                None
-            }
-        } else {
+/// Finds a single offset (stored as a long or as an IFD pointer) with the given tag.
+fn find_single_offset(ifd_values: &[ValueOrPointer], tag: u16) -> Option<u64> {
+    match find_values(ifd_values, tag) {
+        Some(Values::Long(vals))|Some(Values::Ifd(vals)) if vals.len() == 1 => Some(vals[0].into()),
+        _ => None,
+    }
+}
+
+/// Finds an ASCII value with the given tag, stripping the terminating NUL and surrounding
+/// whitespace; blank values count as missing.
+fn find_ascii(ifd_values: &[ValueOrPointer], tag: u16) -> Option<String> {
+    if let Some(Values::Ascii(bytes)) = find_values(ifd_values, tag) {
+        let text = String::from_utf8_lossy(bytes);
+        let trimmed = text.trim_end_matches('\0').trim();
+        if trimmed.is_empty() {
             None
+        } else {
+            Some(trimmed.to_owned())
         }
     } else {
         None
-    };
-    let unit = unit_opt.unwrap_or(2);
-
-    builder.density_x = Some(x_resolution.try_into().unwrap());
-    builder.density_y = Some(y_resolution.try_into().unwrap());
-    builder.density_unit = Some(match unit {
-        2 => DensityUnit::DotsPerInch,
-        3 => DensityUnit::DotsPerCentimeter,
-        _ => DensityUnit::DotsPerInch,
-    });
-
-    Ok(())
+    }
 }
@@ -0,0 +1,208 @@
+//! Opening of input images, which are either local files, images within ZIP archives or, with the
+//! `http` feature, HTTP(S) URLs.
+//!
+//! Neither remote images nor images in archives are written into temporary files; the response
+//! body or the archive entry is read directly. An image within an archive is addressed by
+//! appending its name within the archive to the path of the archive, e.g. `comic.cbz/page01.jpg`.
+
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use jpeg2pdf::archive::{self, ZipArchive};
+
+
+/// The extensions (in lowercase) of JPEG image files.
+pub const JPEG_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "jpe"];
+
+
+/// How the images within an archive are ordered.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ArchiveKind {
+    /// A general ZIP archive, whose images are ordered by name.
+    Zip,
+    /// A comic book archive, whose images are ordered naturally, i.e. numbers within the names are
+    /// compared by their value (`page2` comes before `page10`).
+    Comic,
+}
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Archive(archive::Error),
+    #[cfg(feature = "http")]
+    Http(Box<ureq::Error>),
+    #[cfg(not(feature = "http"))]
+    HttpNotSupported,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "{}", e),
+            Self::Archive(e)
+                => write!(f, "{}", e),
+            #[cfg(feature = "http")]
+            Self::Http(e)
+                => write!(f, "{}", e),
+            #[cfg(not(feature = "http"))]
+            Self::HttpNotSupported
+                => write!(f, "reading images from HTTP(S) URLs requires the http feature"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Archive(e) => Some(e),
+            #[cfg(feature = "http")]
+            Self::Http(e) => Some(e),
+            #[cfg(not(feature = "http"))]
+            Self::HttpNotSupported => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+impl From<archive::Error> for Error {
+    fn from(value: archive::Error) -> Self { Self::Archive(value) }
+}
+
+
+/// Returns whether the given input path is actually an HTTP(S) URL.
+pub fn is_url(path: &Path) -> bool {
+    let Some(path_str) = path.to_str() else { return false };
+    let lower = path_str.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Returns whether the given input is an archive of images and, if so, how its images are
+/// ordered. Files with the extensions `.zip` and `.cbz` are archives; with `comic`, every local
+/// file is taken to be a comic book archive.
+pub fn archive_kind(path: &Path, comic: bool) -> Option<ArchiveKind> {
+    if is_url(path) {
+        return None;
+    }
+    let extension = path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        _ if comic => Some(ArchiveKind::Comic),
+        Some("cbz") => Some(ArchiveKind::Comic),
+        Some("zip") => Some(ArchiveKind::Zip),
+        _ => None,
+    }
+}
+
+/// Compares two names such that runs of digits are compared by their numeric value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let mut a_digits = String::new();
+                while let Some(c) = a_chars.next_if(|c| c.is_ascii_digit()) {
+                    a_digits.push(c);
+                }
+                let mut b_digits = String::new();
+                while let Some(c) = b_chars.next_if(|c| c.is_ascii_digit()) {
+                    b_digits.push(c);
+                }
+                // compare the values without parsing, which might overflow
+                let a_value = a_digits.trim_start_matches('0');
+                let b_value = b_digits.trim_start_matches('0');
+                let ordering = a_value.len().cmp(&b_value.len())
+                    .then_with(|| a_value.cmp(b_value));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (Some(a_char), Some(b_char)) => {
+                if a_char != b_char {
+                    return a_char.cmp(&b_char);
+                }
+                a_chars.next();
+                b_chars.next();
+            },
+        }
+    }
+}
+
+/// Returns the paths of the JPEG images within the given archive, in reading order.
+///
+/// Hidden files and the resource forks stored by macOS are skipped.
+pub fn archive_image_paths(path: &Path, kind: ArchiveKind) -> Result<Vec<PathBuf>, Error> {
+    let archive = ZipArchive::open(BufReader::new(File::open(path)?))?;
+    let mut names: Vec<&str> = archive.entries().iter()
+        .filter(|entry| !entry.is_directory())
+        .map(|entry| entry.name.as_str())
+        .filter(|name| !name.split('/').any(|component| component.starts_with('.') || component == "__MACOSX"))
+        .filter(|name| {
+            let extension = name.rsplit_once('.')
+                .map(|(_stem, ext)| ext.to_lowercase());
+            extension.is_some_and(|ext| JPEG_EXTENSIONS.contains(&ext.as_str()))
+        })
+        .collect();
+    match kind {
+        ArchiveKind::Zip => names.sort_unstable(),
+        ArchiveKind::Comic => names.sort_unstable_by(|a, b| natural_cmp(a, b)),
+    }
+    Ok(names.into_iter().map(|name| path.join(name)).collect())
+}
+
+/// If the given path does not exist but points into an archive, returns the path of the archive
+/// and the name of the entry within it.
+fn archive_member(path: &Path) -> Option<(&Path, String)> {
+    if path.exists() {
+        return None;
+    }
+    let archive_path = path.ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_file())?;
+    let name_components: Vec<String> = path.strip_prefix(archive_path).ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Some((archive_path, name_components.join("/")))
+}
+
+/// Opens the given input, which is either a local file, an image within an archive or an HTTP(S)
+/// URL. Requesting a URL fails if the response has not been received in full within the given
+/// time.
+pub fn open(path: &Path, timeout: Duration) -> Result<Box<dyn Read>, Error> {
+    if is_url(path) {
+        open_url(path.to_str().unwrap(), timeout)
+    } else if let Some((archive_path, name)) = archive_member(path) {
+        let mut archive = ZipArchive::open(BufReader::new(File::open(archive_path)?))?;
+        let data = archive.read(&name)?;
+        Ok(Box::new(Cursor::new(data)))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+#[cfg(feature = "http")]
+fn open_url(url: &str, timeout: Duration) -> Result<Box<dyn Read>, Error> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .build();
+    let response = agent.get(url)
+        .call()
+        .map_err(|e| Error::Http(Box::new(e)))?;
+    Ok(Box::new(response.into_reader()))
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(_url: &str, _timeout: Duration) -> Result<Box<dyn Read>, Error> {
+    Err(Error::HttpNotSupported)
+}
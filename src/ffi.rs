@@ -0,0 +1,117 @@
+//! C bindings for the conversion, for linking the library into programs written in other
+//! languages. The declarations are in `include/jpeg2pdf.h`.
+
+
+use std::panic;
+use std::ptr;
+use std::slice;
+
+use crate::ConvertError;
+use crate::jpeg::{self, DensityUnit};
+
+
+/// The conversion succeeded.
+pub const JPEG2PDF_OK: i32 = 0;
+/// A required pointer was null.
+pub const JPEG2PDF_ERROR_INVALID_ARGUMENT: i32 = 1;
+/// The input is not a JPEG image that could be read.
+pub const JPEG2PDF_ERROR_INVALID_JPEG: i32 = 2;
+/// The image cannot be embedded into a PDF document (e.g. because of its encoding or color space)
+/// or its page size is unknown.
+pub const JPEG2PDF_ERROR_UNSUPPORTED: i32 = 3;
+/// The document could not be assembled or written, or the conversion failed unexpectedly.
+pub const JPEG2PDF_ERROR_INTERNAL: i32 = 4;
+
+
+/// Options for `jpeg2pdf_convert`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct Options {
+    /// The horizontal density in dots per inch to size the page with instead of the one stored in
+    /// the image, or 0 to use the stored one.
+    pub dpi_x: u16,
+    /// The vertical density in dots per inch to size the page with instead of the one stored in
+    /// the image, or 0 to use the stored one.
+    pub dpi_y: u16,
+}
+
+
+fn error_code(error: &ConvertError) -> i32 {
+    match error {
+        ConvertError::Jpeg(_)|ConvertError::Read(_) => JPEG2PDF_ERROR_INVALID_JPEG,
+        ConvertError::UnsupportedFrameType(_)
+            |ConvertError::UnsupportedBitDepth(_)
+            |ConvertError::UnsupportedColorSpace(_)
+            |ConvertError::NoDensityUnit
+            |ConvertError::UnknownDensityUnit(_)
+            |ConvertError::ZeroDensity => JPEG2PDF_ERROR_UNSUPPORTED,
+        ConvertError::Pdf(_)|ConvertError::Io(_) => JPEG2PDF_ERROR_INTERNAL,
+    }
+}
+
+fn convert(jpeg_data: &[u8], options: Options) -> Result<Vec<u8>, ConvertError> {
+    let mut jpeg = jpeg::Image::try_read(jpeg_data)?;
+    if options.dpi_x != 0 && options.dpi_y != 0 {
+        // only affects the page size; the image data retains its own density
+        jpeg.density_unit = DensityUnit::DotsPerInch;
+        jpeg.density_x = options.dpi_x;
+        jpeg.density_y = options.dpi_y;
+    }
+    crate::convert_image(jpeg)
+}
+
+
+/// Converts the JPEG image in the given buffer into a single-page PDF document, as
+/// `convert_bytes` does.
+///
+/// On success, stores a pointer to the document and its length in `pdf_data` and `pdf_len` and
+/// returns `JPEG2PDF_OK`; the document must be released using `jpeg2pdf_free`. Otherwise, returns
+/// one of the error codes and leaves `pdf_data` and `pdf_len` untouched. `options` may be null.
+///
+/// # Safety
+///
+/// `jpeg_data` must point to `jpeg_len` readable bytes, `pdf_data` and `pdf_len` must be valid
+/// for writes and `options` must be null or point to a valid `Options` value.
+#[no_mangle]
+pub unsafe extern "C" fn jpeg2pdf_convert(
+    jpeg_data: *const u8,
+    jpeg_len: usize,
+    pdf_data: *mut *mut u8,
+    pdf_len: *mut usize,
+    options: *const Options,
+) -> i32 {
+    if jpeg_data.is_null() || pdf_data.is_null() || pdf_len.is_null() {
+        return JPEG2PDF_ERROR_INVALID_ARGUMENT;
+    }
+    let jpeg_data = slice::from_raw_parts(jpeg_data, jpeg_len);
+    let options = options.as_ref().copied().unwrap_or_default();
+
+    // unwinding into the caller is undefined behavior
+    let result = match panic::catch_unwind(|| convert(jpeg_data, options)) {
+        Ok(result) => result,
+        Err(_) => return JPEG2PDF_ERROR_INTERNAL,
+    };
+    match result {
+        Ok(pdf) => {
+            let pdf = pdf.into_boxed_slice();
+            *pdf_len = pdf.len();
+            *pdf_data = Box::into_raw(pdf).cast::<u8>();
+            JPEG2PDF_OK
+        },
+        Err(e) => error_code(&e),
+    }
+}
+
+/// Releases a document returned by `jpeg2pdf_convert`. Does nothing if `pdf_data` is null.
+///
+/// # Safety
+///
+/// `pdf_data` and `pdf_len` must have been returned by `jpeg2pdf_convert` and the document must
+/// not have been released already.
+#[no_mangle]
+pub unsafe extern "C" fn jpeg2pdf_free(pdf_data: *mut u8, pdf_len: usize) {
+    if pdf_data.is_null() {
+        return;
+    }
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(pdf_data, pdf_len)));
+}
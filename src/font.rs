@@ -0,0 +1,87 @@
+//! Metrics and encoding for the standard Type 1 fonts, which PDF viewers provide without embedding.
+
+
+/// Widths of the characters 0x20 through 0x7E in Helvetica, in thousandths of the font size.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// Widths of the characters 0x20 through 0x7E in Helvetica-Bold, in thousandths of the font size.
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Width assumed for characters outside of the ASCII range, in thousandths of the font size.
+const FALLBACK_WIDTH: u16 = 556;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum StandardFont {
+    Helvetica,
+    HelveticaBold,
+}
+impl StandardFont {
+    pub fn base_font_name(&self) -> &'static str {
+        match self {
+            Self::Helvetica => "Helvetica",
+            Self::HelveticaBold => "Helvetica-Bold",
+        }
+    }
+
+    /// The height of capital letters, in thousandths of the font size.
+    pub fn cap_height(&self) -> u16 {
+        match self {
+            Self::Helvetica => 718,
+            Self::HelveticaBold => 718,
+        }
+    }
+
+    /// Calculates the width of the given WinAnsi-encoded text at the given font size.
+    pub fn text_width(&self, encoded_text: &[u8], font_size: f64) -> f64 {
+        let widths = match self {
+            Self::Helvetica => &HELVETICA_WIDTHS,
+            Self::HelveticaBold => &HELVETICA_BOLD_WIDTHS,
+        };
+        let total_width: u64 = encoded_text.iter()
+            .map(|&b| match b {
+                0x20..=0x7E => widths[usize::from(b - 0x20)],
+                _ => FALLBACK_WIDTH,
+            })
+            .map(u64::from)
+            .sum();
+        (total_width as f64) * font_size / 1000.0
+    }
+}
+
+
+/// Encodes text in WinAnsiEncoding, replacing characters that cannot be encoded with `?`.
+pub fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '\u{20}'..='\u{7E}'|'\u{A0}'..='\u{FF}' => u8::try_from(u32::from(c)).unwrap(),
+            '\u{20AC}' => 0x80, // euro sign
+            '\u{201A}' => 0x82, // single low quotation mark
+            '\u{201E}' => 0x84, // double low quotation mark
+            '\u{2026}' => 0x85, // ellipsis
+            '\u{2018}' => 0x91, // left single quotation mark
+            '\u{2019}' => 0x92, // right single quotation mark
+            '\u{201C}' => 0x93, // left double quotation mark
+            '\u{201D}' => 0x94, // right double quotation mark
+            '\u{2022}' => 0x95, // bullet
+            '\u{2013}' => 0x96, // en dash
+            '\u{2014}' => 0x97, // em dash
+            '\u{2122}' => 0x99, // trade mark sign
+            _ => b'?',
+        })
+        .collect()
+}
@@ -0,0 +1,505 @@
+//! Reading of ICC color profiles embedded in JPEG images.
+//!
+//! Only matrix/TRC profiles are supported, i.e. RGB profiles described by three colorants and
+//! tone reproduction curves and grayscale profiles described by a single tone reproduction curve.
+//! These cover the profiles commonly embedded by cameras and scanners (sRGB, Adobe RGB, Display P3,
+//! gamma-adjusted grayscale); profiles consisting only of lookup tables (such as most CMYK
+//! profiles) are not supported.
+
+
+use std::fmt;
+
+use crate::jpeg::{Image, MetadataKind};
+
+
+/// The identifier at the start of an APP2 block containing a chunk of an ICC profile.
+const CHUNK_IDENTIFIER: &[u8] = b"ICC_PROFILE\0";
+
+const HEADER_LENGTH: usize = 128;
+const TAG_ENTRY_LENGTH: usize = 12;
+
+/// The colorants of sRGB, adapted to the D50 white point of the profile connection space, as
+/// columns of the matrix converting linear sRGB to XYZ.
+const SRGB_TO_XYZ_D50: [[f64; 3]; 3] = [
+    [0.4360747, 0.3850649, 0.1430804],
+    [0.2225045, 0.7168786, 0.0606169],
+    [0.0139322, 0.0971045, 0.7141733],
+];
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Error {
+    InvalidChunks,
+    Truncated,
+    UnsupportedColorSpace { signature: [u8; 4] },
+    UnsupportedConnectionSpace { signature: [u8; 4] },
+    MissingTag { signature: [u8; 4] },
+    UnsupportedTagType { tag: [u8; 4], signature: [u8; 4] },
+    SingularMatrix,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidChunks
+                => write!(f, "the chunks of the ICC profile are incomplete or inconsistently numbered"),
+            Self::Truncated
+                => write!(f, "ICC profile is truncated"),
+            Self::UnsupportedColorSpace { signature }
+                => write!(f, "ICC profiles for the color space {:?} are not supported", String::from_utf8_lossy(signature)),
+            Self::UnsupportedConnectionSpace { signature }
+                => write!(f, "ICC profiles with the connection space {:?} are not supported", String::from_utf8_lossy(signature)),
+            Self::MissingTag { signature }
+                => write!(f, "ICC profile has no {:?} tag (only matrix/TRC profiles are supported)", String::from_utf8_lossy(signature)),
+            Self::UnsupportedTagType { tag, signature }
+                => write!(f, "ICC profile tag {:?} has the unsupported type {:?}", String::from_utf8_lossy(tag), String::from_utf8_lossy(signature)),
+            Self::SingularMatrix
+                => write!(f, "the colorants of the ICC profile are linearly dependent"),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, Error> {
+    let bytes = data.get(pos..pos + 2)
+        .ok_or(Error::Truncated)?;
+    Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, Error> {
+    let bytes = data.get(pos..pos + 4)
+        .ok_or(Error::Truncated)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn signature_at(data: &[u8], pos: usize) -> Result<[u8; 4], Error> {
+    let bytes = data.get(pos..pos + 4)
+        .ok_or(Error::Truncated)?;
+    Ok(bytes.try_into().unwrap())
+}
+
+/// Reads a signed fixed-point number with 16 fractional bits.
+fn s15_fixed16_at(data: &[u8], pos: usize) -> Result<f64, Error> {
+    let value = u32_at(data, pos)? as i32;
+    Ok(f64::from(value) / 65536.0)
+}
+
+/// Converts a linear sRGB value (0 to 1) to its gamma-encoded form.
+pub fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts a gamma-encoded sRGB value (0 to 1) to its linear form.
+pub fn srgb_decode(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut product = [[0.0; 3]; 3];
+    for (row, product_row) in product.iter_mut().enumerate() {
+        for (column, value) in product_row.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    product
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> Result<[[f64; 3]; 3], Error> {
+    let cofactor = |row: usize, column: usize| {
+        let (r1, r2) = ((row + 1) % 3, (row + 2) % 3);
+        let (c1, c2) = ((column + 1) % 3, (column + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let determinant: f64 = (0..3).map(|column| m[0][column] * cofactor(0, column)).sum();
+    if determinant.abs() < 1e-12 {
+        return Err(Error::SingularMatrix);
+    }
+    let mut inverse = [[0.0; 3]; 3];
+    for (row, inverse_row) in inverse.iter_mut().enumerate() {
+        for (column, value) in inverse_row.iter_mut().enumerate() {
+            // the inverse is the transposed matrix of cofactors divided by the determinant
+            *value = cofactor(column, row) / determinant;
+        }
+    }
+    Ok(inverse)
+}
+
+
+/// A tone reproduction curve, which maps encoded values (0 to 1) to linear ones.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    /// A power function with the given exponent.
+    Gamma(f64),
+    /// A table of equally spaced samples, between which values are interpolated linearly.
+    Table(Vec<f64>),
+    /// A parametric curve (ICC.1 section 10.18) of the given type with its parameters.
+    Parametric { function_type: u16, parameters: [f64; 7] },
+}
+impl Curve {
+    fn read(data: &[u8], tag: [u8; 4]) -> Result<Self, Error> {
+        let signature = signature_at(data, 0)?;
+        match &signature {
+            b"curv" => {
+                let count = usize::try_from(u32_at(data, 8)?).unwrap();
+                match count {
+                    0 => Ok(Self::Gamma(1.0)),
+                    1 => Ok(Self::Gamma(f64::from(u16_at(data, 12)?) / 256.0)),
+                    _ => {
+                        let samples = (0..count)
+                            .map(|i| Ok(f64::from(u16_at(data, 12 + 2 * i)?) / 65535.0))
+                            .collect::<Result<Vec<f64>, Error>>()?;
+                        Ok(Self::Table(samples))
+                    },
+                }
+            },
+            b"para" => {
+                let function_type = u16_at(data, 8)?;
+                let parameter_count = match function_type {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return Err(Error::UnsupportedTagType { tag, signature }),
+                };
+                let mut parameters = [0.0; 7];
+                for (i, parameter) in parameters.iter_mut().take(parameter_count).enumerate() {
+                    *parameter = s15_fixed16_at(data, 12 + 4 * i)?;
+                }
+                Ok(Self::Parametric { function_type, parameters })
+            },
+            _ => Err(Error::UnsupportedTagType { tag, signature }),
+        }
+    }
+
+    /// Applies the curve to an encoded value between 0 and 1.
+    pub fn apply(&self, x: f64) -> f64 {
+        let y = match self {
+            Self::Gamma(gamma) => x.powf(*gamma),
+            Self::Table(samples) => {
+                let position = x.clamp(0.0, 1.0) * (samples.len() - 1) as f64;
+                let index = (position.floor() as usize).min(samples.len() - 2);
+                let fraction = position - index as f64;
+                samples[index] * (1.0 - fraction) + samples[index + 1] * fraction
+            },
+            Self::Parametric { function_type, parameters } => {
+                let [g, a, b, c, d, e, f] = *parameters;
+                match function_type {
+                    0 => x.powf(g),
+                    1 => if x >= -b / a { (a * x + b).powf(g) } else { 0.0 },
+                    2 => if x >= -b / a { (a * x + b).powf(g) + c } else { c },
+                    3 => if x >= d { (a * x + b).powf(g) } else { c * x },
+                    _ => if x >= d { (a * x + b).powf(g) + e } else { c * x + f },
+                }
+            },
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+
+/// A color profile describing how the values of an image map to colors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Profile {
+    Rgb {
+        /// The curves of the red, green and blue components.
+        curves: [Curve; 3],
+        /// The matrix converting linear RGB to XYZ (relative to D50), whose columns are the
+        /// colorants.
+        to_xyz: [[f64; 3]; 3],
+    },
+    Gray {
+        curve: Curve,
+    },
+}
+impl Profile {
+    /// Parses a complete ICC profile.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_LENGTH + 4 {
+            return Err(Error::Truncated);
+        }
+        let color_space = signature_at(data, 16)?;
+        let connection_space = signature_at(data, 20)?;
+        if &connection_space != b"XYZ " {
+            return Err(Error::UnsupportedConnectionSpace { signature: connection_space });
+        }
+
+        let tag_count = usize::try_from(u32_at(data, HEADER_LENGTH)?).unwrap();
+        let find_tag = |wanted: &[u8; 4]| -> Result<&[u8], Error> {
+            for i in 0..tag_count {
+                let entry_pos = HEADER_LENGTH + 4 + i * TAG_ENTRY_LENGTH;
+                if &signature_at(data, entry_pos)? == wanted {
+                    let offset = usize::try_from(u32_at(data, entry_pos + 4)?).unwrap();
+                    let size = usize::try_from(u32_at(data, entry_pos + 8)?).unwrap();
+                    return data.get(offset..offset.saturating_add(size))
+                        .ok_or(Error::Truncated);
+                }
+            }
+            Err(Error::MissingTag { signature: *wanted })
+        };
+        let read_curve = |tag: &[u8; 4]| Curve::read(find_tag(tag)?, *tag);
+        let read_colorant = |tag: &[u8; 4]| -> Result<[f64; 3], Error> {
+            let tag_data = find_tag(tag)?;
+            let signature = signature_at(tag_data, 0)?;
+            if &signature != b"XYZ " {
+                return Err(Error::UnsupportedTagType { tag: *tag, signature });
+            }
+            Ok([
+                s15_fixed16_at(tag_data, 8)?,
+                s15_fixed16_at(tag_data, 12)?,
+                s15_fixed16_at(tag_data, 16)?,
+            ])
+        };
+
+        match &color_space {
+            b"RGB " => {
+                let colorants = [read_colorant(b"rXYZ")?, read_colorant(b"gXYZ")?, read_colorant(b"bXYZ")?];
+                let mut to_xyz = [[0.0; 3]; 3];
+                for (column, colorant) in colorants.iter().enumerate() {
+                    for (row, value) in colorant.iter().enumerate() {
+                        to_xyz[row][column] = *value;
+                    }
+                }
+                Ok(Self::Rgb {
+                    curves: [read_curve(b"rTRC")?, read_curve(b"gTRC")?, read_curve(b"bTRC")?],
+                    to_xyz,
+                })
+            },
+            b"GRAY" => Ok(Self::Gray {
+                curve: read_curve(b"kTRC")?,
+            }),
+            _ => Err(Error::UnsupportedColorSpace { signature: color_space }),
+        }
+    }
+
+    /// Assembles and parses the ICC profile embedded in the given image, if any.
+    ///
+    /// Profiles too large for a single block are split into numbered chunks.
+    pub fn from_image(image: &Image) -> Result<Option<Self>, Error> {
+        let mut chunks: Vec<(u8, u8, &[u8])> = image.leading_blocks.iter()
+            .filter(|block| block.metadata_kind() == Some(MetadataKind::Icc))
+            .map(|block| {
+                let data = &block.data()[CHUNK_IDENTIFIER.len()..];
+                if data.len() < 2 {
+                    return Err(Error::InvalidChunks);
+                }
+                Ok((data[0], data[1], &data[2..]))
+            })
+            .collect::<Result<_, Error>>()?;
+        if chunks.is_empty() {
+            return Ok(None);
+        }
+        chunks.sort_by_key(|&(sequence_number, _count, _data)| sequence_number);
+        let chunk_count = chunks.len();
+        let numbered_consistently = chunks.iter()
+            .enumerate()
+            .all(|(i, &(sequence_number, count, _data))| usize::from(sequence_number) == i + 1 && usize::from(count) == chunk_count);
+        if !numbered_consistently {
+            return Err(Error::InvalidChunks);
+        }
+        let data: Vec<u8> = chunks.iter()
+            .flat_map(|&(_sequence_number, _count, data)| data.iter().copied())
+            .collect();
+        Self::parse(&data).map(Some)
+    }
+
+    /// Returns the matrix converting linear RGB values in this profile to linear sRGB, or `None`
+    /// for a grayscale profile.
+    pub fn to_srgb_matrix(&self) -> Result<Option<[[f64; 3]; 3]>, Error> {
+        match self {
+            Self::Rgb { to_xyz, .. } => Ok(Some(multiply(&invert(&SRGB_TO_XYZ_D50)?, to_xyz))),
+            Self::Gray { .. } => Ok(None),
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::jpeg::Block;
+
+    fn fixed16(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    /// Assembles a profile for the given color space from the given tags.
+    pub(crate) fn build_profile(color_space: &[u8; 4], connection_space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_LENGTH];
+        data[16..20].copy_from_slice(color_space);
+        data[20..24].copy_from_slice(connection_space);
+        data.extend_from_slice(&u32::try_from(tags.len()).unwrap().to_be_bytes());
+        let mut offset = HEADER_LENGTH + 4 + tags.len() * TAG_ENTRY_LENGTH;
+        for (signature, tag_data) in tags {
+            data.extend_from_slice(*signature);
+            data.extend_from_slice(&u32::try_from(offset).unwrap().to_be_bytes());
+            data.extend_from_slice(&u32::try_from(tag_data.len()).unwrap().to_be_bytes());
+            offset += tag_data.len();
+        }
+        for (_signature, tag_data) in tags {
+            data.extend_from_slice(tag_data);
+        }
+        let length = u32::try_from(data.len()).unwrap();
+        data[0..4].copy_from_slice(&length.to_be_bytes());
+        data
+    }
+
+    fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+        let mut data = b"XYZ \0\0\0\0".to_vec();
+        for value in xyz {
+            data.extend_from_slice(&fixed16(value));
+        }
+        data
+    }
+
+    /// A curve sampled at the given encoded values.
+    pub(crate) fn curve_tag(samples: &[u16]) -> Vec<u8> {
+        let mut data = b"curv\0\0\0\0".to_vec();
+        data.extend_from_slice(&u32::try_from(samples.len()).unwrap().to_be_bytes());
+        for sample in samples {
+            data.extend_from_slice(&sample.to_be_bytes());
+        }
+        data
+    }
+
+    /// The sRGB curve as a parametric curve of type 3.
+    pub(crate) fn srgb_curve_tag() -> Vec<u8> {
+        let mut data = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for parameter in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            data.extend_from_slice(&fixed16(parameter));
+        }
+        data
+    }
+
+    /// An RGB profile with the colorants of sRGB and the given curve for each component.
+    pub(crate) fn rgb_profile(curve: Vec<u8>) -> Vec<u8> {
+        let colorant = |column: usize| xyz_tag([0, 1, 2].map(|row| SRGB_TO_XYZ_D50[row][column]));
+        build_profile(b"RGB ", b"XYZ ", &[
+            (b"rXYZ", colorant(0)),
+            (b"gXYZ", colorant(1)),
+            (b"bXYZ", colorant(2)),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ])
+    }
+
+    /// An APP2 block holding the given chunk of an ICC profile.
+    pub(crate) fn icc_block(sequence_number: u8, chunk_count: u8, chunk: &[u8]) -> Block {
+        let mut data = CHUNK_IDENTIFIER.to_vec();
+        data.push(sequence_number);
+        data.push(chunk_count);
+        data.extend_from_slice(chunk);
+        Block::Long { kind: 0xE2, data }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn srgb_encoding_round_trip() {
+        for i in 0..=255 {
+            let encoded = f64::from(i) / 255.0;
+            assert_close(srgb_encode(srgb_decode(encoded)), encoded, 1e-9);
+        }
+        assert_close(srgb_decode(0.5), 0.214041, 1e-6);
+    }
+
+    #[test]
+    fn rgb_profile_parsed() {
+        let profile = Profile::parse(&rgb_profile(srgb_curve_tag())).expect("failed to parse profile");
+        let Profile::Rgb { curves, to_xyz } = &profile else {
+            panic!("not an RGB profile: {:?}", profile);
+        };
+        for row in 0..3 {
+            for column in 0..3 {
+                assert_close(to_xyz[row][column], SRGB_TO_XYZ_D50[row][column], 1e-4);
+            }
+        }
+        for curve in curves {
+            assert!(matches!(curve, Curve::Parametric { function_type: 3, .. }));
+            for i in 0..=10 {
+                let x = f64::from(i) / 10.0;
+                assert_close(curve.apply(x), srgb_decode(x), 1e-4);
+            }
+        }
+
+        // the colorants of sRGB convert to sRGB unchanged
+        let matrix = profile.to_srgb_matrix().expect("failed to invert matrix").expect("no matrix");
+        for (row, matrix_row) in matrix.iter().enumerate() {
+            for (column, &value) in matrix_row.iter().enumerate() {
+                assert_close(value, if row == column { 1.0 } else { 0.0 }, 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn gray_profile_parsed() {
+        let data = build_profile(b"GRAY", b"XYZ ", &[(b"kTRC", curve_tag(&[563]))]);
+        let profile = Profile::parse(&data).expect("failed to parse profile");
+        assert_eq!(profile, Profile::Gray { curve: Curve::Gamma(563.0 / 256.0) });
+        assert_eq!(profile.to_srgb_matrix(), Ok(None));
+    }
+
+    #[test]
+    fn curves_applied() {
+        let identity = Curve::read(&curve_tag(&[]), *b"kTRC").expect("failed to read curve");
+        assert_eq!(identity, Curve::Gamma(1.0));
+        assert_close(identity.apply(0.3), 0.3, 1e-12);
+
+        // interpolated between the samples, clamped outside of them
+        let table = Curve::read(&curve_tag(&[0, 16384, 65535]), *b"kTRC").expect("failed to read curve");
+        assert_close(table.apply(0.25), 0.125, 1e-4);
+        assert_close(table.apply(0.75), 0.625, 1e-4);
+        assert_close(table.apply(1.5), 1.0, 1e-12);
+
+        let gamma = Curve::Gamma(2.0);
+        assert_close(gamma.apply(0.5), 0.25, 1e-12);
+    }
+
+    #[test]
+    fn chunks_assembled() {
+        let data = rgb_profile(srgb_curve_tag());
+        let (first, second) = data.split_at(100);
+        let mut image = Image::try_read(&include_bytes!("../tests/data/small.jpg")[..]).expect("failed to read image");
+        assert_eq!(Profile::from_image(&image), Ok(None));
+
+        // the chunks are ordered by their sequence numbers, not by their position
+        image.leading_blocks.insert(1, icc_block(2, 2, second));
+        image.leading_blocks.insert(1, icc_block(1, 2, first));
+        assert_eq!(Profile::from_image(&image), Ok(Some(Profile::parse(&data).unwrap())));
+
+        image.leading_blocks[2] = icc_block(1, 2, second);
+        assert_eq!(Profile::from_image(&image), Err(Error::InvalidChunks));
+    }
+
+    #[test]
+    fn unsupported_profiles_rejected() {
+        let data = rgb_profile(srgb_curve_tag());
+        assert_eq!(Profile::parse(&data[..HEADER_LENGTH]), Err(Error::Truncated));
+        assert_eq!(Profile::parse(&data[..data.len() - 1]), Err(Error::Truncated));
+
+        let lab = build_profile(b"RGB ", b"Lab ", &[]);
+        assert_eq!(Profile::parse(&lab), Err(Error::UnsupportedConnectionSpace { signature: *b"Lab " }));
+
+        // lookup tables only, as in most CMYK profiles
+        let cmyk = build_profile(b"CMYK", b"XYZ ", &[(b"A2B0", b"mft2\0\0\0\0".to_vec())]);
+        assert_eq!(Profile::parse(&cmyk), Err(Error::UnsupportedColorSpace { signature: *b"CMYK" }));
+
+        let lut_rgb = build_profile(b"RGB ", b"XYZ ", &[(b"A2B0", b"mft2\0\0\0\0".to_vec())]);
+        assert_eq!(Profile::parse(&lut_rgb), Err(Error::MissingTag { signature: *b"rXYZ" }));
+
+        let lut_curve = rgb_profile(b"mft2\0\0\0\0\0\0\0\0".to_vec());
+        assert_eq!(Profile::parse(&lut_curve), Err(Error::UnsupportedTagType { tag: *b"rTRC", signature: *b"mft2" }));
+    }
+}
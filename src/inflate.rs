@@ -0,0 +1,40 @@
+//! Decompression of raw Deflate data (RFC 1951), as found in ZIP archives.
+
+
+use std::fmt;
+
+use miniz_oxide::inflate::{decompress_to_vec_with_limit, TINFLStatus};
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Error {
+    UnexpectedEnd,
+    TooLong { max_length: usize },
+    InvalidData,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd
+                => write!(f, "compressed data ends unexpectedly"),
+            Self::TooLong { max_length }
+                => write!(f, "decompressed data is longer than the expected {} bytes", max_length),
+            Self::InvalidData
+                => write!(f, "invalid compressed data"),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+/// Decompresses raw Deflate data (without a zlib header), failing if the result would be longer
+/// than the given number of bytes.
+pub fn inflate(data: &[u8], max_length: usize) -> Result<Vec<u8>, Error> {
+    decompress_to_vec_with_limit(data, max_length)
+        .map_err(|e| match e.status {
+            TINFLStatus::FailedCannotMakeProgress|TINFLStatus::NeedsMoreInput => Error::UnexpectedEnd,
+            TINFLStatus::HasMoreOutput => Error::TooLong { max_length },
+            _ => Error::InvalidData,
+        })
+}
@@ -0,0 +1,189 @@
+//! Reporting on the structure of JPEG images and on the findings of linting and preflighting them.
+
+
+use std::path::PathBuf;
+
+use jpeg2pdf::jpeg::{Block, DensityUnit, Image};
+use jpeg2pdf::lint::Finding;
+use jpeg2pdf::preflight;
+
+use crate::report::Json;
+
+
+/// Returns the name of the JPEG marker with the given kind.
+pub fn marker_name(kind: u8) -> String {
+    match kind {
+        0xC0 => "SOF0 (baseline DCT)".to_owned(),
+        0xC1 => "SOF1 (extended sequential DCT)".to_owned(),
+        0xC2 => "SOF2 (progressive DCT)".to_owned(),
+        0xC3 => "SOF3 (lossless)".to_owned(),
+        0xC4 => "DHT (Huffman tables)".to_owned(),
+        0xC5 => "SOF5 (differential sequential DCT)".to_owned(),
+        0xC6 => "SOF6 (differential progressive DCT)".to_owned(),
+        0xC7 => "SOF7 (differential lossless)".to_owned(),
+        0xC8 => "JPG (reserved)".to_owned(),
+        0xC9 => "SOF9 (extended sequential DCT, arithmetic)".to_owned(),
+        0xCA => "SOF10 (progressive DCT, arithmetic)".to_owned(),
+        0xCB => "SOF11 (lossless, arithmetic)".to_owned(),
+        0xCC => "DAC (arithmetic coding conditioning)".to_owned(),
+        0xCD => "SOF13 (differential sequential DCT, arithmetic)".to_owned(),
+        0xCE => "SOF14 (differential progressive DCT, arithmetic)".to_owned(),
+        0xCF => "SOF15 (differential lossless, arithmetic)".to_owned(),
+        0xD0..=0xD7 => format!("RST{} (restart)", kind - 0xD0),
+        0xD8 => "SOI (start of image)".to_owned(),
+        0xD9 => "EOI (end of image)".to_owned(),
+        0xDA => "SOS (start of scan)".to_owned(),
+        0xDB => "DQT (quantization tables)".to_owned(),
+        0xDC => "DNL (number of lines)".to_owned(),
+        0xDD => "DRI (restart interval)".to_owned(),
+        0xDE => "DHP (hierarchical progression)".to_owned(),
+        0xDF => "EXP (expand reference components)".to_owned(),
+        0xE0..=0xEF => format!("APP{}", kind - 0xE0),
+        0xFE => "COM (comment)".to_owned(),
+        other => format!("unknown marker 0x{:02X}", other),
+    }
+}
+
+/// Returns the identifier string at the beginning of an application-specific block, if any.
+///
+/// The identifier is the sequence of printable ASCII characters terminated by a NUL byte at the
+/// start of the block's data, e.g. `JFIF` or `Exif`.
+pub fn app_identifier(block: &Block) -> Option<&str> {
+    if !(0xE0..=0xEF).contains(&block.kind()) {
+        return None;
+    }
+    let data = block.data();
+    let nul_pos = data.iter().position(|b| *b == 0x00)?;
+    let identifier = &data[..nul_pos];
+    if identifier.is_empty() || !identifier.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return None;
+    }
+    std::str::from_utf8(identifier).ok()
+}
+
+/// Prints a human-readable report about the given image to standard output.
+pub fn print_report(image: &Image) {
+    println!("  encoding: {}", image.frame_type.description());
+    if !image.frame_type.is_supported_by_dct_decode() {
+        println!("    (not supported by PDF viewers)");
+    }
+    println!("  dimensions: {}x{} px", image.width, image.height);
+    println!("  bit depth: {}", image.bit_depth);
+    if image.is_ycck() {
+        println!("  color space: {:?} (stored as YCCK)", image.color_space);
+    } else {
+        println!("  color space: {:?}", image.color_space);
+    }
+    if let Some(adobe_transform) = image.adobe_transform {
+        println!("  Adobe transform: {:?}", adobe_transform);
+    }
+    match image.density_unit {
+        DensityUnit::NoUnit => println!("  density: none (pixel aspect ratio {}:{})", image.density_x, image.density_y),
+        DensityUnit::DotsPerInch => println!("  density: {}x{} dpi", image.density_x, image.density_y),
+        DensityUnit::DotsPerCentimeter => println!("  density: {}x{} dots per cm", image.density_x, image.density_y),
+        DensityUnit::Other(u) => println!("  density: {}x{} in unknown unit {}", image.density_x, image.density_y, u),
+    }
+    println!("  ICC profile: {}", if image.has_icc_profile() { "present" } else { "absent" });
+
+    println!("  segments:");
+    for block in &image.leading_blocks {
+        print_block(block);
+    }
+    match image.scans() {
+        Ok(scans) => {
+            for (index, scan) in scans.iter().enumerate() {
+                for block in &scan.blocks {
+                    print_block(block);
+                }
+                let restart_count = scan.restart_count();
+                if restart_count > 0 {
+                    println!("    entropy-coded data of scan {} ({} bytes, {} restart markers)", index + 1, scan.data.len(), restart_count);
+                } else {
+                    println!("    entropy-coded data of scan {} ({} bytes)", index + 1, scan.data.len());
+                }
+            }
+        },
+        Err(e) => println!("    image data ({} bytes; cannot split into scans: {})", image.image_data.len(), e),
+    }
+    for block in &image.trailing_blocks {
+        print_block(block);
+    }
+}
+
+fn print_block(block: &Block) {
+    let kind = block.kind();
+    match block {
+        Block::Short { .. } => println!("    0xFF{:02X} {}", kind, marker_name(kind)),
+        Block::Long { data, .. } => {
+            match app_identifier(block) {
+                Some(identifier) => println!("    0xFF{:02X} {} \"{}\" ({} bytes)", kind, marker_name(kind), identifier, data.len()),
+                None => println!("    0xFF{:02X} {} ({} bytes)", kind, marker_name(kind), data.len()),
+            }
+        },
+    }
+}
+
+/// Prints the findings of linting the given images to standard output, one per line.
+pub fn print_findings(results: &[(PathBuf, Vec<Finding>)]) {
+    for (path, findings) in results {
+        for finding in findings {
+            println!("{}: {}", path.display(), finding);
+        }
+    }
+}
+
+/// Returns the findings of linting the given images as JSON.
+pub fn findings_to_json(results: &[(PathBuf, Vec<Finding>)]) -> Json {
+    let inputs = results.iter()
+        .map(|(path, findings)| Json::object([
+            ("path", Json::path(path)),
+            ("findings", Json::Array(findings.iter().map(|finding| Json::object([
+                ("check", Json::string(finding.check.name())),
+                ("severity", Json::string(finding.severity().name())),
+                ("message", Json::string(finding.message.clone())),
+            ])).collect())),
+        ]))
+        .collect();
+    Json::object([
+        ("command", Json::string("lint")),
+        ("inputs", Json::Array(inputs)),
+    ])
+}
+
+/// Prints the findings of preflighting the given images to standard output, one per line,
+/// followed by those concerning the document as a whole.
+pub fn print_preflight_findings(results: &[(PathBuf, Vec<preflight::Finding>)], document_findings: &[preflight::Finding]) {
+    for (path, findings) in results {
+        for finding in findings {
+            println!("{}: {}", path.display(), finding);
+        }
+    }
+    for finding in document_findings {
+        println!("document: {}", finding);
+    }
+}
+
+fn preflight_finding_to_json(finding: &preflight::Finding) -> Json {
+    Json::object([
+        ("target", finding.target.map_or(Json::Null, |target| Json::string(target.name()))),
+        ("check", Json::string(finding.check.name())),
+        ("severity", Json::string(finding.severity.name())),
+        ("message", Json::string(finding.message.clone())),
+        ("fix", Json::string(finding.fix.clone())),
+    ])
+}
+
+/// Returns the findings of preflighting the given images as JSON.
+pub fn preflight_findings_to_json(results: &[(PathBuf, Vec<preflight::Finding>)], document_findings: &[preflight::Finding]) -> Json {
+    let inputs = results.iter()
+        .map(|(path, findings)| Json::object([
+            ("path", Json::path(path)),
+            ("findings", Json::Array(findings.iter().map(preflight_finding_to_json).collect())),
+        ]))
+        .collect();
+    Json::object([
+        ("command", Json::string("preflight")),
+        ("inputs", Json::Array(inputs)),
+        ("document", Json::Array(document_findings.iter().map(preflight_finding_to_json).collect())),
+    ])
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 use std::io::{self, Read, Write};
+use std::ops::Range;
 
 use from_to_repr::from_to_other;
 
@@ -24,9 +25,23 @@ impl Block {
         }
     }
 
+    /// The number of bytes the block takes up when written, including the marker.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Short { .. } => 2,
+            Self::Long { data, .. } => 4 + data.len(),
+        }
+    }
+
     pub fn is_required(&self) -> bool {
         let kind = self.kind();
-        kind < 0xE0 || kind > 0xFE
+        !(0xE0..=0xFE).contains(&kind)
+    }
+
+    /// Classifies an optional block by the kind of metadata it contains, judging by its marker and
+    /// the identifier at the start of its data. Returns `None` for required blocks.
+    pub fn metadata_kind(&self) -> Option<MetadataKind> {
+        metadata_kind_of(self.kind(), self.data())
     }
 
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
@@ -52,7 +67,12 @@ impl Block {
         }
     }
 
-    pub fn try_read<R: Read>(mut reader: R) -> Result<Self, Error> {
+    pub fn try_read<R: Read>(reader: R) -> Result<Self, Error> {
+        Self::try_read_with_limit(reader, usize::MAX)
+    }
+
+    /// Reads a block, failing if its data is longer than the given number of bytes.
+    pub fn try_read_with_limit<R: Read>(mut reader: R, max_data_len: usize) -> Result<Self, Error> {
         let mut buf1 = [0u8];
         reader.read_exact(&mut buf1)?;
 
@@ -78,24 +98,79 @@ impl Block {
                     return Err(Error::BlockTooShort { min_expected: 2, obtained: block_len_incl_len });
                 }
                 let block_len = block_len_incl_len - 2;
+                if block_len > max_data_len {
+                    return Err(Error::BlockTooLong { max_allowed: max_data_len, obtained: block_len });
+                }
 
-                let mut data = vec![0u8; block_len];
-                reader.read_exact(&mut data)?;
+                // only allocate as much as is actually there
+                let mut data = Vec::new();
+                reader.take(u64::try_from(block_len).unwrap()).read_to_end(&mut data)?;
+                if data.len() < block_len {
+                    return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+                }
                 Ok(Block::Long { kind: block_kind, data })
             },
         }
     }
 }
 
+/// Classifies an optional block with the given marker and data by the kind of metadata it
+/// contains, judging by the marker and the identifier at the start of the data. Returns `None` for
+/// required blocks.
+fn metadata_kind_of(kind: u8, data: &[u8]) -> Option<MetadataKind> {
+    if !(0xE0..=0xFE).contains(&kind) {
+        return None;
+    }
+    let metadata_kind = match kind {
+        0xE0 if data.starts_with(b"JFIF\0") || data.starts_with(b"JFXX\0") => MetadataKind::Jfif,
+        0xE1 if data.starts_with(b"Exif\0") => MetadataKind::Exif,
+        0xE1 if data.starts_with(b"http://ns.adobe.com/xap/1.0/\0") || data.starts_with(b"http://ns.adobe.com/xmp/extension/\0")
+            => MetadataKind::Xmp,
+        0xE2 if data.starts_with(b"ICC_PROFILE\0") => MetadataKind::Icc,
+        0xED if data.starts_with(b"Photoshop 3.0\0") => MetadataKind::Iptc,
+        0xEE if data.starts_with(b"Adobe") => MetadataKind::Adobe,
+        0xFE => MetadataKind::Comments,
+        _ => MetadataKind::Other,
+    };
+    Some(metadata_kind)
+}
+
+/// The kind of metadata contained in an optional block.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MetadataKind {
+    /// JFIF header (APP0), including the density, and JFIF extensions.
+    Jfif,
+    /// Exif metadata (APP1) such as camera settings, GPS position and a thumbnail.
+    Exif,
+    /// XMP metadata (APP1).
+    Xmp,
+    /// Embedded ICC color profile (APP2).
+    Icc,
+    /// IPTC metadata in a Photoshop block (APP13).
+    Iptc,
+    /// Adobe block (APP14), which records how the color components were transformed.
+    Adobe,
+    /// Comments (COM).
+    Comments,
+    /// Any other application-specific block.
+    Other,
+}
+
+
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     NotABlock { start_byte: u8 },
     BlockTooShort { min_expected: usize, obtained: usize },
     BlockTooLong { max_allowed: usize, obtained: usize },
+    LeadingBlocksTooLong { max_allowed: usize },
+    ImageDataTooLong { max_allowed: usize },
+    DimensionsTooLarge { width: u16, height: u16, max_width: u16, max_height: u16 },
     IncompleteData { builder: ImageBuilder },
     UnexpectedBlock { expected_kind: u8, obtained_kind: u8 },
     IncorrectImageDataTermination,
+    DataAfterEnd { length: usize },
     NotJfif,
     UnsupportedJfifVersion { obtained: u16 },
     JfifTooShort { min_expected: usize, obtained: usize },
@@ -113,16 +188,24 @@ impl fmt::Display for Error {
                 => write!(f, "block too short -- expected at least {} bytes, obtained {} bytes", min_expected, obtained),
             Self::BlockTooLong { max_allowed, obtained }
                 => write!(f, "block too long -- max allowed {} bytes, obtained {} bytes", max_allowed, obtained),
+            Self::LeadingBlocksTooLong { max_allowed }
+                => write!(f, "blocks before the image data too long -- max allowed {} bytes", max_allowed),
+            Self::ImageDataTooLong { max_allowed }
+                => write!(f, "image data too long -- max allowed {} bytes", max_allowed),
+            Self::DimensionsTooLarge { width, height, max_width, max_height }
+                => write!(f, "image too large -- max allowed {}x{} pixels, obtained {}x{} pixels", max_width, max_height, width, height),
             Self::IncompleteData { builder }
                 => write!(f, "incomplete data in header: {:?}", builder),
             Self::UnexpectedBlock { expected_kind, obtained_kind  }
                 => write!(f, "unexpected block 0x{:02X} (expected 0x{:02X})", obtained_kind, expected_kind),
             Self::IncorrectImageDataTermination
                 => write!(f, "image data terminated incorrectly"),
+            Self::DataAfterEnd { length }
+                => write!(f, "{} bytes of data after end of image", length),
             Self::NotJfif
                 => write!(f, "file is not a JFIF file"),
             Self::UnsupportedJfifVersion { obtained }
-                => write!(f, "unsupported JFIF version; obtained {}.{:02}", (obtained >> 8) & 0xFF, (obtained >> 0) & 0xFF),
+                => write!(f, "unsupported JFIF version; obtained {}.{:02}", (obtained >> 8) & 0xFF, obtained & 0xFF),
             Self::JfifTooShort { min_expected, obtained }
                 => write!(f, "JFIF header too short; expected at least {} bytes, obtained {}", min_expected, obtained),
             Self::SofTooShort { min_expected, obtained }
@@ -139,9 +222,13 @@ impl std::error::Error for Error {
             Self::NotABlock { .. } => None,
             Self::BlockTooShort { .. } => None,
             Self::BlockTooLong { .. } => None,
+            Self::LeadingBlocksTooLong { .. } => None,
+            Self::ImageDataTooLong { .. } => None,
+            Self::DimensionsTooLarge { .. } => None,
             Self::IncompleteData { .. } => None,
             Self::UnexpectedBlock { .. } => None,
             Self::IncorrectImageDataTermination => None,
+            Self::DataAfterEnd { .. } => None,
             Self::NotJfif => None,
             Self::UnsupportedJfifVersion { .. } => None,
             Self::JfifTooShort { .. } => None,
@@ -158,8 +245,50 @@ impl From<crate::exif::Error> for Error {
 }
 
 
+/// Limits on the images read by `Image::try_read_with_limits`, so that untrusted input cannot make
+/// the reader allocate arbitrary amounts of memory.
+///
+/// The default imposes no limits beyond those of the format itself.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Limits {
+    /// The maximum length of the data of a single block (segment), in bytes.
+    pub max_segment_len: usize,
+    /// The maximum total length of the blocks before the image data, including their markers, in
+    /// bytes.
+    pub max_leading_blocks_len: usize,
+    /// The maximum length of the image data (the scans and any blocks between them, up to the
+    /// end-of-image marker), in bytes.
+    pub max_image_data_len: usize,
+    /// The maximum width of the image, in pixels.
+    pub max_width: u16,
+    /// The maximum height of the image, in pixels.
+    pub max_height: u16,
+}
+impl Limits {
+    fn check_dimensions(&self, width: u16, height: u16) -> Result<(), Error> {
+        if width > self.max_width || height > self.max_height {
+            Err(Error::DimensionsTooLarge { width, height, max_width: self.max_width, max_height: self.max_height })
+        } else {
+            Ok(())
+        }
+    }
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_segment_len: usize::MAX,
+            max_leading_blocks_len: usize::MAX,
+            max_image_data_len: usize::MAX,
+            max_width: u16::MAX,
+            max_height: u16::MAX,
+        }
+    }
+}
+
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Image {
+    pub frame_type: FrameType,
     pub bit_depth: u8,
     pub width: u16,
     pub height: u16,
@@ -167,15 +296,35 @@ pub struct Image {
     pub density_unit: DensityUnit,
     pub density_x: u16,
     pub density_y: u16,
+    /// The orientation stored in the Exif metadata (1 to 8, with 1 meaning upright), if any.
+    pub exif_orientation: Option<u16>,
+    /// The color transform recorded in the Adobe block, if any.
+    pub adobe_transform: Option<AdobeTransform>,
     pub leading_blocks: Vec<Block>,
     pub image_data: Vec<u8>,
     pub trailing_blocks: Vec<Block>,
 }
 impl Image {
-    pub fn try_read<R: Read>(mut reader: R) -> Result<Self, Error> {
+    pub fn try_read<R: Read>(reader: R) -> Result<Self, Error> {
+        Self::try_read_with_limits(reader, &Limits::default())
+    }
+
+    /// Reads an image, failing as soon as it exceeds one of the given limits.
+    pub fn try_read_with_limits<R: Read>(mut reader: R, limits: &Limits) -> Result<Self, Error> {
         let mut builder = ImageBuilder::new();
+        let mut leading_blocks_len: usize = 0;
         loop {
-            let block = Block::try_read(&mut reader)?;
+            let block = Block::try_read_with_limit(&mut reader, limits.max_segment_len)?;
+            leading_blocks_len += block.encoded_len();
+            if leading_blocks_len > limits.max_leading_blocks_len {
+                return Err(Error::LeadingBlocksTooLong { max_allowed: limits.max_leading_blocks_len });
+            }
+            if matches!(block.kind(), 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF) && block.data().len() >= 5 {
+                // fail before reading the image data
+                let height = u16::from_be_bytes(block.data()[1..3].try_into().unwrap());
+                let width = u16::from_be_bytes(block.data()[3..5].try_into().unwrap());
+                limits.check_dimensions(width, height)?;
+            }
             builder.leading_blocks.push(block);
             let block_ref = builder.leading_blocks.last().unwrap();
 
@@ -193,19 +342,163 @@ impl Image {
             }
         }
 
-        // read the image data
+        // read the image data: the scans (with any blocks between them) up to end-of-image
         let mut image_data = Vec::new();
-        reader.read_to_end(&mut image_data)?;
+        reader.take(u64::try_from(limits.max_image_data_len).unwrap_or(u64::MAX).saturating_add(1))
+            .read_to_end(&mut image_data)?;
+        if image_data.len() > limits.max_image_data_len {
+            return Err(Error::ImageDataTooLong { max_allowed: limits.max_image_data_len });
+        }
 
-        if image_data.ends_with(&[0xFF, 0xD9]) {
-            // ends with end-of-input, perfect
-            image_data.drain(image_data.len()-2..);
-            builder.trailing_blocks.push(Block::Short { kind: 0xD9 });
-        } else {
-            return Err(Error::IncorrectImageDataTermination);
+        let (scans, end_pos) = split_scans(&image_data)?;
+        builder.number_of_lines = examine_scans(&scans);
+        let end_pos = end_pos
+            .ok_or(Error::IncorrectImageDataTermination)?;
+        if end_pos + 2 < image_data.len() {
+            return Err(Error::DataAfterEnd { length: image_data.len() - (end_pos + 2) });
         }
+        image_data.truncate(end_pos);
+        builder.trailing_blocks.push(Block::Short { kind: 0xD9 });
 
         builder.image_data = image_data;
+        let image = Self::try_from_builder(builder)?;
+        // the height may only have been supplied by a number-of-lines block
+        limits.check_dimensions(image.width, image.height)?;
+        Ok(image)
+    }
+
+    /// Reads an image, working around common kinds of damage instead of failing.
+    ///
+    /// The repairs that were necessary are appended to `repairs`.
+    pub fn try_read_repairing<R: Read>(mut reader: R, repairs: &mut Vec<Repair>) -> Result<Self, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // skip anything before the start-of-image marker
+        let start = data.windows(2)
+            .position(|w| w == [0xFF, 0xD8])
+            .ok_or(Error::UnexpectedBlock { expected_kind: 0xD8, obtained_kind: data.get(1).copied().unwrap_or(0) })?;
+        if start > 0 {
+            repairs.push(Repair::GarbageBeforeStart { length: start });
+        }
+
+        let mut builder = ImageBuilder::new();
+        builder.leading_blocks.push(Block::Short { kind: 0xD8 });
+        let mut pos = start + 2;
+        let mut seen_jfif = false;
+        loop {
+            // find the next marker
+            let marker_pos = match find_marker(&data, pos) {
+                Some(mp) => mp,
+                None => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+            };
+            if marker_pos > pos {
+                repairs.push(Repair::GarbageBetweenBlocks { offset: pos, length: marker_pos - pos });
+            }
+            let kind = data[marker_pos + 1];
+            pos = marker_pos + 2;
+
+            if (0xD0..=0xD9).contains(&kind) {
+                builder.leading_blocks.push(Block::Short { kind });
+                continue;
+            }
+
+            let declared_length = match data.get(pos..pos + 2) {
+                Some(length_bytes) => usize::from(u16::from_be_bytes(length_bytes.try_into().unwrap())),
+                None => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+            };
+            let declared_end = pos + declared_length;
+            let plausible = declared_length >= 2
+                && declared_end <= data.len()
+                && (kind == 0xDA || data.get(declared_end) == Some(&0xFF));
+            let end = if plausible {
+                declared_end
+            } else {
+                // assume the block ends where the next one starts
+                let corrected_end = find_marker(&data, pos + 2)
+                    .ok_or(Error::Io(io::ErrorKind::UnexpectedEof.into()))?;
+                repairs.push(Repair::BlockLengthCorrected {
+                    kind,
+                    declared: declared_length,
+                    corrected: corrected_end - pos,
+                });
+                corrected_end
+            };
+            let block_data = data[pos + 2..end].to_vec();
+            pos = end;
+
+            if kind == 0xE0 {
+                if !block_data.starts_with(b"JFIF\0") {
+                    repairs.push(Repair::BlockRemoved { kind, reason: "not a JFIF header" });
+                    continue;
+                }
+                if seen_jfif {
+                    repairs.push(Repair::BlockRemoved { kind, reason: "duplicate JFIF header" });
+                    continue;
+                }
+                seen_jfif = true;
+            }
+
+            builder.leading_blocks.push(Block::Long { kind, data: block_data });
+            if kind == 0xDA {
+                // start-of-scan; the image data follows
+                break;
+            }
+        }
+
+        // the end-of-image marker follows the last scan; if the blocks between the scans are
+        // damaged, fall back to the first end-of-image marker (marker bytes are escaped within
+        // entropy-coded data)
+        let mut image_data = data.split_off(pos);
+        let (end_pos, split) = match split_scans(&image_data) {
+            Ok((scans, end_pos)) => {
+                builder.number_of_lines = examine_scans(&scans);
+                (end_pos, true)
+            },
+            Err(_) => {
+                let end_pos = image_data.windows(2)
+                    .position(|w| w == [0xFF, 0xD9]);
+                (end_pos, false)
+            },
+        };
+        match end_pos {
+            Some(ep) => {
+                if ep + 2 < image_data.len() {
+                    repairs.push(Repair::GarbageAfterEnd { length: image_data.len() - (ep + 2) });
+                }
+                image_data.truncate(ep);
+            },
+            None => {
+                if image_data.last() == Some(&0xFF) {
+                    // a truncated marker
+                    image_data.pop();
+                }
+                repairs.push(Repair::EndAdded);
+            },
+        }
+        if !split {
+            // damaged image data is tolerated here; the scans up to the end may still be readable
+            if let Ok((scans, _end_pos)) = split_scans(&image_data) {
+                builder.number_of_lines = examine_scans(&scans);
+            }
+        }
+        builder.image_data = image_data;
+        builder.trailing_blocks.push(Block::Short { kind: 0xD9 });
+
+        Self::try_from_builder(builder)
+    }
+
+    /// Processes the blocks collected in the builder and turns it into an image.
+    fn try_from_builder(mut builder: ImageBuilder) -> Result<Self, Error> {
+        let mut offset = 0;
+        for block in &builder.leading_blocks {
+            // offsets within the image as read, i.e. after any repairs
+            log::debug!("segment marker=0xFF{:02X} offset={} length={}", block.kind(), offset, block.encoded_len());
+            offset += block.encoded_len();
+        }
+
+        // a number-of-lines block after the first scan supplies a height missing from the frame
+        let number_of_lines = builder.number_of_lines;
 
         let leading_blocks_clone = builder.leading_blocks.clone();
         for block in &leading_blocks_clone {
@@ -233,11 +526,13 @@ impl Image {
                     builder.density_x = Some(density_x);
                     builder.density_y = Some(density_y);
                 },
-                0xE1 => {
-                    // APP1
-                    if data.starts_with(b"Exif\0\0") {
-                        crate::exif::process(data, &mut builder)?;
-                    }
+                0xE1 if data.starts_with(b"Exif\0\0") => {
+                    // APP1 (Exif)
+                    crate::exif::process(data, &mut builder)?;
+                },
+                0xEE if data.starts_with(b"Adobe") && data.len() >= 12 => {
+                    // APP14 (Adobe): version, two flag words, then the transform
+                    builder.adobe_transform = Some(AdobeTransform::from_base_type(data[11]));
                 },
                 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF => {
                     // start of frame
@@ -248,10 +543,18 @@ impl Image {
                     let height = u16::from_be_bytes(data[1..3].try_into().unwrap());
                     let width = u16::from_be_bytes(data[3..5].try_into().unwrap());
                     let color_space = ColorSpace::from_base_type(data[5]);
+                    builder.frame_type = Some(FrameType::from_base_type(block.kind()));
                     builder.bit_depth = Some(bit_depth);
-                    builder.height = Some(height);
+                    builder.height = match (height, number_of_lines) {
+                        (0, Some(lines)) => Some(lines),
+                        _ => Some(height),
+                    };
                     builder.width = Some(width);
                     builder.color_space = Some(color_space);
+                    log::debug!(
+                        "frame marker=0xFF{:02X} width={} height={} bit_depth={} components={}",
+                        block.kind(), width, height, bit_depth, data[5],
+                    );
                 },
                 _ => {},
             }
@@ -260,6 +563,80 @@ impl Image {
         builder.try_into()
     }
 
+    /// Splits the image data into its scans.
+    ///
+    /// The first scan is described by the leading blocks; the blocks of each further scan (e.g.
+    /// Huffman tables and its start-of-scan block) are stored in the image data.
+    pub fn scans(&self) -> Result<Vec<Scan<'_>>, Error> {
+        let (scans, _end_pos) = split_scans(&self.image_data)?;
+        Ok(scans)
+    }
+
+    /// Iterates over the segments of the image in the order in which they are written: the leading
+    /// blocks, the blocks between the scans (each start-of-scan segment along with the
+    /// entropy-coded data following it) and the trailing blocks.
+    ///
+    /// Yields an error and stops if the data between the scans is damaged.
+    pub fn segments(&self) -> Segments<'_> {
+        Segments {
+            image: self,
+            leading_index: 0,
+            image_data_pos: Some(0),
+            trailing_index: 0,
+        }
+    }
+
+    /// Whether the image stores its four components as YCCK, which the decoder converts to CMYK.
+    pub fn is_ycck(&self) -> bool {
+        matches!(self.color_space, ColorSpace::Cmyk)
+            && matches!(self.adobe_transform, Some(AdobeTransform::Ycck))
+    }
+
+    /// Whether the image still contains its Adobe block, which tells the decoder how to transform
+    /// the colors.
+    pub fn has_adobe_block(&self) -> bool {
+        self.leading_blocks
+            .iter()
+            .any(|b| b.metadata_kind() == Some(MetadataKind::Adobe))
+    }
+
+    /// Whether the image contains an embedded ICC color profile (in one or more APP2 blocks).
+    pub fn has_icc_profile(&self) -> bool {
+        self.leading_blocks
+            .iter()
+            .any(|b| b.kind() == 0xE2 && b.data().starts_with(b"ICC_PROFILE\0"))
+    }
+
+    /// Removes the thumbnail from the Exif metadata while keeping the rest of it, returning the
+    /// number of bytes saved.
+    ///
+    /// A thumbnail that is followed by other metadata is left alone, as is Exif metadata that
+    /// cannot be read.
+    pub fn remove_exif_thumbnail(&mut self) -> usize {
+        let mut saved = 0;
+        for block in &mut self.leading_blocks {
+            if let Block::Long { kind: 0xE1, data } = block {
+                if !data.starts_with(b"Exif\0\0") {
+                    continue;
+                }
+                if let Ok(Some(new_data)) = crate::exif::remove_thumbnail(data) {
+                    saved += data.len() - new_data.len();
+                    *data = new_data;
+                }
+            }
+        }
+        saved
+    }
+
+    /// The number of bytes the image takes up when written.
+    pub fn encoded_len(&self) -> usize {
+        let block_len: usize = self.leading_blocks.iter()
+            .chain(self.trailing_blocks.iter())
+            .map(|b| b.encoded_len())
+            .sum();
+        block_len + self.image_data.len()
+    }
+
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
         for leading_block in &self.leading_blocks {
             leading_block.write(&mut writer)?;
@@ -272,8 +649,9 @@ impl Image {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct ImageBuilder {
+    pub frame_type: Option<FrameType>,
     pub bit_depth: Option<u8>,
     pub width: Option<u16>,
     pub height: Option<u16>,
@@ -281,6 +659,15 @@ pub struct ImageBuilder {
     pub density_unit: Option<DensityUnit>,
     pub density_x: Option<u16>,
     pub density_y: Option<u16>,
+    pub exif_density_unit: Option<DensityUnit>,
+    pub exif_density_x: Option<u16>,
+    pub exif_density_y: Option<u16>,
+    pub exif_orientation: Option<u16>,
+    /// Whether the Exif metadata contains a thumbnail image (a second IFD).
+    pub exif_has_thumbnail: bool,
+    pub adobe_transform: Option<AdobeTransform>,
+    /// The height given by a number-of-lines block after the first scan, if any.
+    pub number_of_lines: Option<u16>,
     pub leading_blocks: Vec<Block>,
     pub image_data: Vec<u8>,
     pub trailing_blocks: Vec<Block>,
@@ -288,6 +675,7 @@ pub struct ImageBuilder {
 impl ImageBuilder {
     pub fn new() -> Self {
         Self {
+            frame_type: None,
             bit_depth: None,
             width: None,
             height: None,
@@ -295,6 +683,13 @@ impl ImageBuilder {
             density_unit: None,
             density_x: None,
             density_y: None,
+            exif_density_unit: None,
+            exif_density_x: None,
+            exif_density_y: None,
+            exif_orientation: None,
+            exif_has_thumbnail: false,
+            adobe_transform: None,
+            number_of_lines: None,
             leading_blocks: Vec::new(),
             image_data: Vec::new(),
             trailing_blocks: Vec::new(),
@@ -302,17 +697,17 @@ impl ImageBuilder {
     }
 
     fn build(&self) -> Option<Image> {
+        let frame_type = self.frame_type?;
         let bit_depth = self.bit_depth?;
         let width = self.width?;
         let height = self.height?;
         let color_space = self.color_space?;
-        let density_unit = self.density_unit?;
-        let density_x = self.density_x?;
-        let density_y = self.density_y?;
+        let (density_unit, density_x, density_y) = self.density();
         let leading_blocks = self.leading_blocks.clone();
         let image_data = self.image_data.clone();
         let trailing_blocks = self.trailing_blocks.clone();
         Some(Image {
+            frame_type,
             bit_depth,
             width,
             height,
@@ -320,21 +715,483 @@ impl ImageBuilder {
             density_unit,
             density_x,
             density_y,
+            exif_orientation: self.exif_orientation,
+            adobe_transform: self.adobe_transform,
             leading_blocks,
             image_data,
             trailing_blocks,
         })
     }
+
+    /// Decides which density information to use.
+    ///
+    /// JFIF density with a physical unit is preferred, followed by the Exif resolution, followed by
+    /// the JFIF pixel aspect ratio. If none of these is available, a square pixel aspect ratio
+    /// without unit is assumed.
+    fn density(&self) -> (DensityUnit, u16, u16) {
+        let jfif_density = match (self.density_unit, self.density_x, self.density_y) {
+            (Some(unit), Some(x), Some(y)) => Some((unit, x, y)),
+            _ => None,
+        };
+        let exif_density = match (self.exif_density_unit, self.exif_density_x, self.exif_density_y) {
+            (Some(unit), Some(x), Some(y)) => Some((unit, x, y)),
+            _ => None,
+        };
+
+        match (jfif_density, exif_density) {
+            (Some(jfif), _) if !matches!(jfif.0, DensityUnit::NoUnit) => jfif,
+            (_, Some(exif)) => exif,
+            (Some(jfif), None) => jfif,
+            (None, None) => (DensityUnit::NoUnit, 1, 1),
+        }
+    }
 }
 impl TryFrom<ImageBuilder> for Image {
     type Error = Error;
 
     fn try_from(value: ImageBuilder) -> Result<Self, Self::Error> {
         value.build()
-            .ok_or_else(|| Error::IncompleteData { builder: value })
+            .ok_or(Error::IncompleteData { builder: value })
+    }
+}
+
+
+/// Finds the next marker at or after the given position, skipping fill bytes.
+///
+/// Returns the position of the 0xFF byte directly preceding the marker type.
+fn find_marker(data: &[u8], start: usize) -> Option<usize> {
+    let mut pos = start;
+    while pos + 1 < data.len() {
+        let kind = data[pos + 1];
+        if data[pos] == 0xFF && kind != 0x00 && kind != 0xFF {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+    None
+}
+
+
+/// A scan of an image: entropy-coded data along with the blocks preceding it.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Scan<'a> {
+    /// The blocks between the previous scan and this one, ending with the start-of-scan block.
+    /// Empty for the first scan, whose blocks are the leading blocks of the image.
+    pub blocks: Vec<Block>,
+    /// The entropy-coded data, including any restart markers and fill bytes, as stored.
+    pub data: &'a [u8],
+}
+impl<'a> Scan<'a> {
+    /// The number of restart markers within the entropy-coded data.
+    pub fn restart_count(&self) -> usize {
+        let mut count = 0;
+        let mut pos = 0;
+        while pos + 1 < self.data.len() {
+            if self.data[pos] == 0xFF && (0xD0..=0xD7).contains(&self.data[pos + 1]) {
+                count += 1;
+                pos += 2;
+            } else {
+                pos += 1;
+            }
+        }
+        count
+    }
+}
+
+/// Finds the end of the entropy-coded data starting at the given position, i.e. the first marker
+/// that is neither a restart marker nor a stuffed zero byte (or the end of the data).
+fn entropy_coded_end(data: &[u8], start: usize) -> usize {
+    let mut pos = start;
+    while pos < data.len() {
+        if data[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        // any number of fill bytes may precede a marker
+        let mut kind_pos = pos + 1;
+        while data.get(kind_pos) == Some(&0xFF) {
+            kind_pos += 1;
+        }
+        match data.get(kind_pos) {
+            Some(0x00|0xD0..=0xD7) => pos = kind_pos + 1,
+            _ => return pos,
+        }
     }
+    data.len()
 }
 
+/// Logs the given scans and returns the height given by a number-of-lines block among them, if
+/// any.
+fn examine_scans(scans: &[Scan<'_>]) -> Option<u16> {
+    for (index, scan) in scans.iter().enumerate() {
+        log::debug!("scan index={} length={} restart_markers={}", index, scan.data.len(), scan.restart_count());
+    }
+    scans.iter()
+        .flat_map(|scan| scan.blocks.iter())
+        .find(|block| block.kind() == 0xDC && block.data().len() >= 2)
+        .map(|block| u16::from_be_bytes(block.data()[0..2].try_into().unwrap()))
+}
+
+/// Splits the data following the first start-of-scan block into scans.
+///
+/// Returns the scans and the position of the end-of-image marker, or `None` if the data ends
+/// without one.
+fn split_scans(data: &[u8]) -> Result<(Vec<Scan<'_>>, Option<usize>), Error> {
+    let mut scans = Vec::new();
+    let mut blocks = Vec::new();
+    // the start-of-scan block of the first scan has already been read
+    let mut in_scan = true;
+    let mut pos = 0;
+    loop {
+        if in_scan {
+            let end = entropy_coded_end(data, pos);
+            scans.push(Scan {
+                blocks: std::mem::take(&mut blocks),
+                data: &data[pos..end],
+            });
+            pos = end;
+            in_scan = false;
+        }
+
+        let (kind, payload_range) = match marker_at(data, pos)? {
+            Some(marker) => marker,
+            None => return Ok((scans, None)),
+        };
+        match kind {
+            0xD9 => return Ok((scans, Some(pos))),
+            0xD0..=0xD8 => blocks.push(Block::Short { kind }),
+            _ => {
+                blocks.push(Block::Long { kind, data: data[payload_range.clone()].to_vec() });
+                if kind == 0xDA {
+                    in_scan = true;
+                }
+            },
+        }
+        pos = payload_range.end;
+    }
+}
+
+/// Reads the marker at the given position (skipping fill bytes), returning its type and the range
+/// of its payload, which is empty for markers without a length. The next marker follows the
+/// payload.
+///
+/// Returns `None` if the data ends before the type of the marker.
+fn marker_at(data: &[u8], pos: usize) -> Result<Option<(u8, Range<usize>)>, Error> {
+    if pos >= data.len() {
+        return Ok(None);
+    }
+    if data[pos] != 0xFF {
+        return Err(Error::NotABlock { start_byte: data[pos] });
+    }
+    let mut kind_pos = pos + 1;
+    while data.get(kind_pos) == Some(&0xFF) {
+        kind_pos += 1;
+    }
+    let kind = match data.get(kind_pos) {
+        Some(&kind) => kind,
+        None => return Ok(None),
+    };
+    match kind {
+        0xD0..=0xD9 => Ok(Some((kind, kind_pos + 1..kind_pos + 1))),
+        _ => {
+            let length_bytes = data.get(kind_pos + 1..kind_pos + 3)
+                .ok_or(Error::Io(io::ErrorKind::UnexpectedEof.into()))?;
+            let length = usize::from(u16::from_be_bytes(length_bytes.try_into().unwrap()));
+            if length < 2 {
+                return Err(Error::BlockTooShort { min_expected: 2, obtained: length });
+            }
+            let payload_range = kind_pos + 3..kind_pos + 1 + length;
+            if payload_range.end > data.len() {
+                return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            Ok(Some((kind, payload_range)))
+        },
+    }
+}
+
+
+/// A segment of an image: a marker along with its payload, as returned by [`Image::segments`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Segment<'a> {
+    /// The type of the marker, i.e. the byte following 0xFF.
+    pub marker: u8,
+    /// The data following the length field; empty for markers without one.
+    pub payload: &'a [u8],
+    /// The entropy-coded data following a start-of-scan segment; empty for other segments.
+    pub scan_data: &'a [u8],
+}
+impl<'a> Segment<'a> {
+    pub fn kind(&self) -> SegmentKind {
+        SegmentKind::from_marker(self.marker)
+    }
+
+    /// The value of the length field, which counts the payload and the length field itself, or
+    /// `None` for markers without one.
+    pub fn length(&self) -> Option<usize> {
+        if (0xD0..=0xD9).contains(&self.marker) {
+            None
+        } else {
+            Some(self.payload.len() + 2)
+        }
+    }
+
+    /// The identifier at the start of the payload of an application segment (APPn), i.e. the
+    /// bytes up to the first NUL byte, e.g. `b"Exif"` or `b"ICC_PROFILE"`. Returns `None` for
+    /// other segments and for application segments without an identifier.
+    pub fn identifier(&self) -> Option<&'a [u8]> {
+        if !(0xE0..=0xEF).contains(&self.marker) {
+            return None;
+        }
+        let nul_pos = self.payload.iter().position(|&b| b == 0x00)?;
+        Some(&self.payload[..nul_pos])
+    }
+
+    /// Whether the segment is needed to decode the image, as opposed to optional metadata.
+    pub fn is_required(&self) -> bool {
+        !(0xE0..=0xFE).contains(&self.marker)
+    }
+
+    /// Classifies an optional segment by the kind of metadata it contains. Returns `None` for
+    /// required segments.
+    pub fn metadata_kind(&self) -> Option<MetadataKind> {
+        metadata_kind_of(self.marker, self.payload)
+    }
+}
+
+/// The kind of a segment, as determined by its marker.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum SegmentKind {
+    /// SOI
+    StartOfImage,
+    /// SOF0 to SOF15, describing the frame encoded in the given way.
+    StartOfFrame(FrameType),
+    /// DHT
+    DefineHuffmanTables,
+    /// DAC
+    DefineArithmeticConditioning,
+    /// DQT
+    DefineQuantizationTables,
+    /// DRI
+    DefineRestartInterval,
+    /// SOS
+    StartOfScan,
+    /// RST0 to RST7, with the given number.
+    Restart(u8),
+    /// APP0 to APP15, with the given number.
+    Application(u8),
+    /// COM
+    Comment,
+    /// EOI
+    EndOfImage,
+    /// Any other marker.
+    Other(u8),
+}
+impl SegmentKind {
+    pub fn from_marker(marker: u8) -> Self {
+        match marker {
+            0xD8 => Self::StartOfImage,
+            0xC4 => Self::DefineHuffmanTables,
+            0xCC => Self::DefineArithmeticConditioning,
+            0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF => Self::StartOfFrame(FrameType::from_base_type(marker)),
+            0xDB => Self::DefineQuantizationTables,
+            0xDD => Self::DefineRestartInterval,
+            0xDA => Self::StartOfScan,
+            0xD0..=0xD7 => Self::Restart(marker - 0xD0),
+            0xE0..=0xEF => Self::Application(marker - 0xE0),
+            0xFE => Self::Comment,
+            0xD9 => Self::EndOfImage,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// An iterator over the segments of an image, as returned by [`Image::segments`].
+#[derive(Clone, Debug)]
+pub struct Segments<'a> {
+    image: &'a Image,
+    leading_index: usize,
+    /// The position of the next marker within the image data, or `None` once it has been
+    /// exhausted.
+    image_data_pos: Option<usize>,
+    trailing_index: usize,
+}
+impl<'a> Segments<'a> {
+    fn next_in_image_data(&mut self, pos: usize) -> Result<Option<Segment<'a>>, Error> {
+        let data = self.image.image_data.as_slice();
+        let (marker, payload_range) = match marker_at(data, pos)? {
+            Some(marker) => marker,
+            None => return Ok(None),
+        };
+        let mut next_pos = payload_range.end;
+        let mut scan_data: &[u8] = &[];
+        if marker == 0xDA {
+            let scan_end = entropy_coded_end(data, next_pos);
+            scan_data = &data[next_pos..scan_end];
+            next_pos = scan_end;
+        }
+        self.image_data_pos = Some(next_pos);
+        Ok(Some(Segment {
+            marker,
+            payload: &data[payload_range],
+            scan_data,
+        }))
+    }
+}
+impl<'a> Iterator for Segments<'a> {
+    type Item = Result<Segment<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let image = self.image;
+        if let Some(block) = image.leading_blocks.get(self.leading_index) {
+            self.leading_index += 1;
+            // the image data directly follows the start-of-scan block ending the leading blocks
+            let scan_data = if self.leading_index == image.leading_blocks.len() && block.kind() == 0xDA {
+                let scan_end = entropy_coded_end(&image.image_data, 0);
+                self.image_data_pos = Some(scan_end);
+                &image.image_data[..scan_end]
+            } else {
+                &[]
+            };
+            return Some(Ok(Segment {
+                marker: block.kind(),
+                payload: block.data(),
+                scan_data,
+            }));
+        }
+
+        if let Some(pos) = self.image_data_pos {
+            match self.next_in_image_data(pos) {
+                Ok(Some(segment)) => return Some(Ok(segment)),
+                Ok(None) => self.image_data_pos = None,
+                Err(e) => {
+                    // do not continue after damaged data
+                    self.image_data_pos = None;
+                    self.trailing_index = image.trailing_blocks.len();
+                    return Some(Err(e));
+                },
+            }
+        }
+
+        let block = image.trailing_blocks.get(self.trailing_index)?;
+        self.trailing_index += 1;
+        Some(Ok(Segment {
+            marker: block.kind(),
+            payload: block.data(),
+            scan_data: &[],
+        }))
+    }
+}
+
+
+/// A kind of damage worked around while reading an image.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Repair {
+    GarbageBeforeStart { length: usize },
+    GarbageBetweenBlocks { offset: usize, length: usize },
+    BlockLengthCorrected { kind: u8, declared: usize, corrected: usize },
+    BlockRemoved { kind: u8, reason: &'static str },
+    GarbageAfterEnd { length: usize },
+    EndAdded,
+}
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GarbageBeforeStart { length }
+                => write!(f, "skipped {} bytes before start of image", length),
+            Self::GarbageBetweenBlocks { offset, length }
+                => write!(f, "skipped {} bytes between blocks at offset {}", length, offset),
+            Self::BlockLengthCorrected { kind, declared, corrected }
+                => write!(f, "corrected length of block 0x{:02X} from {} to {} bytes", kind, declared, corrected),
+            Self::BlockRemoved { kind, reason }
+                => write!(f, "removed block 0x{:02X}: {}", kind, reason),
+            Self::GarbageAfterEnd { length }
+                => write!(f, "skipped {} bytes after end of image", length),
+            Self::EndAdded
+                => write!(f, "added missing end-of-image marker (image data may be truncated)"),
+        }
+    }
+}
+
+
+/// The kind of Start-of-Frame block, which determines how the image is encoded.
+#[derive(Clone, Copy, Debug)]
+#[from_to_other(base_type = u8, derive_compare = "as_int")]
+pub enum FrameType {
+    Baseline = 0xC0,
+    ExtendedSequential = 0xC1,
+    Progressive = 0xC2,
+    Lossless = 0xC3,
+    DifferentialSequential = 0xC5,
+    DifferentialProgressive = 0xC6,
+    DifferentialLossless = 0xC7,
+    ExtendedSequentialArithmetic = 0xC9,
+    ProgressiveArithmetic = 0xCA,
+    LosslessArithmetic = 0xCB,
+    DifferentialSequentialArithmetic = 0xCD,
+    DifferentialProgressiveArithmetic = 0xCE,
+    DifferentialLosslessArithmetic = 0xCF,
+    Other(u8),
+}
+impl FrameType {
+    /// Whether the image is encoded using arithmetic coding instead of Huffman coding.
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            Self::ExtendedSequentialArithmetic|Self::ProgressiveArithmetic|Self::LosslessArithmetic
+                |Self::DifferentialSequentialArithmetic|Self::DifferentialProgressiveArithmetic
+                |Self::DifferentialLosslessArithmetic
+        )
+    }
+
+    /// Whether the image is encoded losslessly instead of using the discrete cosine transform.
+    pub fn is_lossless(&self) -> bool {
+        matches!(
+            self,
+            Self::Lossless|Self::DifferentialLossless|Self::LosslessArithmetic|Self::DifferentialLosslessArithmetic
+        )
+    }
+
+    /// Whether the image is encoded hierarchically (as differences between frames).
+    pub fn is_differential(&self) -> bool {
+        matches!(
+            self,
+            Self::DifferentialSequential|Self::DifferentialProgressive|Self::DifferentialLossless
+                |Self::DifferentialSequentialArithmetic|Self::DifferentialProgressiveArithmetic
+                |Self::DifferentialLosslessArithmetic
+        )
+    }
+
+    /// Whether PDF viewers can be expected to decode the image using the `/DCTDecode` filter.
+    ///
+    /// Only sequential and progressive DCT with Huffman coding is widely supported.
+    pub fn is_supported_by_dct_decode(&self) -> bool {
+        matches!(self, Self::Baseline|Self::ExtendedSequential|Self::Progressive)
+    }
+
+    /// Returns a human-readable description of the encoding process.
+    pub fn description(&self) -> String {
+        if let Self::Other(kind) = self {
+            return format!("unknown frame type 0x{:02X}", kind);
+        }
+
+        let process = match self {
+            Self::Baseline => "baseline sequential DCT",
+            Self::ExtendedSequential|Self::DifferentialSequential
+                |Self::ExtendedSequentialArithmetic|Self::DifferentialSequentialArithmetic
+                => "extended sequential DCT",
+            Self::Progressive|Self::DifferentialProgressive
+                |Self::ProgressiveArithmetic|Self::DifferentialProgressiveArithmetic
+                => "progressive DCT",
+            Self::Lossless|Self::DifferentialLossless
+                |Self::LosslessArithmetic|Self::DifferentialLosslessArithmetic
+                => "lossless",
+            Self::Other(_) => unreachable!(),
+        };
+        let coding = if self.is_arithmetic() { "arithmetic coding" } else { "Huffman coding" };
+        let hierarchy = if self.is_differential() { ", differential (hierarchical)" } else { "" };
+        format!("{}, {}{} (SOF{})", process, coding, hierarchy, self.to_base_type() - 0xC0)
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 #[from_to_other(base_type = u8, derive_compare = "as_int")]
@@ -353,3 +1210,20 @@ pub enum ColorSpace {
     Cmyk = 4,
     Other(u8),
 }
+
+/// How the color components were transformed before encoding, as recorded in an Adobe (APP14)
+/// block.
+///
+/// Adobe applications store four-component images with inverted values, regardless of the
+/// transform.
+#[derive(Clone, Copy, Debug)]
+#[from_to_other(base_type = u8, derive_compare = "as_int")]
+pub enum AdobeTransform {
+    /// The components are stored as they are (RGB or CMYK).
+    Untransformed = 0,
+    /// RGB converted to YCbCr.
+    YCbCr = 1,
+    /// CMYK with the CMY components converted to YCbCr (YCCK).
+    Ycck = 2,
+    Other(u8),
+}
@@ -0,0 +1,311 @@
+//! Conversion of JPEG images into PDF documents without recompressing the image data.
+//!
+//! The conversion works on in-memory buffers and does not touch the file system, which allows it
+//! to be used on platforms without one (such as `wasm32-unknown-unknown`). The command-line tool
+//! is built on top of it and requires the `cli` feature.
+
+
+pub mod archive;
+pub mod caption;
+pub mod content;
+pub mod deflate;
+pub mod encrypt;
+pub mod exif;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod font;
+pub mod icc;
+pub mod inflate;
+pub mod jpeg;
+pub mod lint;
+pub mod nup;
+pub mod optimize;
+pub mod page_numbers;
+pub mod pdf;
+pub mod preflight;
+pub mod resample;
+pub mod srgb;
+pub mod stamp;
+pub mod thumbnail;
+pub mod tiff;
+pub mod tile;
+pub mod toc;
+pub mod verify;
+
+
+use std::fmt;
+use std::io::{self, Cursor};
+use std::sync::Arc;
+
+use crate::content::Operator;
+use crate::jpeg::{ColorSpace, DensityUnit, FrameType};
+
+
+/// The largest page dimension allowed in default user space units; larger pages are scaled using
+/// `/UserUnit`.
+pub const MAX_PAGE_DIMENSION: u64 = 14400;
+
+/// The name and version of this library, as recorded in the documents it creates.
+pub const PRODUCER: &str = concat!("jpeg2pdf ", env!("CARGO_PKG_VERSION"));
+
+
+#[derive(Debug)]
+pub enum ConvertError {
+    Jpeg(jpeg::Error),
+    UnsupportedFrameType(FrameType),
+    UnsupportedBitDepth(u8),
+    UnsupportedColorSpace(u8),
+    NoDensityUnit,
+    UnknownDensityUnit(u8),
+    ZeroDensity,
+    Pdf(pdf::BuildError),
+    Io(io::Error),
+    Read(io::Error),
+}
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jpeg(e)
+                => write!(f, "failed to read JPEG data: {}", e),
+            Self::UnsupportedFrameType(frame_type)
+                => write!(f, "JPEG encoding {} is not supported by PDF viewers; only baseline, extended sequential and progressive DCT with Huffman coding", frame_type.description()),
+            Self::UnsupportedBitDepth(bit_depth)
+                => write!(f, "JPEG bit depth {} is not supported; only 8 bits per component", bit_depth),
+            Self::UnsupportedColorSpace(color_space)
+                => write!(f, "color space {} is not supported (only 1=Grayscale, 2=RGB, 3=CMYK)", color_space),
+            Self::NoDensityUnit
+                => write!(f, "no density unit specified; don't know how to size page"),
+            Self::UnknownDensityUnit(unit)
+                => write!(f, "unknown density unit {}", unit),
+            Self::ZeroDensity
+                => write!(f, "density of 0 specified; don't know how to size page"),
+            Self::Pdf(e)
+                => write!(f, "failed to assemble PDF document: {}", e),
+            Self::Io(e)
+                => write!(f, "failed to write PDF document: {}", e),
+            Self::Read(e)
+                => write!(f, "failed to read JPEG data: {}", e),
+        }
+    }
+}
+impl std::error::Error for ConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Jpeg(e) => Some(e),
+            Self::UnsupportedFrameType(_) => None,
+            Self::UnsupportedBitDepth(_) => None,
+            Self::UnsupportedColorSpace(_) => None,
+            Self::NoDensityUnit => None,
+            Self::UnknownDensityUnit(_) => None,
+            Self::ZeroDensity => None,
+            Self::Pdf(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Read(e) => Some(e),
+        }
+    }
+}
+impl From<jpeg::Error> for ConvertError {
+    fn from(value: jpeg::Error) -> Self { Self::Jpeg(value) }
+}
+impl From<pdf::BuildError> for ConvertError {
+    fn from(value: pdf::BuildError) -> Self { Self::Pdf(value) }
+}
+impl From<io::Error> for ConvertError {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+
+
+/// Checks whether the image data can be embedded into a PDF document as-is.
+pub fn check_encoding(jpeg: &jpeg::Image) -> Result<(), ConvertError> {
+    if !jpeg.frame_type.is_supported_by_dct_decode() {
+        return Err(ConvertError::UnsupportedFrameType(jpeg.frame_type));
+    }
+    if jpeg.bit_depth != 8 {
+        return Err(ConvertError::UnsupportedBitDepth(jpeg.bit_depth));
+    }
+    Ok(())
+}
+
+/// Returns the number of components of the given color space.
+pub fn component_count(color_space: ColorSpace) -> Result<usize, ConvertError> {
+    match color_space {
+        ColorSpace::Grayscale => Ok(1),
+        ColorSpace::Rgb => Ok(3),
+        ColorSpace::Cmyk => Ok(4),
+        ColorSpace::Other(n) => Err(ConvertError::UnsupportedColorSpace(n)),
+    }
+}
+
+/// Calculates the page size of the given image in PDF points from its density.
+pub fn page_size_pt(jpeg: &jpeg::Image) -> Result<(u64, u64), ConvertError> {
+    // default user space unit: 1/72 inch (Adobe point)
+    match jpeg.density_unit {
+        DensityUnit::NoUnit => Err(ConvertError::NoDensityUnit),
+        DensityUnit::Other(u) => Err(ConvertError::UnknownDensityUnit(u)),
+        _ if jpeg.density_x == 0 || jpeg.density_y == 0 => Err(ConvertError::ZeroDensity),
+        DensityUnit::DotsPerInch => {
+            let width_pt = (u64::from(jpeg.width) * 72) / u64::from(jpeg.density_x);
+            let height_pt = (u64::from(jpeg.height) * 72) / u64::from(jpeg.density_y);
+            Ok((width_pt, height_pt))
+        },
+        DensityUnit::DotsPerCentimeter => {
+            let width_pt = (u64::from(jpeg.width) * 7200) / (u64::from(jpeg.density_x) * 254);
+            let height_pt = (u64::from(jpeg.height) * 7200) / (u64::from(jpeg.density_y) * 254);
+            Ok((width_pt, height_pt))
+        },
+    }
+}
+
+/// Returns the user space unit required for a page of the given size, or `None` if the default
+/// unit suffices.
+pub fn user_unit_for(width_pt: u64, height_pt: u64) -> Option<u64> {
+    let user_unit = width_pt.max(height_pt).div_ceil(MAX_PAGE_DIMENSION);
+    if user_unit > 1 {
+        Some(user_unit)
+    } else {
+        None
+    }
+}
+
+/// A standard paper size, from which the density of a scan that does not declare one can be
+/// inferred.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PaperSize {
+    /// ISO 216 A4, 210x297 mm.
+    A4,
+    /// US Letter, 8.5x11 in.
+    Letter,
+}
+impl PaperSize {
+    /// The width and height of the paper in portrait orientation, in inches.
+    pub fn size_in(&self) -> (f64, f64) {
+        match self {
+            Self::A4 => (210.0 / 25.4, 297.0 / 25.4),
+            Self::Letter => (8.5, 11.0),
+        }
+    }
+}
+
+/// Infers the density in dots per inch at which an image of the given size in pixels was scanned,
+/// assuming it shows a sheet of the given paper size.
+///
+/// The orientation of the paper is the one closest to the aspect ratio of the image. If the aspect
+/// ratios differ, the density is chosen such that the image fits onto the sheet. Returns the
+/// density and whether the paper is in landscape orientation.
+pub fn infer_density(width_px: u16, height_px: u16, paper_size: PaperSize) -> (u16, bool) {
+    let (short_in, long_in) = paper_size.size_in();
+    let landscape = width_px > height_px;
+    let (width_in, height_in) = if landscape { (long_in, short_in) } else { (short_in, long_in) };
+    let density = (f64::from(width_px) / width_in).max(f64::from(height_px) / height_in);
+    let density = (density.round() as u16).max(1);
+    (density, landscape)
+}
+
+
+/// Converts a JPEG image into a single-page PDF document, both held in memory.
+///
+/// The page is sized according to the density stored in the image. The result is what the
+/// `convert` subcommand writes without options, with two differences: an image without a density
+/// unit is rejected with `ConvertError::NoDensityUnit` instead of being assumed to have 72 dpi,
+/// and an Exif thumbnail is kept instead of being removed (as with `--keep-exif-thumbnail`).
+pub fn convert_bytes(jpeg_data: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    let jpeg = jpeg::Image::try_read(jpeg_data)?;
+    convert_image(jpeg)
+}
+
+/// Converts a JPEG image that has already been read into a single-page PDF document held in
+/// memory, as `convert_bytes` does.
+///
+/// This allows the image to be read with `jpeg::Image::try_read_with_limits`, e.g. when it comes
+/// from an untrusted source.
+pub fn convert_image(jpeg: jpeg::Image) -> Result<Vec<u8>, ConvertError> {
+    check_encoding(&jpeg)?;
+    let (width_pt, height_pt) = page_size_pt(&jpeg)?;
+
+    let mut builder = pdf::DocumentBuilder::new(pdf::Compression::None);
+    let page_ref = builder.reserve_page();
+    let color_space = jpeg.color_space;
+    let image = pdf::ImageXObject::from_jpeg_image(Arc::new(jpeg), color_space)
+        .ok_or(ConvertError::UnsupportedColorSpace(color_space.into()))?;
+    let image_ref = builder.add(image);
+
+    let mut operators = vec![
+        Operator::SaveState,
+        Operator::ConcatMatrix(content::scale_translate(width_pt as f64, height_pt as f64, 0.0, 0.0)),
+        Operator::DrawXObject("Im0".to_owned()),
+        Operator::RestoreState,
+    ];
+    let user_unit = user_unit_for(width_pt, height_pt);
+    let (width_units, height_units) = match user_unit {
+        Some(user_unit) => {
+            let scale = 1.0 / (user_unit as f64);
+            let mut scaled_operators = vec![
+                Operator::SaveState,
+                Operator::ConcatMatrix(content::scale_translate(scale, scale, 0.0, 0.0)),
+            ];
+            scaled_operators.append(&mut operators);
+            scaled_operators.push(Operator::RestoreState);
+            operators = scaled_operators;
+            (width_pt.div_ceil(user_unit), height_pt.div_ceil(user_unit))
+        },
+        None => (width_pt, height_pt),
+    };
+
+    builder.fill_page(page_ref, pdf::NewPage {
+        width: width_units,
+        height: height_units,
+        user_unit,
+        crop_box: None,
+        rotate: None,
+        struct_parents: None,
+        annotation_ids: Vec::new(),
+        transition: None,
+        display_duration_ms: None,
+        images: vec![image_ref],
+        fonts: Vec::new(),
+        ext_g_states: Vec::new(),
+        optional_content_groups: Vec::new(),
+        operators,
+    });
+    builder.add(pdf::Info {
+        producer: Some(PRODUCER.to_owned()),
+        creator: None,
+        creation_date: None,
+        mod_date: None,
+    });
+    let document = builder.build()?;
+
+    let mut output = Cursor::new(Vec::new());
+    document.write(&mut output)?;
+    Ok(output.into_inner())
+}
+
+/// Converts a JPEG image read from the given reader into a single-page PDF document written to the
+/// given writer, as `convert_bytes` does.
+///
+/// This does not stream: the whole image is read into memory before the conversion starts, and
+/// the whole document is held in memory until it has been converted. The conversion runs on the
+/// blocking thread pool of the runtime (see `tokio::task::spawn_blocking`), so this must be called
+/// from within a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn convert_async<R, W>(mut reader: R, mut writer: W) -> Result<(), ConvertError>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut jpeg_data = Vec::new();
+    reader.read_to_end(&mut jpeg_data).await
+        .map_err(ConvertError::Read)?;
+    let pdf_data = match tokio::task::spawn_blocking(move || convert_bytes(&jpeg_data)).await {
+        Ok(result) => result?,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        // the runtime is shutting down
+        Err(e) => return Err(ConvertError::Io(io::Error::other(e))),
+    };
+    writer.write_all(&pdf_data).await?;
+    writer.flush().await?;
+    Ok(())
+}
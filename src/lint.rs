@@ -0,0 +1,305 @@
+//! Checking of JPEG images for deviations from the standards and other peculiarities which are
+//! tolerated when reading an image but may explain why it is displayed differently than expected.
+
+
+use std::fmt;
+
+use crate::jpeg::{Block, DensityUnit, Image, ImageBuilder};
+
+
+/// Comments longer than this (in bytes) are reported; they probably contain data instead of text.
+pub const MAX_COMMENT_LENGTH: usize = 4096;
+
+/// By how much (relative to the larger value) the JFIF density and the Exif resolution may differ
+/// before they are considered inconsistent, to allow for rounding.
+const RESOLUTION_TOLERANCE: f64 = 0.01;
+
+
+/// How serious a finding is.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Not a problem by itself, but worth knowing, e.g. an embedded thumbnail.
+    Note,
+    /// A deviation from the standards which decoders usually tolerate, possibly in different ways.
+    Warning,
+    /// Damage or a contradiction which decoders cannot resolve reliably.
+    Error,
+}
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Note => "note",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// The check which led to a finding.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Check {
+    /// The image is damaged and could only be read by working around the damage.
+    Damage,
+    /// A segment is in a place where the standards do not allow it.
+    MarkerOrder,
+    /// The image has more than one frame header (SOF).
+    DuplicateFrame,
+    /// The density in the JFIF header differs from the resolution in the Exif metadata.
+    InconsistentResolution,
+    /// The image contains a thumbnail of itself.
+    EmbeddedThumbnail,
+    /// A comment segment is suspiciously large.
+    OversizedComment,
+}
+impl Check {
+    /// The name of the check, as output in machine-readable reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Damage => "damage",
+            Self::MarkerOrder => "marker-order",
+            Self::DuplicateFrame => "duplicate-frame",
+            Self::InconsistentResolution => "inconsistent-resolution",
+            Self::EmbeddedThumbnail => "embedded-thumbnail",
+            Self::OversizedComment => "oversized-comment",
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            Self::Damage => Severity::Error,
+            Self::MarkerOrder => Severity::Warning,
+            Self::DuplicateFrame => Severity::Error,
+            Self::InconsistentResolution => Severity::Warning,
+            Self::EmbeddedThumbnail => Severity::Note,
+            Self::OversizedComment => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Finding {
+    pub check: Check,
+    pub message: String,
+}
+impl Finding {
+    pub fn new(check: Check, message: String) -> Self {
+        Self { check, message }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.check.severity()
+    }
+}
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.severity().name(), self.message, self.check.name())
+    }
+}
+
+
+fn is_frame_header(kind: u8) -> bool {
+    matches!(kind, 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF)
+}
+
+fn is_jfif_header(block: &Block) -> bool {
+    block.kind() == 0xE0 && block.data().starts_with(b"JFIF\0")
+}
+
+fn is_exif(block: &Block) -> bool {
+    block.kind() == 0xE1 && block.data().starts_with(b"Exif\0\0")
+}
+
+/// Converts a density to dots per inch, or `None` if it has no physical unit.
+fn dots_per_inch(unit: DensityUnit, density: u16) -> Option<f64> {
+    match unit {
+        DensityUnit::DotsPerInch => Some(f64::from(density)),
+        DensityUnit::DotsPerCentimeter => Some(f64::from(density) * 2.54),
+        DensityUnit::NoUnit|DensityUnit::Other(_) => None,
+    }
+}
+
+fn describe_density(unit: DensityUnit, x: u16, y: u16) -> String {
+    match unit {
+        DensityUnit::NoUnit => format!("{}x{} (no unit)", x, y),
+        DensityUnit::DotsPerInch => format!("{}x{} dpi", x, y),
+        DensityUnit::DotsPerCentimeter => format!("{}x{} dots per cm", x, y),
+        DensityUnit::Other(u) => format!("{}x{} (unknown unit {})", x, y, u),
+    }
+}
+
+/// Checks the order of the segments before the first scan and between the scans.
+fn check_marker_order(image: &Image, findings: &mut Vec<Finding>) {
+    // the first leading block is always the start-of-image marker
+    let leading = &image.leading_blocks[1..];
+    for block in leading {
+        match block.kind() {
+            0xD0..=0xD7 => findings.push(Finding::new(
+                Check::MarkerOrder,
+                format!("restart marker RST{} outside of the image data", block.kind() - 0xD0),
+            )),
+            0xD8 => findings.push(Finding::new(
+                Check::MarkerOrder,
+                "start-of-image marker (SOI) repeated".to_owned(),
+            )),
+            0xDC => findings.push(Finding::new(
+                Check::MarkerOrder,
+                "number-of-lines segment (DNL) before the first scan".to_owned(),
+            )),
+            _ => {},
+        }
+    }
+
+    let jfif_positions: Vec<usize> = leading.iter()
+        .enumerate()
+        .filter(|(_index, block)| is_jfif_header(block))
+        .map(|(index, _block)| index)
+        .collect();
+    if jfif_positions.first().is_some_and(|&index| index != 0) {
+        findings.push(Finding::new(
+            Check::MarkerOrder,
+            "JFIF header (APP0) does not directly follow the start-of-image marker".to_owned(),
+        ));
+    }
+    if jfif_positions.len() > 1 {
+        findings.push(Finding::new(
+            Check::MarkerOrder,
+            format!("JFIF header (APP0) repeated {} times", jfif_positions.len()),
+        ));
+    }
+
+    // Exif follows the start-of-image marker directly, or the JFIF header if there is one
+    let exif_position = leading.iter().position(is_exif);
+    let expected_exif_position = usize::from(leading.first().is_some_and(is_jfif_header));
+    if exif_position.is_some_and(|index| index != expected_exif_position) {
+        findings.push(Finding::new(
+            Check::MarkerOrder,
+            "Exif metadata (APP1) does not directly follow the start-of-image marker or the JFIF header".to_owned(),
+        ));
+    }
+
+    if let Ok(scans) = image.scans() {
+        for (index, scan) in scans.iter().enumerate().skip(1) {
+            for block in &scan.blocks {
+                if (0xD0..=0xD8).contains(&block.kind()) {
+                    findings.push(Finding::new(
+                        Check::MarkerOrder,
+                        format!("marker 0xFF{:02X} between scans {} and {}", block.kind(), index, index + 1),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_frame_headers(image: &Image, findings: &mut Vec<Finding>) {
+    let scan_blocks: Vec<Block> = image.scans()
+        .map(|scans| scans.into_iter().flat_map(|scan| scan.blocks).collect())
+        .unwrap_or_default();
+    let frame_header_count = image.leading_blocks.iter()
+        .chain(scan_blocks.iter())
+        .filter(|block| is_frame_header(block.kind()))
+        .count();
+    if frame_header_count > 1 {
+        findings.push(Finding::new(
+            Check::DuplicateFrame,
+            format!("{} frame headers (SOF) instead of one; decoders may disagree on the dimensions and encoding", frame_header_count),
+        ));
+    }
+}
+
+/// Reads the Exif metadata of the image, if it has any.
+fn read_exif(image: &Image) -> Option<ImageBuilder> {
+    let block = image.leading_blocks.iter()
+        .find(|block| is_exif(block))?;
+    let mut exif_builder = ImageBuilder::new();
+    crate::exif::process(block.data(), &mut exif_builder).ok()?;
+    Some(exif_builder)
+}
+
+fn check_resolution(image: &Image, exif: Option<&ImageBuilder>, findings: &mut Vec<Finding>) {
+    let jfif_density = image.leading_blocks.iter()
+        .find(|block| is_jfif_header(block) && block.data().len() >= 12)
+        .map(|block| {
+            let data = block.data();
+            (
+                DensityUnit::from_base_type(data[7]),
+                u16::from_be_bytes(data[8..10].try_into().unwrap()),
+                u16::from_be_bytes(data[10..12].try_into().unwrap()),
+            )
+        });
+    let exif_density = exif.and_then(|exif| match (exif.exif_density_unit, exif.exif_density_x, exif.exif_density_y) {
+        (Some(unit), Some(x), Some(y)) => Some((unit, x, y)),
+        _ => None,
+    });
+    let (Some((jfif_unit, jfif_x, jfif_y)), Some((exif_unit, exif_x, exif_y))) = (jfif_density, exif_density) else {
+        return;
+    };
+
+    let differs = |jfif_value: u16, exif_value: u16| {
+        match (dots_per_inch(jfif_unit, jfif_value), dots_per_inch(exif_unit, exif_value)) {
+            (Some(jfif_dpi), Some(exif_dpi)) => (jfif_dpi - exif_dpi).abs() > RESOLUTION_TOLERANCE * jfif_dpi.max(exif_dpi),
+            // without a unit, only the aspect ratio can be compared
+            _ => u32::from(jfif_x) * u32::from(exif_y) != u32::from(jfif_y) * u32::from(exif_x),
+        }
+    };
+    if differs(jfif_x, exif_x) || differs(jfif_y, exif_y) {
+        findings.push(Finding::new(
+            Check::InconsistentResolution,
+            format!(
+                "JFIF density {} differs from Exif resolution {}",
+                describe_density(jfif_unit, jfif_x, jfif_y), describe_density(exif_unit, exif_x, exif_y),
+            ),
+        ));
+    }
+}
+
+fn check_thumbnails(image: &Image, exif: Option<&ImageBuilder>, findings: &mut Vec<Finding>) {
+    for block in &image.leading_blocks {
+        let data = block.data();
+        if is_jfif_header(block) && data.len() >= 14 && data[12] > 0 && data[13] > 0 {
+            findings.push(Finding::new(
+                Check::EmbeddedThumbnail,
+                format!("JFIF header contains a {}x{} thumbnail", data[12], data[13]),
+            ));
+        } else if block.kind() == 0xE0 && data.starts_with(b"JFXX\0") {
+            findings.push(Finding::new(
+                Check::EmbeddedThumbnail,
+                "JFIF extension segment (JFXX) contains a thumbnail".to_owned(),
+            ));
+        }
+    }
+    if exif.is_some_and(|exif| exif.exif_has_thumbnail) {
+        findings.push(Finding::new(
+            Check::EmbeddedThumbnail,
+            "Exif metadata contains a thumbnail".to_owned(),
+        ));
+    }
+}
+
+fn check_comments(image: &Image, findings: &mut Vec<Finding>) {
+    for block in image.leading_blocks.iter().chain(image.trailing_blocks.iter()) {
+        if block.kind() == 0xFE && block.data().len() > MAX_COMMENT_LENGTH {
+            findings.push(Finding::new(
+                Check::OversizedComment,
+                format!("comment segment (COM) of {} bytes", block.data().len()),
+            ));
+        }
+    }
+}
+
+/// Checks the image for deviations from the JPEG, JFIF and Exif standards and other peculiarities
+/// and returns what was found, most serious first.
+///
+/// Damage which was worked around while reading the image is not known to the image; it can be
+/// reported as findings of [`Check::Damage`].
+pub fn lint(image: &Image) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    check_marker_order(image, &mut findings);
+    check_frame_headers(image, &mut findings);
+    let exif = read_exif(image);
+    check_resolution(image, exif.as_ref(), &mut findings);
+    check_thumbnails(image, exif.as_ref(), &mut findings);
+    check_comments(image, &mut findings);
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity()));
+    findings
+}
@@ -0,0 +1,55 @@
+//! Output of log messages to standard error, their level chosen by the number of `--verbose`
+//! options.
+//!
+//! Each message is a line of `key=value` pairs prefixed with the time elapsed since the start of
+//! the run, its level and the module it originates from, e.g.
+//! `[   0.012s DEBUG jpeg2pdf::pdf] object id=3 kind=page offset=1234 length=210`.
+
+
+use std::io::Write;
+use std::time::Instant;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+
+struct StderrLogger {
+    start: Instant,
+}
+impl Log for StderrLogger {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        // filtered by the maximum level
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        // a failure to log is no reason to fail the conversion
+        let _ = writeln!(
+            std::io::stderr().lock(),
+            "[{:9.3}s {:5} {}] {}",
+            elapsed, record.level(), record.target(), record.args(),
+        );
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+
+/// Outputs log messages up to the level corresponding to the given verbosity: none at 0, stages
+/// and their timing at 1, parsed segments and written objects at 2, everything at 3 and above.
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => return,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let logger = StderrLogger {
+        start: Instant::now(),
+    };
+    log::set_boxed_logger(Box::new(logger))
+        .expect("logger already initialized");
+    log::set_max_level(level);
+}
@@ -1,105 +1,1336 @@
-mod exif;
-mod jpeg;
-mod pdf;
+mod assemble;
+mod batch;
+mod config;
+mod fetch;
+mod inspect;
+mod logging;
+mod manifest;
+mod opts;
+mod report;
+mod watch;
 
 
-use std::collections::BTreeMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::CommandFactory;
 
-use crate::jpeg::{ColorSpace, DensityUnit};
+use jpeg2pdf::{exif, jpeg, lint, nup, optimize, pdf, preflight, resample, srgb, thumbnail, tile, toc};
+use jpeg2pdf::jpeg::{ColorSpace, DensityUnit, MetadataKind};
 
+use crate::assemble::{assemble_document, crop_area_pt, DocumentAssembler, PageOverview};
+use crate::opts::{
+    parse_opts, BookletFlip, Collate, Command, DecodeArray, DocumentOpts, ExportFormat, ExportTiffOpts, ImageOpts,
+    MergeOpts, NUpLayout, Opts, PixelAspect, PixelRect, ReportOpts, Rotation, Seconds,
+};
 
-#[derive(Parser)]
-struct Opts {
-    #[arg(short, long)]
-    remove_optional_metadata: bool,
 
-    input_jpeg_path: PathBuf,
-    output_pdf_path: PathBuf,
+/// The size of a blank page if there is no other page to take the size from (A4 portrait).
+const DEFAULT_BLANK_PAGE_SIZE_PT: (u64, u64) = (595, 842);
+
+
+/// Approximately how many bytes the structure of a PDF file takes up apart from its pages.
+const DOCUMENT_OVERHEAD_BYTES: u64 = 16 * 1024;
+
+/// Approximately how many bytes each page takes up apart from its image data.
+const PAGE_OVERHEAD_BYTES: u64 = 2 * 1024;
+
+/// How much the brightness of an 8x8 block must differ from the background for it to count as ink.
+const BLANK_MIN_DIFFERENCE: u8 = 24;
+
+/// How many times wider than tall an image must be for --detect-spreads to take it for a
+/// double-page spread. Single pages are rarely wider than tall, spreads of two portrait pages
+/// around 1.4 times.
+const SPREAD_ASPECT_RATIO: f64 = 1.2;
+
+
+/// Returns the paths of the images of the given input: the input itself or, if it is an archive,
+/// the JPEG images within it in reading order.
+fn input_image_paths(path: &Path, from_cbz: bool) -> Result<Vec<PathBuf>, String> {
+    let Some(kind) = fetch::archive_kind(path, from_cbz) else {
+        return Ok(vec![path.to_owned()]);
+    };
+    let image_paths = fetch::archive_image_paths(path, kind)
+        .map_err(|e| format!("failed to read archive {}: {}", path.display(), e))?;
+    if image_paths.is_empty() {
+        return Err(format!("archive {} contains no JPEG images", path.display()));
+    }
+    Ok(image_paths)
+}
+
+/// Reads a JPEG image from a file, an archive or an HTTP(S) URL, returning it along with the repairs that were
+/// made to it.
+fn read_jpeg(path: &Path, repair: bool, timeout: Seconds) -> Result<(jpeg::Image, Vec<jpeg::Repair>), String> {
+    let timeout = Duration::from_millis(timeout.milliseconds.into());
+    let jpeg_file = fetch::open(path, timeout)
+        .map_err(|e| format!("failed to open input JPEG file {}: {}", path.display(), e))?;
+    let mut repairs = Vec::new();
+    let start = Instant::now();
+    let result = if repair {
+        jpeg::Image::try_read_repairing(jpeg_file, &mut repairs)
+    } else {
+        jpeg::Image::try_read(jpeg_file)
+    };
+    let jpeg = result
+        .map_err(|e| format!("failed to read JPEG file {}: {}", path.display(), e))?;
+    log::info!(
+        "read path={} width={} height={} bytes={} repairs={} elapsed={:.3}s",
+        path.display(), jpeg.width, jpeg.height, jpeg.encoded_len(), repairs.len(), start.elapsed().as_secs_f64(),
+    );
+    Ok((jpeg, repairs))
 }
 
+/// Reads a JPEG image and checks it for problems. Damage which prevents reading the image is
+/// reported as well, along with the problems of the image as repaired.
+fn lint_jpeg(path: &Path, timeout: Seconds) -> Vec<lint::Finding> {
+    let timeout = Duration::from_millis(timeout.milliseconds.into());
+    let mut data = Vec::new();
+    fetch::open(path, timeout)
+        .and_then(|mut jpeg_file| Ok(jpeg_file.read_to_end(&mut data)?))
+        .unwrap_or_else(|e| panic!("failed to open input JPEG file {}: {}", path.display(), e));
 
-fn main() {
-    let opts = Opts::parse();
+    let error = match jpeg::Image::try_read(data.as_slice()) {
+        Ok(jpeg) => return lint::lint(&jpeg),
+        Err(e) => e,
+    };
+    let mut findings = vec![lint::Finding::new(lint::Check::Damage, format!("cannot be read without repairs: {}", error))];
+    let mut repairs = Vec::new();
+    match jpeg::Image::try_read_repairing(data.as_slice(), &mut repairs) {
+        Ok(jpeg) => {
+            for repair in &repairs {
+                findings.push(lint::Finding::new(lint::Check::Damage, format!("repairable: {}", repair)));
+            }
+            findings.extend(lint::lint(&jpeg));
+        },
+        Err(e) => findings.push(lint::Finding::new(lint::Check::Damage, format!("cannot be read even with repairs: {}", e))),
+    }
+    findings
+}
 
-    // crunch the JPEG
-    let jpeg_file = File::open(&opts.input_jpeg_path)
-        .expect("failed to open input JPEG file");
-    let mut jpeg = jpeg::Image::try_read(jpeg_file)
-        .expect("failed to read JPEG file");
+/// Gives an image without a density unit a density in dots per inch: the one inferred from the
+/// given paper size or, without one, 72 dpi.
+///
+/// The stored densities still describe the pixel aspect ratio, which is kept: the lower density is
+/// replaced by the assumed one and the other is scaled accordingly. If either stored density is
+/// zero, the pixels are taken to be square. Returns whether the image was taken to be landscape
+/// when inferring the density.
+fn assume_density(jpeg: &mut jpeg::Image, infer_for: Option<jpeg2pdf::PaperSize>) -> Option<bool> {
+    let (ratio_x, ratio_y) = if jpeg.density_x == 0 || jpeg.density_y == 0 {
+        (1.0, 1.0)
+    } else {
+        let lower = f64::from(jpeg.density_x.min(jpeg.density_y));
+        (f64::from(jpeg.density_x) / lower, f64::from(jpeg.density_y) / lower)
+    };
+    let (density, landscape) = match infer_for {
+        Some(paper_size) => {
+            // the size in pixels the image would have with square pixels at the lower density
+            let square_width = (f64::from(jpeg.width) / ratio_x).round().max(1.0) as u16;
+            let square_height = (f64::from(jpeg.height) / ratio_y).round().max(1.0) as u16;
+            let (density, landscape) = jpeg2pdf::infer_density(square_width, square_height, paper_size);
+            (density, Some(landscape))
+        },
+        None => (72, None),
+    };
+    let scale = |ratio: f64| (f64::from(density) * ratio).round().min(f64::from(u16::MAX)) as u16;
+    jpeg.density_unit = DensityUnit::DotsPerInch;
+    jpeg.density_x = scale(ratio_x);
+    jpeg.density_y = scale(ratio_y);
+    landscape
+}
 
-    if jpeg.bit_depth != 8 {
-        panic!("JPEG bit depth {} is not supported; only 8 bits per component", jpeg.bit_depth);
+fn read_jpeg_for_pdf(input_index: usize, path: &Path, image_opts: &ImageOpts, report: &mut report::Report) -> Result<Vec<OutputPage>, String> {
+    let (mut jpeg, repairs) = read_jpeg(path, image_opts.repair, image_opts.timeout)?;
+    for repair in &repairs {
+        report.warn(input_index, format!("repaired: {}", repair));
+    }
+    report.input_mut(input_index).properties = Some(report::ImageProperties::of(&jpeg));
+    // determined before the metadata is stripped
+    let date = image_date(path, &jpeg);
+    if let Some(density) = image_opts.dpi {
+        // only affects the page size; the image data retains its own density
+        jpeg.density_unit = DensityUnit::DotsPerInch;
+        jpeg.density_x = density.x;
+        jpeg.density_y = density.y;
+    } else if matches!(jpeg.density_unit, DensityUnit::NoUnit) && image_opts.width.is_none() && image_opts.height.is_none() {
+        let landscape = assume_density(&mut jpeg, image_opts.infer_dpi_for);
+        let density = if jpeg.density_x == jpeg.density_y {
+            jpeg.density_x.to_string()
+        } else {
+            format!("{}x{}", jpeg.density_x, jpeg.density_y)
+        };
+        match (image_opts.infer_dpi_for, landscape) {
+            (Some(paper_size), Some(landscape)) => report.note(input_index, format!(
+                "no density unit specified; inferred {} dpi from {:?} {}",
+                density, paper_size, if landscape { "landscape" } else { "portrait" },
+            )),
+            _ => report.warn(input_index, format!("no density unit specified; assumed {} dpi", density)),
+        }
     }
-    if let ColorSpace::Other(n) = jpeg.color_space {
-        panic!("color space {} is not supported (only 1=Grayscale, 2=RGB, 3=CMYK)", n);
+    if image_opts.rotate.is_none() {
+        match jpeg.exif_orientation {
+            None|Some(1) => {},
+            Some(3) => report.warn(input_index, "Exif orientation ignored; --rotate 180 would display the image upright".to_owned()),
+            Some(6) => report.warn(input_index, "Exif orientation ignored; --rotate 90 would display the image upright".to_owned()),
+            Some(8) => report.warn(input_index, "Exif orientation ignored; --rotate 270 would display the image upright".to_owned()),
+            Some(other) => report.warn(input_index, format!("Exif orientation {} (mirrored) ignored", other)),
+        }
     }
 
-    if opts.remove_optional_metadata {
-        // remove unimportant leading blocks
-        jpeg.leading_blocks.retain(|b| b.is_required());
+    jpeg2pdf::check_encoding(&jpeg)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let color_space = match image_opts.assume_colorspace {
+        Some(assumed) => assumed.to_color_space(),
+        None => jpeg.color_space,
+    };
+    let component_count = jpeg2pdf::component_count(color_space)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if let Some(decode_array) = &image_opts.decode_array {
+        if decode_array.0.len() != 2 * component_count {
+            return Err(format!(
+                "{}: decode array has {} values, but the color space has {} components, requiring {}",
+                path.display(), decode_array.0.len(), component_count, 2 * component_count,
+            ));
+        }
+    }
+    if image_opts.pixel_aspect == PixelAspect::Square && jpeg.density_x != jpeg.density_y {
+        let (original_width, original_height) = (jpeg.width, jpeg.height);
+        let start = Instant::now();
+        let result = resample::resample_to_square(&jpeg);
+        log::info!("resample path={} elapsed={:.3}s", path.display(), start.elapsed().as_secs_f64());
+        match result {
+            Ok(Some(resampled)) => {
+                report.note(input_index, format!(
+                    "resampled from {}x{} to {}x{} pixels for square pixels",
+                    original_width, original_height, resampled.width, resampled.height,
+                ));
+                jpeg = resampled;
+            },
+            Ok(None) => {},
+            Err(e) => report.note(input_index, format!("not resampled to square pixels: {}", e)),
+        }
     }
 
-    // default user space unit: 1/72 inch (Adobe point)
-    let (width_pt, height_pt) = match jpeg.density_unit {
-        DensityUnit::NoUnit => panic!("no density unit specified; don't know how to size page"),
-        DensityUnit::Other(u) => panic!("unknown density unit {}", u),
-        DensityUnit::DotsPerInch => {
-            let width_pt = (u64::from(jpeg.width) * 72) / u64::from(jpeg.density_x);
-            let height_pt = (u64::from(jpeg.height) * 72) / u64::from(jpeg.density_y);
-            (width_pt, height_pt)
-        },
-        DensityUnit::DotsPerCentimeter => {
-            let width_pt = (u64::from(jpeg.width) * 7200) / (u64::from(jpeg.density_x) * 254);
-            let height_pt = (u64::from(jpeg.height) * 7200) / (u64::from(jpeg.density_y) * 254);
-            (width_pt, height_pt)
+    let full_size_pt = match (image_opts.width, image_opts.height) {
+        (None, None) => jpeg2pdf::page_size_pt(&jpeg)
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        (width, height) => {
+            // the density is only used for the aspect ratio, which works without a unit
+            if jpeg.density_x == 0 || jpeg.density_y == 0 {
+                return Err(format!("{}: {}", path.display(), jpeg2pdf::ConvertError::ZeroDensity));
+            }
+            let aspect_width = f64::from(jpeg.width) / f64::from(jpeg.density_x);
+            let aspect_height = f64::from(jpeg.height) / f64::from(jpeg.density_y);
+            let is_spread = image_opts.detect_spreads
+                && aspect_width >= SPREAD_ASPECT_RATIO * aspect_height;
+            if is_spread {
+                report.note(input_index, "treated as a double-page spread".to_owned());
+            }
+            let width = width.map(|w| if is_spread { 2.0 * w.pt } else { w.pt });
+            let (width_pt, height_pt) = match (width, height) {
+                (Some(w), Some(h)) => (w, h.pt),
+                (Some(w), None) => (w, w * aspect_height / aspect_width),
+                (None, Some(h)) => (h.pt * aspect_width / aspect_height, h.pt),
+                (None, None) => unreachable!(),
+            };
+            ((width_pt.round() as u64).max(1), (height_pt.round() as u64).max(1))
         },
     };
 
-    // PDF document structure:
-    // 1 = catalog
-    // 2 = pages
-    // 3 = page
-    // 4 = page resources
-    // 5 = page contents
-    // 6 = image
+    if image_opts.convert_to_srgb && jpeg.has_icc_profile() {
+        let start = Instant::now();
+        let result = srgb::convert_to_srgb(&jpeg);
+        log::info!("convert-to-srgb path={} elapsed={:.3}s", path.display(), start.elapsed().as_secs_f64());
+        match result {
+            Ok(Some(converted)) => {
+                report.note(input_index, "converted to sRGB".to_owned());
+                jpeg = converted;
+            },
+            Ok(None) => {},
+            Err(e) => report.note(input_index, format!("not converted to sRGB: {}", e)),
+        }
+    }
 
-    let catalog = pdf::ObjectData::Catalog(pdf::Catalog {
-        root_page_id: 2,
-    });
-    let pages = pdf::ObjectData::Pages(pdf::Pages {
-        page_ids: vec![3],
-    });
-    let page = pdf::ObjectData::Page(pdf::Page {
-        parent_id: 2,
-        resources_id: 4,
-        contents_id: 5,
-        width_pt,
-        height_pt,
-    });
-    let resources = pdf::ObjectData::PageResources(pdf::PageResources {
-        image_xobject_ids: vec![6],
-    });
-    let contents = pdf::ObjectData::PageContents(pdf::PageContents {
-        commands: format!("q {} 0 0 {} 0 0 cm /Im0 Do Q", width_pt, height_pt),
+    // remove unimportant leading blocks as requested
+    let remove_block = |block: &jpeg::Block| match block.metadata_kind() {
+        None => false,
+        Some(kind) if !image_opts.keep.is_empty() => !image_opts.keep.contains(&kind),
+        Some(kind) => image_opts.remove_optional_metadata || image_opts.strip.contains(&kind),
+    };
+    for block in jpeg.leading_blocks.iter().filter(|b| remove_block(b)) {
+        if (0xE0..=0xEF).contains(&block.kind()) && block.metadata_kind() == Some(MetadataKind::Other) {
+            report.note(input_index, format!("unknown APP{} segment dropped", block.kind() - 0xE0));
+        }
+    }
+    jpeg.leading_blocks.retain(|b| !remove_block(b));
+    if !image_opts.keep_exif_thumbnail {
+        let saved = jpeg.remove_exif_thumbnail();
+        if saved > 0 {
+            report.note(input_index, format!("Exif thumbnail removed ({} bytes)", saved));
+        }
+    }
+    if image_opts.optimize_jpeg {
+        let start = Instant::now();
+        let result = optimize::optimize(&jpeg);
+        log::info!("optimize path={} elapsed={:.3}s", path.display(), start.elapsed().as_secs_f64());
+        match result {
+            Ok(optimized) => {
+                let original_len = jpeg.encoded_len();
+                let optimized_len = optimized.encoded_len();
+                if optimized_len < original_len {
+                    report.note(input_index, format!("optimized from {} to {} bytes", original_len, optimized_len));
+                    jpeg = optimized;
+                }
+            },
+            Err(e) => report.note(input_index, format!("not optimized: {}", e)),
+        }
+    }
+
+    let crop = image_opts.crop
+        .map(|c| c.to_pixel_rect(jpeg.width.into(), jpeg.height.into()))
+        .transpose()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut tiles = Vec::new();
+    if let Some(tile_size) = image_opts.tile_size {
+        if jpeg.width > tile_size || jpeg.height > tile_size {
+            let start = Instant::now();
+            let result = tile::split(&jpeg, tile_size, tile_size);
+            log::info!("split-tiles path={} elapsed={:.3}s", path.display(), start.elapsed().as_secs_f64());
+            match result {
+                Ok(all_tiles) => {
+                    report.note(input_index, format!("split into {} tiles", all_tiles.len()));
+                    tiles = all_tiles.into_iter()
+                        // tiles outside the crop area would never be visible
+                        .filter(|t| crop.is_none_or(|c| tile_overlaps(t.left, t.top, &t.image, &c)))
+                        .map(|t| ImageTile {
+                            left: t.left,
+                            top: t.top,
+                            jpeg: Arc::new(t.image),
+                        })
+                        .collect();
+                },
+                Err(e) => report.warn(input_index, format!("not split into tiles: {}", e)),
+            }
+        }
+    }
+
+    let alt_text = if image_opts.alt_text_sidecar {
+        let sidecar_path = path.with_extension("alt.txt");
+        match std::fs::read_to_string(&sidecar_path) {
+            Ok(text) => Some(text.trim().to_owned()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => image_opts.alt_text.clone(),
+            Err(e) => return Err(format!("failed to read alternative text file {}: {}", sidecar_path.display(), e)),
+        }
+    } else {
+        image_opts.alt_text.clone()
+    };
+
+    // a tall image becomes several pages, each showing a slice of it
+    let mut page_crops = vec![crop];
+    if let Some(paginate_height) = image_opts.paginate_height {
+        let area = crop.unwrap_or(PixelRect {
+            left: 0,
+            top: 0,
+            width: jpeg.width.into(),
+            height: jpeg.height.into(),
+        });
+        let slice_height_px = paginate_height.to_pixels(jpeg.height.into(), full_size_pt.1);
+        let slices = slice_area(&area, slice_height_px);
+        if slices.len() > 1 {
+            report.note(input_index, format!("paginated into {} pages", slices.len()));
+            page_crops = slices.into_iter().map(Some).collect();
+        }
+    }
+
+    let color_key_mask = image_opts.transparent_color.and_then(|transparent_color| {
+        let color_key_mask = transparent_color.to_color_key_mask(color_space, jpeg.bit_depth);
+        if color_key_mask.is_none() {
+            report.warn(input_index, "transparent colors are not supported for CMYK images; ignored".to_owned());
+        }
+        color_key_mask
     });
-    let image = pdf::ObjectData::ImageXObject(pdf::ImageXObject::from_jpeg_image(&jpeg).unwrap());
 
-    let mut pdf = pdf::Document {
-        objects: BTreeMap::new(),
+    let jpeg = Arc::new(jpeg);
+    let output_pages: Vec<OutputPage> = page_crops.into_iter()
+        .map(|page_crop| OutputPage::Image(ImagePage {
+            input_index,
+            path: path.to_owned(),
+            jpeg: Arc::clone(&jpeg),
+            color_space,
+            decode_array: image_opts.decode_array.clone(),
+            color_key_mask: color_key_mask.clone(),
+            interpolate: image_opts.interpolate,
+            rendering_intent: image_opts.rendering_intent,
+            full_size_pt,
+            crop: page_crop,
+            tiles: tiles.iter()
+                .filter(|t| page_crop.is_none_or(|c| tile_overlaps(t.left, t.top, &t.jpeg, &c)))
+                .cloned()
+                .collect(),
+            rotation: image_opts.rotate,
+            alt_text: alt_text.clone(),
+            date: date.clone(),
+        }))
+        .collect();
+    report.input_mut(input_index).page_size_pt = Some(output_pages[0].size_pt());
+    report.check_strict(input_index)?;
+    Ok(output_pages)
+}
+
+/// Determines when the image was taken: according to its Exif metadata or, failing that, when
+/// the file was last modified (in UTC). Formatted as `YYYY-MM-DD HH:MM:SS`.
+fn image_date(path: &Path, jpeg: &jpeg::Image) -> Option<String> {
+    if let Some(date_time) = exif::date_time(jpeg) {
+        // Exif separates the parts of the date with colons too
+        return Some(date_time.replacen(':', "-", 2));
+    }
+    let since_epoch = std::fs::metadata(path).ok()?
+        .modified().ok()?
+        .duration_since(std::time::UNIX_EPOCH).ok()?;
+    let date = pdf::Date::from_unix_time(i64::try_from(since_epoch.as_secs()).ok()?);
+    Some(format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        date.year, date.month, date.day, date.hour, date.minute, date.second,
+    ))
+}
+
+/// A page of the output document showing an image.
+struct ImagePage {
+    /// The index of the image in the report.
+    input_index: usize,
+    path: PathBuf,
+    /// Shared with the image XObject, which writes it out directly.
+    jpeg: Arc<jpeg::Image>,
+    /// The color space of the image, which may differ from the one the image declares.
+    color_space: ColorSpace,
+    decode_array: Option<DecodeArray>,
+    /// See [`pdf::ImageXObject::color_key_mask`].
+    color_key_mask: Option<Vec<u16>>,
+    interpolate: bool,
+    rendering_intent: Option<pdf::RenderingIntent>,
+    /// The size of the whole image in PDF points, before cropping.
+    full_size_pt: (u64, u64),
+    crop: Option<PixelRect>,
+    /// The parts into which the image has been split, or empty if the image is drawn whole.
+    tiles: Vec<ImageTile>,
+    rotation: Option<Rotation>,
+    alt_text: Option<String>,
+    /// When the image was taken, as shown in its caption.
+    date: Option<String>,
+}
+
+/// A part of an image, drawn at its position within the image.
+#[derive(Clone)]
+struct ImageTile {
+    /// The horizontal position of the tile within the image, in pixels from the left.
+    left: u16,
+    /// The vertical position of the tile within the image, in pixels from the top.
+    top: u16,
+    jpeg: Arc<jpeg::Image>,
+}
+
+/// Whether any part of the tile at the given position lies within the given area.
+fn tile_overlaps(left: u16, top: u16, tile_image: &jpeg::Image, area: &PixelRect) -> bool {
+    let left = u64::from(left);
+    let top = u64::from(top);
+    left < area.left + area.width && area.left < left + u64::from(tile_image.width)
+        && top < area.top + area.height && area.top < top + u64::from(tile_image.height)
+}
+
+/// Divides the given area of an image into horizontal slices of the given height, from the top;
+/// the last slice may be shorter.
+fn slice_area(area: &PixelRect, slice_height_px: u64) -> Vec<PixelRect> {
+    (area.top..area.top + area.height)
+        .step_by(usize::try_from(slice_height_px).unwrap_or(usize::MAX))
+        .map(|top| PixelRect {
+            left: area.left,
+            top,
+            width: area.width,
+            height: slice_height_px.min(area.top + area.height - top),
+        })
+        .collect()
+}
+
+/// A page of the output document.
+enum OutputPage {
+    Image(ImagePage),
+    Blank { width_pt: u64, height_pt: u64 },
+    TableOfContents(toc::TocPage),
+    /// A page stating the given message in place of an image that could not be converted.
+    Placeholder { message: String },
+    /// Image and blank pages drawn side by side onto one sheet (see --nup), each along with its
+    /// cell on the sheet as `(left, bottom, width, height)`.
+    Sheet { width_pt: u64, height_pt: u64, cells: Vec<(OutputPage, (f64, f64, f64, f64))> },
+}
+impl OutputPage {
+    pub fn size_pt(&self) -> (u64, u64) {
+        match self {
+            Self::Image(ImagePage { full_size_pt, crop: None, .. }) => *full_size_pt,
+            Self::Image(ImagePage { jpeg, full_size_pt, crop: Some(crop), .. }) => {
+                let (_left_pt, _bottom_pt, width_pt, height_pt) = crop_area_pt(jpeg, *full_size_pt, crop);
+                (width_pt.round() as u64, height_pt.round() as u64)
+            },
+            Self::Blank { width_pt, height_pt }|Self::Sheet { width_pt, height_pt, .. } => (*width_pt, *height_pt),
+            Self::TableOfContents(_)|Self::Placeholder { .. } => DEFAULT_BLANK_PAGE_SIZE_PT,
+        }
+    }
+
+    /// Estimates how many bytes this page adds to the PDF file.
+    pub fn estimated_size(&self) -> u64 {
+        let image_size = match self {
+            Self::Image(ImagePage { jpeg, tiles, .. }) if tiles.is_empty() => u64::try_from(jpeg.encoded_len()).unwrap(),
+            Self::Image(ImagePage { tiles, .. }) => tiles.iter()
+                .map(|t| u64::try_from(t.jpeg.encoded_len()).unwrap())
+                .sum(),
+            Self::Sheet { cells, .. } => cells.iter()
+                .map(|(cell, _area)| cell.estimated_size() - PAGE_OVERHEAD_BYTES)
+                .sum(),
+            Self::Blank { .. }|Self::TableOfContents(_)|Self::Placeholder { .. } => 0,
+        };
+        PAGE_OVERHEAD_BYTES + image_size
+    }
+}
+
+/// Distributes the pages onto multiple documents as requested.
+fn split_pages(output_pages: Vec<OutputPage>, merge_opts: &MergeOpts, report: &mut report::Report) -> Vec<Vec<OutputPage>> {
+    let max_pages = merge_opts.split_every
+        .map(|n| usize::try_from(n).unwrap_or(usize::MAX))
+        .unwrap_or(usize::MAX);
+    let max_size = merge_opts.split_max_size
+        .map(|mb| (mb * 1_000_000.0) as u64)
+        .unwrap_or(u64::MAX);
+
+    let mut documents = Vec::new();
+    let mut current_pages = Vec::new();
+    let mut current_size = DOCUMENT_OVERHEAD_BYTES;
+    for output_page in output_pages {
+        let page_size = output_page.estimated_size();
+        let full = current_pages.len() >= max_pages
+            || (current_size + page_size > max_size && !current_pages.is_empty());
+        if full {
+            documents.push(std::mem::take(&mut current_pages));
+            current_size = DOCUMENT_OVERHEAD_BYTES;
+        }
+        if DOCUMENT_OVERHEAD_BYTES + page_size > max_size {
+            if let OutputPage::Image(ImagePage { input_index, .. }) = &output_page {
+                report.warn(*input_index, "image alone exceeds the maximum file size".to_owned());
+            }
+        }
+        current_size += page_size;
+        current_pages.push(output_page);
+    }
+    if !current_pages.is_empty() {
+        documents.push(current_pages);
+    }
+    documents
+}
+
+/// Inserts a number between the stem and the extension of a path, e.g. `scans_001.pdf`.
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}_{:03}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}_{:03}", stem, number),
     };
-    pdf.objects.insert(1, catalog);
-    pdf.objects.insert(2, pages);
-    pdf.objects.insert(3, page);
-    pdf.objects.insert(4, resources);
-    pdf.objects.insert(5, contents);
-    pdf.objects.insert(6, image);
-
-    let output = File::create(&opts.output_pdf_path)
-        .expect("failed to create output PDF file");
-    pdf.write(output)
-        .expect("failed to write output PDF file");
+    path.with_file_name(file_name)
+}
+
+/// Orders the pages of the output document as requested and inserts blank pages, returning why
+/// this is impossible if it is.
+fn arrange_pages(image_pages: Vec<OutputPage>, merge_opts: &MergeOpts, report: &mut report::Report) -> Result<Vec<OutputPage>, String> {
+    let mut output_pages: Vec<OutputPage> = match merge_opts.collate {
+        Collate::InOrder => image_pages,
+        Collate::DuplexReverseBacks => {
+            // fronts in order, followed by backs in reverse order
+            if !image_pages.len().is_multiple_of(2) {
+                return Err(format!("duplex collation requires an even number of images; obtained {}", image_pages.len()));
+            }
+            let mut fronts = image_pages;
+            let backs = fronts.split_off(fronts.len() / 2);
+            let mut output_pages = Vec::with_capacity(2 * fronts.len());
+            for (front, back) in fronts.into_iter().zip(backs.into_iter().rev()) {
+                output_pages.push(front);
+                output_pages.push(back);
+            }
+            output_pages
+        },
+    };
+
+    if merge_opts.skip_blank || merge_opts.skip_duplicates {
+        output_pages = skip_pages(output_pages, merge_opts, report);
+    }
+
+    // insert blank pages from the back so that the page numbers stay valid
+    let mut insert_after = merge_opts.insert_blank_after.clone();
+    insert_after.sort_unstable();
+    for &after_page in insert_after.iter().rev() {
+        if after_page > output_pages.len() {
+            return Err(format!("cannot insert a blank page after page {}; only {} pages", after_page, output_pages.len()));
+        }
+
+        let (width_pt, height_pt) = match &merge_opts.blank_page_size {
+            Some(size) => (size.width_pt, size.height_pt),
+            None => {
+                // same size as the preceding page (or the following page if there is none)
+                let neighbor_index = after_page.saturating_sub(1);
+                output_pages.get(neighbor_index)
+                    .map(|p| p.size_pt())
+                    .unwrap_or(DEFAULT_BLANK_PAGE_SIZE_PT)
+            },
+        };
+        output_pages.insert(after_page, OutputPage::Blank { width_pt, height_pt });
+    }
+
+    if merge_opts.booklet {
+        output_pages = booklet_order(output_pages, merge_opts.booklet_flip);
+        output_pages = impose_pages(output_pages, NUpLayout { columns: 2, rows: 1 }, merge_opts);
+    } else if let Some(layout) = merge_opts.nup {
+        output_pages = impose_pages(output_pages, layout, merge_opts);
+    }
+
+    if merge_opts.toc {
+        output_pages = prepend_table_of_contents(output_pages);
+    }
+
+    Ok(output_pages)
+}
+
+/// Reorders the given pages into printer spreads for a booklet, padded with blank pages to a
+/// multiple of four: the last and the first page, then the second and the second-to-last page,
+/// and so on, alternating between the front and the back of each sheet of paper.
+fn booklet_order(output_pages: Vec<OutputPage>, flip: BookletFlip) -> Vec<OutputPage> {
+    let (width_pt, height_pt) = output_pages.last()
+        .map(|p| p.size_pt())
+        .unwrap_or(DEFAULT_BLANK_PAGE_SIZE_PT);
+    let page_count = output_pages.len().next_multiple_of(4);
+    let mut pages: Vec<Option<OutputPage>> = output_pages.into_iter()
+        .map(Some)
+        .collect();
+    pages.resize_with(page_count, || Some(OutputPage::Blank { width_pt, height_pt }));
+
+    let mut spreads = Vec::with_capacity(page_count);
+    for side in 0..page_count / 2 {
+        let (left, right) = if side % 2 == 0 {
+            (page_count - 1 - side, side)
+        } else {
+            (side, page_count - 1 - side)
+        };
+        let mut left_page = pages[left].take().unwrap();
+        let mut right_page = pages[right].take().unwrap();
+        if side % 2 == 1 && flip == BookletFlip::LongEdge {
+            // the back side is printed upside down, which swaps the pages and turns them around
+            for page in [&mut left_page, &mut right_page] {
+                if let OutputPage::Image(ImagePage { rotation, .. }) = page {
+                    let degrees = rotation.map_or(0, |r| r.degrees);
+                    *rotation = Some(Rotation { degrees: (degrees + 180) % 360 });
+                }
+            }
+            std::mem::swap(&mut left_page, &mut right_page);
+        }
+        spreads.push(left_page);
+        spreads.push(right_page);
+    }
+    spreads
+}
+
+/// Groups the given pages onto sheets in the given layout, filling each sheet row by row.
+fn impose_pages(output_pages: Vec<OutputPage>, layout: NUpLayout, merge_opts: &MergeOpts) -> Vec<OutputPage> {
+    let (width_pt, height_pt) = match merge_opts.nup_sheet_size {
+        Some(size) => (size.width_pt, size.height_pt),
+        None => {
+            // A4, in landscape if the layout is wider than it is tall
+            let (short_pt, long_pt) = DEFAULT_BLANK_PAGE_SIZE_PT;
+            if layout.columns > layout.rows {
+                (long_pt, short_pt)
+            } else {
+                (short_pt, long_pt)
+            }
+        },
+    };
+    let cell_areas = nup::cell_areas(layout.columns, layout.rows, width_pt as f64, height_pt as f64, merge_opts.nup_gutter.pt);
+
+    let mut sheets = Vec::new();
+    let mut output_pages = output_pages.into_iter().peekable();
+    while output_pages.peek().is_some() {
+        let cells = cell_areas.iter()
+            .map_while(|&area| Some((output_pages.next()?, area)))
+            .collect();
+        sheets.push(OutputPage::Sheet { width_pt, height_pt, cells });
+    }
+    sheets
+}
+
+/// Inserts table-of-contents pages listing the images before the given pages.
+fn prepend_table_of_contents(output_pages: Vec<OutputPage>) -> Vec<OutputPage> {
+    let make_entries = |page_index_offset: usize| -> Vec<toc::TocEntry> {
+        output_pages.iter()
+            .enumerate()
+            .flat_map(|(page_index, page)| match page {
+                // each image on a sheet has its own entry
+                OutputPage::Sheet { cells, .. } => cells.iter()
+                    .map(|(cell, _area)| (page_index, cell))
+                    .collect(),
+                _ => vec![(page_index, page)],
+            })
+            .filter_map(|(page_index, page)| match page {
+                OutputPage::Image(ImagePage { path, .. }) => Some(toc::TocEntry {
+                    title: path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned(),
+                    page_index: page_index_offset + page_index,
+                }),
+                _ => None,
+            })
+            .collect()
+    };
+
+    // the number of table-of-contents pages only depends on the number of entries
+    let (_toc_width_pt, toc_height_pt) = DEFAULT_BLANK_PAGE_SIZE_PT;
+    let toc_page_count = toc::TocPage::paginate(make_entries(0), toc_height_pt as f64).len();
+    let toc_pages = toc::TocPage::paginate(make_entries(toc_page_count), toc_height_pt as f64);
+
+    let mut all_pages: Vec<OutputPage> = toc_pages.into_iter()
+        .map(OutputPage::TableOfContents)
+        .collect();
+    all_pages.extend(output_pages);
+    all_pages
+}
+
+/// Removes blank and duplicate images as requested and outputs a summary of what was omitted.
+fn skip_pages(output_pages: Vec<OutputPage>, merge_opts: &MergeOpts, report: &mut report::Report) -> Vec<OutputPage> {
+    let mut kept_pages = Vec::with_capacity(output_pages.len());
+    let mut skipped_blank = Vec::new();
+    let mut skipped_duplicate = Vec::new();
+    let mut previous_hash: Option<(PathBuf, [u64; 4])> = None;
+
+    for output_page in output_pages {
+        let (input_index, path, jpeg) = match &output_page {
+            OutputPage::Image(ImagePage { input_index, path, jpeg, .. }) => (*input_index, path, jpeg),
+            OutputPage::Blank { .. }|OutputPage::TableOfContents(_)|OutputPage::Placeholder { .. }|OutputPage::Sheet { .. } => {
+                kept_pages.push(output_page);
+                continue;
+            },
+        };
+        let thumbnail = match thumbnail::decode_thumbnail(jpeg) {
+            Ok(t) => t,
+            Err(e) => {
+                report.warn(input_index, format!("cannot check whether the image is blank or a duplicate: {}", e));
+                kept_pages.push(output_page);
+                continue;
+            },
+        };
+
+        if merge_opts.skip_blank {
+            let ink_percent = 100.0 * thumbnail.ink_coverage(BLANK_MIN_DIFFERENCE);
+            if ink_percent < merge_opts.blank_threshold {
+                skipped_blank.push(path.clone());
+                report.input_mut(input_index).status = report::InputStatus::SkippedBlank;
+                continue;
+            }
+        }
+
+        if merge_opts.skip_duplicates {
+            let hash = thumbnail.difference_hash();
+            if let Some((previous_path, previous_hash)) = &previous_hash {
+                if thumbnail::hash_distance(&hash, previous_hash) <= merge_opts.duplicate_threshold {
+                    skipped_duplicate.push((path.clone(), previous_path.clone()));
+                    report.input_mut(input_index).status = report::InputStatus::SkippedDuplicate {
+                        original_path: previous_path.clone(),
+                    };
+                    continue;
+                }
+            }
+            previous_hash = Some((path.clone(), hash));
+        }
+
+        kept_pages.push(output_page);
+    }
+
+    if merge_opts.skip_blank {
+        eprintln!("omitted {} blank image(s)", skipped_blank.len());
+        for path in &skipped_blank {
+            eprintln!("  {}", path.display());
+        }
+    }
+    if merge_opts.skip_duplicates {
+        eprintln!("omitted {} duplicate image(s)", skipped_duplicate.len());
+        for (path, original_path) in &skipped_duplicate {
+            eprintln!("  {} (duplicate of {})", path.display(), original_path.display());
+        }
+    }
+
+    kept_pages
+}
+
+
+/// Merges the input images into one PDF document (or several, if splitting is requested).
+fn merge(merge_opts: &MergeOpts, report: &mut report::Report) -> Result<(), String> {
+    if can_stream(merge_opts) {
+        // each image is written out before the next one is read
+        let mut inputs = Vec::new();
+        for input in &merge_opts.input_jpeg_paths {
+            let image_opts = input.image_opts(&merge_opts.image_opts);
+            for image_path in input_image_paths(&input.path, image_opts.from_cbz)? {
+                inputs.push((report.add_input(&image_path), image_path, image_opts.clone()));
+            }
+        }
+        stream_document(&inputs, &merge_opts.document_opts, &merge_opts.output_pdf_path, report)?;
+    } else {
+        let mut image_pages: Vec<OutputPage> = Vec::new();
+        for input in &merge_opts.input_jpeg_paths {
+            // the images of an archive are merged in its place
+            let image_opts = input.image_opts(&merge_opts.image_opts);
+            for image_path in input_image_paths(&input.path, image_opts.from_cbz)? {
+                let input_index = report.add_input(&image_path);
+                image_pages.extend(read_jpeg_for_pdf(input_index, &image_path, &image_opts, report)?);
+            }
+        }
+        let output_pages = arrange_pages(image_pages, merge_opts, report)?;
+        if merge_opts.split_every.is_some() || merge_opts.split_max_size.is_some() {
+            let documents = split_pages(output_pages, merge_opts, report);
+            for (index, document_pages) in documents.iter().enumerate() {
+                let path = numbered_path(&merge_opts.output_pdf_path, index + 1);
+                write_document(document_pages, &merge_opts.document_opts, &path, report)?;
+            }
+        } else {
+            write_document(&output_pages, &merge_opts.document_opts, &merge_opts.output_pdf_path, report)?;
+        }
+    }
+    // warnings that arise while the document is assembled
+    (0..report.inputs.len())
+        .try_for_each(|index| report.check_strict(index))
+}
+
+/// Converts the input of the given job into a PDF document, returning why the conversion failed
+/// if it did.
+///
+/// In strict mode, the conversion also fails if a warning arose about the input or any of the
+/// images in it, in which case the document may have been written already.
+fn convert_job(input_index: usize, job: &batch::Job, image_opts: &ImageOpts, document_opts: &DocumentOpts, report: &mut report::Report) -> Result<(), String> {
+    // the images of an archive are added to the report as they are found
+    let first_image_index = report.inputs.len();
+    convert_job_input(input_index, job, image_opts, document_opts, report)?;
+    std::iter::once(input_index)
+        .chain(first_image_index..report.inputs.len())
+        .try_for_each(|index| report.check_strict(index))
+}
+
+fn convert_job_input(input_index: usize, job: &batch::Job, image_opts: &ImageOpts, document_opts: &DocumentOpts, report: &mut report::Report) -> Result<(), String> {
+    if fetch::archive_kind(&job.input_path, image_opts.from_cbz).is_none() {
+        let output_pages = read_jpeg_for_pdf(input_index, &job.input_path, image_opts, report)?;
+        return write_document(&output_pages, document_opts, &job.output_path, report);
+    }
+
+    // an archive becomes a document with one page per image, each of which is reported separately
+    let image_paths = input_image_paths(&job.input_path, image_opts.from_cbz)?;
+    // the number of pages has to be known up front for streaming
+    if document_opts.verify || image_opts.paginate_height.is_some() {
+        let mut output_pages = Vec::new();
+        for image_path in image_paths {
+            let image_index = report.add_input(&image_path);
+            output_pages.extend(read_jpeg_for_pdf(image_index, &image_path, image_opts, report)?);
+        }
+        write_document(&output_pages, document_opts, &job.output_path, report)?;
+    } else {
+        let inputs: Vec<(usize, PathBuf, ImageOpts)> = image_paths.into_iter()
+            .map(|image_path| (report.add_input(&image_path), image_path, image_opts.clone()))
+            .collect();
+        stream_document(&inputs, document_opts, &job.output_path, report)?;
+    }
+    report.input_mut(input_index).status = report::InputStatus::Converted {
+        output_path: job.output_path.clone(),
+    };
+    Ok(())
+}
+
+/// Whether the pages of a merged document can be written out while the images are being read,
+/// which requires that neither the order nor the number of the pages depends on the images.
+fn can_stream(merge_opts: &MergeOpts) -> bool {
+    merge_opts.collate == Collate::InOrder
+        && merge_opts.insert_blank_after.is_empty()
+        && !merge_opts.toc
+        && !merge_opts.skip_blank
+        && !merge_opts.skip_duplicates
+        && merge_opts.split_every.is_none()
+        && merge_opts.split_max_size.is_none()
+        && merge_opts.image_opts.paginate_height.is_none()
+        && merge_opts.nup.is_none()
+        && !merge_opts.booklet
+        // verification re-reads the whole document
+        && !merge_opts.document_opts.verify
+}
+
+/// Reads the given images (along with their index in the report and their options) and writes a
+/// PDF document with one page per image to the given path, recording it in the report.
+///
+/// Each page is written out before the next image is read, so that only one image at a time is
+/// held in memory.
+fn stream_document(inputs: &[(usize, PathBuf, ImageOpts)], document_opts: &DocumentOpts, path: &Path, report: &mut report::Report) -> Result<(), String> {
+    let overview = PageOverview {
+        page_count: inputs.len(),
+        has_toc: false,
+        // whether a sidecar file exists only becomes known when its image is read
+        has_alt_text: inputs.iter()
+            .any(|(_input_index, _image_path, image_opts)| image_opts.alt_text.is_some() || image_opts.alt_text_sidecar),
+    };
+    let start = Instant::now();
+    let mut assembler = DocumentAssembler::new(&overview, document_opts)?;
+    if let Some(id_seed) = id_seed(document_opts, path) {
+        assembler.builder.set_id_seed(&id_seed);
+    }
+    let write_error = |e: std::io::Error| format!("failed to write output PDF file {}: {}", path.display(), e);
+    let output = File::create(path)
+        .map_err(|e| format!("failed to create output PDF file {}: {}", path.display(), e))?;
+    let mut writer = assembler.builder.start_writing(output)
+        .map_err(write_error)?;
+    for (input_index, image_path, image_opts) in inputs {
+        for output_page in read_jpeg_for_pdf(*input_index, image_path, image_opts, report)? {
+            assembler.add_page(&output_page, report);
+        }
+        assembler.builder.write_pending(&mut writer)
+            .map_err(write_error)?;
+    }
+    let manifest_pages = std::mem::take(&mut assembler.manifest_pages);
+    let mut output = assembler.finish()?
+        .write_rest(writer)
+        .map_err(|e| format!("failed to write output PDF file {}: {}", path.display(), e))?;
+    let size_bytes = output.stream_position()
+        .map_err(write_error)?;
+    log::info!(
+        "write path={} pages={} bytes={} elapsed={:.3}s",
+        path.display(), inputs.len(), size_bytes, start.elapsed().as_secs_f64(),
+    );
+
+    report.add_output(path, inputs.len(), size_bytes);
+    add_to_manifest(document_opts, path, size_bytes, manifest_pages, report)?;
+    for (input_index, _image_path, _image_opts) in inputs {
+        report.input_mut(*input_index).status = report::InputStatus::Converted {
+            output_path: path.to_owned(),
+        };
+    }
+    Ok(())
+}
+
+/// Assembles a PDF document from the given pages, writes it to the given path and records it in
+/// the report.
+fn write_document(output_pages: &[OutputPage], document_opts: &DocumentOpts, path: &Path, report: &mut report::Report) -> Result<(), String> {
+    let (mut pdf, manifest_pages) = assemble_document(output_pages, document_opts, report)?;
+    pdf.id_seed = id_seed(document_opts, path);
+    let size_bytes = write_pdf(&pdf, path)?;
+    if document_opts.verify {
+        let start = Instant::now();
+        let written = std::fs::read(path)
+            .map_err(|e| format!("failed to re-read output PDF file {}: {}", path.display(), e))?;
+        jpeg2pdf::verify::verify(&pdf, &written)
+            .map_err(|e| format!("verification of {} failed: {}", path.display(), e))?;
+        log::info!("verify path={} elapsed={:.3}s", path.display(), start.elapsed().as_secs_f64());
+    }
+    report.add_output(path, output_pages.len(), size_bytes);
+    add_to_manifest(document_opts, path, size_bytes, manifest_pages, report)?;
+    let sheet_cells = output_pages.iter()
+        .flat_map(|output_page| match output_page {
+            OutputPage::Sheet { cells, .. } => cells.iter().map(|(cell, _area)| cell).collect(),
+            other => vec![other],
+        });
+    for output_page in sheet_cells {
+        if let OutputPage::Image(ImagePage { input_index, .. }) = output_page {
+            report.input_mut(*input_index).status = report::InputStatus::Converted {
+                output_path: path.to_owned(),
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Returns the seed of the ID of the document written to the given path, if one has been given;
+/// the file name makes the IDs of the documents written by one run differ.
+fn id_seed(document_opts: &DocumentOpts, path: &Path) -> Option<Vec<u8>> {
+    let seed = document_opts.id_seed.as_ref()?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    Some(format!("{}\0{}", seed, file_name).into_bytes())
+}
+
+/// Adds the document just written to the given path to the manifest if one has been requested,
+/// taking the offsets of its objects from its cross-reference table, and rewrites the manifest
+/// file such that it is complete even if a later document fails.
+fn add_to_manifest(document_opts: &DocumentOpts, path: &Path, size_bytes: u64, pages: Vec<manifest::PageManifest>, report: &mut report::Report) -> Result<(), String> {
+    let Some(manifest_path) = &document_opts.manifest else { return Ok(()) };
+    let object_offsets = File::open(path)
+        .map_err(jpeg2pdf::verify::Error::Io)
+        .and_then(jpeg2pdf::verify::read_object_offsets)
+        .map_err(|e| format!("failed to read object offsets of {}: {}", path.display(), e))?;
+    report.manifest.push(manifest::DocumentManifest {
+        path: path.to_owned(),
+        size_bytes,
+        pages,
+        object_offsets,
+    });
+    manifest::write(manifest_path, &report.manifest)
+        .map_err(|e| format!("failed to write manifest file {}: {}", manifest_path.display(), e))
+}
+
+/// Writes the given document to the given path and returns its size in bytes.
+fn write_pdf(pdf: &pdf::Document, path: &Path) -> Result<u64, String> {
+    let start = Instant::now();
+    let write_error = |e: std::io::Error| format!("failed to write output PDF file {}: {}", path.display(), e);
+    let mut output = File::create(path)
+        .map_err(|e| format!("failed to create output PDF file {}: {}", path.display(), e))?;
+    pdf.write(&mut output)
+        .map_err(write_error)?;
+    let size_bytes = output.stream_position()
+        .map_err(write_error)?;
+    log::info!("write path={} bytes={} elapsed={:.3}s", path.display(), size_bytes, start.elapsed().as_secs_f64());
+    Ok(size_bytes)
+}
+
+/// The horizontal and vertical resolution in pixels per inch at which the given image fills a page
+/// of the given size.
+fn tiff_resolution(jpeg: &jpeg::Image, full_size_pt: (u64, u64)) -> (jpeg2pdf::tiff::Rational, jpeg2pdf::tiff::Rational) {
+    let rational = |numerator: u32, denominator: u32| jpeg2pdf::tiff::Rational { numerator, denominator }.reduced();
+    // the density is exact, while the page size has been rounded to whole points
+    if jpeg2pdf::page_size_pt(jpeg).is_ok_and(|size_pt| size_pt == full_size_pt) {
+        match jpeg.density_unit {
+            DensityUnit::DotsPerInch => return (
+                rational(jpeg.density_x.into(), 1),
+                rational(jpeg.density_y.into(), 1),
+            ),
+            DensityUnit::DotsPerCentimeter => return (
+                rational(u32::from(jpeg.density_x) * 254, 100),
+                rational(u32::from(jpeg.density_y) * 254, 100),
+            ),
+            DensityUnit::NoUnit|DensityUnit::Other(_) => {},
+        }
+    }
+    let (width_pt, height_pt) = full_size_pt;
+    (
+        rational(u32::from(jpeg.width) * 72, u32::try_from(width_pt).unwrap_or(u32::MAX)),
+        rational(u32::from(jpeg.height) * 72, u32::try_from(height_pt).unwrap_or(u32::MAX)),
+    )
+}
+
+/// Reads the given images and writes a multi-page TIFF file with one page per image, recording it
+/// in the report.
+///
+/// Each page is written out before the next image is read, so that only one image at a time is
+/// held in memory.
+fn export_tiff(export_opts: &ExportTiffOpts, report: &mut report::Report) {
+    let path = &export_opts.output_tiff_path;
+    let output = File::create(path)
+        .expect("failed to create output TIFF file");
+    let mut writer = jpeg2pdf::tiff::TiffWriter::new(std::io::BufWriter::new(output))
+        .expect("failed to write output TIFF file");
+    let mut input_indexes = Vec::new();
+    for input in &export_opts.input_jpeg_paths {
+        let mut image_opts = input.image_opts(&export_opts.image_opts);
+        // each image becomes exactly one page
+        let paginate = image_opts.paginate_height.take().is_some();
+        let image_paths = input_image_paths(&input.path, image_opts.from_cbz)
+            .unwrap_or_else(|e| panic!("{}", e));
+        for image_path in image_paths {
+            let input_index = report.add_input(&image_path);
+            let mut output_pages = read_jpeg_for_pdf(input_index, &image_path, &image_opts, report)
+                .unwrap_or_else(|e| panic!("{}", e));
+            let OutputPage::Image(image_page) = output_pages.remove(0) else {
+                unreachable!("reading an image yields an image page");
+            };
+
+            // only what TIFF can express is carried over from the page
+            if paginate {
+                report.warn(input_index, "pagination is not supported in TIFF output; the whole image is stored as one page".to_owned());
+            }
+            if image_page.crop.is_some() {
+                report.warn(input_index, "cropping is not supported in TIFF output; the whole image is stored".to_owned());
+            }
+            if image_page.decode_array.is_some() {
+                report.warn(input_index, "decode array is not supported in TIFF output; ignored".to_owned());
+            }
+            if image_page.color_key_mask.is_some() {
+                report.warn(input_index, "transparent colors are not supported in TIFF output; ignored".to_owned());
+            }
+            if image_page.interpolate || image_page.rendering_intent.is_some() {
+                report.warn(input_index, "interpolation and rendering intents are not supported in TIFF output; ignored".to_owned());
+            }
+            let description = image_page.alt_text.clone()
+                .filter(|text| {
+                    let is_ascii = text.is_ascii();
+                    if !is_ascii {
+                        report.warn(input_index, "alternative text is not ASCII and cannot be stored in TIFF; omitted".to_owned());
+                    }
+                    is_ascii
+                });
+            report.check_strict(input_index)
+                .unwrap_or_else(|e| panic!("{}", e));
+            let jpeg = &image_page.jpeg;
+            let resolution = tiff_resolution(jpeg, image_page.full_size_pt);
+            let orientation = match image_page.rotation.map(|r| r.degrees) {
+                Some(90) => 6,
+                Some(180) => 3,
+                Some(270) => 8,
+                _ => 1,
+            };
+            let page = jpeg2pdf::tiff::Page {
+                image: jpeg,
+                color_space: image_page.color_space,
+                resolution,
+                orientation,
+                description,
+            };
+            writer.write_page(&page)
+                .unwrap_or_else(|e| panic!("{}: failed to write TIFF page: {}", image_path.display(), e));
+            input_indexes.push(input_index);
+        }
+    }
+    let mut output = writer.finish()
+        .expect("failed to write output TIFF file");
+    let size_bytes = output.stream_position()
+        .expect("failed to determine size of output TIFF file");
+
+    report.add_output(path, input_indexes.len(), size_bytes);
+    for input_index in input_indexes {
+        report.input_mut(input_index).status = report::InputStatus::Converted {
+            output_path: path.to_owned(),
+        };
+    }
+}
+
+/// Outputs the collected warnings and, if requested, the report.
+fn finish_report(report: &report::Report, report_opts: &ReportOpts) {
+    if !report_opts.quiet {
+        report.print_warnings();
+    }
+    if report_opts.json {
+        println!("{}", report.to_json());
+    }
+}
+
+
+/// Writes the man page of the given command followed by those of its subcommands (e.g.
+/// jpeg2pdf-export-tiff), to which the page of the command refers.
+fn write_man_pages(command: &clap::Command, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+    clap_mangen::Man::new(command.clone())
+        .render(writer)?;
+    for subcommand in command.get_subcommands().filter(|sc| sc.get_name() != "help") {
+        write_man_pages(subcommand, writer)?;
+    }
+    Ok(())
+}
+
+
+fn main() {
+    let opts = parse_opts();
+    logging::init(opts.verbose);
+
+    if opts.generate_manpage {
+        let mut command = Opts::command();
+        command.build();
+        write_man_pages(&command, &mut std::io::stdout().lock())
+            .expect("failed to write man page");
+        return;
+    }
+    let command = opts.command
+        .expect("subcommand required by parser");
+
+    match command {
+        Command::Convert(convert_opts) => {
+            let job = batch::Job {
+                input_path: convert_opts.input_jpeg_path,
+                output_path: convert_opts.output_pdf_path,
+            };
+            let mut report = report::Report::new("convert", convert_opts.report_opts.strict);
+            let input_index = report.add_input(&job.input_path);
+            convert_job(input_index, &job, &convert_opts.image_opts, &convert_opts.document_opts, &mut report)
+                .unwrap_or_else(|e| panic!("{}", e));
+            finish_report(&report, &convert_opts.report_opts);
+        },
+        Command::Merge(merge_opts) => {
+            let mut report = report::Report::new("merge", merge_opts.report_opts.strict);
+            let result = merge(&merge_opts, &mut report);
+            finish_report(&report, &merge_opts.report_opts);
+            if let Err(e) = result {
+                eprintln!("merging failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Command::Batch(batch_opts) => {
+            if let Some(output_dir) = &batch_opts.output_dir {
+                std::fs::create_dir_all(output_dir)
+                    .unwrap_or_else(|e| panic!("failed to create output directory {}: {}", output_dir.display(), e));
+            }
+            let jobs: Vec<batch::Job> = batch_opts.input_jpeg_paths
+                .iter()
+                .map(|input| batch::Job::for_input(&input.path, batch_opts.output_dir.as_deref()))
+                .collect();
+            let mut resume_state = if batch_opts.resume {
+                let state_path = match (&batch_opts.state_file, &batch_opts.output_dir) {
+                    (Some(state_file), _) => state_file.clone(),
+                    (None, Some(output_dir)) => output_dir.join(batch::DEFAULT_STATE_FILE_NAME),
+                    (None, None) => PathBuf::from(batch::DEFAULT_STATE_FILE_NAME),
+                };
+                let state = batch::ResumeState::open(&state_path)
+                    .unwrap_or_else(|e| panic!("failed to open resume state file {}: {}", state_path.display(), e));
+                Some(state)
+            } else {
+                None
+            };
+            let mut report = report::Report::new("batch", batch_opts.report_opts.strict);
+            for job in &jobs {
+                report.add_input(&job.input_path);
+            }
+            let outcome = batch::run_jobs(&jobs, resume_state.as_mut(), batch_opts.on_error, |index, job| {
+                let image_opts = batch_opts.input_jpeg_paths[index].image_opts(&batch_opts.image_opts);
+                let Err(reason) = convert_job(index, job, &image_opts, &batch_opts.document_opts, &mut report) else {
+                    return Ok(());
+                };
+
+                // a partial PDF file would pass for a converted image
+                let _ = std::fs::remove_file(&job.output_path);
+                let placeholder_path = if batch_opts.on_error == batch::OnError::Placeholder {
+                    let placeholder = OutputPage::Placeholder {
+                        message: format!("conversion failed: {}", reason),
+                    };
+                    match write_document(&[placeholder], &batch_opts.document_opts, &job.output_path, &mut report) {
+                        Ok(()) => Some(job.output_path.clone()),
+                        Err(e) => {
+                            let _ = std::fs::remove_file(&job.output_path);
+                            report.warn(index, format!("failed to write placeholder: {}", e));
+                            None
+                        },
+                    }
+                } else {
+                    None
+                };
+                report.input_mut(index).status = report::InputStatus::Failed {
+                    reason: reason.clone(),
+                    placeholder_path,
+                };
+                Err(reason)
+            });
+            for &index in &outcome.skipped {
+                report.input_mut(index).status = report::InputStatus::SkippedAlreadyConverted;
+            }
+            finish_report(&report, &batch_opts.report_opts);
+            if outcome.exit_code() != 0 {
+                std::process::exit(outcome.exit_code());
+            }
+        },
+        Command::Watch(watch_opts) => {
+            std::fs::create_dir_all(&watch_opts.output_dir)
+                .unwrap_or_else(|e| panic!("failed to create output directory {}: {}", watch_opts.output_dir.display(), e));
+            let settle_time = Duration::from_millis(watch_opts.settle_time.milliseconds.into());
+            let poll_interval = Duration::from_millis(watch_opts.poll_interval.milliseconds.into());
+            let mut watcher = watch::Watcher::new(&watch_opts.input_dir, &watch_opts.output_dir, settle_time);
+            eprintln!("watching {} for JPEG images", watch_opts.input_dir.display());
+            let mut poll_failing = false;
+            loop {
+                let jobs = match watcher.poll() {
+                    Ok(jobs) => {
+                        if poll_failing {
+                            eprintln!("input directory {} readable again", watch_opts.input_dir.display());
+                            poll_failing = false;
+                        }
+                        jobs
+                    },
+                    Err(e) => {
+                        // e.g. a network share that is temporarily unavailable; try again later
+                        if !poll_failing {
+                            eprintln!("failed to read input directory {}: {}; retrying", watch_opts.input_dir.display(), e);
+                            poll_failing = true;
+                        }
+                        Vec::new()
+                    },
+                };
+                for job in &jobs {
+                    eprintln!("{} -> {}", job.input_path.display(), job.output_path.display());
+                    let mut report = report::Report::new("watch", watch_opts.report_opts.strict);
+                    let input_index = report.add_input(&job.input_path);
+                    // a broken image must not stop the service
+                    if let Err(reason) = convert_job(input_index, job, &watch_opts.image_opts, &watch_opts.document_opts, &mut report) {
+                        // a partial PDF file would pass for a converted image on the next run
+                        let _ = std::fs::remove_file(&job.output_path);
+                        eprintln!("{}: conversion failed: {}; waiting for the image to be replaced", job.input_path.display(), reason);
+                    }
+                    finish_report(&report, &watch_opts.report_opts);
+                }
+                std::thread::sleep(poll_interval);
+            }
+        },
+        Command::Inspect(inspect_opts) => {
+            let image_paths = inspect_opts.input_jpeg_paths.iter()
+                .flat_map(|path| input_image_paths(path, false).unwrap_or_else(|e| panic!("{}", e)));
+            for path in image_paths {
+                let (jpeg, repairs) = read_jpeg(&path, inspect_opts.repair, inspect_opts.timeout)
+                    .unwrap_or_else(|e| panic!("{}", e));
+                for repair in &repairs {
+                    eprintln!("{}: repaired: {}", path.display(), repair);
+                }
+                println!("{}:", path.display());
+                inspect::print_report(&jpeg);
+            }
+        },
+        Command::Lint(lint_opts) => {
+            let results: Vec<(PathBuf, Vec<lint::Finding>)> = lint_opts.input_jpeg_paths.iter()
+                .flat_map(|path| input_image_paths(path, false).unwrap_or_else(|e| panic!("{}", e)))
+                .map(|path| {
+                    let findings = lint_jpeg(&path, lint_opts.timeout);
+                    (path, findings)
+                })
+                .collect();
+            if lint_opts.json {
+                println!("{}", inspect::findings_to_json(&results));
+            } else {
+                inspect::print_findings(&results);
+            }
+            let any_problems = results.iter()
+                .flat_map(|(_path, findings)| findings)
+                .any(|finding| finding.severity() >= lint::Severity::Warning);
+            if any_problems {
+                std::process::exit(1);
+            }
+        },
+        Command::Preflight(preflight_opts) => {
+            let mut preflight = preflight::Preflight::new(&preflight_opts.target);
+            let results: Vec<(PathBuf, Vec<preflight::Finding>)> = preflight_opts.input_jpeg_paths.iter()
+                .flat_map(|path| input_image_paths(path, false).unwrap_or_else(|e| panic!("{}", e)))
+                .map(|path| {
+                    let (mut jpeg, repairs) = read_jpeg(&path, preflight_opts.repair, preflight_opts.timeout)
+                        .unwrap_or_else(|e| panic!("{}", e));
+                    for repair in &repairs {
+                        eprintln!("{}: repaired: {}", path.display(), repair);
+                    }
+                    // size the pages as the conversion would
+                    if let Some(density) = preflight_opts.dpi {
+                        jpeg.density_unit = DensityUnit::DotsPerInch;
+                        jpeg.density_x = density.x;
+                        jpeg.density_y = density.y;
+                    } else if matches!(jpeg.density_unit, DensityUnit::NoUnit) {
+                        assume_density(&mut jpeg, preflight_opts.infer_dpi_for);
+                    }
+                    let findings = preflight.check_image(&jpeg);
+                    (path, findings)
+                })
+                .collect();
+            let document_findings = preflight.finish();
+            if preflight_opts.json {
+                println!("{}", inspect::preflight_findings_to_json(&results, &document_findings));
+            } else {
+                inspect::print_preflight_findings(&results, &document_findings);
+            }
+            let any_problems = results.iter()
+                .flat_map(|(_path, findings)| findings)
+                .chain(document_findings.iter())
+                .any(|finding| finding.severity >= lint::Severity::Warning);
+            if any_problems {
+                std::process::exit(1);
+            }
+        },
+        Command::Export(ExportFormat::Tiff(export_opts)) => {
+            let mut report = report::Report::new("export-tiff", export_opts.report_opts.strict);
+            export_tiff(&export_opts, &mut report);
+            finish_report(&report, &export_opts.report_opts);
+        },
+        Command::Completions(completions_opts) => {
+            clap_complete::generate(completions_opts.shell, &mut Opts::command(), "jpeg2pdf", &mut std::io::stdout());
+        },
+    }
 }
@@ -0,0 +1,124 @@
+//! A JSON manifest of the written documents, listing for each page its source image, the IDs and
+//! byte offsets of its objects and a SHA-256 digest of each embedded image, so that archival
+//! conversions can be audited and verified later on.
+
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use jpeg2pdf::encrypt::sha256;
+use jpeg2pdf::jpeg;
+use jpeg2pdf::pdf::PdfObjectId;
+
+use crate::report::Json;
+
+
+/// An image XObject drawn on a page.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageManifest {
+    pub object_id: PdfObjectId,
+    /// The digest of the image data as embedded, i.e. before any ASCII encoding or encryption.
+    pub sha256: [u8; 32],
+    pub width_px: u16,
+    pub height_px: u16,
+    /// The horizontal and vertical resolution at which the image is placed on the page (before
+    /// any shrinking to make room for a caption), in pixels per inch.
+    pub dpi: (u64, u64),
+}
+impl ImageManifest {
+    /// Describes the image XObject with the given ID, which shows the given image at the given
+    /// size in points.
+    pub fn of(object_id: PdfObjectId, image: &jpeg::Image, width_pt: f64, height_pt: f64) -> Self {
+        let mut data = Vec::with_capacity(image.encoded_len());
+        image.write(&mut data)
+            .expect("failed to serialize image");
+        let dpi = |px: u16, pt: f64| (f64::from(px) * 72.0 / pt).round() as u64;
+        Self {
+            object_id,
+            sha256: sha256(&data),
+            width_px: image.width,
+            height_px: image.height,
+            dpi: (dpi(image.width, width_pt), dpi(image.height, height_pt)),
+        }
+    }
+
+    fn to_json(&self, object_offsets: &BTreeMap<PdfObjectId, usize>) -> Json {
+        let sha256: String = self.sha256.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Json::object([
+            ("object_id", Json::Number(self.object_id)),
+            ("offset", offset_json(object_offsets, self.object_id)),
+            ("sha256", Json::String(sha256)),
+            ("width_px", Json::Number(self.width_px.into())),
+            ("height_px", Json::Number(self.height_px.into())),
+            ("dpi", Json::object([
+                ("x", Json::Number(self.dpi.0)),
+                ("y", Json::Number(self.dpi.1)),
+            ])),
+        ])
+    }
+}
+
+/// A page of a written document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PageManifest {
+    pub object_id: PdfObjectId,
+    /// What the page shows: `image`, `blank`, `table-of-contents` or `placeholder`.
+    pub kind: &'static str,
+    /// The image file the page was made from, if any.
+    pub source: Option<PathBuf>,
+    pub size_pt: (u64, u64),
+    pub images: Vec<ImageManifest>,
+}
+
+/// A written document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DocumentManifest {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub pages: Vec<PageManifest>,
+    /// The offset of each object from the start of the file, as recorded in the cross-reference
+    /// table.
+    pub object_offsets: BTreeMap<PdfObjectId, usize>,
+}
+impl DocumentManifest {
+    pub fn to_json(&self) -> Json {
+        let pages = self.pages.iter()
+            .enumerate()
+            .map(|(index, page)| Json::object([
+                ("page", Json::Number(u64::try_from(index + 1).unwrap())),
+                ("kind", Json::string(page.kind)),
+                ("source", page.source.as_deref().map_or(Json::Null, Json::path)),
+                ("object_id", Json::Number(page.object_id)),
+                ("offset", offset_json(&self.object_offsets, page.object_id)),
+                ("width_pt", Json::Number(page.size_pt.0)),
+                ("height_pt", Json::Number(page.size_pt.1)),
+                ("images", Json::Array(
+                    page.images.iter()
+                        .map(|image| image.to_json(&self.object_offsets))
+                        .collect()
+                )),
+            ]))
+            .collect();
+        Json::object([
+            ("path", Json::path(&self.path)),
+            ("size_bytes", Json::Number(self.size_bytes)),
+            ("pages", Json::Array(pages)),
+        ])
+    }
+}
+
+fn offset_json(object_offsets: &BTreeMap<PdfObjectId, usize>, object_id: PdfObjectId) -> Json {
+    object_offsets.get(&object_id)
+        .map_or(Json::Null, |&offset| Json::Number(u64::try_from(offset).unwrap()))
+}
+
+/// Writes a manifest of the given documents to the given path, replacing any existing file.
+pub fn write(path: &Path, documents: &[DocumentManifest]) -> Result<(), io::Error> {
+    let json = Json::object([
+        ("documents", Json::Array(documents.iter().map(|d| d.to_json()).collect())),
+    ]);
+    std::fs::write(path, format!("{}\n", json))
+}
@@ -0,0 +1,44 @@
+//! N-up imposition: multiple pages drawn side by side onto one sheet, as for proof sheets.
+
+
+use crate::content::Matrix;
+
+
+/// Returns the areas of the cells into which a sheet of the given size is divided, as
+/// `(left, bottom, width, height)` in reading order (row by row, starting at the top left).
+///
+/// The gutter separates the cells from each other and from the edges of the sheet.
+pub fn cell_areas(columns: u32, rows: u32, sheet_width_pt: f64, sheet_height_pt: f64, gutter_pt: f64) -> Vec<(f64, f64, f64, f64)> {
+    let cell_width_pt = ((sheet_width_pt - f64::from(columns + 1) * gutter_pt) / f64::from(columns)).max(0.0);
+    let cell_height_pt = ((sheet_height_pt - f64::from(rows + 1) * gutter_pt) / f64::from(rows)).max(0.0);
+    let mut areas = Vec::with_capacity(usize::try_from(columns * rows).unwrap());
+    for row in 0..rows {
+        let top_pt = sheet_height_pt - gutter_pt - f64::from(row) * (cell_height_pt + gutter_pt);
+        for column in 0..columns {
+            let left_pt = gutter_pt + f64::from(column) * (cell_width_pt + gutter_pt);
+            areas.push((left_pt, top_pt - cell_height_pt, cell_width_pt, cell_height_pt));
+        }
+    }
+    areas
+}
+
+/// Returns the transformation that places a page of the given size, rotated clockwise by the
+/// given number of degrees (a multiple of 90), into the given area as large as possible without
+/// distorting it, centered within the area.
+pub fn fit_transform(page_width_pt: f64, page_height_pt: f64, rotation_degrees: u16, area: (f64, f64, f64, f64)) -> Matrix {
+    // rotate the page about its origin and move it back into the positive quadrant
+    let (rotation, rotated_width_pt, rotated_height_pt): (Matrix, f64, f64) = match rotation_degrees % 360 {
+        0 => ([1.0, 0.0, 0.0, 1.0, 0.0, 0.0], page_width_pt, page_height_pt),
+        90 => ([0.0, -1.0, 1.0, 0.0, 0.0, page_width_pt], page_height_pt, page_width_pt),
+        180 => ([-1.0, 0.0, 0.0, -1.0, page_width_pt, page_height_pt], page_width_pt, page_height_pt),
+        270 => ([0.0, 1.0, -1.0, 0.0, page_height_pt, 0.0], page_height_pt, page_width_pt),
+        other => panic!("rotation by {} degrees is not a multiple of 90 degrees", other),
+    };
+
+    let (left_pt, bottom_pt, area_width_pt, area_height_pt) = area;
+    let scale = (area_width_pt / rotated_width_pt).min(area_height_pt / rotated_height_pt);
+    let offset_x = left_pt + (area_width_pt - scale * rotated_width_pt) / 2.0;
+    let offset_y = bottom_pt + (area_height_pt - scale * rotated_height_pt) / 2.0;
+    let [a, b, c, d, e, f] = rotation;
+    [scale * a, scale * b, scale * c, scale * d, scale * e + offset_x, scale * f + offset_y]
+}
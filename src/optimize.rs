@@ -0,0 +1,754 @@
+//! Lossless optimization of JPEG images.
+//!
+//! The entropy-coded data is decoded into Huffman symbols and re-encoded using tables built from
+//! the frequencies of the symbols in the image at hand (JPEG Annex K.2). Quantization and Huffman
+//! tables are merged into one block each, dropping definitions that are overwritten or unused, and
+//! comments are removed. The pixels remain unchanged.
+//!
+//! Only sequential images with Huffman coding and a single scan are supported.
+
+
+use std::fmt;
+
+use crate::jpeg::{Block, Image};
+use crate::thumbnail::{BitReader, HuffmanTable};
+
+
+#[derive(Debug)]
+pub enum Error {
+    MissingBlock { kind: u8 },
+    BlockTooShort { kind: u8 },
+    UnsupportedCoding { sof_kind: u8 },
+    InvalidTable { kind: u8, table: u8 },
+    MissingTable { kind: u8, table: u8 },
+    InvalidHuffmanCode,
+    MultipleScans,
+    ZeroDimension,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingBlock { kind }
+                => write!(f, "required block 0x{:02X} missing", kind),
+            Self::BlockTooShort { kind }
+                => write!(f, "block 0x{:02X} too short", kind),
+            Self::UnsupportedCoding { sof_kind }
+                => write!(f, "re-encoding images of frame type 0x{:02X} is not supported", sof_kind),
+            Self::InvalidTable { kind, table }
+                => write!(f, "invalid table {} in block 0x{:02X}", table, kind),
+            Self::MissingTable { kind, table }
+                => write!(f, "table {} referenced but not defined in a block 0x{:02X}", table, kind),
+            Self::InvalidHuffmanCode
+                => write!(f, "invalid Huffman code in image data"),
+            Self::MultipleScans
+                => write!(f, "re-encoding images with multiple scans is not supported"),
+            Self::ZeroDimension
+                => write!(f, "image has zero width or height"),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+/// The number of code lengths in a Huffman table definition.
+const MAX_CODE_LENGTH: usize = 16;
+
+/// The code and its length for each symbol.
+pub(crate) type EncodingTable = [(u32, u32); 256];
+
+/// A Huffman table definition for each class (DC and AC) and table number, if defined.
+pub(crate) type TableDefinitions = [[Option<TableDefinition>; 4]; 2];
+
+/// A Huffman table as stored in a DHT block.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct TableDefinition {
+    /// The number of codes of each length from 1 to 16 bits.
+    counts: [u8; MAX_CODE_LENGTH],
+    /// The symbols, ordered by the length of their codes.
+    values: Vec<u8>,
+}
+impl TableDefinition {
+    /// Builds an optimal table of codes of at most 16 bits for the given symbol frequencies,
+    /// following the procedure in JPEG Annex K.2.
+    fn optimal(frequencies: &[u64; 256]) -> Self {
+        // a reserved symbol with the lowest frequency ensures that no code consists only of 1 bits
+        let mut freq = [0u64; 257];
+        freq[..256].copy_from_slice(frequencies);
+        freq[256] = 1;
+        let mut code_size = [0usize; 257];
+        let mut others: [Option<usize>; 257] = [None; 257];
+
+        loop {
+            // find the two least frequent symbols (preferring later ones)
+            let mut v1 = None;
+            for i in 0..257 {
+                if freq[i] > 0 && v1.is_none_or(|v: usize| freq[i] <= freq[v]) {
+                    v1 = Some(i);
+                }
+            }
+            let mut v2 = None;
+            for i in 0..257 {
+                if freq[i] > 0 && Some(i) != v1 && v2.is_none_or(|v: usize| freq[i] <= freq[v]) {
+                    v2 = Some(i);
+                }
+            }
+            let (Some(mut v1), Some(mut v2)) = (v1, v2) else {
+                break;
+            };
+
+            // merge the two trees
+            freq[v1] += freq[v2];
+            freq[v2] = 0;
+            code_size[v1] += 1;
+            while let Some(other) = others[v1] {
+                v1 = other;
+                code_size[v1] += 1;
+            }
+            others[v1] = Some(v2);
+            code_size[v2] += 1;
+            while let Some(other) = others[v2] {
+                v2 = other;
+                code_size[v2] += 1;
+            }
+        }
+
+        // count the codes of each length
+        let max_size = code_size.iter().copied().max().unwrap_or(0).max(MAX_CODE_LENGTH);
+        let mut bits = vec![0usize; max_size + 1];
+        for &size in &code_size {
+            if size > 0 {
+                bits[size] += 1;
+            }
+        }
+
+        // shorten codes that are too long
+        for i in (MAX_CODE_LENGTH + 1..=max_size).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+
+        // remove the reserved symbol, which has one of the longest codes
+        let mut i = MAX_CODE_LENGTH;
+        while bits[i] == 0 {
+            i -= 1;
+        }
+        bits[i] -= 1;
+
+        let mut counts = [0u8; MAX_CODE_LENGTH];
+        for (count, &bit_count) in counts.iter_mut().zip(bits[1..=MAX_CODE_LENGTH].iter()) {
+            *count = u8::try_from(bit_count).unwrap();
+        }
+        let mut values = Vec::new();
+        for size in 1..=max_size {
+            for symbol in 0..=255u8 {
+                if code_size[usize::from(symbol)] == size {
+                    values.push(symbol);
+                }
+            }
+        }
+        Self {
+            counts,
+            values,
+        }
+    }
+
+    pub fn codes(&self) -> EncodingTable {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0;
+        let mut symbols = self.values.iter();
+        for length in 1..=MAX_CODE_LENGTH {
+            for _ in 0..self.counts[length - 1] {
+                if let Some(&symbol) = symbols.next() {
+                    codes[usize::from(symbol)] = (code, length as u32);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct FrameComponent {
+    pub id: u8,
+    pub horizontal_sampling: usize,
+    pub vertical_sampling: usize,
+    pub quantization_table: usize,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct ScanComponent {
+    pub frame_index: usize,
+    pub dc_table: usize,
+    pub ac_table: usize,
+}
+
+/// An element of the entropy-coded data.
+#[derive(Clone, Copy)]
+pub(crate) enum Event {
+    /// A Huffman-coded symbol followed by additional bits.
+    Symbol { class: usize, table: usize, symbol: u8, extra_bits: u32, extra_bit_count: u32 },
+    Restart,
+}
+
+
+/// Writes bits into entropy-coded data, stuffing zero bytes where necessary.
+pub(crate) struct BitWriter {
+    pub data: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+impl BitWriter {
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        if count == 0 {
+            return;
+        }
+        self.bit_buffer = (self.bit_buffer << count) | (value & ((1 << count) - 1));
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            let byte = (self.bit_buffer >> (self.bit_count - 8)) as u8;
+            self.data.push(byte);
+            if byte == 0xFF {
+                self.data.push(0x00);
+            }
+            self.bit_count -= 8;
+        }
+        self.bit_buffer &= (1 << self.bit_count) - 1;
+    }
+
+    /// Pads the last byte with 1 bits.
+    pub fn pad(&mut self) {
+        if self.bit_count > 0 {
+            self.write_bits(0xFF, 8 - self.bit_count);
+        }
+    }
+}
+
+
+/// The structure of an image consisting of a single sequential scan, as required for decoding its
+/// entropy-coded data into symbols and re-encoding them.
+pub(crate) struct SequentialScan<'a> {
+    pub frame_components: Vec<FrameComponent>,
+    pub scan_components: Vec<ScanComponent>,
+    /// The quantization table definitions (including the precision and table number), which are
+    /// kept verbatim.
+    pub quantization_tables: [Option<&'a [u8]>; 4],
+    pub decoding_tables: [[Option<HuffmanTable>; 4]; 2],
+    pub restart_interval: u16,
+    pub geometry: McuGeometry,
+}
+impl<'a> SequentialScan<'a> {
+    pub fn parse(image: &'a Image) -> Result<Self, Error> {
+        let mut quantization_tables: [Option<&[u8]>; 4] = [None; 4];
+        let mut huffman_tables: [[Option<TableDefinition>; 4]; 2] = Default::default();
+        let mut frame: Option<(u8, Vec<FrameComponent>)> = None;
+        let mut restart_interval = 0;
+        let mut scan_components = None;
+
+        for block in &image.leading_blocks {
+            let kind = block.kind();
+            let data = block.data();
+            match kind {
+                0xDB => {
+                    // quantization tables, which we keep verbatim
+                    let mut rest = data;
+                    while !rest.is_empty() {
+                        let precision = rest[0] >> 4;
+                        let table = rest[0] & 0x0F;
+                        let table_length = if precision == 0 { 64 } else { 128 };
+                        if rest.len() < 1 + table_length {
+                            return Err(Error::BlockTooShort { kind });
+                        }
+                        if table > 3 {
+                            return Err(Error::InvalidTable { kind, table });
+                        }
+                        quantization_tables[usize::from(table)] = Some(&rest[..1 + table_length]);
+                        rest = &rest[1 + table_length..];
+                    }
+                },
+                0xC4 => {
+                    // Huffman tables
+                    let mut rest = data;
+                    while !rest.is_empty() {
+                        if rest.len() < 17 {
+                            return Err(Error::BlockTooShort { kind });
+                        }
+                        let class = rest[0] >> 4;
+                        let table = rest[0] & 0x0F;
+                        let mut counts = [0u8; MAX_CODE_LENGTH];
+                        counts.copy_from_slice(&rest[1..17]);
+                        let value_count: usize = counts.iter().map(|&c| usize::from(c)).sum();
+                        if rest.len() < 17 + value_count {
+                            return Err(Error::BlockTooShort { kind });
+                        }
+                        if class > 1 || table > 3 {
+                            return Err(Error::InvalidTable { kind, table });
+                        }
+                        huffman_tables[usize::from(class)][usize::from(table)] = Some(TableDefinition {
+                            counts,
+                            values: rest[17..17 + value_count].to_vec(),
+                        });
+                        rest = &rest[17 + value_count..];
+                    }
+                },
+                0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF => {
+                    // start of frame
+                    if data.len() < 6 {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let component_count = usize::from(data[5]);
+                    if data.len() < 6 + 3 * component_count {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let components = data[6..6 + 3 * component_count]
+                        .chunks(3)
+                        .map(|c| FrameComponent {
+                            id: c[0],
+                            horizontal_sampling: usize::from(c[1] >> 4).max(1),
+                            vertical_sampling: usize::from(c[1] & 0x0F).max(1),
+                            quantization_table: usize::from(c[2] & 0x03),
+                        })
+                        .collect();
+                    frame = Some((kind, components));
+                },
+                0xDD => {
+                    // restart interval
+                    if data.len() < 2 {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    restart_interval = u16::from_be_bytes([data[0], data[1]]);
+                },
+                0xDA => {
+                    // start of scan
+                    let (_, frame_components) = frame.as_ref()
+                        .ok_or(Error::MissingBlock { kind: 0xC0 })?;
+                    if data.is_empty() {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let component_count = usize::from(data[0]);
+                    if data.len() < 1 + 2 * component_count + 3 {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let mut components = Vec::with_capacity(component_count);
+                    for c in data[1..1 + 2 * component_count].chunks(2) {
+                        let frame_index = frame_components.iter()
+                            .position(|fc| fc.id == c[0])
+                            .ok_or(Error::MissingBlock { kind: 0xC0 })?;
+                        components.push(ScanComponent {
+                            frame_index,
+                            dc_table: usize::from(c[1] >> 4) & 0x03,
+                            ac_table: usize::from(c[1] & 0x0F) & 0x03,
+                        });
+                    }
+                    scan_components = Some(components);
+                },
+                _ => {},
+            }
+        }
+
+        let (sof_kind, frame_components) = frame
+            .ok_or(Error::MissingBlock { kind: 0xC0 })?;
+        let scan_components = scan_components
+            .ok_or(Error::MissingBlock { kind: 0xDA })?;
+        if !matches!(sof_kind, 0xC0|0xC1) {
+            return Err(Error::UnsupportedCoding { sof_kind });
+        }
+        if image.width == 0 || image.height == 0 || frame_components.is_empty() || scan_components.is_empty() {
+            return Err(Error::ZeroDimension);
+        }
+        if has_further_markers(&image.image_data) {
+            return Err(Error::MultipleScans);
+        }
+        for fc in &frame_components {
+            if quantization_tables[fc.quantization_table].is_none() {
+                return Err(Error::MissingTable { kind: 0xDB, table: fc.quantization_table as u8 });
+            }
+        }
+
+        let mut decoding_tables: [[Option<HuffmanTable>; 4]; 2] = Default::default();
+        for sc in &scan_components {
+            for (class, table) in [(0, sc.dc_table), (1, sc.ac_table)] {
+                let definition = huffman_tables[class][table].as_ref()
+                    .ok_or(Error::MissingTable { kind: 0xC4, table: table as u8 })?;
+                decoding_tables[class][table] = Some(HuffmanTable::new(&definition.counts, &definition.values));
+            }
+        }
+
+        let geometry = McuGeometry::new(image, &frame_components, &scan_components);
+        Ok(Self {
+            frame_components,
+            scan_components,
+            quantization_tables,
+            decoding_tables,
+            restart_interval,
+            geometry,
+        })
+    }
+
+    /// Decodes the entropy-coded data of the scan, passing each symbol to `visit`.
+    pub fn walk<F: FnMut(Event)>(&self, image: &Image, mut visit: F) -> Result<(), Error> {
+        let mut reader = BitReader::new(&image.image_data);
+        let mut mcus_until_restart = self.restart_interval;
+        for _ in 0..self.geometry.mcus_down * self.geometry.mcus_across {
+            if self.restart_interval > 0 {
+                if mcus_until_restart == 0 {
+                    reader.restart();
+                    visit(Event::Restart);
+                    mcus_until_restart = self.restart_interval;
+                }
+                mcus_until_restart -= 1;
+            }
+
+            for &scan_index in &self.geometry.block_components {
+                let sc = self.scan_components[scan_index];
+                let dc_table = self.decoding_tables[0][sc.dc_table].as_ref().unwrap();
+                let ac_table = self.decoding_tables[1][sc.ac_table].as_ref().unwrap();
+                let size = reader.decode(dc_table)
+                    .map_err(|_| Error::InvalidHuffmanCode)?;
+                let extra_bit_count = u32::from(size).min(16);
+                visit(Event::Symbol {
+                    class: 0,
+                    table: sc.dc_table,
+                    symbol: size,
+                    extra_bits: reader.read_bits(extra_bit_count),
+                    extra_bit_count,
+                });
+
+                let mut k = 1;
+                while k < 64 {
+                    let run_size = reader.decode(ac_table)
+                        .map_err(|_| Error::InvalidHuffmanCode)?;
+                    let run = run_size >> 4;
+                    let size = run_size & 0x0F;
+                    let extra_bit_count = u32::from(size);
+                    visit(Event::Symbol {
+                        class: 1,
+                        table: sc.ac_table,
+                        symbol: run_size,
+                        extra_bits: reader.read_bits(extra_bit_count),
+                        extra_bit_count,
+                    });
+                    if size == 0 {
+                        if run != 15 {
+                            // end of block
+                            break;
+                        }
+                        k += 16;
+                    } else {
+                        k += usize::from(run) + 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges the quantization tables used by the frame into one block.
+    pub fn quantization_block(&self) -> Block {
+        let mut used_quantization_tables: Vec<usize> = self.frame_components.iter()
+            .map(|fc| fc.quantization_table)
+            .collect();
+        used_quantization_tables.sort_unstable();
+        used_quantization_tables.dedup();
+        let mut quantization_data = Vec::new();
+        for table in used_quantization_tables {
+            quantization_data.extend_from_slice(self.quantization_tables[table].unwrap());
+        }
+        Block::Long { kind: 0xDB, data: quantization_data }
+    }
+
+    /// Counts the symbols of each Huffman table and builds optimal tables from the frequencies.
+    pub fn optimal_tables<F>(&self, count_symbols: F) -> Result<[[Option<TableDefinition>; 4]; 2], Error>
+        where F: FnOnce(&mut [[[u64; 256]; 4]; 2]) -> Result<(), Error>
+    {
+        let mut frequencies = [[[0u64; 256]; 4]; 2];
+        count_symbols(&mut frequencies)?;
+        let mut optimized_tables: [[Option<TableDefinition>; 4]; 2] = Default::default();
+        for class in 0..2 {
+            for table in 0..4 {
+                if self.decoding_tables[class][table].is_some() {
+                    optimized_tables[class][table] = Some(TableDefinition::optimal(&frequencies[class][table]));
+                }
+            }
+        }
+        Ok(optimized_tables)
+    }
+}
+
+/// How the blocks of a scan are arranged into minimum coded units (MCUs).
+pub(crate) struct McuGeometry {
+    pub mcus_across: usize,
+    pub mcus_down: usize,
+    /// The width of an MCU in pixels of the image.
+    pub mcu_width_px: usize,
+    /// The height of an MCU in pixels of the image.
+    pub mcu_height_px: usize,
+    /// For each block of an MCU, in order, the index of its component within the scan.
+    pub block_components: Vec<usize>,
+}
+impl McuGeometry {
+    fn new(image: &Image, frame_components: &[FrameComponent], scan_components: &[ScanComponent]) -> Self {
+        let max_horizontal = frame_components.iter().map(|c| c.horizontal_sampling).max().unwrap();
+        let max_vertical = frame_components.iter().map(|c| c.vertical_sampling).max().unwrap();
+        let width_px = usize::from(image.width);
+        let height_px = usize::from(image.height);
+
+        if scan_components.len() == 1 {
+            // non-interleaved: one block per MCU
+            let fc = frame_components[scan_components[0].frame_index];
+            let mcu_width_px = 8 * max_horizontal / fc.horizontal_sampling;
+            let mcu_height_px = 8 * max_vertical / fc.vertical_sampling;
+            Self {
+                mcus_across: (width_px * fc.horizontal_sampling).div_ceil(max_horizontal).div_ceil(8),
+                mcus_down: (height_px * fc.vertical_sampling).div_ceil(max_vertical).div_ceil(8),
+                mcu_width_px,
+                mcu_height_px,
+                block_components: vec![0],
+            }
+        } else {
+            let mut block_components = Vec::new();
+            for (scan_index, sc) in scan_components.iter().enumerate() {
+                let fc = frame_components[sc.frame_index];
+                for _ in 0..fc.horizontal_sampling * fc.vertical_sampling {
+                    block_components.push(scan_index);
+                }
+            }
+            Self {
+                mcus_across: width_px.div_ceil(8 * max_horizontal),
+                mcus_down: height_px.div_ceil(8 * max_vertical),
+                mcu_width_px: 8 * max_horizontal,
+                mcu_height_px: 8 * max_vertical,
+                block_components,
+            }
+        }
+    }
+}
+
+/// Merges the given Huffman tables into one block.
+pub(crate) fn huffman_block(tables: &[[Option<TableDefinition>; 4]; 2]) -> Block {
+    let mut huffman_data = Vec::new();
+    for (class, class_tables) in tables.iter().enumerate() {
+        for (table, definition) in class_tables.iter().enumerate() {
+            if let Some(definition) = definition {
+                huffman_data.push(((class as u8) << 4) | (table as u8));
+                huffman_data.extend_from_slice(&definition.counts);
+                huffman_data.extend_from_slice(&definition.values);
+            }
+        }
+    }
+    Block::Long { kind: 0xC4, data: huffman_data }
+}
+
+/// Replaces the table definitions in the leading blocks of an image with the given merged blocks.
+///
+/// The quantization tables take the place of the first definition; the Huffman tables directly
+/// precede the start-of-scan block. Blocks for which `keep` returns `false` are dropped.
+pub(crate) fn replace_tables<F: Fn(&Block) -> bool>(
+    leading_blocks: &[Block],
+    quantization_block: Block,
+    huffman_block: Block,
+    keep: F,
+) -> Vec<Block> {
+    let mut new_blocks = Vec::with_capacity(leading_blocks.len());
+    let mut quantization_block = Some(quantization_block);
+    let mut huffman_block = Some(huffman_block);
+    for block in leading_blocks {
+        match block.kind() {
+            0xDB => {
+                if let Some(quantization_block) = quantization_block.take() {
+                    new_blocks.push(quantization_block);
+                }
+            },
+            0xC4 => {},
+            0xDA => {
+                if let Some(huffman_block) = huffman_block.take() {
+                    new_blocks.push(huffman_block);
+                }
+                new_blocks.push(block.clone());
+            },
+            _ if keep(block) => new_blocks.push(block.clone()),
+            _ => {},
+        }
+    }
+    new_blocks
+}
+
+
+/// Optimizes the Huffman coding of the given image.
+pub fn optimize(image: &Image) -> Result<Image, Error> {
+    let scan = SequentialScan::parse(image)?;
+
+    // count the symbols
+    let optimized_tables = scan.optimal_tables(|frequencies| {
+        scan.walk(image, |event| {
+            if let Event::Symbol { class, table, symbol, .. } = event {
+                frequencies[class][table][usize::from(symbol)] += 1;
+            }
+        })
+    })?;
+    reencode(image, &scan, &optimized_tables)
+}
+
+/// Encodes the symbols of the given image anew using the given Huffman tables, which replace those
+/// of the image.
+fn reencode(image: &Image, scan: &SequentialScan<'_>, tables: &TableDefinitions) -> Result<Image, Error> {
+    let codes: [[Option<EncodingTable>; 4]; 2] = [0, 1].map(|class|
+        [0, 1, 2, 3].map(|table: usize| tables[class][table].as_ref().map(|t| t.codes()))
+    );
+
+    // re-encode the symbols
+    let mut writer = BitWriter::new();
+    let mut restart_number = 0;
+    scan.walk(image, |event| {
+        match event {
+            Event::Symbol { class, table, symbol, extra_bits, extra_bit_count } => {
+                let (code, length) = codes[class][table].as_ref().unwrap()[usize::from(symbol)];
+                writer.write_bits(code, length);
+                writer.write_bits(extra_bits, extra_bit_count);
+            },
+            Event::Restart => {
+                writer.pad();
+                writer.data.push(0xFF);
+                writer.data.push(0xD0 + restart_number);
+                restart_number = (restart_number + 1) % 8;
+            },
+        }
+    })?;
+    writer.pad();
+
+    // merge the tables into one block each, removing comments
+    let leading_blocks = replace_tables(
+        &image.leading_blocks,
+        scan.quantization_block(),
+        huffman_block(tables),
+        |block| block.kind() != 0xFE,
+    );
+
+    Ok(Image {
+        frame_type: image.frame_type,
+        bit_depth: image.bit_depth,
+        width: image.width,
+        height: image.height,
+        color_space: image.color_space,
+        density_unit: image.density_unit,
+        density_x: image.density_x,
+        density_y: image.density_y,
+        exif_orientation: image.exif_orientation,
+        adobe_transform: image.adobe_transform,
+        leading_blocks,
+        image_data: writer.data,
+        trailing_blocks: image.trailing_blocks.clone(),
+    })
+}
+
+/// Checks whether the entropy-coded data contains markers other than restart markers.
+fn has_further_markers(data: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos + 1 < data.len() {
+        if data[pos] == 0xFF {
+            match data[pos + 1] {
+                0x00|0xD0..=0xD7 => pos += 2,
+                // fill byte
+                0xFF => pos += 1,
+                _ => return true,
+            }
+        } else {
+            pos += 1;
+        }
+    }
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srgb::BlockDecoder;
+
+    const SMALL_JPEG: &[u8] = include_bytes!("../tests/data/small.jpg");
+
+    /// Decodes the quantized coefficients of each block of the given image.
+    fn coefficients(image: &Image) -> Vec<[i32; 64]> {
+        let scan = SequentialScan::parse(image).expect("failed to parse scan");
+        let mut decoder = BlockDecoder::new(scan.geometry.block_components.clone());
+        let mut blocks = Vec::new();
+        scan.walk(image, |event| blocks.extend(decoder.visit(event).map(|(_index, block)| block)))
+            .expect("failed to decode scan");
+        blocks.extend(decoder.finish().map(|(_index, block)| block));
+        blocks
+    }
+
+    /// Writes the given image and reads it back, so that it is decoded as stored.
+    fn reread(image: &Image) -> Image {
+        let mut data = Vec::new();
+        image.write(&mut data).expect("failed to write image");
+        Image::try_read(&data[..]).expect("failed to read image back")
+    }
+
+    #[test]
+    fn coefficients_unchanged() {
+        let image = Image::try_read(SMALL_JPEG).expect("failed to read image");
+        let original_blocks = coefficients(&image);
+        // 16x16 pixels with 2x2 luma subsampling: four luma blocks and one block of each chroma component
+        assert_eq!(original_blocks.len(), 6);
+
+        let optimized = reread(&optimize(&image).expect("failed to optimize image"));
+        assert!(optimized.encoded_len() < image.encoded_len());
+        assert_eq!(coefficients(&optimized), original_blocks);
+    }
+
+    #[test]
+    fn coefficients_unchanged_from_poor_tables() {
+        // the test image already has optimal tables, so that its entropy-coded data stays the same;
+        // give it tables in which each symbol that occurs is equally likely instead
+        let image = Image::try_read(SMALL_JPEG).expect("failed to read image");
+        let scan = SequentialScan::parse(&image).expect("failed to parse scan");
+        let flat_tables = scan.optimal_tables(|frequencies| {
+            scan.walk(&image, |event| {
+                if let Event::Symbol { class, table, symbol, .. } = event {
+                    frequencies[class][table][usize::from(symbol)] = 1;
+                }
+            })
+        }).expect("failed to build tables");
+        let poor = reread(&reencode(&image, &scan, &flat_tables).expect("failed to re-encode image"));
+        assert_ne!(poor.image_data, image.image_data);
+        assert_eq!(coefficients(&poor), coefficients(&image));
+
+        let optimized = reread(&optimize(&poor).expect("failed to optimize image"));
+        assert!(optimized.image_data.len() < poor.image_data.len());
+        assert_eq!(coefficients(&optimized), coefficients(&image));
+    }
+
+    #[test]
+    fn multiple_scans_rejected() {
+        // repeat the scan (header and entropy-coded data) before the end of the image
+        let scan_start = SMALL_JPEG.windows(2)
+            .position(|w| w == [0xFF, 0xDA])
+            .unwrap();
+        let image_end = SMALL_JPEG.len() - 2;
+        let mut data = SMALL_JPEG[..image_end].to_vec();
+        data.extend_from_slice(&SMALL_JPEG[scan_start..]);
+
+        let image = Image::try_read(&data[..]).expect("failed to read image");
+        assert_eq!(image.scans().expect("failed to split scans").len(), 2);
+        assert!(matches!(optimize(&image), Err(Error::MultipleScans)));
+    }
+}
@@ -0,0 +1,1715 @@
+//! The command-line options and the parsers of their values, along with the presetting of options
+//! from the configuration file.
+
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+
+use jpeg2pdf::{caption, content, encrypt, page_numbers, pdf, preflight};
+use jpeg2pdf::jpeg::{ColorSpace, MetadataKind};
+use jpeg2pdf::stamp::{Stamp, StampPosition};
+
+use crate::{batch, config};
+
+
+/// The name of the configuration file read if no other file is specified.
+pub const DEFAULT_CONFIG_FILE_NAME: &str = "jpeg2pdf.toml";
+
+
+#[derive(Parser)]
+// options preset by the configuration file may be given again on the command line
+#[command(args_override_self = true)]
+pub struct Opts {
+    /// Preset options using the given configuration file instead of ~/.config/jpeg2pdf.toml.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Output log messages to standard error: the stages of the run and their timing if given
+    /// once, additionally the parsed segments and written objects if given twice.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Output a man page in roff format describing the subcommands and their options, then exit.
+    #[arg(long, exclusive = true)]
+    pub generate_manpage: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Converts a single JPEG image into a single-page PDF document.
+    Convert(ConvertOpts),
+
+    /// Merges multiple JPEG images into one PDF document with one page per image.
+    Merge(MergeOpts),
+
+    /// Converts many JPEG images, each into its own single-page PDF document.
+    Batch(BatchOpts),
+
+    /// Monitors a directory and converts each JPEG image arriving in it into its own single-page
+    /// PDF document.
+    Watch(WatchOpts),
+
+    /// Outputs information about the structure of JPEG images.
+    Inspect(InspectOpts),
+
+    /// Checks JPEG images for deviations from the standards and other peculiarities, exiting
+    /// with status 1 if any warnings or errors are found.
+    Lint(LintOpts),
+
+    /// Checks JPEG images against the limits of programs which process PDF documents, such as
+    /// Ghostscript, macOS Preview and PDF/A validators, suggesting how to avoid each problem and
+    /// exiting with status 1 if any warnings or errors are found.
+    Preflight(PreflightOpts),
+
+    /// Wraps JPEG images into formats other than PDF.
+    #[command(subcommand)]
+    Export(ExportFormat),
+
+    /// Outputs a script which makes the given shell complete the subcommands and options.
+    Completions(CompletionsOpts),
+}
+
+#[derive(Args, Clone)]
+pub struct ImageOpts {
+    #[arg(short, long)]
+    pub remove_optional_metadata: bool,
+
+    /// Remove the given kinds of metadata blocks, as a comma-separated list, e.g. exif,xmp.
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "keep")]
+    pub strip: Vec<MetadataKind>,
+
+    /// Remove all metadata blocks except the given kinds, as a comma-separated list, e.g. icc.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub keep: Vec<MetadataKind>,
+
+    /// Keep the thumbnail in the Exif metadata, which is otherwise removed even if the rest of the
+    /// Exif metadata is kept.
+    #[arg(long)]
+    pub keep_exif_thumbnail: bool,
+
+    /// Losslessly shrink the image data by re-encoding it with Huffman tables optimized for each
+    /// image, merging the quantization and Huffman tables and removing comments. Only sequential
+    /// images with a single scan can be optimized; others are embedded unchanged.
+    #[arg(long)]
+    pub optimize_jpeg: bool,
+
+    /// Convert the colors of images with an embedded ICC profile to sRGB, for PDF viewers that
+    /// ignore the profile. The image is decoded and encoded anew, which loses some quality. Only
+    /// sequential images with a single scan and RGB or grayscale profiles can be converted; others
+    /// are embedded unchanged.
+    #[arg(long)]
+    pub convert_to_srgb: bool,
+
+    /// Losslessly split images wider or taller than the given number of pixels into tiles of at
+    /// most that size, which are drawn next to each other, for PDF viewers that fail on large
+    /// images. Only sequential images with a single scan can be split.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(16..))]
+    pub tile_size: Option<u16>,
+
+    /// Work around common kinds of damage to JPEG files instead of failing.
+    #[arg(long)]
+    pub repair: bool,
+
+    /// How long to wait for an image given as an HTTP(S) URL to be downloaded, in seconds.
+    #[arg(long, default_value = "30")]
+    pub timeout: Seconds,
+
+    /// Read each input as a comic book archive (a ZIP file of images), whatever its extension,
+    /// ordering its images naturally (page2 before page10). Inputs with the extension .cbz are
+    /// always read this way, and inputs with the extension .zip are read as archives whose images
+    /// are ordered by name.
+    #[arg(long)]
+    pub from_cbz: bool,
+
+    /// Treat images that are considerably wider than tall as double-page spreads: with --width,
+    /// such an image is given a page twice as wide instead of being shrunk to the width of a
+    /// single page.
+    #[arg(long)]
+    pub detect_spreads: bool,
+
+    /// Only show the given area of each image, as LEFTxTOPxWIDTHxHEIGHT. Each value is in pixels
+    /// or, if followed by %, a percentage of the image's width or height.
+    #[arg(long)]
+    pub crop: Option<Crop>,
+
+    /// Slice images taller than the given height into several pages, for receipts and long
+    /// screenshots: a length with a unit (e.g. 297mm, 11in or 842pt) or a number of pixels (e.g.
+    /// 2000px). All pages show parts of the same embedded image.
+    #[arg(long)]
+    pub paginate_height: Option<SliceHeight>,
+
+    /// The width of each page (before cropping), overriding the image density, e.g. 594mm, 23.4in
+    /// or 1684pt. If no height is given, the aspect ratio is retained.
+    #[arg(long)]
+    pub width: Option<PhysicalLength>,
+
+    /// The height of each page (before cropping), overriding the image density, e.g. 841mm, 33.1in
+    /// or 2384pt. If no width is given, the aspect ratio is retained.
+    #[arg(long)]
+    pub height: Option<PhysicalLength>,
+
+    /// Treat the images as having the given density in dots per inch, as DPI or XDPIxYDPI,
+    /// regardless of what they declare.
+    #[arg(long)]
+    pub dpi: Option<Density>,
+
+    /// Infer the density of images which do not declare one from the given paper size, assuming
+    /// that they are scans of a whole sheet in the orientation matching their aspect ratio.
+    #[arg(long, value_enum, conflicts_with = "dpi")]
+    pub infer_dpi_for: Option<jpeg2pdf::PaperSize>,
+
+    /// How to place images whose horizontal and vertical densities differ (non-square pixels, e.g.
+    /// 204x98 dpi faxes): keep the pixels by scaling the image differently in each direction, or
+    /// resample the image to square pixels at the higher density. Resampling decodes and encodes
+    /// the image anew, which loses some quality; only sequential images with a single scan can be
+    /// resampled.
+    #[arg(long, value_enum, default_value_t)]
+    pub pixel_aspect: PixelAspect,
+
+    /// Rotate each page clockwise by the given number of degrees (a multiple of 90) when displayed.
+    #[arg(long)]
+    pub rotate: Option<Rotation>,
+
+    /// Treat the images as having the given color space, regardless of what they declare.
+    #[arg(long, value_enum)]
+    pub assume_colorspace: Option<AssumedColorSpace>,
+
+    /// The decode array for the images as comma-separated numbers, two per color component, e.g.
+    /// 1,0,1,0,1,0,1,0 to invert a CMYK image.
+    #[arg(long)]
+    pub decode_array: Option<DecodeArray>,
+
+    /// Ask viewers to smooth the images when scaling them up instead of showing the individual
+    /// pixels.
+    #[arg(long)]
+    pub interpolate: bool,
+
+    /// How viewers and printers map the colors of the images that the output device cannot
+    /// reproduce.
+    #[arg(long, value_enum)]
+    pub rendering_intent: Option<pdf::RenderingIntent>,
+
+    /// Leave the areas of the given color (as RRGGBB, optionally followed by a tolerance of up to
+    /// 255 per component, e.g. FFFFFF:16) unpainted, so that whatever lies beneath the image shows
+    /// through, e.g. to place a scanned stamp without its white paper. Not supported for CMYK
+    /// images.
+    #[arg(long)]
+    pub transparent_color: Option<TransparentColor>,
+
+    /// A textual description of the images for accessibility purposes. Implies --tagged.
+    #[arg(long)]
+    pub alt_text: Option<String>,
+
+    /// Read the description of each image from a file next to it, named like the image but with
+    /// the extension .alt.txt (e.g. scan001.alt.txt for scan001.jpg). Falls back to --alt-text
+    /// if the file does not exist. Implies --tagged.
+    #[arg(long)]
+    pub alt_text_sidecar: bool,
+}
+
+#[derive(Args)]
+pub struct DocumentOpts {
+    /// Prepare an empty signature field which can be filled in by an external signing tool.
+    #[arg(long)]
+    pub prepare_signature: bool,
+
+    /// Number of bytes reserved for the signature contents.
+    #[arg(long, default_value_t = 8192)]
+    pub signature_size: usize,
+
+    /// Compress the page content streams.
+    #[arg(short, long)]
+    pub compress: bool,
+
+    /// Encode all streams, including the image data, as ASCII text for channels that only carry
+    /// 7-bit data: "hex" doubles the size of the data, "85" (ASCII85) adds a quarter.
+    #[arg(long, value_enum, default_value_t)]
+    pub ascii_encoding: pdf::AsciiEncoding,
+
+    /// The PDF version declared in the header: "auto" for the lowest version supporting the
+    /// features used, or "2.0" for organizations requiring PDF 2.0. Encrypted documents are
+    /// always PDF 2.0.
+    #[arg(long, value_enum, default_value_t)]
+    pub pdf_version: pdf::PdfVersion,
+
+    /// Serialize the images into memory in the given number of threads (0 for one per processor
+    /// core) while writing the document, which speeds up writing documents with many images.
+    #[arg(long, default_value_t = 1)]
+    pub write_threads: usize,
+
+    /// Output a tagged PDF with a structure tree for accessibility.
+    #[arg(long)]
+    pub tagged: bool,
+
+    /// Put the images and the text drawn onto the pages (stamps and page numbers) into separate
+    /// layers, which viewers can show and hide independently.
+    #[arg(long)]
+    pub layers: bool,
+
+    /// Add a clickable link to a page, as "page=N rect=LEFTxTOPxWIDTHxHEIGHT url=URL" or
+    /// "page=N rect=LEFTxTOPxWIDTHxHEIGHT goto=PAGE". The rectangle is given in points from the
+    /// top left corner of the page. Can be passed multiple times.
+    #[arg(long)]
+    pub link: Vec<LinkSpec>,
+
+    /// Define a named destination, which links from outside the document (e.g. to
+    /// document.pdf#NAME) can lead to, as NAME=PAGE with the page counted from 1. Can be passed
+    /// multiple times.
+    #[arg(long)]
+    pub destination: Vec<DestinationSpec>,
+
+    /// Define a named destination for the page of each image, named "file:" followed by the file
+    /// name of the image without its extension, e.g. file:scan_003.
+    #[arg(long)]
+    pub file_destinations: bool,
+
+    /// The application recorded as having produced the PDF document; empty to record none.
+    #[arg(long, default_value = jpeg2pdf::PRODUCER)]
+    pub producer: String,
+
+    /// The application recorded as having created the original document.
+    #[arg(long)]
+    pub creator: Option<String>,
+
+    /// The creation date recorded in the document: a date and time as in RFC 3339 (e.g.
+    /// 2024-05-01T12:34:56+02:00), "now" (the time given in the environment variable
+    /// SOURCE_DATE_EPOCH if it is set) or "none", which keeps the output reproducible.
+    #[arg(long, default_value = "none")]
+    pub date: DateSpec,
+
+    /// Derive the permanent part of the document ID recorded in the trailer from the given text
+    /// (along with the file name of the document) instead of from the content, so that it stays
+    /// the same when the document is built again from changed images or with another date.
+    #[arg(long)]
+    pub id_seed: Option<String>,
+
+    /// Re-read each PDF file once written and check its structure: that the cross-reference table
+    /// points at the objects, that the stream lengths are correct and that the embedded image data
+    /// matches the input.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Write a JSON manifest of the written documents to the given file, listing for each page
+    /// the source image, the IDs and byte offsets of its objects, and the SHA-256 digest,
+    /// dimensions and resolution of each embedded image.
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub background_opts: BackgroundOpts,
+
+    #[command(flatten)]
+    pub stamp_opts: StampOpts,
+
+    #[command(flatten)]
+    pub page_number_opts: PageNumberOpts,
+
+    #[command(flatten)]
+    pub caption_opts: CaptionOpts,
+
+    #[command(flatten)]
+    pub encryption_opts: EncryptionOpts,
+
+    #[command(flatten)]
+    pub view_opts: ViewOpts,
+}
+
+#[derive(Args)]
+pub struct ReportOpts {
+    /// Once done, output a report on each input image and output file as JSON to standard output.
+    #[arg(long)]
+    pub json: bool,
+
+    /// Fail instead of continuing with an assumption whenever a warning arises.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Do not output warnings and notes at the end of the run.
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+#[derive(Args)]
+pub struct ViewOpts {
+    /// Ask the viewer to resize its window to fit the first page.
+    #[arg(long)]
+    pub fit_window: bool,
+
+    /// Ask the viewer to show two pages side by side, with odd-numbered pages on the right.
+    #[arg(long)]
+    pub two_page_view: bool,
+
+    /// The order in which the pages are read: "rtl" (e.g. for manga) asks the viewer to place
+    /// pages shown side by side from right to left.
+    #[arg(long, value_enum, default_value_t)]
+    pub reading_direction: pdf::ReadingDirection,
+
+    /// Ask the viewer to open the document at the given page (counted from 1), fitted into the
+    /// window.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub open_at_page: Option<u64>,
+
+    /// Ask the viewer to hide its toolbar.
+    #[arg(long)]
+    pub hide_toolbar: bool,
+
+    /// Present the document as a slideshow: open it in full-screen mode, change pages using a
+    /// transition effect and advance to the next page automatically.
+    #[arg(long)]
+    pub presentation: bool,
+
+    /// The transition effect between pages in presentation mode.
+    #[arg(long, value_enum, default_value_t, requires = "presentation")]
+    pub transition: pdf::TransitionStyle,
+
+    /// How long the transition between pages takes in presentation mode, in seconds.
+    #[arg(long, default_value = "1", requires = "presentation")]
+    pub transition_duration: Seconds,
+
+    /// How long each page is shown in presentation mode before advancing to the next one, in
+    /// seconds; 0 to only advance manually.
+    #[arg(long, default_value = "5", requires = "presentation")]
+    pub slide_duration: Seconds,
+}
+
+#[derive(Args)]
+pub struct BackgroundOpts {
+    /// A JPEG image, e.g. a letterhead, to draw underneath the image on each page, stretched to
+    /// fill the page. The image is multiplied onto it, so that the background shows through
+    /// wherever the image is white.
+    #[arg(long)]
+    pub background: Option<PathBuf>,
+
+    /// The opacity of the background in percent.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub background_opacity: u8,
+}
+
+#[derive(Args)]
+pub struct StampOpts {
+    /// Text to stamp onto each page, e.g. "CONFIDENTIAL".
+    #[arg(long)]
+    pub stamp: Option<String>,
+
+    /// Where to place the stamp on the page.
+    #[arg(long, value_enum, default_value_t)]
+    pub stamp_position: StampPosition,
+
+    /// The font size of the stamp in points.
+    #[arg(long, default_value_t = 48.0, value_parser = parse_positive_number)]
+    pub stamp_size: f64,
+
+    /// The opacity of the stamp in percent.
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub stamp_opacity: u8,
+
+    /// The counterclockwise rotation of the stamp in degrees.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub stamp_rotation: f64,
+}
+impl StampOpts {
+    pub fn to_stamp(&self) -> Option<Stamp> {
+        let text = self.stamp.as_ref()?;
+        Some(Stamp {
+            text: text.clone(),
+            position: self.stamp_position,
+            font_size: self.stamp_size,
+            rotation_deg: self.stamp_rotation,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct PageNumberOpts {
+    /// Number the pages using the given text, in which {page} is replaced by the page number and
+    /// {total} by the number of pages, e.g. "Page {page} of {total}" to produce "Page 3 of 10".
+    #[arg(long)]
+    pub page_numbers: Option<String>,
+
+    /// Where to place the page numbers on the page.
+    #[arg(long, value_enum, default_value_t = StampPosition::Bottom)]
+    pub page_number_position: StampPosition,
+
+    /// The font size of the page numbers in points.
+    #[arg(long, default_value_t = 10.0, value_parser = parse_positive_number)]
+    pub page_number_size: f64,
+}
+impl PageNumberOpts {
+    pub fn to_page_numbers(&self) -> Option<page_numbers::PageNumbers> {
+        let format = self.page_numbers.as_ref()?;
+        Some(page_numbers::PageNumbers {
+            format: format.clone(),
+            position: self.page_number_position,
+            font_size: self.page_number_size,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct CaptionOpts {
+    /// Caption each image using the given text, in which {filename} is replaced by the file name,
+    /// {date} by the date the image was taken (from its Exif metadata, or else the modification
+    /// time of the file) and {page} by the page number, e.g. "{filename} — {date}". The image is
+    /// shrunk to make room for the caption beneath it.
+    #[arg(long)]
+    pub caption: Option<String>,
+
+    /// The font size of the captions in points.
+    #[arg(long, default_value_t = 10.0, value_parser = parse_positive_number)]
+    pub caption_size: f64,
+}
+impl CaptionOpts {
+    pub fn to_caption(&self) -> Option<caption::Caption> {
+        let format = self.caption.as_ref()?;
+        Some(caption::Caption {
+            format: format.clone(),
+            font_size: self.caption_size,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct EncryptionOpts {
+    /// Encrypt the document with AES-256 (which requires PDF 2.0) such that it can only be opened
+    /// with the given password or the owner password.
+    #[arg(long, conflicts_with = "ascii_encoding")]
+    pub user_password: Option<String>,
+
+    /// Encrypt the document with AES-256 (which requires PDF 2.0) such that whoever opens it with
+    /// the given password is not bound by --deny. Defaults to the user password; with only an
+    /// owner password, anyone can open the document, subject to --deny.
+    #[arg(long, conflicts_with = "ascii_encoding")]
+    pub owner_password: Option<String>,
+
+    /// Ask viewers to deny the given operations to users who open an encrypted document without
+    /// the owner password, as a comma-separated list.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub deny: Vec<encrypt::Permission>,
+}
+impl EncryptionOpts {
+    pub fn to_encryption(&self) -> Option<encrypt::Encryption> {
+        if self.user_password.is_none() && self.owner_password.is_none() {
+            return None;
+        }
+        let user_password = self.user_password.as_deref().unwrap_or("");
+        let owner_password = self.owner_password.as_deref().unwrap_or(user_password);
+        let permissions: Vec<encrypt::Permission> = encrypt::Permission::ALL.into_iter()
+            .filter(|permission| !self.deny.contains(permission))
+            .collect();
+        let mut random = [0; encrypt::RANDOM_LEN];
+        getrandom::getrandom(&mut random)
+            .expect("failed to obtain random bytes for the encryption keys");
+        Some(encrypt::Encryption::new(user_password, owner_password, &permissions, &random))
+    }
+}
+
+#[derive(Args)]
+pub struct ConvertOpts {
+    #[command(flatten)]
+    pub image_opts: ImageOpts,
+
+    #[command(flatten)]
+    pub document_opts: DocumentOpts,
+
+    #[command(flatten)]
+    pub report_opts: ReportOpts,
+
+    pub input_jpeg_path: PathBuf,
+    pub output_pdf_path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct MergeOpts {
+    #[command(flatten)]
+    pub image_opts: ImageOpts,
+
+    #[command(flatten)]
+    pub document_opts: DocumentOpts,
+
+    #[command(flatten)]
+    pub report_opts: ReportOpts,
+
+    /// The order in which the images are placed onto pages.
+    #[arg(long, value_enum, default_value_t)]
+    pub collate: Collate,
+
+    /// Insert a blank page after the given page number (after collation; 0 for the very
+    /// beginning). Can be passed multiple times.
+    #[arg(long)]
+    pub insert_blank_after: Vec<usize>,
+
+    /// The size of inserted blank pages in points, as WIDTHxHEIGHT. By default, a blank page has
+    /// the same size as the page before it.
+    #[arg(long)]
+    pub blank_page_size: Option<PageSize>,
+
+    /// Start the document with a table of contents listing the images and linking to their pages.
+    #[arg(long)]
+    pub toc: bool,
+
+    /// Omit images that appear to be blank (after collation, before inserting blank pages).
+    #[arg(long)]
+    pub skip_blank: bool,
+
+    /// The share of the image (in percent) that must differ from the background for the image not
+    /// to be considered blank.
+    #[arg(long, default_value_t = 0.1)]
+    pub blank_threshold: f64,
+
+    /// Omit images that appear to be duplicates of the preceding image.
+    #[arg(long)]
+    pub skip_duplicates: bool,
+
+    /// The maximum number of bits (out of 256) by which the perceptual hashes of two images may
+    /// differ for them to be considered duplicates.
+    #[arg(long, default_value_t = 8)]
+    pub duplicate_threshold: u32,
+
+    /// Write multiple PDF files with at most the given number of pages each, numbered like
+    /// OUTPUT_001.pdf, OUTPUT_002.pdf, etc.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["toc", "link", "destination"])]
+    pub split_every: Option<u64>,
+
+    /// Write multiple PDF files of at most approximately the given size in megabytes
+    /// (1 MB = 1000000 bytes) each, numbered like OUTPUT_001.pdf, OUTPUT_002.pdf, etc.
+    #[arg(long, value_parser = parse_positive_number, conflicts_with_all = ["toc", "link", "destination"])]
+    pub split_max_size: Option<f64>,
+
+    /// Place multiple images onto each page as COLUMNSxROWS (e.g. 2x1 or 2x2), for printing proof
+    /// sheets. Each image is scaled down to fit its cell without distortion. Happens after
+    /// inserting blank pages, which leave their cell empty.
+    #[arg(long, conflicts_with = "caption")]
+    pub nup: Option<NUpLayout>,
+
+    /// Arrange the images for printing as a booklet: two images side by side on each page, in
+    /// the order in which printing the pages on both sides of the paper and folding the stack in
+    /// the middle yields the images in order. Blank pages are added to make the number of images a
+    /// multiple of four.
+    #[arg(long, conflicts_with_all = ["nup", "caption", "toc"])]
+    pub booklet: bool,
+
+    /// How the printer turns the paper over to print the back sides of a booklet.
+    #[arg(long, value_enum, default_value_t, requires = "booklet")]
+    pub booklet_flip: BookletFlip,
+
+    /// The size of the pages onto which multiple images are placed with --nup or --booklet, in
+    /// points, as WIDTHxHEIGHT. By default, A4 in landscape if there are more columns than rows
+    /// (as for a booklet) and in portrait otherwise.
+    #[arg(long)]
+    pub nup_sheet_size: Option<PageSize>,
+
+    /// The space between the images placed onto a page with --nup or --booklet and around the
+    /// edges of the page, with a unit (mm, cm, in or pt).
+    #[arg(long, default_value = "5mm")]
+    pub nup_gutter: PhysicalLength,
+
+    /// The images to place onto pages. Options can be given for individual images in brackets,
+    /// e.g. scan1.jpg[rotate=90,dpi=300]; see the README for the available options.
+    #[arg(required = true)]
+    pub input_jpeg_paths: Vec<InputSpec>,
+    pub output_pdf_path: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Collate {
+    /// Pages are output in the order in which the images are given.
+    #[default]
+    InOrder,
+
+    /// The first half of the images are front sides in order, the second half are back sides in
+    /// reverse order (as obtained when turning over a stack of paper in a simplex scanner).
+    DuplexReverseBacks,
+}
+
+/// How the printer turns the paper over to print the back sides of a booklet.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum BookletFlip {
+    /// The paper is turned over along its short edge, which keeps the back sides upright.
+    #[default]
+    ShortEdge,
+
+    /// The paper is turned over along its long edge, which requires the back sides to be rotated
+    /// by 180 degrees.
+    LongEdge,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum PixelAspect {
+    /// The image is stretched on the page to match its densities.
+    #[default]
+    Keep,
+
+    /// The image is resampled such that both densities are equal.
+    Square,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum AssumedColorSpace {
+    Gray,
+    Rgb,
+    Cmyk,
+}
+impl AssumedColorSpace {
+    pub fn to_color_space(self) -> ColorSpace {
+        match self {
+            Self::Gray => ColorSpace::Grayscale,
+            Self::Rgb => ColorSpace::Rgb,
+            Self::Cmyk => ColorSpace::Cmyk,
+        }
+    }
+}
+
+/// A decode array (pairs of minimum and maximum values), given as comma-separated numbers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeArray(pub Vec<f64>);
+impl DecodeArray {
+    pub fn to_pdf(&self) -> String {
+        let numbers: Vec<String> = self.0.iter()
+            .map(|&n| content::format_number(n))
+            .collect();
+        format!("[ {} ]", numbers.join(" "))
+    }
+}
+impl FromStr for DecodeArray {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numbers: Vec<f64> = s.split(',')
+            .map(|n| n.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid decode array {:?}: {}", s, e))?;
+        if numbers.iter().any(|n| !n.is_finite()) {
+            return Err(format!("decode array {:?} contains a non-finite number", s));
+        }
+        Ok(Self(numbers))
+    }
+}
+
+/// A color to be made transparent, given as `RRGGBB` or `RRGGBB:TOLERANCE`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TransparentColor {
+    pub rgb: [u8; 3],
+    /// How far each component may deviate from the color and still be transparent.
+    pub tolerance: u8,
+}
+impl TransparentColor {
+    /// Returns the color key mask of an image in the given color space with the given number of
+    /// bits per component, or `None` if the color cannot be expressed in that color space.
+    ///
+    /// Grayscale images take the luma of the color.
+    pub fn to_color_key_mask(self, color_space: ColorSpace, bits_per_component: u8) -> Option<Vec<u16>> {
+        let [red, green, blue] = self.rgb.map(f64::from);
+        let components = match color_space {
+            ColorSpace::Rgb => vec![red, green, blue],
+            ColorSpace::Grayscale => vec![0.299 * red + 0.587 * green + 0.114 * blue],
+            ColorSpace::Cmyk|ColorSpace::Other(_) => return None,
+        };
+        // scale from 8 bits to the bit depth of the image (e.g. 12)
+        let max_sample = f64::from((1u16 << bits_per_component) - 1);
+        let scale = max_sample / 255.0;
+        let tolerance = f64::from(self.tolerance);
+        let mut mask = Vec::with_capacity(2 * components.len());
+        for component in components {
+            mask.push(((component - tolerance).max(0.0) * scale).round() as u16);
+            mask.push(((component + tolerance).min(255.0) * scale).round() as u16);
+        }
+        Some(mask)
+    }
+}
+impl FromStr for TransparentColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (color_str, tolerance_str) = match s.split_once(':') {
+            Some((color_str, tolerance_str)) => (color_str, Some(tolerance_str)),
+            None => (s, None),
+        };
+        if color_str.len() != 6 || !color_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("color {:?} not in the format RRGGBB", color_str));
+        }
+        let component = |index: usize| u8::from_str_radix(&color_str[2*index..2*index+2], 16).unwrap();
+        let tolerance = match tolerance_str {
+            Some(tolerance_str) => tolerance_str.parse()
+                .map_err(|e| format!("invalid color tolerance {:?}: {}", tolerance_str, e))?,
+            None => 0,
+        };
+        Ok(Self {
+            rgb: [component(0), component(1), component(2)],
+            tolerance,
+        })
+    }
+}
+
+/// A density in dots per inch, given as `DPI` or `XDPIxYDPI`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Density {
+    pub x: u16,
+    pub y: u16,
+}
+impl FromStr for Density {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x_str, y_str) = s.split_once('x')
+            .unwrap_or((s, s));
+        let x: u16 = x_str.parse()
+            .map_err(|e| format!("invalid density {:?}: {}", x_str, e))?;
+        let y: u16 = y_str.parse()
+            .map_err(|e| format!("invalid density {:?}: {}", y_str, e))?;
+        if x == 0 || y == 0 {
+            return Err(format!("density {:?} is zero", s));
+        }
+        Ok(Self { x, y })
+    }
+}
+
+/// A clockwise rotation by a multiple of 90 degrees.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rotation {
+    pub degrees: u16,
+}
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let degrees: i64 = s.parse()
+            .map_err(|e| format!("invalid rotation {:?}: {}", s, e))?;
+        if degrees % 90 != 0 {
+            return Err(format!("rotation {:?} is not a multiple of 90 degrees", s));
+        }
+        Ok(Self { degrees: u16::try_from(degrees.rem_euclid(360)).unwrap() })
+    }
+}
+
+/// An input image along with options that override those given for all images, given as
+/// `PATH[KEY=VALUE,KEY=VALUE,...]` or just `PATH`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputSpec {
+    pub path: PathBuf,
+    pub overrides: Vec<ImageOverride>,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageOverride {
+    Crop(Crop),
+    Width(PhysicalLength),
+    Height(PhysicalLength),
+    Dpi(Density),
+    PixelAspect(PixelAspect),
+    Rotate(Rotation),
+    AssumeColorspace(AssumedColorSpace),
+    AltText(String),
+    TransparentColor(TransparentColor),
+}
+impl InputSpec {
+    /// Returns the image options for this input, i.e. the given options with the overrides applied.
+    pub fn image_opts(&self, image_opts: &ImageOpts) -> ImageOpts {
+        let mut image_opts = image_opts.clone();
+        for image_override in &self.overrides {
+            match image_override {
+                ImageOverride::Crop(crop) => image_opts.crop = Some(*crop),
+                ImageOverride::Width(width) => image_opts.width = Some(*width),
+                ImageOverride::Height(height) => image_opts.height = Some(*height),
+                ImageOverride::Dpi(density) => image_opts.dpi = Some(*density),
+                ImageOverride::PixelAspect(pixel_aspect) => image_opts.pixel_aspect = *pixel_aspect,
+                ImageOverride::Rotate(rotation) => image_opts.rotate = Some(*rotation),
+                ImageOverride::AssumeColorspace(color_space) => image_opts.assume_colorspace = Some(*color_space),
+                ImageOverride::AltText(alt_text) => {
+                    image_opts.alt_text = Some(alt_text.clone());
+                    image_opts.alt_text_sidecar = false;
+                },
+                ImageOverride::TransparentColor(color) => image_opts.transparent_color = Some(*color),
+            }
+        }
+        image_opts
+    }
+}
+impl FromStr for InputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let plain = Self {
+            path: PathBuf::from(s),
+            overrides: Vec::new(),
+        };
+        // an existing file whose name happens to end in brackets is taken as-is
+        let Some(options_str) = s.strip_suffix(']') else {
+            return Ok(plain);
+        };
+        let Some(bracket_pos) = options_str.rfind('[') else {
+            return Ok(plain);
+        };
+        if Path::new(s).exists() {
+            return Ok(plain);
+        }
+
+        let mut overrides = Vec::new();
+        for piece in options_str[bracket_pos+1..].split(',') {
+            let (key, value) = piece.split_once('=')
+                .ok_or_else(|| format!("image option {:?} not in the format KEY=VALUE", piece))?;
+            let image_override = match key {
+                "crop" => ImageOverride::Crop(value.parse()?),
+                "width" => ImageOverride::Width(value.parse()?),
+                "height" => ImageOverride::Height(value.parse()?),
+                "dpi" => ImageOverride::Dpi(value.parse()?),
+                "pixel-aspect" => ImageOverride::PixelAspect(PixelAspect::from_str(value, true)?),
+                "rotate" => ImageOverride::Rotate(value.parse()?),
+                "assume-colorspace" => ImageOverride::AssumeColorspace(AssumedColorSpace::from_str(value, true)?),
+                "alt-text" => ImageOverride::AltText(value.to_owned()),
+                "transparent-color" => ImageOverride::TransparentColor(value.parse()?),
+                other => return Err(format!("unknown image option {:?}", other)),
+            };
+            overrides.push(image_override);
+        }
+        Ok(Self {
+            path: PathBuf::from(&options_str[..bracket_pos]),
+            overrides,
+        })
+    }
+}
+
+/// Parses a number that is finite and greater than zero, such as a font size.
+pub fn parse_positive_number(s: &str) -> Result<f64, String> {
+    let number: f64 = s.parse()
+        .map_err(|e| format!("invalid number {:?}: {}", s, e))?;
+    if !(number > 0.0 && number.is_finite()) {
+        return Err(format!("number {:?} is not finite and positive", s));
+    }
+    Ok(number)
+}
+
+/// A duration given in (possibly fractional) seconds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Seconds {
+    pub milliseconds: u32,
+}
+impl FromStr for Seconds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let seconds: f64 = s.parse()
+            .map_err(|e| format!("invalid number of seconds {:?}: {}", s, e))?;
+        if !(0.0..=86400.0).contains(&seconds) {
+            return Err(format!("number of seconds {:?} not between 0 and 86400", s));
+        }
+        Ok(Self {
+            milliseconds: (seconds * 1000.0).round() as u32,
+        })
+    }
+}
+
+/// The creation date to record in a document.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DateSpec {
+    Now,
+    None,
+    At(pdf::Date),
+}
+impl DateSpec {
+    pub fn to_date(self) -> Option<pdf::Date> {
+        match self {
+            Self::Now => {
+                // reproducible builds may fix the current time
+                let unix_time = match std::env::var("SOURCE_DATE_EPOCH") {
+                    Ok(epoch) => epoch.trim().parse()
+                        .unwrap_or_else(|e| panic!("invalid SOURCE_DATE_EPOCH {:?}: {}", epoch, e)),
+                    Err(_) => {
+                        let since_epoch = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .expect("system clock is set before 1970");
+                        i64::try_from(since_epoch.as_secs()).unwrap()
+                    },
+                };
+                Some(pdf::Date::from_unix_time(unix_time))
+            },
+            Self::None => None,
+            Self::At(date) => Some(date),
+        }
+    }
+}
+impl FromStr for DateSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "now" => return Ok(Self::Now),
+            "none" => return Ok(Self::None),
+            _ => {},
+        }
+
+        // YYYY-MM-DDTHH:MM:SS, optionally with fractional seconds, then Z or +HH:MM/-HH:MM
+        let format_error = || format!("date {:?} not in the format YYYY-MM-DDTHH:MM:SS+HH:MM (or \"now\" or \"none\")", s);
+        let bytes = s.as_bytes();
+        if bytes.len() < 20 || !s.is_ascii() {
+            return Err(format_error());
+        }
+        let separators_ok = bytes[4] == b'-' && bytes[7] == b'-'
+            && matches!(bytes[10], b'T'|b't'|b' ')
+            && bytes[13] == b':' && bytes[16] == b':';
+        if !separators_ok {
+            return Err(format_error());
+        }
+        let number = |range: std::ops::Range<usize>| -> Result<u16, String> {
+            let digits = &s[range];
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format_error());
+            }
+            digits.parse().map_err(|_| format_error())
+        };
+        let year = number(0..4)?;
+        let month = number(5..7)?;
+        let day = number(8..10)?;
+        let hour = number(11..13)?;
+        let minute = number(14..16)?;
+        let second = number(17..19)?;
+
+        let mut rest = &s[19..];
+        if let Some(fraction) = rest.strip_prefix('.') {
+            let digit_count = fraction.bytes().take_while(|b| b.is_ascii_digit()).count();
+            if digit_count == 0 {
+                return Err(format_error());
+            }
+            rest = &fraction[digit_count..];
+        }
+        let utc_offset_minutes: i16 = match rest {
+            "Z"|"z" => 0,
+            _ if rest.len() == 6 && (rest.starts_with('+') || rest.starts_with('-')) && rest.as_bytes()[3] == b':' => {
+                let offset_hours = number(s.len() - 5..s.len() - 3)?;
+                let offset_minutes = number(s.len() - 2..s.len())?;
+                if offset_hours > 23 || offset_minutes > 59 {
+                    return Err(format!("invalid time zone offset in date {:?}", s));
+                }
+                let offset = i16::try_from(offset_hours * 60 + offset_minutes).unwrap();
+                if rest.starts_with('-') { -offset } else { offset }
+            },
+            _ => return Err(format_error()),
+        };
+
+        let days_in_month = match month {
+            1|3|5|7|8|10|12 => 31,
+            4|6|9|11 => 30,
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            _ => return Err(format!("invalid month in date {:?}", s)),
+        };
+        if day == 0 || day > days_in_month || hour > 23 || minute > 59 || second > 60 {
+            return Err(format!("invalid date or time in {:?}", s));
+        }
+        Ok(Self::At(pdf::Date {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: hour as u8,
+            minute: minute as u8,
+            // PDF dates have no leap seconds
+            second: second.min(59) as u8,
+            utc_offset_minutes,
+        }))
+    }
+}
+
+/// A named destination to define.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DestinationSpec {
+    pub name: String,
+    /// The page the destination leads to, counted from 1.
+    pub page: usize,
+}
+impl FromStr for DestinationSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, page) = s.rsplit_once('=')
+            .ok_or_else(|| format!("destination {:?} not in the format NAME=PAGE", s))?;
+        if name.is_empty() {
+            return Err(format!("destination {:?} has an empty name", s));
+        }
+        let page: usize = page.parse()
+            .map_err(|e| format!("invalid page number {:?}: {}", page, e))?;
+        if page == 0 {
+            return Err("page numbers start at 1".to_owned());
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            page,
+        })
+    }
+}
+
+/// A link to add to a page.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkSpec {
+    /// The page on which the link is placed, counted from 1.
+    pub page: usize,
+    pub left_pt: f64,
+    pub top_pt: f64,
+    pub width_pt: f64,
+    pub height_pt: f64,
+    pub target: LinkSpecTarget,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkSpecTarget {
+    Url(String),
+    /// A page of the document, counted from 1.
+    Page(usize),
+}
+impl FromStr for LinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut page = None;
+        let mut rect = None;
+        let mut target = None;
+        for piece in s.split_whitespace() {
+            let (key, value) = piece.split_once('=')
+                .ok_or_else(|| format!("link property {:?} not in the format KEY=VALUE", piece))?;
+            match key {
+                "page" => {
+                    let number: usize = value.parse()
+                        .map_err(|e| format!("invalid page number {:?}: {}", value, e))?;
+                    if number == 0 {
+                        return Err("page numbers start at 1".to_owned());
+                    }
+                    page = Some(number);
+                },
+                "rect" => {
+                    let numbers: Vec<f64> = value.split('x')
+                        .map(|n| n.parse::<f64>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("invalid link rectangle {:?}: {}", value, e))?;
+                    if numbers.len() != 4 {
+                        return Err(format!("link rectangle {:?} not in the format LEFTxTOPxWIDTHxHEIGHT", value));
+                    }
+                    rect = Some((numbers[0], numbers[1], numbers[2], numbers[3]));
+                },
+                "url" => {
+                    target = Some(LinkSpecTarget::Url(value.to_owned()));
+                },
+                "goto" => {
+                    let number: usize = value.parse()
+                        .map_err(|e| format!("invalid page number {:?}: {}", value, e))?;
+                    if number == 0 {
+                        return Err("page numbers start at 1".to_owned());
+                    }
+                    target = Some(LinkSpecTarget::Page(number));
+                },
+                other => return Err(format!("unknown link property {:?}", other)),
+            }
+        }
+
+        let page = page.ok_or("link has no page")?;
+        let (left_pt, top_pt, width_pt, height_pt) = rect.ok_or("link has no rect")?;
+        let target = target.ok_or("link has neither url nor goto")?;
+        Ok(Self { page, left_pt, top_pt, width_pt, height_pt, target })
+    }
+}
+
+/// A page size in PDF points, given as `WIDTHxHEIGHT`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PageSize {
+    pub width_pt: u64,
+    pub height_pt: u64,
+}
+impl FromStr for PageSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width_str, height_str) = s.split_once('x')
+            .ok_or_else(|| format!("page size {:?} not in the format WIDTHxHEIGHT", s))?;
+        let width_pt = width_str.parse()
+            .map_err(|e| format!("invalid page width {:?}: {}", width_str, e))?;
+        let height_pt = height_str.parse()
+            .map_err(|e| format!("invalid page height {:?}: {}", height_str, e))?;
+        Ok(Self { width_pt, height_pt })
+    }
+}
+
+/// The number of columns and rows of pages on each sheet, given as `COLUMNSxROWS`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NUpLayout {
+    pub columns: u32,
+    pub rows: u32,
+}
+impl FromStr for NUpLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (columns_str, rows_str) = s.split_once('x')
+            .ok_or_else(|| format!("layout {:?} not in the format COLUMNSxROWS", s))?;
+        let parse_count = |count_str: &str, what: &str| -> Result<u32, String> {
+            let count: u32 = count_str.parse()
+                .map_err(|e| format!("invalid number of {} {:?}: {}", what, count_str, e))?;
+            if count == 0 {
+                return Err(format!("number of {} must not be zero", what));
+            }
+            Ok(count)
+        };
+        Ok(Self {
+            columns: parse_count(columns_str, "columns")?,
+            rows: parse_count(rows_str, "rows")?,
+        })
+    }
+}
+
+/// A physical length, given with a unit (mm, cm, in or pt).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicalLength {
+    pub pt: f64,
+}
+impl FromStr for PhysicalLength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let units = [
+            ("mm", 72.0 / 25.4),
+            ("cm", 72.0 / 2.54),
+            ("in", 72.0),
+            ("pt", 1.0),
+        ];
+        for (unit, pt_per_unit) in units {
+            if let Some(number_str) = s.strip_suffix(unit) {
+                let number: f64 = number_str.parse()
+                    .map_err(|e| format!("invalid length {:?}: {}", number_str, e))?;
+                if !(number > 0.0 && number.is_finite()) {
+                    return Err(format!("length {:?} is not positive", s));
+                }
+                return Ok(Self { pt: number * pt_per_unit });
+            }
+        }
+        Err(format!("length {:?} has no unit (mm, cm, in or pt)", s))
+    }
+}
+
+/// A length measured in pixels or as a percentage of an image dimension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageLength {
+    Pixels(u64),
+    Percent(f64),
+}
+impl ImageLength {
+    pub fn to_pixels(self, dimension_px: u64) -> u64 {
+        match self {
+            Self::Pixels(px) => px,
+            Self::Percent(percent) => ((dimension_px as f64) * percent / 100.0).round() as u64,
+        }
+    }
+}
+impl FromStr for ImageLength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(percent_str) = s.strip_suffix('%') {
+            let percent: f64 = percent_str.parse()
+                .map_err(|e| format!("invalid percentage {:?}: {}", percent_str, e))?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("percentage {} is not between 0 and 100", percent));
+            }
+            Ok(Self::Percent(percent))
+        } else {
+            let px = s.parse()
+                .map_err(|e| format!("invalid pixel count {:?}: {}", s, e))?;
+            Ok(Self::Pixels(px))
+        }
+    }
+}
+
+/// The area of an image to show, given as `LEFTxTOPxWIDTHxHEIGHT`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crop {
+    pub left: ImageLength,
+    pub top: ImageLength,
+    pub width: ImageLength,
+    pub height: ImageLength,
+}
+impl Crop {
+    /// Calculates the crop area in pixels for an image of the given size.
+    pub fn to_pixel_rect(self, image_width_px: u64, image_height_px: u64) -> Result<PixelRect, String> {
+        let rect = PixelRect {
+            left: self.left.to_pixels(image_width_px),
+            top: self.top.to_pixels(image_height_px),
+            width: self.width.to_pixels(image_width_px),
+            height: self.height.to_pixels(image_height_px),
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return Err("crop area is empty".to_owned());
+        }
+        if rect.left + rect.width > image_width_px || rect.top + rect.height > image_height_px {
+            return Err(format!(
+                "crop area {}x{}x{}x{} exceeds image size {}x{}",
+                rect.left, rect.top, rect.width, rect.height, image_width_px, image_height_px,
+            ));
+        }
+        Ok(rect)
+    }
+}
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pieces: Vec<&str> = s.split('x').collect();
+        if pieces.len() != 4 {
+            return Err(format!("crop area {:?} not in the format LEFTxTOPxWIDTHxHEIGHT", s));
+        }
+        Ok(Self {
+            left: pieces[0].parse()?,
+            top: pieces[1].parse()?,
+            width: pieces[2].parse()?,
+            height: pieces[3].parse()?,
+        })
+    }
+}
+
+/// The height of the pages into which a tall image is sliced, as a physical length or in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SliceHeight {
+    Physical(PhysicalLength),
+    Pixels(u64),
+}
+impl SliceHeight {
+    /// Calculates the height in pixels for an image of the given height in pixels and in points.
+    pub fn to_pixels(self, image_height_px: u64, image_height_pt: u64) -> u64 {
+        match self {
+            Self::Physical(length) => ((image_height_px as f64) * length.pt / (image_height_pt as f64)).round().max(1.0) as u64,
+            Self::Pixels(px) => px,
+        }
+    }
+}
+impl FromStr for SliceHeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(px_str) = s.strip_suffix("px") {
+            let px: u64 = px_str.parse()
+                .map_err(|e| format!("invalid pixel count {:?}: {}", px_str, e))?;
+            if px == 0 {
+                return Err("pixel count is zero".to_owned());
+            }
+            Ok(Self::Pixels(px))
+        } else {
+            let length = s.parse()
+                .map_err(|e| format!("{} or px", e))?;
+            Ok(Self::Physical(length))
+        }
+    }
+}
+
+/// A rectangular area of an image in pixels, measured from the top left corner.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PixelRect {
+    pub left: u64,
+    pub top: u64,
+    pub width: u64,
+    pub height: u64,
+}
+
+#[derive(Args)]
+pub struct BatchOpts {
+    #[command(flatten)]
+    pub image_opts: ImageOpts,
+
+    #[command(flatten)]
+    pub document_opts: DocumentOpts,
+
+    #[command(flatten)]
+    pub report_opts: ReportOpts,
+
+    /// The directory into which to write the PDF files. By default, each PDF file is written next
+    /// to its JPEG image.
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Record which images have been converted and skip images that have already been converted
+    /// by a previous run.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// The file in which to record which images have been converted. By default, this is
+    /// jpeg2pdf-batch.state in the output directory (or in the current directory if no output
+    /// directory is given).
+    #[arg(long, requires = "resume")]
+    pub state_file: Option<PathBuf>,
+
+    /// What to do when an image cannot be converted. The exit status is 1 if some images could
+    /// not be converted and 2 if none could.
+    #[arg(long, value_enum, default_value_t)]
+    pub on_error: batch::OnError,
+
+    /// The images to convert. Options can be given for individual images in brackets, e.g.
+    /// scan1.jpg[rotate=90,dpi=300]; see the README for the available options.
+    #[arg(required = true)]
+    pub input_jpeg_paths: Vec<InputSpec>,
+}
+
+#[derive(Args)]
+pub struct WatchOpts {
+    #[command(flatten)]
+    pub image_opts: ImageOpts,
+
+    #[command(flatten)]
+    pub document_opts: DocumentOpts,
+
+    #[command(flatten)]
+    pub report_opts: ReportOpts,
+
+    /// How often to look for new images, in seconds.
+    #[arg(long, default_value = "2")]
+    pub poll_interval: Seconds,
+
+    /// How long an image must remain unchanged before it is converted, in seconds, so that images
+    /// which are still being written are not picked up.
+    #[arg(long, default_value = "5")]
+    pub settle_time: Seconds,
+
+    /// The directory to monitor.
+    pub input_dir: PathBuf,
+
+    /// The directory into which to write the PDF files.
+    pub output_dir: PathBuf,
+}
+
+#[derive(Args)]
+pub struct InspectOpts {
+    /// Work around common kinds of damage to JPEG files instead of failing.
+    #[arg(long)]
+    pub repair: bool,
+
+    /// How long to wait for an image given as an HTTP(S) URL to be downloaded, in seconds.
+    #[arg(long, default_value = "30")]
+    pub timeout: Seconds,
+
+    #[arg(required = true)]
+    pub input_jpeg_paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct LintOpts {
+    /// Output the findings as JSON to standard output.
+    #[arg(long)]
+    pub json: bool,
+
+    /// How long to wait for an image given as an HTTP(S) URL to be downloaded, in seconds.
+    #[arg(long, default_value = "30")]
+    pub timeout: Seconds,
+
+    #[arg(required = true)]
+    pub input_jpeg_paths: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct PreflightOpts {
+    /// Check against the given targets, as a comma-separated list; all of them by default.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub target: Vec<preflight::Target>,
+
+    /// Treat the images as having the given density in dots per inch, as DPI or XDPIxYDPI, as
+    /// when converting them with --dpi.
+    #[arg(long)]
+    pub dpi: Option<Density>,
+
+    /// Infer the density of images which do not declare one from the given paper size, as when
+    /// converting them with --infer-dpi-for.
+    #[arg(long, value_enum, conflicts_with = "dpi")]
+    pub infer_dpi_for: Option<jpeg2pdf::PaperSize>,
+
+    /// Work around common kinds of damage to JPEG files instead of failing.
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Output the findings as JSON to standard output.
+    #[arg(long)]
+    pub json: bool,
+
+    /// How long to wait for an image given as an HTTP(S) URL to be downloaded, in seconds.
+    #[arg(long, default_value = "30")]
+    pub timeout: Seconds,
+
+    #[arg(required = true)]
+    pub input_jpeg_paths: Vec<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum ExportFormat {
+    /// Wraps multiple JPEG images into one multi-page TIFF file with one page per image, storing
+    /// the image data unchanged (JPEG compression).
+    Tiff(ExportTiffOpts),
+}
+
+#[derive(Args)]
+pub struct ExportTiffOpts {
+    #[command(flatten)]
+    pub image_opts: ImageOpts,
+
+    #[command(flatten)]
+    pub report_opts: ReportOpts,
+
+    /// The images to place onto pages. Options can be given for individual images in brackets,
+    /// e.g. scan1.jpg[rotate=90,dpi=300]; see the README for the available options.
+    #[arg(required = true)]
+    pub input_jpeg_paths: Vec<InputSpec>,
+    pub output_tiff_path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct CompletionsOpts {
+    /// The shell to output the completion script for.
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+
+/// Returns the path of the configuration file read if none is given on the command line.
+pub fn default_config_path() -> Option<PathBuf> {
+    if let Some(config_dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(config_dir).join(DEFAULT_CONFIG_FILE_NAME));
+    }
+    let home_dir = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home_dir).join(".config").join(DEFAULT_CONFIG_FILE_NAME))
+}
+
+/// Whether the given command or any of its subcommands has an option with the given long name.
+pub fn has_long_option(command: &clap::Command, long: &str) -> bool {
+    command.get_subcommands()
+        .any(|sc| sc.get_arguments().any(|arg| arg.get_long() == Some(long)) || has_long_option(sc, long))
+}
+
+/// Parses the command line, preceding the options given for the subcommand with those preset in
+/// the configuration file.
+pub fn parse_opts() -> Opts {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let command = Opts::command();
+    let matches = command.clone().get_matches_from(&args);
+    let opts = Opts::from_arg_matches(&matches)
+        .unwrap_or_else(|e| e.exit());
+    // the subcommand cannot be marked as required as --generate-manpage does without one
+    match (&opts.command, opts.generate_manpage) {
+        (None, false) => command.clone()
+            .error(clap::error::ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit(),
+        (Some(_), true) => command.clone()
+            .error(clap::error::ErrorKind::ArgumentConflict, "--generate-manpage cannot be used with a subcommand")
+            .exit(),
+        _ => {},
+    }
+
+    let config_path = match &opts.config {
+        Some(path) => path.clone(),
+        None => match default_config_path() {
+            Some(path) if path.is_file() => path,
+            _ => return opts,
+        },
+    };
+    let config_text = std::fs::read_to_string(&config_path)
+        .unwrap_or_else(|e| panic!("failed to read configuration file {}: {}", config_path.display(), e));
+    let config = config::Config::parse(&config_text)
+        .unwrap_or_else(|e| panic!("{}: {}", config_path.display(), e));
+
+    // the section is named after the subcommand; the options belong to the innermost one (e.g.
+    // "tiff" in "export tiff")
+    let Some(subcommand_name) = matches.subcommand_name() else {
+        // --generate-manpage
+        return opts;
+    };
+    let mut subcommand_names = vec![subcommand_name];
+    let mut subcommand = command.find_subcommand(subcommand_name).unwrap();
+    let mut subcommand_matches = matches.subcommand_matches(subcommand_name).unwrap();
+    while let Some(name) = subcommand_matches.subcommand_name() {
+        subcommand_names.push(name);
+        subcommand = subcommand.find_subcommand(name).unwrap();
+        subcommand_matches = subcommand_matches.subcommand_matches(name).unwrap();
+    }
+    let option_kind = |long: &str| subcommand.get_arguments()
+        .find(|arg| arg.get_long() == Some(long))
+        .map(|arg| match arg.get_action() {
+            ArgAction::SetTrue|ArgAction::SetFalse|ArgAction::Count => config::OptionKind::Flag,
+            _ => config::OptionKind::Value,
+        });
+    let is_known = |long: &str| has_long_option(&command, long);
+    let config_args = config.to_args(subcommand_name, option_kind, is_known)
+        .unwrap_or_else(|e| panic!("{}: {}", config_path.display(), e));
+
+    // insert the preset options right after the subcommand, so that the command line overrides them
+    let mut subcommand_pos = 0;
+    for name in &subcommand_names {
+        subcommand_pos += 1;
+        while subcommand_pos < args.len() && args[subcommand_pos] != *name {
+            if args[subcommand_pos] == "--config" {
+                subcommand_pos += 1;
+            }
+            subcommand_pos += 1;
+        }
+    }
+    let mut new_args = args[..=subcommand_pos].to_vec();
+    new_args.extend(config_args);
+    new_args.extend_from_slice(&args[subcommand_pos + 1..]);
+    Opts::parse_from(new_args)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_pixels_and_percentages() {
+        assert_eq!(
+            "10x20x50%x100".parse::<Crop>(),
+            Ok(Crop {
+                left: ImageLength::Pixels(10),
+                top: ImageLength::Pixels(20),
+                width: ImageLength::Percent(50.0),
+                height: ImageLength::Pixels(100),
+            }),
+        );
+    }
+
+    #[test]
+    fn crop_invalid() {
+        assert!("10x20x30".parse::<Crop>().is_err());
+        assert!("10x20x30x40x50".parse::<Crop>().is_err());
+        assert!("10x20x30xabc".parse::<Crop>().is_err());
+        assert!("10x20x101%x40".parse::<Crop>().is_err());
+        assert!("-1x20x30x40".parse::<Crop>().is_err());
+    }
+
+    #[test]
+    fn crop_to_pixel_rect() {
+        let crop: Crop = "25%x0x50%x10".parse().unwrap();
+        assert_eq!(
+            crop.to_pixel_rect(200, 100),
+            Ok(PixelRect { left: 50, top: 0, width: 100, height: 10 }),
+        );
+        assert!(crop.to_pixel_rect(200, 5).is_err());
+        let empty: Crop = "0x0x0x10".parse().unwrap();
+        assert!(empty.to_pixel_rect(200, 100).is_err());
+    }
+
+    #[test]
+    fn link_url_and_goto() {
+        assert_eq!(
+            "page=2 rect=10x20.5x100x50 url=https://example.com/".parse::<LinkSpec>(),
+            Ok(LinkSpec {
+                page: 2,
+                left_pt: 10.0,
+                top_pt: 20.5,
+                width_pt: 100.0,
+                height_pt: 50.0,
+                target: LinkSpecTarget::Url("https://example.com/".to_owned()),
+            }),
+        );
+        // the properties may be given in any order
+        assert_eq!(
+            "goto=3 rect=0x0x1x1 page=1".parse::<LinkSpec>().map(|link| link.target),
+            Ok(LinkSpecTarget::Page(3)),
+        );
+    }
+
+    #[test]
+    fn link_invalid() {
+        assert!("rect=0x0x1x1 goto=3".parse::<LinkSpec>().is_err());
+        assert!("page=1 goto=3".parse::<LinkSpec>().is_err());
+        assert!("page=1 rect=0x0x1x1".parse::<LinkSpec>().is_err());
+        assert!("page=0 rect=0x0x1x1 goto=3".parse::<LinkSpec>().is_err());
+        assert!("page=1 rect=0x0x1x1 goto=0".parse::<LinkSpec>().is_err());
+        assert!("page=1 rect=0x0x1 goto=3".parse::<LinkSpec>().is_err());
+        assert!("page=1 rect=0x0x1x1 goto=3 color=red".parse::<LinkSpec>().is_err());
+        assert!("page=1 rect=0x0x1x1 goto".parse::<LinkSpec>().is_err());
+    }
+
+    #[test]
+    fn date_keywords() {
+        assert_eq!("now".parse::<DateSpec>(), Ok(DateSpec::Now));
+        assert_eq!("none".parse::<DateSpec>(), Ok(DateSpec::None));
+        assert_eq!(DateSpec::None.to_date(), None);
+    }
+
+    #[test]
+    fn date_with_offset() {
+        assert_eq!(
+            "2024-02-29T13:45:06.789-05:30".parse::<DateSpec>(),
+            Ok(DateSpec::At(pdf::Date {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 13,
+                minute: 45,
+                second: 6,
+                utc_offset_minutes: -330,
+            })),
+        );
+        assert_eq!(
+            "2000-01-01 00:00:60Z".parse::<DateSpec>(),
+            Ok(DateSpec::At(pdf::Date {
+                year: 2000,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 59,
+                utc_offset_minutes: 0,
+            })),
+        );
+    }
+
+    #[test]
+    fn date_invalid() {
+        assert!("yesterday".parse::<DateSpec>().is_err());
+        assert!("2024-02-29T13:45:06".parse::<DateSpec>().is_err());
+        assert!("2023-02-29T13:45:06Z".parse::<DateSpec>().is_err());
+        assert!("1900-02-29T13:45:06Z".parse::<DateSpec>().is_err());
+        assert!("2024-13-01T00:00:00Z".parse::<DateSpec>().is_err());
+        assert!("2024-01-01T24:00:00Z".parse::<DateSpec>().is_err());
+        assert!("2024-01-01T00:00:00.Z".parse::<DateSpec>().is_err());
+        assert!("2024-01-01T00:00:00+24:00".parse::<DateSpec>().is_err());
+        assert!("2024-01-01T00:00:00+0100".parse::<DateSpec>().is_err());
+        assert!("2024-01-01T00:00:00+1:000".parse::<DateSpec>().is_err());
+    }
+
+    #[test]
+    fn input_plain_path() {
+        assert_eq!(
+            "scans/page1.jpg".parse::<InputSpec>(),
+            Ok(InputSpec { path: PathBuf::from("scans/page1.jpg"), overrides: Vec::new() }),
+        );
+    }
+
+    #[test]
+    fn input_with_overrides() {
+        assert_eq!(
+            "scans/page[1].jpg[rotate=-90,dpi=300x150,alt-text=A cat]".parse::<InputSpec>(),
+            Ok(InputSpec {
+                path: PathBuf::from("scans/page[1].jpg"),
+                overrides: vec![
+                    ImageOverride::Rotate(Rotation { degrees: 270 }),
+                    ImageOverride::Dpi(Density { x: 300, y: 150 }),
+                    ImageOverride::AltText("A cat".to_owned()),
+                ],
+            }),
+        );
+    }
+
+    #[test]
+    fn input_overrides_applied() {
+        let input: InputSpec = "a.jpg[crop=0x0x10x10,alt-text=Override]".parse().unwrap();
+        let matches = ImageOpts::augment_args(clap::Command::new("test"))
+            .get_matches_from(["test", "--alt-text-sidecar"]);
+        let image_opts = ImageOpts::from_arg_matches(&matches).unwrap();
+        assert!(image_opts.alt_text_sidecar);
+        let overridden = input.image_opts(&image_opts);
+        assert_eq!(overridden.crop, Some("0x0x10x10".parse().unwrap()));
+        assert_eq!(overridden.alt_text.as_deref(), Some("Override"));
+        assert!(!overridden.alt_text_sidecar);
+    }
+
+    #[test]
+    fn input_invalid_overrides() {
+        assert!("a.jpg[rotate=45]".parse::<InputSpec>().is_err());
+        assert!("a.jpg[dpi]".parse::<InputSpec>().is_err());
+        assert!("a.jpg[color=red]".parse::<InputSpec>().is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Page numbers drawn onto each page.
+
+
+use crate::content::Operator;
+use crate::font::{encode_win_ansi, StandardFont};
+use crate::stamp::{text_origin, StampPosition};
+
+
+/// The font in which page numbers are set.
+pub const PAGE_NUMBER_FONT: StandardFont = StandardFont::Helvetica;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageNumbers {
+    /// The text drawn onto each page, in which `{page}` is replaced by the number of the page and
+    /// `{total}` by the number of pages in the document.
+    pub format: String,
+    pub position: StampPosition,
+    pub font_size: f64,
+}
+impl PageNumbers {
+    /// Returns the text drawn onto the page with the given (one-based) number.
+    pub fn text(&self, page_number: usize, page_count: usize) -> String {
+        self.format
+            .replace("{page}", &page_number.to_string())
+            // the placeholder of earlier versions, which clap's help cannot display
+            .replace("{n}", &page_number.to_string())
+            .replace("{total}", &page_count.to_string())
+    }
+
+    /// Generates the content stream operators to draw the page number on a page of the given size.
+    ///
+    /// `font_name` is the resource name (without the leading slash) of the page number font.
+    pub fn operators(&self, page_number: usize, page_count: usize, page_width_pt: f64, page_height_pt: f64, font_name: &str) -> Vec<Operator> {
+        let encoded_text = encode_win_ansi(&self.text(page_number, page_count));
+        let text_width = PAGE_NUMBER_FONT.text_width(&encoded_text, self.font_size);
+        let text_height = f64::from(PAGE_NUMBER_FONT.cap_height()) * self.font_size / 1000.0;
+        let (origin_x, origin_y) = text_origin(
+            self.position, text_width, text_height, 0.0, page_width_pt, page_height_pt,
+        );
+
+        vec![
+            Operator::SaveState,
+            Operator::SetFillRgb(0.0, 0.0, 0.0),
+            Operator::BeginText,
+            Operator::SetFont { name: font_name.to_owned(), size: self.font_size },
+            Operator::SetTextMatrix([1.0, 0.0, 0.0, 1.0, origin_x, origin_y]),
+            Operator::ShowText(encoded_text),
+            Operator::EndText,
+            Operator::RestoreState,
+        ]
+    }
+}
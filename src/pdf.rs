@@ -1,67 +1,978 @@
-use std::{collections::BTreeMap, io::{self, Seek, Write}};
+use std::{collections::{BTreeMap, BTreeSet}, fmt, io::{self, BufWriter, Seek, SeekFrom, Write}, marker::PhantomData, sync::Arc};
 
-use crate::jpeg::ColorSpace;
+use crate::content::{self, Operator};
+use crate::deflate::zlib_compress;
+use crate::encrypt::{sha256, Encryption, ObjectEncryptor, Sha256};
+use crate::font::StandardFont;
+use crate::jpeg::{self, AdobeTransform, ColorSpace};
 
 
 pub type PdfObjectId = u64;
+pub type PdfGeneration = u16;
+
+/// The generation number of each object written into a new document; it is only incremented when
+/// an object number is freed and reused by an incremental update.
+pub const INITIAL_GENERATION: PdfGeneration = 0;
+
+/// The generation number of object 0, the head of the list of free objects, which marks it as
+/// never to be reused.
+pub const FREE_LIST_HEAD_GENERATION: PdfGeneration = 65535;
 
 
 #[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Document {
     pub objects: BTreeMap<PdfObjectId, ObjectData>,
+    pub compression: Compression,
+    pub ascii_encoding: AsciiEncoding,
+    /// The number of threads serializing the images; see [`DocumentWriter::set_threads`].
+    pub write_threads: usize,
+    pub version: PdfVersion,
+    pub encryption: Option<Encryption>,
+    /// See [`DocumentWriter::set_id_seed`].
+    pub id_seed: Option<Vec<u8>>,
 }
 impl Document {
-    pub fn write<W: Seek + Write>(&self, mut writer: W) -> Result<(), io::Error> {
+    /// Writes the document to the given writer, starting at its current position.
+    ///
+    /// The output is buffered internally; the writer is only seeked at the end to fill in the
+    /// version and the signature byte ranges (if necessary).
+    pub fn write<W: Seek + Write>(&self, writer: W) -> Result<(), io::Error> {
+        let mut document_writer = DocumentWriter::new(writer, self.compression, self.ascii_encoding)?;
+        document_writer.set_threads(self.write_threads);
+        document_writer.set_version(self.version);
+        if let Some(encryption) = &self.encryption {
+            document_writer.set_encryption(encryption.clone());
+        }
+        if let Some(id_seed) = &self.id_seed {
+            document_writer.set_id_seed(id_seed);
+        }
+        document_writer.write_objects(self.objects.iter().map(|(&id, data)| (id, data)))?;
+        document_writer.finish()?;
+        Ok(())
+    }
+}
+
+
+/// Writes a document object by object, so that the objects do not all have to be held in memory
+/// at the same time.
+///
+/// The objects may be written in any order; the cross-reference table is accumulated along the way
+/// and output by [`DocumentWriter::finish`] along with the trailer. The document is only valid
+/// once every object ID up to the highest one has been written and it contains a catalog.
+pub struct DocumentWriter<W: Seek + Write> {
+    output: CountingWriter<BufWriter<W>>,
+    pdf_start_pos: u64,
+    compression: Compression,
+    ascii_encoding: AsciiEncoding,
+    /// The offset and the generation number of each object written so far.
+    xref_entries: BTreeMap<PdfObjectId, (u64, PdfGeneration)>,
+    signature_placeholders: Vec<SignaturePlaceholder>,
+    root_obj_id: Option<PdfObjectId>,
+    info_obj_id: Option<PdfObjectId>,
+    needs_user_unit: bool,
+    threads: usize,
+    version: PdfVersion,
+    encryption: Option<Encryption>,
+    id_seed: Option<Vec<u8>>,
+}
+impl<W: Seek + Write> DocumentWriter<W> {
+    /// The number of images serialized by each thread before they are written out, which bounds
+    /// the memory taken up by serialized images waiting to be written.
+    const IMAGES_PER_THREAD: usize = 4;
+
+    /// Starts a document at the current position of the given writer by writing the header.
+    pub fn new(mut writer: W, compression: Compression, ascii_encoding: AsciiEncoding) -> Result<Self, io::Error> {
         let pdf_start_pos = writer.stream_position()?;
+        let mut output = CountingWriter::new(BufWriter::new(writer), pdf_start_pos);
+
+        // the version is raised by finish if necessary
+        write!(output, "%PDF-1.5\n")?;
+        if ascii_encoding == AsciiEncoding::None {
+            // binary detection comment line
+            output.write_all(&[b'%', 0xE2, 0xE3, 0xCF, 0xD3, b'\n'])?;
+        }
 
-        write!(writer, "%PDF-1.5\n")?;
-        // binary detection comment line
-        writer.write_all(&[b'%', 0xE2, 0xE3, 0xCF, 0xD3, b'\n'])?;
+        Ok(Self {
+            output,
+            pdf_start_pos,
+            compression,
+            ascii_encoding,
+            xref_entries: BTreeMap::new(),
+            signature_placeholders: Vec::new(),
+            root_obj_id: None,
+            info_obj_id: None,
+            needs_user_unit: false,
+            threads: 1,
+            version: PdfVersion::Auto,
+            encryption: None,
+            id_seed: None,
+        })
+    }
 
-        // output each object
-        let mut xref_offsets = BTreeMap::new();
-        for (&id, data) in &self.objects {
-            let object_start_pos = writer.stream_position()?;
-            xref_offsets.insert(id, object_start_pos - pdf_start_pos);
-            write!(writer, "{} 0 obj\n", id)?;
-            data.write_to_pdf(&mut writer)?;
-            write!(writer, "endobj\n")?;
+    /// Serializes the images passed to [`DocumentWriter::write_objects`] in the given number of
+    /// threads (0 for one per processor core) while the other objects are written in order.
+    ///
+    /// The output is the same regardless of the number of threads; each image is serialized into
+    /// memory and copied into the output in its place once ready.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = if threads == 0 {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            threads
+        };
+    }
+
+    /// Declares the given version in the header of the document.
+    pub fn set_version(&mut self, version: PdfVersion) {
+        self.version = version;
+    }
+
+    /// Encrypts the strings and streams of the objects written from now on, which makes the
+    /// document require PDF 2.0.
+    ///
+    /// The entries of [`RawObject`]s and [`Catalog::extra_entries`] are written verbatim and
+    /// therefore must not contain strings. Encrypted stream data is binary even if an ASCII
+    /// encoding has been requested.
+    pub fn set_encryption(&mut self, encryption: Encryption) {
+        self.encryption = Some(encryption);
+    }
+
+    /// Derives the permanent part of the document ID from the given seed instead of the content.
+    ///
+    /// The trailer records the ID as a pair: a permanent identifier and one that changes along
+    /// with the content. Both are derived from a digest of the written bytes by default, which
+    /// makes them change whenever anything in the document (such as the creation date) does; a
+    /// seed keeps the first one the same across builds, e.g. of successive versions of a document.
+    pub fn set_id_seed(&mut self, seed: &[u8]) {
+        self.id_seed = Some(seed.to_vec());
+    }
+
+    /// Writes an object with the given ID.
+    pub fn write_object(&mut self, id: PdfObjectId, data: &ObjectData) -> Result<(), io::Error> {
+        self.write_object_segment(id, data, None)
+    }
+
+    /// Writes the given objects in order, serializing the images in parallel if multiple threads
+    /// have been requested using [`DocumentWriter::set_threads`].
+    pub fn write_objects<'a, I: IntoIterator<Item = (PdfObjectId, &'a ObjectData)>>(&mut self, objects: I) -> Result<(), io::Error> {
+        if self.threads <= 1 {
+            for (id, data) in objects {
+                self.write_object(id, data)?;
+            }
+            return Ok(());
         }
 
-        let max_obj_id = self.objects.keys()
+        let objects: Vec<(PdfObjectId, &ObjectData)> = objects.into_iter().collect();
+        let mut remaining = objects.as_slice();
+        while !remaining.is_empty() {
+            // take objects up to the one after the last image of the batch
+            let mut image_count = 0;
+            let batch_len = remaining.iter()
+                .position(|(_id, data)| {
+                    if matches!(data, ObjectData::ImageXObject(_)) {
+                        image_count += 1;
+                    }
+                    image_count == self.threads * Self::IMAGES_PER_THREAD
+                })
+                .map(|index| index + 1)
+                .unwrap_or(remaining.len());
+            let (batch, rest) = remaining.split_at(batch_len);
+            remaining = rest;
+
+            let segments = self.serialize_images(batch)?;
+            for (&(id, data), segment) in batch.iter().zip(segments.iter()) {
+                self.write_object_segment(id, data, segment.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Serializes the images among the given objects, dividing them between the threads. Returns
+    /// the serialized data of each object which is an image.
+    fn serialize_images(&self, objects: &[(PdfObjectId, &ObjectData)]) -> Result<Vec<Option<Vec<u8>>>, io::Error> {
+        let images: Vec<(usize, PdfObjectId, &ImageXObject)> = objects.iter()
+            .enumerate()
+            .filter_map(|(index, (id, data))| match data {
+                ObjectData::ImageXObject(image) => Some((index, *id, image)),
+                _ => None,
+            })
+            .collect();
+        let (compression, ascii_encoding) = (self.compression, self.ascii_encoding);
+        let encryption = self.encryption.as_ref();
+        let thread_count = self.threads.min(images.len());
+        let serialized: Vec<Vec<(usize, Vec<u8>)>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..thread_count)
+                .map(|thread_index| {
+                    let images = &images;
+                    scope.spawn(move || {
+                        // every nth image, which balances the work if large and small images alternate
+                        images.iter()
+                            .skip(thread_index)
+                            .step_by(thread_count)
+                            .map(|&(index, id, image)| {
+                                let mut segment = Vec::with_capacity(image.data.encoded_len() + 512);
+                                let mut encoding = ObjectEncoding::new(compression, ascii_encoding, encryption, id);
+                                image.write_to_pdf(&mut segment, &mut encoding)?;
+                                Ok((index, segment))
+                            })
+                            .collect::<Result<Vec<_>, io::Error>>()
+                    })
+                })
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().expect("image serialization thread panicked"))
+                .collect::<Result<_, io::Error>>()
+        })?;
+
+        let mut segments = vec![None; objects.len()];
+        for (index, segment) in serialized.into_iter().flatten() {
+            segments[index] = Some(segment);
+        }
+        Ok(segments)
+    }
+
+    /// Writes an object with the given ID, taking its serialized data from the given segment if
+    /// it has already been serialized.
+    fn write_object_segment(&mut self, id: PdfObjectId, data: &ObjectData, segment: Option<&[u8]>) -> Result<(), io::Error> {
+        let output = &mut self.output;
+        let offset = output.position() - self.pdf_start_pos;
+        self.xref_entries.insert(id, (offset, INITIAL_GENERATION));
+        write!(output, "{} {} obj\n", id, INITIAL_GENERATION)?;
+        match (data, segment) {
+            (_, Some(segment)) => output.write_all(segment)?,
+            (ObjectData::Signature(signature), None) => {
+                // we need to know where the placeholders are
+                self.signature_placeholders.push(signature.write_placeholder_to_pdf(output)?);
+            },
+            (_, None) => {
+                let mut encoding = ObjectEncoding::new(self.compression, self.ascii_encoding, self.encryption.as_ref(), id);
+                data.write_to_pdf(&mut *output, &mut encoding)?;
+            },
+        }
+        write!(output, "endobj\n")?;
+        log::debug!(
+            "object id={} kind={} offset={} length={}",
+            id, data.kind_name(), offset, output.position() - self.pdf_start_pos - offset,
+        );
+
+        match data {
+            ObjectData::Catalog(_) if self.root_obj_id.is_none() => self.root_obj_id = Some(id),
+            ObjectData::Info(_) if self.info_obj_id.is_none() => self.info_obj_id = Some(id),
+            // user units were introduced in PDF 1.6
+            ObjectData::Page(Page { user_unit: Some(_), .. }) => self.needs_user_unit = true,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Writes the cross-reference table and the trailer, completing the document. Returns the
+    /// writer, positioned at the end of the document.
+    pub fn finish(self) -> Result<W, io::Error> {
+        let Self {
+            mut output, pdf_start_pos, xref_entries, signature_placeholders, root_obj_id, info_obj_id,
+            needs_user_unit, version, encryption, id_seed, ..
+        } = self;
+
+        let max_obj_id = xref_entries.keys()
             .copied()
             .max()
             .expect("no objects");
 
-        let xref_pos = writer.stream_position()?;
-        write!(writer, "xref\n")?;
-        write!(writer, "0 {}\n", max_obj_id + 1)?;
-        let mut cur_obj_id = 0;
-        for (&id, &xref_offset) in &xref_offsets {
-            while cur_obj_id < id {
-                write!(writer, "{:010} 65535 f\r\n", xref_offset)?;
-                cur_obj_id += 1;
-            }
-            write!(writer, "{:010} 00000 n\r\n", xref_offset)?;
-            cur_obj_id += 1;
+        let xref_pos = output.position();
+        write!(output, "xref\n")?;
+        write!(output, "0 {}\n", max_obj_id + 1)?;
+        for entry in XrefEntry::table(&xref_entries) {
+            entry.write(&mut output)?;
         }
 
-        let root_obj_id = self.objects.iter()
-            .filter(|(_id, data)| matches!(data, ObjectData::Catalog(_)))
-            .map(|(id, _data)| *id)
-            .nth(0)
-            .expect("no catalog object found");
+        let root_obj_id = root_obj_id.expect("no catalog object found");
 
-        write!(writer, "trailer\n")?;
-        write!(writer, "<< /Size {}", max_obj_id + 1)?;
-        write!(writer, " /Root {} 0 R", root_obj_id)?;
-        write!(writer, " >>\n")?;
-        write!(writer, "startxref\n")?;
-        write!(writer, "{}\n", xref_pos - pdf_start_pos)?;
-        write!(writer, "%%EOF\n")?;
+        write!(output, "trailer\n")?;
+        write!(output, "<< /Size {}", max_obj_id + 1)?;
+        write!(output, " /Root {} 0 R", root_obj_id)?;
+        if let Some(info_obj_id) = info_obj_id {
+            write!(output, " /Info {} 0 R", info_obj_id)?;
+        }
+        if let Some(encryption) = &encryption {
+            write!(output, " /Encrypt ")?;
+            encryption.write_to_pdf(&mut output)?;
+        }
+        // the digest covers everything up to the trailer
+        let content_digest = output.digest.clone().finish();
+        let seed_digest = id_seed.as_deref().map(sha256);
+        let content_id = &content_digest[0..16];
+        let permanent_id = seed_digest.as_ref().map_or(content_id, |digest| &digest[0..16]);
+        write!(output, " /ID [ {} {} ]", hex_string(permanent_id), hex_string(content_id))?;
+        write!(output, " >>\n")?;
+        write!(output, "startxref\n")?;
+        write!(output, "{}\n", xref_pos - pdf_start_pos)?;
+        write!(output, "%%EOF\n")?;
+
+        let pdf_end_pos = output.position();
+        log::debug!(
+            "cross-reference table offset={} size={} document_length={}",
+            xref_pos - pdf_start_pos, max_obj_id + 1, pdf_end_pos - pdf_start_pos,
+        );
+        let mut writer = output.into_inner()
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+
+        let raised_version = if version == PdfVersion::Pdf20 || encryption.is_some() {
+            // the AES-256 security handler was introduced in PDF 2.0
+            Some("2.0")
+        } else if needs_user_unit {
+            Some("1.6")
+        } else {
+            None
+        };
+        if let Some(raised_version) = raised_version {
+            // directly after "%PDF-"
+            writer.seek(SeekFrom::Start(pdf_start_pos + 5))?;
+            write!(writer, "{}", raised_version)?;
+        }
+
+        // now that we know the length of the file, fill in the signature byte ranges
+        for placeholder in &signature_placeholders {
+            let byte_range = placeholder.byte_range(pdf_start_pos, pdf_end_pos);
+            writer.seek(SeekFrom::Start(placeholder.byte_range_pos))?;
+            write_byte_range(&mut writer, byte_range)?;
+        }
+        if raised_version.is_some() || !signature_placeholders.is_empty() {
+            writer.seek(SeekFrom::Start(pdf_end_pos))?;
+        }
+
+        Ok(writer)
+    }
+}
+
+
+/// An entry of the cross-reference table.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum XrefEntry {
+    InUse { offset: u64, generation: PdfGeneration },
+    /// A free object number, linked to the next free one (0 for the last).
+    Free { next_free_id: PdfObjectId, generation: PdfGeneration },
+}
+impl XrefEntry {
+    /// Returns the entries of every object number from 0 up to the highest one written, given the
+    /// offset and generation number of each written object.
+    ///
+    /// The object numbers which have not been written are free; they form a linked list in
+    /// ascending order, headed by object 0.
+    fn table(written: &BTreeMap<PdfObjectId, (u64, PdfGeneration)>) -> Vec<Self> {
+        let max_obj_id = written.keys().copied().max().unwrap_or(0);
+        let mut free_ids: Vec<PdfObjectId> = (1..=max_obj_id)
+            .filter(|id| !written.contains_key(id))
+            .collect();
+        // the last free object links back to the head of the list
+        free_ids.push(0);
+
+        let mut free_ids = free_ids.into_iter();
+        let mut entries = vec![Self::Free {
+            next_free_id: free_ids.next().unwrap(),
+            generation: FREE_LIST_HEAD_GENERATION,
+        }];
+        for id in 1..=max_obj_id {
+            entries.push(match written.get(&id) {
+                Some(&(offset, generation)) => Self::InUse { offset, generation },
+                None => Self::Free {
+                    next_free_id: free_ids.next().unwrap(),
+                    generation: INITIAL_GENERATION,
+                },
+            });
+        }
+        entries
+    }
+
+    /// Writes the entry in its fixed 20-byte format.
+    fn write<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        match self {
+            Self::InUse { offset, generation } => write!(writer, "{:010} {:05} n\r\n", offset, generation),
+            Self::Free { next_free_id, generation } => write!(writer, "{:010} {:05} f\r\n", next_free_id, generation),
+        }
+    }
+}
+
+
+/// A writer that keeps track of its position in the output, which saves asking the underlying
+/// writer for it (usually a system call), and of a digest of the output, from which the document
+/// ID is derived.
+struct CountingWriter<W: Write> {
+    inner: W,
+    position: u64,
+    digest: Sha256,
+}
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W, position: u64) -> Self {
+        Self { inner, position, digest: Sha256::new() }
+    }
+
+    fn position(&self) -> u64 { self.position }
+
+    fn into_inner(self) -> W { self.inner }
+}
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += u64::try_from(written).unwrap();
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reference to an object of type `T` within a document being built by a [`DocumentBuilder`].
+pub struct ObjectRef<T> {
+    id: PdfObjectId,
+    object_type: PhantomData<fn() -> T>,
+}
+impl<T> ObjectRef<T> {
+    fn new(id: PdfObjectId) -> Self {
+        Self { id, object_type: PhantomData }
+    }
+
+    pub fn id(&self) -> PdfObjectId { self.id }
+}
+impl<T> Clone for ObjectRef<T> {
+    fn clone(&self) -> Self { *self }
+}
+impl<T> Copy for ObjectRef<T> {}
+impl<T> fmt::Debug for ObjectRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectRef({})", self.id)
+    }
+}
+impl<T> PartialEq for ObjectRef<T> {
+    fn eq(&self, other: &Self) -> bool { self.id == other.id }
+}
+impl<T> Eq for ObjectRef<T> {}
+
+/// The description of a page to be added to a [`DocumentBuilder`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewPage {
+    /// The width of the page in user space units.
+    pub width: u64,
+    /// The height of the page in user space units.
+    pub height: u64,
+    /// The size of a user space unit in points, if not 1.
+    pub user_unit: Option<u64>,
+    pub crop_box: Option<Rectangle>,
+    /// The clockwise rotation of the page when displayed, in degrees (a multiple of 90).
+    pub rotate: Option<u16>,
+    /// The key of this page's entry in the structure parent tree.
+    pub struct_parents: Option<u64>,
+    pub annotation_ids: Vec<PdfObjectId>,
+    /// The transition effect when changing to this page in presentation mode.
+    pub transition: Option<Transition>,
+    /// How long the page is shown in presentation mode before advancing, in milliseconds.
+    pub display_duration_ms: Option<u32>,
+    /// Images available to the content stream as `/Im0`, `/Im1`, ...
+    pub images: Vec<ObjectRef<ImageXObject>>,
+    /// Fonts available to the content stream as `/F0`, `/F1`, ...
+    pub fonts: Vec<ObjectRef<Type1Font>>,
+    /// Graphics states available to the content stream as `/GS0`, `/GS1`, ...
+    pub ext_g_states: Vec<ObjectRef<ExtGState>>,
+    /// Optional content groups available to the content stream as `/OC0`, `/OC1`, ...
+    pub optional_content_groups: Vec<ObjectRef<OptionalContentGroup>>,
+    pub operators: Vec<Operator>,
+}
+
+/// Assembles a [`Document`], assigning object IDs automatically.
+///
+/// Objects that have to be referenced before they can be constructed can be reserved first and
+/// filled in later; [`DocumentBuilder::build`] fails if a reserved object was never filled in.
+pub struct DocumentBuilder {
+    objects: BTreeMap<PdfObjectId, ObjectData>,
+    reserved_ids: BTreeSet<PdfObjectId>,
+    page_ids: Vec<PdfObjectId>,
+    catalog: Catalog,
+    next_id: PdfObjectId,
+    compression: Compression,
+    ascii_encoding: AsciiEncoding,
+    write_threads: usize,
+    version: PdfVersion,
+    encryption: Option<Encryption>,
+    id_seed: Option<Vec<u8>>,
+    /// Whether objects have been written out by [`DocumentBuilder::write_pending`].
+    has_written: bool,
+}
+impl DocumentBuilder {
+    const CATALOG_ID: PdfObjectId = 1;
+    const PAGES_ID: PdfObjectId = 2;
+
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            objects: BTreeMap::new(),
+            reserved_ids: BTreeSet::new(),
+            page_ids: Vec::new(),
+            catalog: Catalog {
+                root_page_id: Self::PAGES_ID,
+                acro_form: None,
+                struct_tree_root_id: None,
+                viewer_preferences: None,
+                page_layout: None,
+                page_mode: None,
+                open_action: None,
+                optional_content_group_ids: Vec::new(),
+                named_destinations: BTreeMap::new(),
+                extra_entries: String::new(),
+            },
+            next_id: Self::PAGES_ID + 1,
+            compression,
+            ascii_encoding: AsciiEncoding::None,
+            write_threads: 1,
+            version: PdfVersion::Auto,
+            encryption: None,
+            id_seed: None,
+            has_written: false,
+        }
+    }
+
+    /// Encodes all streams, including the image data, as ASCII, making the document 7-bit clean.
+    pub fn set_ascii_encoding(&mut self, ascii_encoding: AsciiEncoding) {
+        self.ascii_encoding = ascii_encoding;
+    }
+
+    /// Serializes the images in the given number of threads when the document is written; see
+    /// [`DocumentWriter::set_threads`].
+    pub fn set_write_threads(&mut self, threads: usize) {
+        self.write_threads = threads;
+    }
+
+    /// Declares the given version in the header of the document; see
+    /// [`DocumentWriter::set_version`].
+    pub fn set_version(&mut self, version: PdfVersion) {
+        self.version = version;
+    }
+
+    /// Encrypts the document; see [`DocumentWriter::set_encryption`].
+    pub fn set_encryption(&mut self, encryption: Encryption) {
+        self.encryption = Some(encryption);
+    }
+
+    /// Derives the permanent part of the document ID from the given seed; see
+    /// [`DocumentWriter::set_id_seed`].
+    pub fn set_id_seed(&mut self, seed: &[u8]) {
+        self.id_seed = Some(seed.to_vec());
+    }
+
+    /// The document catalog, whose optional entries can be modified.
+    pub fn catalog_mut(&mut self) -> &mut Catalog {
+        &mut self.catalog
+    }
+
+    /// The pages added so far, in order.
+    pub fn pages(&self) -> Vec<ObjectRef<Page>> {
+        self.page_ids.iter()
+            .map(|&id| ObjectRef::new(id))
+            .collect()
+    }
+
+    /// The number of pages added so far.
+    pub fn page_count(&self) -> usize {
+        self.page_ids.len()
+    }
+
+    fn allocate_id(&mut self) -> PdfObjectId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Reserves an ID for an object that will be filled in later using [`DocumentBuilder::fill`].
+    pub fn reserve<T>(&mut self) -> ObjectRef<T> where ObjectData: From<T> {
+        let id = self.allocate_id();
+        self.reserved_ids.insert(id);
+        ObjectRef::new(id)
+    }
+
+    /// Fills in an object whose ID has been reserved.
+    pub fn fill<T>(&mut self, reference: ObjectRef<T>, object: T) where ObjectData: From<T> {
+        let was_reserved = self.reserved_ids.remove(&reference.id);
+        assert!(was_reserved, "object {} was not reserved or has already been filled in", reference.id);
+        self.objects.insert(reference.id, object.into());
+    }
+
+    pub fn add<T>(&mut self, object: T) -> ObjectRef<T> where ObjectData: From<T> {
+        let id = self.allocate_id();
+        self.objects.insert(id, object.into());
+        ObjectRef::new(id)
+    }
 
+    /// Reserves the IDs for a page that will be filled in later using
+    /// [`DocumentBuilder::fill_page`].
+    pub fn reserve_page(&mut self) -> ObjectRef<Page> {
+        // page, then resources, then contents
+        let page_id = self.allocate_id();
+        let resources_id = self.allocate_id();
+        let contents_id = self.allocate_id();
+        self.reserved_ids.insert(page_id);
+        self.reserved_ids.insert(resources_id);
+        self.reserved_ids.insert(contents_id);
+        ObjectRef::new(page_id)
+    }
+
+    /// Fills in a reserved page and appends it to the document. Pages appear in the document in
+    /// the order in which they are filled in.
+    pub fn fill_page(&mut self, reference: ObjectRef<Page>, new_page: NewPage) {
+        let page_id = reference.id;
+        let resources_id = page_id + 1;
+        let contents_id = page_id + 2;
+        for id in [page_id, resources_id, contents_id] {
+            let was_reserved = self.reserved_ids.remove(&id);
+            assert!(was_reserved, "page object {} was not reserved or has already been filled in", id);
+        }
+
+        let page = Page {
+            parent_id: Self::PAGES_ID,
+            resources_id,
+            contents_id,
+            annotation_ids: new_page.annotation_ids,
+            width: new_page.width,
+            height: new_page.height,
+            user_unit: new_page.user_unit,
+            crop_box: new_page.crop_box,
+            rotate: new_page.rotate,
+            struct_parents: new_page.struct_parents,
+            transition: new_page.transition,
+            display_duration_ms: new_page.display_duration_ms,
+        };
+        let resources = PageResources {
+            image_xobject_ids: new_page.images.iter().map(|r| r.id).collect(),
+            font_ids: new_page.fonts.iter().map(|r| r.id).collect(),
+            ext_g_state_ids: new_page.ext_g_states.iter().map(|r| r.id).collect(),
+            optional_content_group_ids: new_page.optional_content_groups.iter().map(|r| r.id).collect(),
+        };
+        let contents = PageContents {
+            commands: content::serialize(&new_page.operators),
+        };
+        self.objects.insert(page_id, ObjectData::Page(page));
+        self.objects.insert(resources_id, ObjectData::PageResources(resources));
+        self.objects.insert(contents_id, ObjectData::PageContents(contents));
+        self.page_ids.push(page_id);
+    }
+
+    /// Returns a page that has already been filled in.
+    pub fn page(&self, reference: ObjectRef<Page>) -> &Page {
+        match self.objects.get(&reference.id) {
+            Some(ObjectData::Page(page)) => page,
+            _ => panic!("page {} has not been filled in", reference.id),
+        }
+    }
+
+    /// Attaches an annotation to a page that has already been filled in.
+    pub fn attach_annotation<T>(&mut self, page: ObjectRef<Page>, annotation: ObjectRef<T>) {
+        match self.objects.get_mut(&page.id) {
+            Some(ObjectData::Page(page)) => page.annotation_ids.push(annotation.id),
+            _ => panic!("page {} has not been filled in", page.id),
+        }
+    }
+
+    pub fn add_page(&mut self, new_page: NewPage) -> ObjectRef<Page> {
+        let reference = self.reserve_page();
+        self.fill_page(reference, new_page);
+        reference
+    }
+
+    /// Starts writing the document being built to the given writer, for use with
+    /// [`DocumentBuilder::write_pending`] and [`DocumentBuilder::write_rest`].
+    pub fn start_writing<W: Seek + Write>(&self, writer: W) -> Result<DocumentWriter<W>, io::Error> {
+        let mut document_writer = DocumentWriter::new(writer, self.compression, self.ascii_encoding)?;
+        document_writer.set_threads(self.write_threads);
+        document_writer.set_version(self.version);
+        if let Some(encryption) = &self.encryption {
+            document_writer.set_encryption(encryption.clone());
+        }
+        if let Some(id_seed) = &self.id_seed {
+            document_writer.set_id_seed(id_seed);
+        }
+        Ok(document_writer)
+    }
+
+    /// Writes the objects added or filled in so far to the given writer and drops them, so that
+    /// they no longer take up memory. Pages are kept until the document is complete since
+    /// annotations may still be attached to them.
+    ///
+    /// Once objects have been written this way, the document has to be completed using
+    /// [`DocumentBuilder::write_rest`] instead of [`DocumentBuilder::build`].
+    pub fn write_pending<W: Seek + Write>(&mut self, writer: &mut DocumentWriter<W>) -> Result<(), io::Error> {
+        let pending_ids: Vec<PdfObjectId> = self.objects.iter()
+            .filter(|(_id, data)| !matches!(data, ObjectData::Page(_)))
+            .map(|(&id, _data)| id)
+            .collect();
+        let pending: Vec<(PdfObjectId, ObjectData)> = pending_ids.into_iter()
+            .map(|id| (id, self.objects.remove(&id).unwrap()))
+            .collect();
+        writer.write_objects(pending.iter().map(|(id, data)| (*id, data)))?;
+        self.has_written = true;
         Ok(())
     }
+
+    /// Adds the catalog and the page tree, checking that the document is complete.
+    fn complete(&mut self) -> Result<(), BuildError> {
+        if let Some(&id) = self.reserved_ids.first() {
+            return Err(BuildError::UnfilledObject { id });
+        }
+        if self.page_ids.is_empty() {
+            return Err(BuildError::NoPages);
+        }
+
+        self.objects.insert(Self::CATALOG_ID, ObjectData::Catalog(self.catalog.clone()));
+        self.objects.insert(Self::PAGES_ID, ObjectData::Pages(Pages { page_ids: self.page_ids.clone() }));
+        Ok(())
+    }
+
+    pub fn build(mut self) -> Result<Document, BuildError> {
+        assert!(!self.has_written, "objects have already been written; use write_rest instead");
+        self.complete()?;
+        Ok(Document {
+            objects: self.objects,
+            compression: self.compression,
+            ascii_encoding: self.ascii_encoding,
+            write_threads: self.write_threads,
+            version: self.version,
+            encryption: self.encryption,
+            id_seed: self.id_seed,
+        })
+    }
+
+    /// Writes the objects not yet written by [`DocumentBuilder::write_pending`] and completes the
+    /// document. Returns the writer, positioned at the end of the document.
+    pub fn write_rest<W: Seek + Write>(mut self, mut writer: DocumentWriter<W>) -> Result<W, WriteError> {
+        self.complete()?;
+        writer.write_objects(self.objects.iter().map(|(&id, data)| (id, data)))?;
+        Ok(writer.finish()?)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BuildError {
+    UnfilledObject { id: PdfObjectId },
+    NoPages,
+}
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnfilledObject { id }
+                => write!(f, "object {} was reserved but never filled in", id),
+            Self::NoPages
+                => write!(f, "document has no pages"),
+        }
+    }
+}
+impl std::error::Error for BuildError {}
+
+#[derive(Debug)]
+pub enum WriteError {
+    Build(BuildError),
+    Io(io::Error),
+}
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Build(e)
+                => write!(f, "{}", e),
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Build(e) => Some(e),
+            Self::Io(e) => Some(e),
+        }
+    }
+}
+impl From<BuildError> for WriteError {
+    fn from(value: BuildError) -> Self { Self::Build(value) }
+}
+impl From<io::Error> for WriteError {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+
+/// How streams that aren't already compressed are encoded.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Compression {
+    #[default]
+    None,
+    Flate,
+}
+
+/// The version of PDF declared in the header of a document.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PdfVersion {
+    /// The lowest version supporting the features used: 1.5, 1.6 for pages with user units and
+    /// 2.0 for encrypted documents.
+    #[default]
+    Auto,
+    /// PDF 2.0 (ISO 32000-2).
+    #[cfg_attr(feature = "cli", value(name = "2.0"))]
+    Pdf20,
+}
+
+/// How streams are encoded as ASCII text, which happens after any compression.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AsciiEncoding {
+    /// The data is written as binary.
+    #[default]
+    None,
+    /// Each byte is written as two hexadecimal digits.
+    Hex,
+    /// Each four bytes are written as five characters.
+    #[cfg_attr(feature = "cli", value(name = "85"))]
+    Ascii85,
+}
+impl AsciiEncoding {
+    /// The number of characters after which a line break is inserted into the encoded data.
+    const LINE_LENGTH: usize = 76;
+
+    /// The filter decoding the data, or `None` if the data is written as binary.
+    pub fn filter(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Hex => Some("/ASCIIHexDecode"),
+            Self::Ascii85 => Some("/ASCII85Decode"),
+        }
+    }
+
+    /// Encodes the given data, including the end-of-data marker.
+    ///
+    /// Line breaks, which the decoding filters ignore, are inserted at regular intervals.
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let (encoded, end_marker) = match self {
+            Self::None => return data.to_vec(),
+            Self::Hex => {
+                let encoded: String = data.iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect();
+                (encoded, ">")
+            },
+            Self::Ascii85 => {
+                // the PDF filter does not expect the leading <~
+                let mut encoded = ascii85::encode(data);
+                encoded.drain(..2);
+                encoded.truncate(encoded.len() - 2);
+                (encoded, "~>")
+            },
+        };
+        let mut ret = Vec::with_capacity(encoded.len() + encoded.len() / Self::LINE_LENGTH + end_marker.len());
+        for (i, line) in encoded.as_bytes().chunks(Self::LINE_LENGTH).enumerate() {
+            if i > 0 {
+                ret.push(b'\n');
+            }
+            ret.extend_from_slice(line);
+        }
+        ret.extend_from_slice(end_marker.as_bytes());
+        ret
+    }
+}
+
+/// How the strings and streams of an object are written: compressed and encoded as requested for
+/// the document's streams and, if the document is encrypted, encrypted.
+pub struct ObjectEncoding<'a> {
+    pub compression: Compression,
+    pub ascii_encoding: AsciiEncoding,
+    encryptor: Option<ObjectEncryptor<'a>>,
+}
+impl<'a> ObjectEncoding<'a> {
+    /// Prepares the encoding of the object with the given ID.
+    pub fn new(compression: Compression, ascii_encoding: AsciiEncoding, encryption: Option<&'a Encryption>, id: PdfObjectId) -> Self {
+        Self {
+            compression,
+            ascii_encoding,
+            encryptor: encryption.map(|encryption| encryption.for_object(id)),
+        }
+    }
+
+    /// Whether the strings and streams of the object are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryptor.is_some()
+    }
+
+    /// Encodes text as a PDF text string; see [`text_string`].
+    pub fn text_string(&mut self, text: &str) -> String {
+        match &mut self.encryptor {
+            Some(encryptor) => hex_string(&encryptor.encrypt(&text_bytes(text))),
+            None => text_string(text),
+        }
+    }
+
+    /// Encodes bytes as a PDF string; see [`byte_string`].
+    pub fn byte_string(&mut self, bytes: &[u8]) -> String {
+        match &mut self.encryptor {
+            Some(encryptor) => hex_string(&encryptor.encrypt(bytes)),
+            None => byte_string(bytes),
+        }
+    }
+
+    /// Encodes bytes as a PDF literal string; see [`literal_string`]. Encrypted strings are output
+    /// as hexadecimal strings instead.
+    pub fn literal_string(&mut self, bytes: &[u8]) -> String {
+        match &mut self.encryptor {
+            Some(encryptor) => hex_string(&encryptor.encrypt(bytes)),
+            None => literal_string(bytes),
+        }
+    }
+
+    /// Encrypts the data of a stream as stored in the file, i.e. after compression and encoding,
+    /// or returns it unchanged if the object is not encrypted.
+    pub fn encrypt_stream_data(&mut self, data: Vec<u8>) -> Vec<u8> {
+        match &mut self.encryptor {
+            Some(encryptor) => encryptor.encrypt(&data),
+            None => data,
+        }
+    }
+
+    /// Writes the `/Filter` and `/Length` entries of a stream dictionary, closes the dictionary
+    /// and writes the stream, compressing, encoding and encrypting its data as requested.
+    fn write_stream<W: Write>(&mut self, mut writer: W, data: &[u8]) -> Result<(), io::Error> {
+        let compressed_data;
+        let (compression_filter, data) = match self.compression {
+            Compression::None => (None, data),
+            Compression::Flate => {
+                compressed_data = zlib_compress(data);
+                (Some("/FlateDecode"), compressed_data.as_slice())
+            },
+        };
+        let encoded_data;
+        let data = match self.ascii_encoding {
+            AsciiEncoding::None => data,
+            _ => {
+                encoded_data = self.ascii_encoding.encode(data);
+                encoded_data.as_slice()
+            },
+        };
+        let encrypted_data;
+        let data = match &mut self.encryptor {
+            None => data,
+            Some(encryptor) => {
+                encrypted_data = encryptor.encrypt(data);
+                encrypted_data.as_slice()
+            },
+        };
+        // the filter applied last is the first one to be decoded
+        let filters: Vec<&str> = self.ascii_encoding.filter().into_iter()
+            .chain(compression_filter)
+            .collect();
+        match filters.as_slice() {
+            [] => {},
+            [filter] => write!(writer, " /Filter {}", filter)?,
+            filters => write!(writer, " /Filter [ {} ]", filters.join(" "))?,
+        }
+        write!(writer, " /Length {}", data.len())?;
+        write!(writer, " >>\nstream\n")?;
+        writer.write_all(data)?;
+        write!(writer, "\nendstream\n")
+    }
 }
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -72,16 +983,91 @@ pub enum ObjectData {
     PageResources(PageResources),
     PageContents(PageContents),
     ImageXObject(ImageXObject),
+    SignatureField(SignatureField),
+    Signature(Signature),
+    Font(Type1Font),
+    ExtGState(ExtGState),
+    StructTreeRoot(StructTreeRoot),
+    StructElement(StructElement),
+    LinkAnnotation(LinkAnnotation),
+    Info(Info),
+    OptionalContentGroup(OptionalContentGroup),
+    Raw(RawObject),
+}
+impl From<ImageXObject> for ObjectData {
+    fn from(value: ImageXObject) -> Self { Self::ImageXObject(value) }
+}
+impl From<SignatureField> for ObjectData {
+    fn from(value: SignatureField) -> Self { Self::SignatureField(value) }
+}
+impl From<Signature> for ObjectData {
+    fn from(value: Signature) -> Self { Self::Signature(value) }
+}
+impl From<Type1Font> for ObjectData {
+    fn from(value: Type1Font) -> Self { Self::Font(value) }
+}
+impl From<ExtGState> for ObjectData {
+    fn from(value: ExtGState) -> Self { Self::ExtGState(value) }
+}
+impl From<StructTreeRoot> for ObjectData {
+    fn from(value: StructTreeRoot) -> Self { Self::StructTreeRoot(value) }
+}
+impl From<StructElement> for ObjectData {
+    fn from(value: StructElement) -> Self { Self::StructElement(value) }
+}
+impl From<LinkAnnotation> for ObjectData {
+    fn from(value: LinkAnnotation) -> Self { Self::LinkAnnotation(value) }
+}
+impl From<Info> for ObjectData {
+    fn from(value: Info) -> Self { Self::Info(value) }
+}
+impl From<OptionalContentGroup> for ObjectData {
+    fn from(value: OptionalContentGroup) -> Self { Self::OptionalContentGroup(value) }
+}
+impl From<RawObject> for ObjectData {
+    fn from(value: RawObject) -> Self { Self::Raw(value) }
 }
 impl ObjectData {
-    pub fn write_to_pdf<W: Write>(&self, writer: W) -> Result<(), io::Error> {
+    /// The kind of object, as output in log messages.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Self::Catalog(_) => "catalog",
+            Self::Pages(_) => "pages",
+            Self::Page(_) => "page",
+            Self::PageResources(_) => "page-resources",
+            Self::PageContents(_) => "page-contents",
+            Self::ImageXObject(_) => "image",
+            Self::SignatureField(_) => "signature-field",
+            Self::Signature(_) => "signature",
+            Self::Font(_) => "font",
+            Self::ExtGState(_) => "ext-g-state",
+            Self::StructTreeRoot(_) => "struct-tree-root",
+            Self::StructElement(_) => "struct-element",
+            Self::LinkAnnotation(_) => "link-annotation",
+            Self::Info(_) => "info",
+            Self::OptionalContentGroup(_) => "optional-content-group",
+            Self::Raw(_) => "raw",
+        }
+    }
+
+    pub fn write_to_pdf<W: Write>(&self, writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
         match self {
-            Self::Catalog(obj) => obj.write_to_pdf(writer),
+            Self::Catalog(obj) => obj.write_to_pdf(writer, encoding),
             Self::Page(obj) => obj.write_to_pdf(writer),
             Self::Pages(obj) => obj.write_to_pdf(writer),
             Self::PageResources(obj) => obj.write_to_pdf(writer),
-            Self::PageContents(obj) => obj.write_to_pdf(writer),
-            Self::ImageXObject(obj) => obj.write_to_pdf(writer),
+            Self::PageContents(obj) => obj.write_to_pdf(writer, encoding),
+            Self::ImageXObject(obj) => obj.write_to_pdf(writer, encoding),
+            Self::SignatureField(obj) => obj.write_to_pdf(writer, encoding),
+            Self::Signature(obj) => obj.write_to_pdf(writer),
+            Self::Font(obj) => obj.write_to_pdf(writer),
+            Self::ExtGState(obj) => obj.write_to_pdf(writer),
+            Self::StructTreeRoot(obj) => obj.write_to_pdf(writer),
+            Self::StructElement(obj) => obj.write_to_pdf(writer, encoding),
+            Self::LinkAnnotation(obj) => obj.write_to_pdf(writer, encoding),
+            Self::Info(obj) => obj.write_to_pdf(writer, encoding),
+            Self::OptionalContentGroup(obj) => obj.write_to_pdf(writer, encoding),
+            Self::Raw(obj) => obj.write_to_pdf(writer, encoding),
         }
     }
 }
@@ -89,15 +1075,254 @@ impl ObjectData {
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Catalog {
     pub root_page_id: PdfObjectId,
+    pub acro_form: Option<AcroForm>,
+    pub struct_tree_root_id: Option<PdfObjectId>,
+    pub viewer_preferences: Option<ViewerPreferences>,
+    pub page_layout: Option<PageLayout>,
+    pub page_mode: Option<PageMode>,
+    pub open_action: Option<Destination>,
+    /// The optional content groups (layers) of the document, listed in this order by viewers.
+    pub optional_content_group_ids: Vec<PdfObjectId>,
+    /// The destinations that can be referred to by name, e.g. from links in other documents.
+    pub named_destinations: BTreeMap<String, Destination>,
+    /// Further entries written verbatim into the catalog dictionary, e.g.
+    /// `/OutputIntents [ 7 0 R ]` to refer to a [`RawObject`]. Empty if there are none.
+    pub extra_entries: String,
 }
 impl Catalog {
-    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
         write!(writer, "<< /Type /Catalog")?;
         write!(writer, " /Pages {} 0 R", self.root_page_id)?;
+        if let Some(acro_form) = &self.acro_form {
+            write!(writer, " /AcroForm ")?;
+            acro_form.write_to_pdf(&mut writer)?;
+        }
+        if let Some(struct_tree_root_id) = self.struct_tree_root_id {
+            write!(writer, " /StructTreeRoot {} 0 R", struct_tree_root_id)?;
+            write!(writer, " /MarkInfo << /Marked true >>")?;
+        }
+        if let Some(viewer_preferences) = &self.viewer_preferences {
+            write!(writer, " /ViewerPreferences ")?;
+            viewer_preferences.write_to_pdf(&mut writer)?;
+        }
+        if let Some(page_layout) = self.page_layout {
+            write!(writer, " /PageLayout /{}", page_layout.as_pdf_name())?;
+        }
+        if let Some(page_mode) = self.page_mode {
+            write!(writer, " /PageMode /{}", page_mode.as_pdf_name())?;
+        }
+        if let Some(open_action) = &self.open_action {
+            write!(writer, " /OpenAction ")?;
+            open_action.write_to_pdf(&mut writer)?;
+        }
+        if !self.optional_content_group_ids.is_empty() {
+            let group_refs: Vec<String> = self.optional_content_group_ids.iter()
+                .map(|id| format!("{} 0 R", id))
+                .collect();
+            let group_refs = group_refs.join(" ");
+            write!(writer, " /OCProperties << /OCGs [ {} ] /D << /Order [ {} ] >> >>", group_refs, group_refs)?;
+        }
+        if !self.named_destinations.is_empty() {
+            // a name tree consisting of a single leaf, whose keys are ordered by their bytes (as
+            // are the keys of the map)
+            write!(writer, " /Names << /Dests << /Names [")?;
+            for (name, destination) in &self.named_destinations {
+                write!(writer, " {} ", encoding.byte_string(name.as_bytes()))?;
+                destination.write_to_pdf(&mut writer)?;
+            }
+            write!(writer, " ] >> >>")?;
+        }
+        if !self.extra_entries.is_empty() {
+            write!(writer, " {}", self.extra_entries)?;
+        }
+        write!(writer, " >>\n")
+    }
+}
+
+/// The document information dictionary, referenced from the trailer.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Info {
+    /// The application that converted the document to PDF.
+    pub producer: Option<String>,
+    /// The application that created the original document.
+    pub creator: Option<String>,
+    pub creation_date: Option<Date>,
+    pub mod_date: Option<Date>,
+}
+impl Info {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<<")?;
+        if let Some(producer) = &self.producer {
+            write!(writer, " /Producer {}", encoding.text_string(producer))?;
+        }
+        if let Some(creator) = &self.creator {
+            write!(writer, " /Creator {}", encoding.text_string(creator))?;
+        }
+        if let Some(creation_date) = &self.creation_date {
+            write!(writer, " /CreationDate {}", encoding.literal_string(creation_date.to_pdf_string().as_bytes()))?;
+        }
+        if let Some(mod_date) = &self.mod_date {
+            write!(writer, " /ModDate {}", encoding.literal_string(mod_date.to_pdf_string().as_bytes()))?;
+        }
         write!(writer, " >>\n")
     }
 }
 
+/// A point in time, given in local time along with the offset of the local time zone from UTC.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// The offset of the local time zone from UTC in minutes, positive east of Greenwich.
+    pub utc_offset_minutes: i16,
+}
+impl Date {
+    /// Converts the given number of seconds since the Unix epoch (1970-01-01 00:00:00 UTC) into a
+    /// date in UTC.
+    pub fn from_unix_time(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86400);
+        let seconds_of_day = seconds.rem_euclid(86400);
+
+        // convert the days into a civil date (proleptic Gregorian calendar)
+        let shifted_days = days + 719468;
+        let era = shifted_days.div_euclid(146097);
+        let day_of_era = shifted_days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year: year.clamp(0, 9999) as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+            utc_offset_minutes: 0,
+        }
+    }
+
+    /// Formats the date as a PDF date string, e.g. `D:20240501123456+02'00'`.
+    pub fn to_pdf_string(&self) -> String {
+        let sign = if self.utc_offset_minutes < 0 { '-' } else { '+' };
+        let offset = self.utc_offset_minutes.unsigned_abs();
+        format!(
+            "D:{:04}{:02}{:02}{:02}{:02}{:02}{}{:02}'{:02}'",
+            self.year, self.month, self.day, self.hour, self.minute, self.second,
+            sign, offset / 60, offset % 60,
+        )
+    }
+}
+
+/// How the viewer should present the document. Written inline into the catalog.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ViewerPreferences {
+    pub fit_window: bool,
+    pub hide_toolbar: bool,
+    pub direction: ReadingDirection,
+}
+impl ViewerPreferences {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<<")?;
+        if self.fit_window {
+            write!(writer, " /FitWindow true")?;
+        }
+        if self.hide_toolbar {
+            write!(writer, " /HideToolbar true")?;
+        }
+        if self.direction != ReadingDirection::default() {
+            write!(writer, " /Direction /{}", self.direction.as_pdf_name())?;
+        }
+        write!(writer, " >>")
+    }
+}
+
+/// The order in which the pages are read, which determines on which side of each other the viewer
+/// places pages shown side by side.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ReadingDirection {
+    /// From left to right, as in most Western languages.
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "ltr"))]
+    LeftToRight,
+    /// From right to left, as in Arabic, Hebrew or Japanese manga.
+    #[cfg_attr(feature = "cli", value(name = "rtl"))]
+    RightToLeft,
+}
+impl ReadingDirection {
+    pub fn as_pdf_name(&self) -> &'static str {
+        match self {
+            Self::LeftToRight => "L2R",
+            Self::RightToLeft => "R2L",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PageLayout {
+    /// Two pages side by side, with odd-numbered pages on the right.
+    TwoPageRight,
+}
+impl PageLayout {
+    pub fn as_pdf_name(&self) -> &'static str {
+        match self {
+            Self::TwoPageRight => "TwoPageRight",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PageMode {
+    /// Full-screen mode, without menu bar, window controls or other windows.
+    FullScreen,
+}
+impl PageMode {
+    pub fn as_pdf_name(&self) -> &'static str {
+        match self {
+            Self::FullScreen => "FullScreen",
+        }
+    }
+}
+
+/// A destination within the document: a page, zoomed to fit into the window.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Destination {
+    pub page_id: PdfObjectId,
+}
+impl Destination {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "[ {} 0 R /Fit ]", self.page_id)
+    }
+}
+
+/// The interactive form of a document. Written inline into the catalog.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct AcroForm {
+    pub field_ids: Vec<PdfObjectId>,
+    pub sig_flags: u32,
+}
+impl AcroForm {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<< /Fields [")?;
+        for &field_id in &self.field_ids {
+            write!(writer, " {} 0 R", field_id)?;
+        }
+        write!(writer, " ]")?;
+        if self.sig_flags != 0 {
+            write!(writer, " /SigFlags {}", self.sig_flags)?;
+        }
+        write!(writer, " >>")
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Pages {
     pub page_ids: Vec<PdfObjectId>,
@@ -120,23 +1345,150 @@ pub struct Page {
     pub parent_id: PdfObjectId,
     pub resources_id: PdfObjectId,
     pub contents_id: PdfObjectId,
-    pub width_pt: u64,
-    pub height_pt: u64,
+    pub annotation_ids: Vec<PdfObjectId>,
+    /// The width of the page in user space units.
+    pub width: u64,
+    /// The height of the page in user space units.
+    pub height: u64,
+    /// The size of a user space unit in points, if not 1.
+    pub user_unit: Option<u64>,
+    pub crop_box: Option<Rectangle>,
+    /// The clockwise rotation of the page when displayed, in degrees (a multiple of 90).
+    pub rotate: Option<u16>,
+    /// The key of this page's entry in the structure parent tree.
+    pub struct_parents: Option<u64>,
+    /// The transition effect when changing to this page in presentation mode.
+    pub transition: Option<Transition>,
+    /// How long the page is shown in presentation mode before advancing, in milliseconds.
+    pub display_duration_ms: Option<u32>,
 }
 impl Page {
     pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
         write!(writer, "<< /Type /Page")?;
         write!(writer, " /Parent {} 0 R", self.parent_id)?;
         write!(writer, " /Resources {} 0 R", self.resources_id)?;
-        write!(writer, " /MediaBox [ 0 0 {} {} ]", self.width_pt, self.height_pt)?;
+        write!(writer, " /MediaBox [ 0 0 {} {} ]", self.width, self.height)?;
+        if let Some(user_unit) = self.user_unit {
+            write!(writer, " /UserUnit {}", user_unit)?;
+        }
+        if let Some(crop_box) = &self.crop_box {
+            write!(writer, " /CropBox ")?;
+            crop_box.write_to_pdf(&mut writer)?;
+        }
+        if let Some(rotate) = self.rotate {
+            write!(writer, " /Rotate {}", rotate)?;
+        }
+        if let Some(struct_parents) = self.struct_parents {
+            write!(writer, " /StructParents {}", struct_parents)?;
+        }
+        if let Some(transition) = &self.transition {
+            write!(writer, " /Trans ")?;
+            transition.write_to_pdf(&mut writer)?;
+        }
+        if let Some(display_duration_ms) = self.display_duration_ms {
+            write!(writer, " /Dur {}", milliseconds_as_seconds(display_duration_ms))?;
+        }
         write!(writer, " /Contents {} 0 R", self.contents_id)?;
+        if !self.annotation_ids.is_empty() {
+            write!(writer, " /Annots [")?;
+            for &annotation_id in &self.annotation_ids {
+                write!(writer, " {} 0 R", annotation_id)?;
+            }
+            write!(writer, " ]")?;
+        }
         write!(writer, " >>\n")
     }
 }
 
+/// The style of a transition effect between pages.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TransitionStyle {
+    /// The new page simply replaces the old one.
+    Replace,
+    /// Two lines sweep across the screen, revealing the new page.
+    Split,
+    /// Multiple lines sweep across the screen like venetian blinds.
+    Blinds,
+    /// A rectangle expands from the center, revealing the new page.
+    Box,
+    /// A single line sweeps across the screen.
+    Wipe,
+    /// The old page dissolves gradually.
+    #[default]
+    Dissolve,
+    /// The old page fades into the new one.
+    Fade,
+    /// The new page pushes the old one off the screen.
+    Push,
+    /// The new page slides over the old one.
+    Cover,
+    /// The old page slides off, uncovering the new one.
+    Uncover,
+}
+impl TransitionStyle {
+    pub fn as_pdf_name(&self) -> &'static str {
+        match self {
+            Self::Replace => "R",
+            Self::Split => "Split",
+            Self::Blinds => "Blinds",
+            Self::Box => "Box",
+            Self::Wipe => "Wipe",
+            Self::Dissolve => "Dissolve",
+            Self::Fade => "Fade",
+            Self::Push => "Push",
+            Self::Cover => "Cover",
+            Self::Uncover => "Uncover",
+        }
+    }
+}
+
+/// A transition effect when changing to a page in presentation mode. Written inline.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Transition {
+    pub style: TransitionStyle,
+    pub duration_ms: u32,
+}
+impl Transition {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /Trans /S /{}", self.style.as_pdf_name())?;
+        write!(writer, " /D {}", milliseconds_as_seconds(self.duration_ms))?;
+        write!(writer, " >>")
+    }
+}
+
+/// Formats a duration given in milliseconds as a PDF number of seconds.
+fn milliseconds_as_seconds(milliseconds: u32) -> String {
+    let seconds = milliseconds / 1000;
+    let fraction = milliseconds % 1000;
+    if fraction == 0 {
+        format!("{}", seconds)
+    } else {
+        let fraction_digits = format!("{:03}", fraction);
+        format!("{}.{}", seconds, fraction_digits.trim_end_matches('0'))
+    }
+}
+
+/// A rectangle in default user space units. Written inline.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Rectangle {
+    pub left: u64,
+    pub bottom: u64,
+    pub right: u64,
+    pub top: u64,
+}
+impl Rectangle {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "[ {} {} {} {} ]", self.left, self.bottom, self.right, self.top)
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct PageResources {
     pub image_xobject_ids: Vec<PdfObjectId>,
+    pub font_ids: Vec<PdfObjectId>,
+    pub ext_g_state_ids: Vec<PdfObjectId>,
+    pub optional_content_group_ids: Vec<PdfObjectId>,
 }
 impl PageResources {
     pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
@@ -146,6 +1498,27 @@ impl PageResources {
             write!(writer, " /Im{} {} 0 R", image_index, image_xobject_id)?;
         }
         write!(writer, " >>")?;
+        if !self.font_ids.is_empty() {
+            write!(writer, " /Font <<")?;
+            for (font_index, font_id) in self.font_ids.iter().copied().enumerate() {
+                write!(writer, " /F{} {} 0 R", font_index, font_id)?;
+            }
+            write!(writer, " >>")?;
+        }
+        if !self.ext_g_state_ids.is_empty() {
+            write!(writer, " /ExtGState <<")?;
+            for (state_index, state_id) in self.ext_g_state_ids.iter().copied().enumerate() {
+                write!(writer, " /GS{} {} 0 R", state_index, state_id)?;
+            }
+            write!(writer, " >>")?;
+        }
+        if !self.optional_content_group_ids.is_empty() {
+            write!(writer, " /Properties <<")?;
+            for (group_index, group_id) in self.optional_content_group_ids.iter().copied().enumerate() {
+                write!(writer, " /OC{} {} 0 R", group_index, group_id)?;
+            }
+            write!(writer, " >>")?;
+        }
         write!(writer, " >>\n")
     }
 }
@@ -155,11 +1528,44 @@ pub struct PageContents {
     pub commands: String,
 }
 impl PageContents {
-    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
-        write!(writer, "<< /Length {} >>\n", self.commands.len())?;
-        write!(writer, "stream\n")?;
-        write!(writer, "{}", self.commands)?;
-        write!(writer, "\nendstream\n")
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<<")?;
+        encoding.write_stream(writer, self.commands.as_bytes())
+    }
+}
+
+/// An optional content group, i.e. a layer of content which viewers can show and hide.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct OptionalContentGroup {
+    /// The name of the layer as shown by viewers.
+    pub name: String,
+}
+impl OptionalContentGroup {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /OCG /Name {} >>\n", encoding.text_string(&self.name))
+    }
+}
+
+/// An object which this crate does not model, written out verbatim, e.g. an output intent.
+///
+/// Other objects can refer to it by its ID like to any other object.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RawObject {
+    /// The entries of the object's dictionary in PDF syntax, without the enclosing `<<` and `>>`,
+    /// e.g. `/Type /OCG /Name (Scans)`. If the object has a stream, `/Filter` and `/Length` are
+    /// added automatically and must not be given.
+    pub dict: String,
+    /// The unencoded data of the object's stream, if it has one. It is compressed and encoded like
+    /// the page contents.
+    pub stream: Option<Vec<u8>>,
+}
+impl RawObject {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<< {}", self.dict)?;
+        match &self.stream {
+            Some(stream) => encoding.write_stream(writer, stream),
+            None => write!(writer, " >>\n"),
+        }
     }
 }
 
@@ -169,15 +1575,33 @@ pub struct ImageXObject {
     pub height: u64,
     pub color_space: &'static str,
     pub bits_per_component: u8,
+    /// Whether viewers should smooth the image when scaling it up.
     pub interpolate: bool,
+    /// The rendering intent for the image, or `None` to leave it to the viewer (which usually
+    /// uses relative colorimetric).
+    pub intent: Option<RenderingIntent>,
+    /// The decode array, e.g. `[ 1 0 ]` to invert a grayscale image.
+    pub decode: Option<String>,
+    /// The colors which are not painted, leaving whatever is beneath the image visible: a
+    /// minimum and a maximum sample value (before decoding) for each color component.
+    pub color_key_mask: Option<Vec<u16>>,
+    /// A grayscale image XObject providing the opacity of each pixel.
+    pub smask_id: Option<PdfObjectId>,
     pub data_filters: Vec<String>,
-    pub data: Vec<u8>,
+    /// The `/ColorTransform` parameter of the `/DCTDecode` filter: whether the decoder converts the
+    /// colors from YCbCr (or YCCK) if the image has no Adobe block saying so.
+    pub color_transform: Option<u8>,
+    pub data: ImageData,
 }
 impl ImageXObject {
-    pub fn from_jpeg_image(jpeg_image: &crate::jpeg::Image) -> Option<Self> {
+    /// Creates an image XObject from a JPEG image in the given color space, which is usually the
+    /// image's own `color_space`.
+    ///
+    /// The image is shared instead of copied and written out directly when the document is written.
+    pub fn from_jpeg_image(jpeg_image: Arc<jpeg::Image>, color_space: ColorSpace) -> Option<Self> {
         let width = jpeg_image.width.into();
         let height = jpeg_image.height.into();
-        let color_space = match jpeg_image.color_space {
+        let color_space = match color_space {
             ColorSpace::Grayscale => "/DeviceGray",
             ColorSpace::Rgb => "/DeviceRGB",
             ColorSpace::Cmyk => "/DeviceCMYK",
@@ -185,36 +1609,483 @@ impl ImageXObject {
         };
         let bits_per_component = jpeg_image.bit_depth;
         let interpolate = false;
+        let intent = None;
+        // Adobe applications store four components inverted
+        let decode = match (color_space, jpeg_image.adobe_transform) {
+            ("/DeviceCMYK", Some(_)) => Some("[ 1 0 1 0 1 0 1 0 ]".to_owned()),
+            _ => None,
+        };
+        let color_key_mask = None;
+        let smask_id = None;
         let data_filters = vec!["/DCTDecode".to_owned()];
-        let mut data = Vec::new();
-        jpeg_image.write(&mut data).ok()?;
+        // without the Adobe block, the decoder assumes YCbCr for three components and no transform
+        // for four, unless told otherwise
+        let color_transform = if jpeg_image.has_adobe_block() {
+            None
+        } else {
+            match jpeg_image.adobe_transform {
+                None|Some(AdobeTransform::Other(_)) => None,
+                Some(AdobeTransform::Untransformed) => Some(0),
+                Some(AdobeTransform::YCbCr|AdobeTransform::Ycck) => Some(1),
+            }
+        };
+        let data = ImageData::Jpeg(jpeg_image);
         Some(Self {
             width,
             height,
             color_space,
             bits_per_component,
             interpolate,
+            intent,
+            decode,
+            color_key_mask,
+            smask_id,
             data_filters,
+            color_transform,
             data,
         })
     }
 
-    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+    /// The data as written into the stream, i.e. encoded as requested.
+    pub fn stream_data(&self, ascii_encoding: AsciiEncoding) -> Result<Vec<u8>, io::Error> {
+        let mut data = Vec::with_capacity(self.data.encoded_len());
+        self.data.write_to_pdf(&mut data)?;
+        Ok(ascii_encoding.encode(&data))
+    }
+
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
         write!(writer, "<< /Type /XObject /Subtype /Image")?;
         write!(writer, " /Width {}", self.width)?;
         write!(writer, " /Height {}", self.height)?;
         write!(writer, " /ColorSpace {}", self.color_space)?;
         write!(writer, " /BitsPerComponent {}", self.bits_per_component)?;
-        if self.data_filters.len() > 0 {
+        if self.interpolate {
+            write!(writer, " /Interpolate true")?;
+        }
+        if let Some(intent) = self.intent {
+            write!(writer, " /Intent /{}", intent.as_pdf_name())?;
+        }
+        if let Some(decode) = &self.decode {
+            write!(writer, " /Decode {}", decode)?;
+        }
+        if let Some(color_key_mask) = &self.color_key_mask {
+            write!(writer, " /Mask [")?;
+            for value in color_key_mask {
+                write!(writer, " {}", value)?;
+            }
+            write!(writer, " ]")?;
+        }
+        if let Some(smask_id) = self.smask_id {
+            write!(writer, " /SMask {} 0 R", smask_id)?;
+        }
+        // the ASCII encoding is the outermost filter
+        let ascii_encoding = encoding.ascii_encoding;
+        let filters: Vec<&str> = ascii_encoding.filter().into_iter()
+            .chain(self.data_filters.iter().map(|f| f.as_str()))
+            .collect();
+        if !filters.is_empty() {
             write!(writer, " /Filter [")?;
-            for filter in &self.data_filters {
+            for filter in &filters {
                 write!(writer, " {}", filter)?;
             }
             write!(writer, " ]")?;
         }
-        write!(writer, " /Length {}", self.data.len())?;
-        write!(writer, " >>\nstream\n")?;
-        writer.write_all(&self.data)?;
+        if let Some(color_transform) = self.color_transform {
+            write!(writer, " /DecodeParms [")?;
+            for filter in &filters {
+                if *filter == "/DCTDecode" {
+                    write!(writer, " << /ColorTransform {} >>", color_transform)?;
+                } else {
+                    write!(writer, " null")?;
+                }
+            }
+            write!(writer, " ]")?;
+        }
+        if ascii_encoding == AsciiEncoding::None && !encoding.is_encrypted() {
+            // write the image data directly instead of copying it first
+            write!(writer, " /Length {}", self.data.encoded_len())?;
+            write!(writer, " >>\nstream\n")?;
+            self.data.write_to_pdf(&mut writer)?;
+        } else {
+            let data = encoding.encrypt_stream_data(self.stream_data(ascii_encoding)?);
+            write!(writer, " /Length {}", data.len())?;
+            write!(writer, " >>\nstream\n")?;
+            writer.write_all(&data)?;
+        }
         write!(writer, "\nendstream\n")
     }
 }
+
+/// The data of an image XObject.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ImageData {
+    Bytes(Vec<u8>),
+    /// A JPEG image, which is serialized straight into the output.
+    Jpeg(Arc<jpeg::Image>),
+}
+impl ImageData {
+    /// The number of bytes the data takes up when written.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Bytes(bytes) => bytes.len(),
+            Self::Jpeg(jpeg_image) => jpeg_image.encoded_len(),
+        }
+    }
+
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        match self {
+            Self::Bytes(bytes) => writer.write_all(bytes),
+            Self::Jpeg(jpeg_image) => jpeg_image.write(writer)
+                .map_err(|e| match e {
+                    jpeg::Error::Io(io_error) => io_error,
+                    other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+                }),
+        }
+    }
+}
+
+/// How colors outside the gamut of the output device are mapped into it when an image is rendered.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum RenderingIntent {
+    /// All colors are compressed into the gamut, preserving their relations; suited to photographs.
+    Perceptual,
+    /// Colors within the gamut are kept, relative to the white point of the output medium.
+    RelativeColorimetric,
+    /// Saturation is preserved at the expense of accuracy; suited to charts and graphics.
+    Saturation,
+    /// Colors within the gamut are kept exactly, including the white point of the source.
+    AbsoluteColorimetric,
+}
+impl RenderingIntent {
+    pub fn as_pdf_name(&self) -> &'static str {
+        match self {
+            Self::Perceptual => "Perceptual",
+            Self::RelativeColorimetric => "RelativeColorimetric",
+            Self::Saturation => "Saturation",
+            Self::AbsoluteColorimetric => "AbsoluteColorimetric",
+        }
+    }
+}
+
+/// One of the standard Type 1 fonts, which is not embedded into the document.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Type1Font {
+    pub font: StandardFont,
+}
+impl Type1Font {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /Font /Subtype /Type1")?;
+        write!(writer, " /BaseFont /{}", self.font.base_font_name())?;
+        write!(writer, " /Encoding /WinAnsiEncoding")?;
+        write!(writer, " >>\n")
+    }
+}
+
+/// A graphics state parameter dictionary.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ExtGState {
+    /// The opacity of stroking and non-stroking operations in percent.
+    pub opacity_percent: Option<u8>,
+    /// How what is drawn is combined with what is already on the page.
+    pub blend_mode: Option<BlendMode>,
+}
+impl ExtGState {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /ExtGState")?;
+        if let Some(opacity_percent) = self.opacity_percent {
+            let alpha = f64::from(opacity_percent) / 100.0;
+            write!(writer, " /CA {:.2} /ca {:.2}", alpha, alpha)?;
+        }
+        if let Some(blend_mode) = self.blend_mode {
+            write!(writer, " /BM /{}", blend_mode.as_pdf_name())?;
+        }
+        write!(writer, " >>\n")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BlendMode {
+    /// The colors are multiplied, so that white leaves what is underneath unchanged and black
+    /// stays black.
+    Multiply,
+}
+impl BlendMode {
+    pub fn as_pdf_name(&self) -> &'static str {
+        match self {
+            Self::Multiply => "Multiply",
+        }
+    }
+}
+
+/// The root of the logical structure of a tagged document.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StructTreeRoot {
+    pub kid_ids: Vec<PdfObjectId>,
+    /// For each page with marked content (indexed by the page's `struct_parents` value), the
+    /// structure elements containing the page's marked content (indexed by marked-content ID).
+    pub parent_tree: Vec<Vec<PdfObjectId>>,
+}
+impl StructTreeRoot {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /StructTreeRoot")?;
+        write!(writer, " /K [")?;
+        for &kid_id in &self.kid_ids {
+            write!(writer, " {} 0 R", kid_id)?;
+        }
+        write!(writer, " ]")?;
+        write!(writer, " /ParentTree << /Nums [")?;
+        for (key, element_ids) in self.parent_tree.iter().enumerate() {
+            write!(writer, " {} [", key)?;
+            for &element_id in element_ids {
+                write!(writer, " {} 0 R", element_id)?;
+            }
+            write!(writer, " ]")?;
+        }
+        write!(writer, " ] >>")?;
+        write!(writer, " /ParentTreeNextKey {}", self.parent_tree.len())?;
+        write!(writer, " >>\n")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum StructElementKid {
+    Element(PdfObjectId),
+    /// Marked content with the given ID on the structure element's page.
+    MarkedContent(u64),
+}
+
+/// An element of the logical structure of a tagged document.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StructElement {
+    pub structure_type: &'static str,
+    pub parent_id: PdfObjectId,
+    pub page_id: Option<PdfObjectId>,
+    pub kids: Vec<StructElementKid>,
+    pub alt_text: Option<String>,
+}
+impl StructElement {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /StructElem")?;
+        write!(writer, " /S /{}", self.structure_type)?;
+        write!(writer, " /P {} 0 R", self.parent_id)?;
+        if let Some(page_id) = self.page_id {
+            write!(writer, " /Pg {} 0 R", page_id)?;
+        }
+        write!(writer, " /K [")?;
+        for kid in &self.kids {
+            match kid {
+                StructElementKid::Element(element_id) => write!(writer, " {} 0 R", element_id)?,
+                StructElementKid::MarkedContent(mcid) => write!(writer, " {}", mcid)?,
+            }
+        }
+        write!(writer, " ]")?;
+        if let Some(alt_text) = &self.alt_text {
+            write!(writer, " /Alt {}", encoding.text_string(alt_text))?;
+        }
+        write!(writer, " >>\n")
+    }
+}
+
+/// Encodes text as a PDF text string, including the delimiters.
+///
+/// Text that can be represented in PDFDocEncoding (printable ASCII, tabs, line breaks and most of
+/// Latin-1) is output as a literal string; anything else as a hexadecimal string in UTF-16BE with
+/// a byte order mark.
+pub fn text_string(text: &str) -> String {
+    match pdf_doc_encode(text) {
+        Some(bytes) => literal_string(&bytes),
+        None => hex_string(&text_bytes(text)),
+    }
+}
+
+/// Encodes text as the bytes of a PDF text string: in PDFDocEncoding if possible, otherwise in
+/// UTF-16BE with a byte order mark.
+fn text_bytes(text: &str) -> Vec<u8> {
+    if let Some(bytes) = pdf_doc_encode(text) {
+        return bytes;
+    }
+    let mut bytes = Vec::with_capacity(2 * text.len() + 2);
+    bytes.extend_from_slice(&[0xFE, 0xFF]);
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    bytes
+}
+
+/// Encodes text in PDFDocEncoding, or returns `None` if it contains characters which cannot be
+/// represented that way.
+///
+/// Only the characters that PDFDocEncoding shares with Latin-1 are used; the code points that
+/// PDFDocEncoding assigns differently (0x18 to 0x1F, 0x7F to 0xA0 and 0xAD) are avoided.
+fn pdf_doc_encode(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|c| match u32::from(c) {
+            0x09|0x0A|0x0D|0x20..=0x7E|0xA1..=0xAC|0xAE..=0xFF => Some(u8::try_from(u32::from(c)).unwrap()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Encodes bytes as a PDF string, including the delimiters: as a literal string if the bytes are
+/// mostly printable ASCII, as a hexadecimal string otherwise.
+pub fn byte_string(bytes: &[u8]) -> String {
+    let escaped_count = bytes.iter()
+        .filter(|b| !(0x20..=0x7E).contains(*b))
+        .count();
+    // an octal escape takes four characters, a hexadecimal byte two
+    if bytes.len() + 3 * escaped_count <= 2 * bytes.len() {
+        literal_string(bytes)
+    } else {
+        hex_string(bytes)
+    }
+}
+
+/// Encodes bytes as a PDF literal string, including the delimiting parentheses.
+///
+/// Parentheses and backslashes are escaped; bytes outside of printable ASCII are output as octal
+/// escapes so that the result can be embedded in a content stream as-is.
+pub fn literal_string(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity(bytes.len() + 2);
+    ret.push('(');
+    for &b in bytes {
+        match b {
+            b'('|b')'|b'\\' => {
+                ret.push('\\');
+                ret.push(char::from(b));
+            },
+            0x20..=0x7E => ret.push(char::from(b)),
+            other => ret.push_str(&format!("\\{:03o}", other)),
+        }
+    }
+    ret.push(')');
+    ret
+}
+
+/// Encodes bytes as a PDF hexadecimal string, including the delimiting angle brackets.
+pub fn hex_string(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity(2 * bytes.len() + 2);
+    ret.push('<');
+    for &b in bytes {
+        ret.push_str(&format!("{:02X}", b));
+    }
+    ret.push('>');
+    ret
+}
+
+/// Where a link leads.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum LinkTarget {
+    Uri(String),
+    Destination(Destination),
+}
+
+/// A clickable area on a page.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LinkAnnotation {
+    pub rect: Rectangle,
+    pub target: LinkTarget,
+}
+impl LinkAnnotation {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /Annot /Subtype /Link")?;
+        write!(writer, " /Rect ")?;
+        self.rect.write_to_pdf(&mut writer)?;
+        // no visible border
+        write!(writer, " /Border [ 0 0 0 ]")?;
+        match &self.target {
+            LinkTarget::Uri(uri) => {
+                write!(writer, " /A << /S /URI /URI {} >>", encoding.literal_string(uri.as_bytes()))?;
+            },
+            LinkTarget::Destination(destination) => {
+                write!(writer, " /Dest ")?;
+                destination.write_to_pdf(&mut writer)?;
+            },
+        }
+        write!(writer, " >>\n")
+    }
+}
+
+/// A signature form field, merged with its (invisible) widget annotation.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SignatureField {
+    pub name: String,
+    pub page_id: PdfObjectId,
+    pub signature_id: PdfObjectId,
+}
+impl SignatureField {
+    pub fn write_to_pdf<W: Write>(&self, mut writer: W, encoding: &mut ObjectEncoding) -> Result<(), io::Error> {
+        write!(writer, "<< /Type /Annot /Subtype /Widget")?;
+        write!(writer, " /FT /Sig")?;
+        write!(writer, " /T {}", encoding.text_string(&self.name))?;
+        write!(writer, " /V {} 0 R", self.signature_id)?;
+        write!(writer, " /P {} 0 R", self.page_id)?;
+        // zero-size rectangle: the signature is not visible on the page
+        write!(writer, " /Rect [ 0 0 0 0 ]")?;
+        // flags: Print (4) | Locked (128)
+        write!(writer, " /F 132")?;
+        write!(writer, " >>\n")
+    }
+}
+
+/// A signature dictionary with space reserved for a detached PKCS#7 signature.
+///
+/// The byte range and the signature contents are written as placeholders which are filled in by
+/// [`Document::write`] and an external signing tool, respectively.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Signature {
+    pub contents_size: usize,
+}
+impl Signature {
+    pub fn write_to_pdf<W: Write>(&self, writer: W) -> Result<(), io::Error> {
+        self.write_placeholder_to_pdf(&mut CountingWriter::new(writer, 0))?;
+        Ok(())
+    }
+
+    fn write_placeholder_to_pdf<W: Write>(&self, writer: &mut CountingWriter<W>) -> Result<SignaturePlaceholder, io::Error> {
+        write!(writer, "<< /Type /Sig")?;
+        write!(writer, " /Filter /Adobe.PPKLite")?;
+        write!(writer, " /SubFilter /adbe.pkcs7.detached")?;
+        write!(writer, " /ByteRange ")?;
+        let byte_range_pos = writer.position();
+        write_byte_range(&mut *writer, [0; 4])?;
+        write!(writer, " /Contents ")?;
+        let contents_start_pos = writer.position();
+        write!(writer, "<")?;
+        writer.write_all("00".repeat(self.contents_size).as_bytes())?;
+        write!(writer, ">")?;
+        let contents_end_pos = writer.position();
+        write!(writer, " >>\n")?;
+        Ok(SignaturePlaceholder {
+            byte_range_pos,
+            contents_start_pos,
+            contents_end_pos,
+        })
+    }
+}
+
+/// The absolute stream positions of the placeholders in a signature dictionary.
+struct SignaturePlaceholder {
+    byte_range_pos: u64,
+    contents_start_pos: u64,
+    contents_end_pos: u64,
+}
+impl SignaturePlaceholder {
+    /// Calculates the byte range covering the whole document except for the signature contents.
+    fn byte_range(&self, pdf_start_pos: u64, pdf_end_pos: u64) -> [u64; 4] {
+        [
+            0,
+            self.contents_start_pos - pdf_start_pos,
+            self.contents_end_pos - pdf_start_pos,
+            pdf_end_pos - self.contents_end_pos,
+        ]
+    }
+}
+
+/// Writes a byte range array with fixed-width numbers, allowing it to be overwritten in place.
+fn write_byte_range<W: Write>(mut writer: W, byte_range: [u64; 4]) -> Result<(), io::Error> {
+    write!(
+        writer, "[ {:010} {:010} {:010} {:010} ]",
+        byte_range[0], byte_range[1], byte_range[2], byte_range[3],
+    )
+}
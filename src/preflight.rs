@@ -0,0 +1,334 @@
+//! Checking of JPEG images against the limits of programs which process the PDF documents they
+//! are embedded in, so that problems can be fixed before a document reaches such a program.
+//!
+//! The checks assume that the images are embedded the way this crate embeds them: unchanged, with
+//! a device color space and with the page sized according to the density of the image.
+
+
+use std::fmt;
+
+use crate::jpeg::{ColorSpace, FrameType, Image};
+use crate::lint::Severity;
+use crate::{ConvertError, MAX_PAGE_DIMENSION};
+
+
+/// A program (or a standard) whose limits the images are checked against.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Target {
+    /// Ghostscript, which is also used by many printing systems and document converters.
+    Ghostscript,
+    /// Preview and other programs built on the PDF support of macOS.
+    Preview,
+    /// The PDF/A archival standard as checked by validators, in its strictest part PDF/A-1b.
+    PdfA,
+}
+impl Target {
+    pub const ALL: [Target; 3] = [Self::Ghostscript, Self::Preview, Self::PdfA];
+
+    /// The name of the target, as output in machine-readable reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ghostscript => "ghostscript",
+            Self::Preview => "preview",
+            Self::PdfA => "pdf-a",
+        }
+    }
+
+    fn limits(self) -> Limits {
+        match self {
+            Self::Ghostscript => Limits {
+                // JPEG_MAX_DIMENSION of the IJG library
+                max_dimension_px: Some(65500),
+                max_stream_length: None,
+                min_page_dimension: None,
+                supports_user_unit: true,
+                uses_embedded_profile: false,
+                needs_output_intent: false,
+            },
+            Self::Preview => Limits {
+                max_dimension_px: None,
+                max_stream_length: None,
+                min_page_dimension: None,
+                supports_user_unit: false,
+                uses_embedded_profile: true,
+                needs_output_intent: false,
+            },
+            Self::PdfA => Limits {
+                max_dimension_px: None,
+                // the largest integer allowed by PDF/A, which limits the /Length of a stream
+                max_stream_length: Some(2_147_483_647),
+                min_page_dimension: Some(3),
+                // /UserUnit was introduced in PDF 1.6; PDF/A-1 is based on PDF 1.4
+                supports_user_unit: false,
+                uses_embedded_profile: false,
+                needs_output_intent: true,
+            },
+        }
+    }
+}
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What a target can process.
+struct Limits {
+    /// The largest width or height of an image, in pixels.
+    max_dimension_px: Option<u16>,
+    /// The largest length of the data of an image, in bytes.
+    max_stream_length: Option<u64>,
+    /// The smallest width or height of a page, in points.
+    min_page_dimension: Option<u64>,
+    /// Whether pages larger than [`MAX_PAGE_DIMENSION`] are scaled according to their `/UserUnit`.
+    supports_user_unit: bool,
+    /// Whether the colors of an image are interpreted according to the ICC profile embedded in it
+    /// instead of the device color space it is declared with.
+    uses_embedded_profile: bool,
+    /// Whether device color spaces are only allowed along with an output intent, which this crate
+    /// does not write.
+    needs_output_intent: bool,
+}
+
+/// The check which led to a finding.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Check {
+    /// The image cannot be embedded at all.
+    Encoding,
+    /// The image is too wide or too tall.
+    Dimensions,
+    /// The image data is too long.
+    StreamSize,
+    /// The page is too large or too small.
+    PageSize,
+    /// The colors will not be displayed as intended.
+    ColorSpace,
+}
+impl Check {
+    /// The name of the check, as output in machine-readable reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Encoding => "encoding",
+            Self::Dimensions => "dimensions",
+            Self::StreamSize => "stream-size",
+            Self::PageSize => "page-size",
+            Self::ColorSpace => "color-space",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Finding {
+    /// The target which cannot process the image, or `None` if no target can.
+    pub target: Option<Target>,
+    pub check: Check,
+    pub severity: Severity,
+    pub message: String,
+    /// How to avoid the problem.
+    pub fix: String,
+}
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(target) = self.target {
+            write!(f, "{}: ", target)?;
+        }
+        write!(f, "{}: {} ({}); fix: {}", self.severity.name(), self.message, self.check.name(), self.fix)
+    }
+}
+
+
+/// Describes how to split the image into tiles of at most the given size.
+fn tile_fix(image: &Image, max_size: u16) -> String {
+    let splittable = matches!(image.frame_type, FrameType::Baseline|FrameType::ExtendedSequential)
+        && image.scans().is_ok_and(|scans| scans.len() == 1);
+    if splittable {
+        format!("split the image with --tile-size {}", max_size)
+    } else {
+        format!("re-encode the image as a sequential JPEG, then split it with --tile-size {}", max_size)
+    }
+}
+
+fn color_space_name(color_space: ColorSpace) -> String {
+    match color_space {
+        ColorSpace::Grayscale => "DeviceGray".to_owned(),
+        ColorSpace::Rgb => "DeviceRGB".to_owned(),
+        ColorSpace::Cmyk => "DeviceCMYK".to_owned(),
+        ColorSpace::Other(n) => format!("{} components", n),
+    }
+}
+
+
+/// Collects the findings for the images of a document, one image at a time.
+pub struct Preflight {
+    targets: Vec<Target>,
+    has_rgb: bool,
+    has_cmyk: bool,
+    has_images: bool,
+}
+impl Preflight {
+    /// Prepares checking against the given targets, or against all of them if none are given.
+    pub fn new(targets: &[Target]) -> Self {
+        let mut targets = if targets.is_empty() { Target::ALL.to_vec() } else { targets.to_vec() };
+        targets.sort_unstable();
+        targets.dedup();
+        Self {
+            targets,
+            has_rgb: false,
+            has_cmyk: false,
+            has_images: false,
+        }
+    }
+
+    /// Checks an image which is to be embedded into the document and returns what was found, most
+    /// serious first.
+    ///
+    /// The density of the image determines the size of its page; it must already have been
+    /// replaced if the page is to be sized differently.
+    pub fn check_image(&mut self, image: &Image) -> Vec<Finding> {
+        self.has_images = true;
+        match image.color_space {
+            ColorSpace::Rgb => self.has_rgb = true,
+            ColorSpace::Cmyk => self.has_cmyk = true,
+            ColorSpace::Grayscale|ColorSpace::Other(_) => {},
+        }
+
+        let mut findings = Vec::new();
+        if let Err(e) = crate::check_encoding(image) {
+            findings.push(Finding {
+                target: None,
+                check: Check::Encoding,
+                severity: Severity::Error,
+                message: e.to_string(),
+                fix: "re-encode the image as a baseline JPEG with 8 bits per component".to_owned(),
+            });
+            return findings;
+        }
+
+        let page_size = match crate::page_size_pt(image) {
+            Ok(page_size) => Some(page_size),
+            Err(e @ ConvertError::ZeroDensity) => {
+                findings.push(Finding {
+                    target: None,
+                    check: Check::PageSize,
+                    severity: Severity::Error,
+                    message: e.to_string(),
+                    fix: "size the page using --dpi or --width and --height".to_owned(),
+                });
+                None
+            },
+            Err(_) => None,
+        };
+        let stream_length = u64::try_from(image.encoded_len()).unwrap();
+        for &target in &self.targets {
+            let limits = target.limits();
+
+            if let Some(max_dimension_px) = limits.max_dimension_px {
+                if image.width > max_dimension_px || image.height > max_dimension_px {
+                    findings.push(Finding {
+                        target: Some(target),
+                        check: Check::Dimensions,
+                        severity: Severity::Error,
+                        message: format!(
+                            "image of {}x{} pixels exceeds the limit of {} pixels per side",
+                            image.width, image.height, max_dimension_px,
+                        ),
+                        fix: tile_fix(image, max_dimension_px),
+                    });
+                }
+            }
+
+            if let Some(max_stream_length) = limits.max_stream_length {
+                if stream_length > max_stream_length {
+                    findings.push(Finding {
+                        target: Some(target),
+                        check: Check::StreamSize,
+                        severity: Severity::Error,
+                        message: format!(
+                            "image data of {} bytes exceeds the limit of {} bytes per stream",
+                            stream_length, max_stream_length,
+                        ),
+                        fix: format!("{} so that each tile is embedded as a separate stream", tile_fix(image, 16384)),
+                    });
+                }
+            }
+
+            if let Some((width_pt, height_pt)) = page_size {
+                if !limits.supports_user_unit && crate::user_unit_for(width_pt, height_pt).is_some() {
+                    findings.push(Finding {
+                        target: Some(target),
+                        check: Check::PageSize,
+                        severity: Severity::Warning,
+                        message: format!(
+                            "page of {}x{} points exceeds {} points per side and is only displayed at the right size if /UserUnit is supported",
+                            width_pt, height_pt, MAX_PAGE_DIMENSION,
+                        ),
+                        fix: format!("make the page at most {} inches per side using --width and --height or a higher --dpi", MAX_PAGE_DIMENSION / 72),
+                    });
+                }
+                if let Some(min_page_dimension) = limits.min_page_dimension {
+                    if width_pt < min_page_dimension || height_pt < min_page_dimension {
+                        findings.push(Finding {
+                            target: Some(target),
+                            check: Check::PageSize,
+                            severity: Severity::Error,
+                            message: format!(
+                                "page of {}x{} points is smaller than {} points per side",
+                                width_pt, height_pt, min_page_dimension,
+                            ),
+                            fix: "make the page larger using --width and --height or a lower --dpi".to_owned(),
+                        });
+                    }
+                }
+            }
+
+            if !limits.uses_embedded_profile && !limits.needs_output_intent && image.has_icc_profile() {
+                findings.push(Finding {
+                    target: Some(target),
+                    check: Check::ColorSpace,
+                    severity: Severity::Warning,
+                    message: format!(
+                        "embedded ICC profile is ignored; the colors are interpreted as {}",
+                        color_space_name(image.color_space),
+                    ),
+                    fix: "convert the colors with --convert-to-srgb".to_owned(),
+                });
+            }
+        }
+
+        findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+        findings
+    }
+
+    /// Returns what was found about the document as a whole once all of its images have been
+    /// checked, most serious first.
+    pub fn finish(self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        if !self.has_images {
+            return findings;
+        }
+        for &target in &self.targets {
+            if !target.limits().needs_output_intent {
+                continue;
+            }
+            findings.push(Finding {
+                target: Some(target),
+                check: Check::ColorSpace,
+                severity: Severity::Error,
+                message: "images are embedded with device color spaces, but the document has no output intent (nor the required XMP metadata)".to_owned(),
+                fix: "convert the finished document with a PDF/A converter, which adds both".to_owned(),
+            });
+            if self.has_rgb && self.has_cmyk {
+                findings.push(Finding {
+                    target: Some(target),
+                    check: Check::ColorSpace,
+                    severity: Severity::Error,
+                    message: "RGB and CMYK images are mixed, but a document can only have one output intent".to_owned(),
+                    fix: "convert the CMYK images to RGB, or put them into a separate document".to_owned(),
+                });
+            }
+        }
+        findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+        findings
+    }
+}
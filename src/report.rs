@@ -0,0 +1,356 @@
+//! Collection of the results of a run and of the warnings that arose, output at the end of the run
+//! for humans or as JSON.
+
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use jpeg2pdf::jpeg::{self, ColorSpace, DensityUnit};
+
+use crate::manifest;
+
+
+/// A JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    pub fn object<K: Into<String>, I: IntoIterator<Item = (K, Json)>>(members: I) -> Self {
+        Self::Object(
+            members.into_iter()
+                .map(|(k, v)| (k.into(), v))
+                .collect()
+        )
+    }
+
+    pub fn string<S: Into<String>>(s: S) -> Self {
+        Self::String(s.into())
+    }
+
+    pub fn path(path: &Path) -> Self {
+        Self::String(path.to_string_lossy().into_owned())
+    }
+}
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) => write!(f, "{}", n),
+            Self::String(s) => write_json_string(f, s),
+            Self::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Self::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_json_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_json_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+
+/// How serious a warning is.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Purely informational, e.g. how much an image was optimized.
+    Note,
+    /// Something had to be assumed or was lost; the output might not be as intended.
+    Warning,
+}
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Note => "note",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning {
+    pub severity: Severity,
+    pub message: String,
+}
+impl Warning {
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("severity", Json::string(self.severity.name())),
+            ("message", Json::string(self.message.clone())),
+        ])
+    }
+}
+
+
+/// What became of an input image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InputStatus {
+    /// The image has not been processed (yet).
+    Pending,
+    Converted { output_path: PathBuf },
+    SkippedBlank,
+    SkippedDuplicate { original_path: PathBuf },
+    SkippedAlreadyConverted,
+    /// The conversion failed for the given reason; a page stating it may have been written
+    /// instead.
+    Failed { reason: String, placeholder_path: Option<PathBuf> },
+}
+
+/// The properties of an input image as detected from its data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageProperties {
+    pub encoding: String,
+    pub width_px: u16,
+    pub height_px: u16,
+    pub bit_depth: u8,
+    pub color_space: ColorSpace,
+    /// Whether the four components are stored as YCCK instead of CMYK.
+    pub is_ycck: bool,
+    pub density_unit: DensityUnit,
+    pub density_x: u16,
+    pub density_y: u16,
+    pub has_icc_profile: bool,
+}
+impl ImageProperties {
+    pub fn of(image: &jpeg::Image) -> Self {
+        Self {
+            encoding: image.frame_type.description(),
+            width_px: image.width,
+            height_px: image.height,
+            bit_depth: image.bit_depth,
+            color_space: image.color_space,
+            is_ycck: image.is_ycck(),
+            density_unit: image.density_unit,
+            density_x: image.density_x,
+            density_y: image.density_y,
+            has_icc_profile: image.has_icc_profile(),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let color_space = match self.color_space {
+            ColorSpace::Grayscale => Json::string("gray"),
+            ColorSpace::Rgb => Json::string("rgb"),
+            ColorSpace::Cmyk if self.is_ycck => Json::string("ycck"),
+            ColorSpace::Cmyk => Json::string("cmyk"),
+            ColorSpace::Other(n) => Json::string(format!("other-{}", n)),
+        };
+        let density_unit = match self.density_unit {
+            DensityUnit::NoUnit => Json::Null,
+            DensityUnit::DotsPerInch => Json::string("dpi"),
+            DensityUnit::DotsPerCentimeter => Json::string("dpcm"),
+            DensityUnit::Other(u) => Json::string(format!("other-{}", u)),
+        };
+        Json::object([
+            ("encoding", Json::string(self.encoding.clone())),
+            ("width_px", Json::Number(self.width_px.into())),
+            ("height_px", Json::Number(self.height_px.into())),
+            ("bit_depth", Json::Number(self.bit_depth.into())),
+            ("color_space", color_space),
+            ("density", Json::object([
+                ("unit", density_unit),
+                ("x", Json::Number(self.density_x.into())),
+                ("y", Json::Number(self.density_y.into())),
+            ])),
+            ("icc_profile", Json::Bool(self.has_icc_profile)),
+        ])
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InputReport {
+    pub path: PathBuf,
+    pub status: InputStatus,
+    /// `None` if the image has not been read.
+    pub properties: Option<ImageProperties>,
+    /// The size of the page in PDF points, after cropping.
+    pub page_size_pt: Option<(u64, u64)>,
+    pub warnings: Vec<Warning>,
+}
+impl InputReport {
+    pub fn to_json(&self) -> Json {
+        let mut members = vec![
+            ("path", Json::path(&self.path)),
+        ];
+        match &self.status {
+            InputStatus::Pending => {
+                members.push(("status", Json::string("pending")));
+            },
+            InputStatus::Converted { output_path } => {
+                members.push(("status", Json::string("converted")));
+                members.push(("output", Json::path(output_path)));
+            },
+            InputStatus::SkippedBlank => {
+                members.push(("status", Json::string("skipped-blank")));
+            },
+            InputStatus::SkippedDuplicate { original_path } => {
+                members.push(("status", Json::string("skipped-duplicate")));
+                members.push(("duplicate_of", Json::path(original_path)));
+            },
+            InputStatus::SkippedAlreadyConverted => {
+                members.push(("status", Json::string("skipped-already-converted")));
+            },
+            InputStatus::Failed { reason, placeholder_path } => {
+                members.push(("status", Json::string("failed")));
+                members.push(("error", Json::string(reason.clone())));
+                if let Some(placeholder_path) = placeholder_path {
+                    members.push(("placeholder", Json::path(placeholder_path)));
+                }
+            },
+        }
+        members.push(("properties", self.properties.as_ref().map_or(Json::Null, |p| p.to_json())));
+        let page = match self.page_size_pt {
+            Some((width_pt, height_pt)) => Json::object([
+                ("width_pt", Json::Number(width_pt)),
+                ("height_pt", Json::Number(height_pt)),
+            ]),
+            None => Json::Null,
+        };
+        members.push(("page", page));
+        members.push(("warnings", Json::Array(self.warnings.iter().map(|w| w.to_json()).collect())));
+        Json::object(members)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutputReport {
+    pub path: PathBuf,
+    pub page_count: usize,
+    pub size_bytes: u64,
+}
+impl OutputReport {
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("path", Json::path(&self.path)),
+            ("pages", Json::Number(u64::try_from(self.page_count).unwrap())),
+            ("size_bytes", Json::Number(self.size_bytes)),
+        ])
+    }
+}
+
+
+/// The results of a run, collected as it progresses.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Report {
+    pub command: &'static str,
+    /// Whether warnings (but not notes) abort the run.
+    pub strict: bool,
+    pub inputs: Vec<InputReport>,
+    pub outputs: Vec<OutputReport>,
+    /// The documents listed in the manifest, if one has been requested.
+    pub manifest: Vec<manifest::DocumentManifest>,
+}
+impl Report {
+    pub fn new(command: &'static str, strict: bool) -> Self {
+        Self {
+            command,
+            strict,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            manifest: Vec::new(),
+        }
+    }
+
+    /// Adds an input image which has not been processed yet and returns its index.
+    pub fn add_input(&mut self, path: &Path) -> usize {
+        self.inputs.push(InputReport {
+            path: path.to_owned(),
+            status: InputStatus::Pending,
+            properties: None,
+            page_size_pt: None,
+            warnings: Vec::new(),
+        });
+        self.inputs.len() - 1
+    }
+
+    pub fn input_mut(&mut self, index: usize) -> &mut InputReport {
+        &mut self.inputs[index]
+    }
+
+    /// Records a warning about the input image with the given index.
+    pub fn add_warning(&mut self, index: usize, severity: Severity, message: String) {
+        self.inputs[index].warnings.push(Warning { severity, message });
+    }
+
+    /// In strict mode, fails with the first warning of severity [`Severity::Warning`] recorded
+    /// about the input image with the given index.
+    pub fn check_strict(&self, index: usize) -> Result<(), String> {
+        if !self.strict {
+            return Ok(());
+        }
+        let input = &self.inputs[index];
+        match input.warnings.iter().find(|w| w.severity >= Severity::Warning) {
+            Some(warning) => Err(format!("{}: {} (treated as an error due to --strict)", input.path.display(), warning.message)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn warn(&mut self, index: usize, message: String) {
+        self.add_warning(index, Severity::Warning, message);
+    }
+
+    pub fn note(&mut self, index: usize, message: String) {
+        self.add_warning(index, Severity::Note, message);
+    }
+
+    /// Outputs all warnings collected during the run to standard error.
+    pub fn print_warnings(&self) {
+        for input in &self.inputs {
+            for warning in &input.warnings {
+                eprintln!("{}: {}: {}", input.path.display(), warning.severity.name(), warning.message);
+            }
+        }
+    }
+
+    pub fn add_output(&mut self, path: &Path, page_count: usize, size_bytes: u64) {
+        self.outputs.push(OutputReport {
+            path: path.to_owned(),
+            page_count,
+            size_bytes,
+        });
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("command", Json::string(self.command)),
+            ("inputs", Json::Array(self.inputs.iter().map(|i| i.to_json()).collect())),
+            ("outputs", Json::Array(self.outputs.iter().map(|o| o.to_json()).collect())),
+        ])
+    }
+}
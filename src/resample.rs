@@ -0,0 +1,295 @@
+//! Resampling of JPEG images with non-square pixels to square pixels.
+//!
+//! Some images, notably those derived from faxes (e.g. at 204x98 dpi), have a different density
+//! horizontally than vertically. Placing them on a page requires scaling them differently in each
+//! direction, which not every program processing the document handles well. To avoid this, the
+//! image is decoded, stretched in the direction of the lower density (by linear interpolation) until
+//! both densities match the higher one, and encoded anew using its original quantization tables
+//! and chroma subsampling. Like the conversion to sRGB, this is lossy.
+//!
+//! Only sequential images with Huffman coding and a single scan are supported.
+
+
+use std::fmt;
+
+use crate::jpeg::{Block, Image};
+use crate::optimize::{self, huffman_block, replace_tables, SequentialScan};
+use crate::srgb::{dct_basis, decode_block, encode_block, encode_blocks, quantization_values, BlockDecoder};
+
+
+#[derive(Debug)]
+pub enum Error {
+    Scan(optimize::Error),
+    UnsupportedBitDepth(u8),
+    ZeroDensity,
+    TooLarge { width: u64, height: u64 },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scan(e)
+                => write!(f, "{}", e),
+            Self::UnsupportedBitDepth(bit_depth)
+                => write!(f, "resampling images with {} bits per component is not supported; only 8", bit_depth),
+            Self::ZeroDensity
+                => write!(f, "the image has a density of 0"),
+            Self::TooLarge { width, height }
+                => write!(f, "the resampled image would be {}x{} pixels, more than JPEG allows", width, height),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Scan(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<optimize::Error> for Error {
+    fn from(value: optimize::Error) -> Self { Self::Scan(value) }
+}
+
+
+/// The samples of one component of an image.
+struct Plane {
+    width: usize,
+    height: usize,
+    samples: Vec<f32>,
+}
+impl Plane {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            samples: vec![0.0; width * height],
+        }
+    }
+
+    /// Returns the sample at the given position, repeating the samples at the edges for positions
+    /// beyond them.
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.samples[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    /// Stretches the plane to the given size by linear interpolation between the nearest samples.
+    fn resize(&self, new_width: usize, new_height: usize) -> Plane {
+        // the position in the original plane of each sample of the new one, split into the index
+        // of the preceding sample and the weight of the following one
+        let positions = |old_length: usize, new_length: usize| -> Vec<(usize, f32)> {
+            (0..new_length)
+                .map(|i| {
+                    let position = ((i as f64 + 0.5) * old_length as f64 / new_length as f64 - 0.5)
+                        .clamp(0.0, (old_length - 1) as f64);
+                    let index = position.floor();
+                    (index as usize, (position - index) as f32)
+                })
+                .collect()
+        };
+        let xs = positions(self.width, new_width);
+        let ys = positions(self.height, new_height);
+
+        let mut resized = Plane::new(new_width, new_height);
+        for (y, &(y0, y_weight)) in ys.iter().enumerate() {
+            for (x, &(x0, x_weight)) in xs.iter().enumerate() {
+                let top = self.get(x0, y0) * (1.0 - x_weight) + self.get(x0 + 1, y0) * x_weight;
+                let bottom = self.get(x0, y0 + 1) * (1.0 - x_weight) + self.get(x0 + 1, y0 + 1) * x_weight;
+                resized.samples[y * new_width + x] = top * (1.0 - y_weight) + bottom * y_weight;
+            }
+        }
+        resized
+    }
+}
+
+/// A component of the scan and where its blocks lie within an MCU.
+struct ComponentLayout {
+    /// The number of blocks of the component across an MCU.
+    blocks_across: usize,
+    /// The number of blocks of the component down an MCU.
+    blocks_down: usize,
+    /// The horizontal and vertical sampling factors relative to the largest ones.
+    horizontal_sampling: usize,
+    vertical_sampling: usize,
+    /// The quantization values in zigzag order.
+    quantization: [f64; 64],
+}
+impl ComponentLayout {
+    /// The number of samples of the component across and down an image of the given size.
+    fn plane_size(&self, max_horizontal: usize, max_vertical: usize, width: usize, height: usize) -> (usize, usize) {
+        (
+            (width * self.horizontal_sampling).div_ceil(max_horizontal),
+            (height * self.vertical_sampling).div_ceil(max_vertical),
+        )
+    }
+}
+
+
+/// Resamples the given image such that its horizontal and vertical densities are equal.
+///
+/// Returns `None` if they already are, in which case the image can be used as it is.
+pub fn resample_to_square(image: &Image) -> Result<Option<Image>, Error> {
+    if image.density_x == image.density_y {
+        return Ok(None);
+    }
+    if image.density_x == 0 || image.density_y == 0 {
+        return Err(Error::ZeroDensity);
+    }
+    if image.bit_depth != 8 {
+        return Err(Error::UnsupportedBitDepth(image.bit_depth));
+    }
+    let scan = SequentialScan::parse(image)?;
+    if scan.scan_components.len() != scan.frame_components.len() {
+        return Err(Error::Scan(optimize::Error::MultipleScans));
+    }
+
+    // stretch the direction with the lower density
+    let (width, height) = (usize::from(image.width), usize::from(image.height));
+    let density = image.density_x.max(image.density_y);
+    let new_width = (width as f64 * f64::from(density) / f64::from(image.density_x)).round() as u64;
+    let new_height = (height as f64 * f64::from(density) / f64::from(image.density_y)).round() as u64;
+    let (Ok(new_width_u16), Ok(new_height_u16)) = (u16::try_from(new_width), u16::try_from(new_height)) else {
+        return Err(Error::TooLarge { width: new_width, height: new_height });
+    };
+    let (new_width, new_height) = (usize::from(new_width_u16), usize::from(new_height_u16));
+
+    let geometry = &scan.geometry;
+    let interleaved = scan.scan_components.len() > 1;
+    let max_horizontal = scan.frame_components.iter().map(|c| c.horizontal_sampling).max().unwrap();
+    let max_vertical = scan.frame_components.iter().map(|c| c.vertical_sampling).max().unwrap();
+    let mut components = Vec::with_capacity(scan.scan_components.len());
+    let mut block_positions = Vec::new();
+    for (scan_index, sc) in scan.scan_components.iter().enumerate() {
+        let fc = scan.frame_components[sc.frame_index];
+        // a non-interleaved scan has one block per MCU
+        let (blocks_across, blocks_down) = if interleaved {
+            (fc.horizontal_sampling, fc.vertical_sampling)
+        } else {
+            (1, 1)
+        };
+        for block_down in 0..blocks_down {
+            for block_across in 0..blocks_across {
+                block_positions.push((scan_index, block_across, block_down));
+            }
+        }
+        components.push(ComponentLayout {
+            blocks_across,
+            blocks_down,
+            horizontal_sampling: fc.horizontal_sampling,
+            vertical_sampling: fc.vertical_sampling,
+            quantization: quantization_values(scan.quantization_tables[fc.quantization_table].unwrap()),
+        });
+    }
+
+    // decode the blocks into planes covering all MCUs
+    let basis = dct_basis();
+    let mut planes: Vec<Plane> = components.iter()
+        .map(|c| Plane::new(8 * c.blocks_across * geometry.mcus_across, 8 * c.blocks_down * geometry.mcus_down))
+        .collect();
+    let mut decode = |block_index: usize, coefficients: &[i32; 64]| {
+        let mcu_index = block_index / block_positions.len();
+        let (mcu_down, mcu_across) = (mcu_index / geometry.mcus_across, mcu_index % geometry.mcus_across);
+        if mcu_down >= geometry.mcus_down {
+            // superfluous data after the last MCU
+            return;
+        }
+        let (scan_index, block_across, block_down) = block_positions[block_index % block_positions.len()];
+        let component = &components[scan_index];
+        let plane = &mut planes[scan_index];
+        let left = 8 * (mcu_across * component.blocks_across + block_across);
+        let top = 8 * (mcu_down * component.blocks_down + block_down);
+        let block_samples = decode_block(&basis, coefficients, &component.quantization);
+        for y in 0..8 {
+            for x in 0..8 {
+                plane.samples[(top + y) * plane.width + left + x] = block_samples[y * 8 + x] as f32;
+            }
+        }
+    };
+    let mut decoder = BlockDecoder::new(geometry.block_components.clone());
+    scan.walk(image, |event| {
+        if let Some((block_index, coefficients)) = decoder.visit(event) {
+            decode(block_index, &coefficients);
+        }
+    })?;
+    if let Some((block_index, coefficients)) = decoder.finish() {
+        decode(block_index, &coefficients);
+    }
+
+    // resize the visible part of each plane
+    let planes: Vec<Plane> = planes.iter()
+        .zip(components.iter())
+        .map(|(plane, component)| {
+            let (old_width, old_height) = component.plane_size(max_horizontal, max_vertical, width, height);
+            let (resized_width, resized_height) = component.plane_size(max_horizontal, max_vertical, new_width, new_height);
+            let mut visible = Plane::new(old_width, old_height);
+            for y in 0..old_height {
+                visible.samples[y * old_width..(y + 1) * old_width]
+                    .copy_from_slice(&plane.samples[y * plane.width..y * plane.width + old_width]);
+            }
+            visible.resize(resized_width, resized_height)
+        })
+        .collect();
+
+    // encode the blocks of the resized planes
+    let mcus_across = new_width.div_ceil(geometry.mcu_width_px);
+    let mcus_down = new_height.div_ceil(geometry.mcu_height_px);
+    let mut blocks = Vec::with_capacity(mcus_across * mcus_down * block_positions.len());
+    for mcu_down in 0..mcus_down {
+        for mcu_across in 0..mcus_across {
+            for &(scan_index, block_across, block_down) in &block_positions {
+                let component = &components[scan_index];
+                let plane = &planes[scan_index];
+                let left = 8 * (mcu_across * component.blocks_across + block_across);
+                let top = 8 * (mcu_down * component.blocks_down + block_down);
+                let mut block_samples = [0.0; 64];
+                for y in 0..8 {
+                    for x in 0..8 {
+                        block_samples[y * 8 + x] = f64::from(plane.get(left + x, top + y));
+                    }
+                }
+                blocks.push(encode_block(&basis, &block_samples, &component.quantization));
+            }
+        }
+    }
+    let (tables, image_data) = encode_blocks(
+        &scan,
+        &blocks,
+        &geometry.block_components,
+        usize::from(scan.restart_interval),
+    )?;
+
+    let mut leading_blocks = replace_tables(
+        &image.leading_blocks,
+        scan.quantization_block(),
+        huffman_block(&tables),
+        |_block| true,
+    );
+    for block in &mut leading_blocks {
+        match block {
+            Block::Long { kind: 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF, data } => {
+                data[1..3].copy_from_slice(&new_height_u16.to_be_bytes());
+                data[3..5].copy_from_slice(&new_width_u16.to_be_bytes());
+            },
+            Block::Long { kind: 0xE0, data } if data.starts_with(b"JFIF\0") && data.len() >= 12 => {
+                data[8..10].copy_from_slice(&density.to_be_bytes());
+                data[10..12].copy_from_slice(&density.to_be_bytes());
+            },
+            _ => {},
+        }
+    }
+
+    Ok(Some(Image {
+        frame_type: image.frame_type,
+        bit_depth: image.bit_depth,
+        width: new_width_u16,
+        height: new_height_u16,
+        color_space: image.color_space,
+        density_unit: image.density_unit,
+        density_x: density,
+        density_y: density,
+        exif_orientation: image.exif_orientation,
+        adobe_transform: image.adobe_transform,
+        leading_blocks,
+        image_data,
+        trailing_blocks: image.trailing_blocks.clone(),
+    }))
+}
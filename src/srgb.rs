@@ -0,0 +1,824 @@
+//! Conversion of JPEG images with an embedded ICC color profile to sRGB.
+//!
+//! PDF viewers which ignore ICC-based color spaces display all images as if they were sRGB, which
+//! makes images in wider color spaces such as Adobe RGB look dull. To avoid this, the image is
+//! decoded, its colors are converted from the embedded profile to sRGB, and it is encoded anew
+//! using its original quantization tables and chroma subsampling. Unlike the other transformations
+//! of JPEG images, this one is lossy. The profile is removed from the converted image.
+//!
+//! Only sequential images with Huffman coding and a single scan are supported, and only the
+//! profiles supported by [`crate::icc`].
+
+
+use std::f64::consts::PI;
+use std::fmt;
+
+use crate::icc::{self, srgb_encode, Profile};
+use crate::jpeg::{AdobeTransform, ColorSpace, Image, MetadataKind};
+use crate::optimize::{
+    self, huffman_block, replace_tables, BitWriter, EncodingTable, Event, SequentialScan,
+    TableDefinitions,
+};
+use crate::tile::{decode_difference, encode_difference};
+
+
+/// The position of each coefficient of a block in zigzag order within the block in row-major
+/// order.
+pub(crate) const ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The number of linear values for which the sRGB encoding is calculated in advance. The curve is
+/// steep near black, so this is considerably more than the 256 encoded values.
+const ENCODING_STEPS: usize = 16384;
+
+/// By how much the conversion from the profile to sRGB may differ from doing nothing (in units of
+/// a component value) for the profile to be considered equivalent to sRGB.
+const IDENTITY_TOLERANCE: f64 = 0.5;
+
+/// The largest magnitude of an AC coefficient of an image with 8 bits per component.
+const MAX_AC_COEFFICIENT: i32 = 1023;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Scan(optimize::Error),
+    Profile(icc::Error),
+    UnsupportedBitDepth(u8),
+    ProfileMismatch { color_space: ColorSpace },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scan(e)
+                => write!(f, "{}", e),
+            Self::Profile(e)
+                => write!(f, "{}", e),
+            Self::UnsupportedBitDepth(bit_depth)
+                => write!(f, "converting images with {} bits per component is not supported; only 8", bit_depth),
+            Self::ProfileMismatch { color_space }
+                => write!(f, "the ICC profile does not describe the color space {:?} of the image", color_space),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Scan(e) => Some(e),
+            Self::Profile(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<optimize::Error> for Error {
+    fn from(value: optimize::Error) -> Self { Self::Scan(value) }
+}
+impl From<icc::Error> for Error {
+    fn from(value: icc::Error) -> Self { Self::Profile(value) }
+}
+
+
+/// Clamps a sample to the range of 8-bit values and rounds it.
+fn to_u8(value: f64) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+/// The conversion of the pixels of an image from its profile to sRGB.
+enum ColorTransform {
+    Rgb {
+        /// For each component, the linear value of each encoded value.
+        linear: Box<[[f64; 256]; 3]>,
+        /// Converts linear RGB values of the profile into linear sRGB values.
+        matrix: [[f64; 3]; 3],
+        /// The encoded sRGB value of each of `ENCODING_STEPS` equally spaced linear values.
+        encoded: Vec<u8>,
+        /// Whether the image stores YCbCr instead of RGB.
+        ycbcr: bool,
+    },
+    Gray {
+        /// The sRGB value of each value of the image.
+        lookup: [u8; 256],
+    },
+}
+impl ColorTransform {
+    /// Prepares the conversion of the given image with the given profile, or returns `None` if the
+    /// profile is equivalent to sRGB.
+    fn new(profile: &Profile, image: &Image) -> Result<Option<Self>, Error> {
+        match (profile, image.color_space) {
+            (Profile::Rgb { curves, .. }, ColorSpace::Rgb) => {
+                let matrix = profile.to_srgb_matrix()?.unwrap();
+                let mut linear = [[0.0; 256]; 3];
+                for (component_linear, curve) in linear.iter_mut().zip(curves.iter()) {
+                    for (value, linear_value) in component_linear.iter_mut().enumerate() {
+                        *linear_value = curve.apply(value as f64 / 255.0);
+                    }
+                }
+
+                let is_identity_matrix = (0..3)
+                    .all(|row| (0..3).all(|column| {
+                        let expected = if row == column { 1.0 } else { 0.0 };
+                        (matrix[row][column] - expected).abs() * 255.0 < IDENTITY_TOLERANCE
+                    }));
+                let are_srgb_curves = linear.iter()
+                    .all(|component_linear| component_linear.iter().enumerate().all(|(value, &linear_value)|
+                        (srgb_encode(linear_value) * 255.0 - value as f64).abs() < IDENTITY_TOLERANCE
+                    ));
+                if is_identity_matrix && are_srgb_curves {
+                    return Ok(None);
+                }
+
+                let encoded = (0..ENCODING_STEPS)
+                    .map(|step| to_u8(srgb_encode(step as f64 / (ENCODING_STEPS - 1) as f64) * 255.0))
+                    .collect();
+                // without an Adobe block, three components are YCbCr as prescribed by JFIF
+                let ycbcr = !matches!(image.adobe_transform, Some(AdobeTransform::Untransformed));
+                Ok(Some(Self::Rgb {
+                    linear: Box::new(linear),
+                    matrix,
+                    encoded,
+                    ycbcr,
+                }))
+            },
+            (Profile::Gray { curve }, ColorSpace::Grayscale) => {
+                let mut lookup = [0u8; 256];
+                for (value, converted) in lookup.iter_mut().enumerate() {
+                    *converted = to_u8(srgb_encode(curve.apply(value as f64 / 255.0)) * 255.0);
+                }
+                if lookup.iter().enumerate().all(|(value, &converted)| usize::from(converted) == value) {
+                    return Ok(None);
+                }
+                Ok(Some(Self::Gray { lookup }))
+            },
+            (_, color_space) => Err(Error::ProfileMismatch { color_space }),
+        }
+    }
+
+    /// Converts the samples of one pixel (with as many components as the image) in place.
+    fn apply(&self, samples: &mut [f64]) {
+        match self {
+            Self::Rgb { linear, matrix, encoded, ycbcr } => {
+                let rgb = if *ycbcr {
+                    let (y, cb, cr) = (samples[0], samples[1] - 128.0, samples[2] - 128.0);
+                    [
+                        to_u8(y + 1.402 * cr),
+                        to_u8(y - 0.344136 * cb - 0.714136 * cr),
+                        to_u8(y + 1.772 * cb),
+                    ]
+                } else {
+                    [to_u8(samples[0]), to_u8(samples[1]), to_u8(samples[2])]
+                };
+                let linear_rgb = [
+                    linear[0][usize::from(rgb[0])],
+                    linear[1][usize::from(rgb[1])],
+                    linear[2][usize::from(rgb[2])],
+                ];
+                let mut srgb = [0.0; 3];
+                for (converted, row) in srgb.iter_mut().zip(matrix.iter()) {
+                    let linear_value: f64 = row.iter().zip(linear_rgb.iter()).map(|(m, v)| m * v).sum();
+                    let step = (linear_value.clamp(0.0, 1.0) * (ENCODING_STEPS - 1) as f64).round() as usize;
+                    *converted = f64::from(encoded[step]);
+                }
+                if *ycbcr {
+                    let [r, g, b] = srgb;
+                    samples[0] = 0.299 * r + 0.587 * g + 0.114 * b;
+                    samples[1] = -0.168736 * r - 0.331264 * g + 0.5 * b + 128.0;
+                    samples[2] = 0.5 * r - 0.418688 * g - 0.081312 * b + 128.0;
+                } else {
+                    samples[..3].copy_from_slice(&srgb);
+                }
+            },
+            Self::Gray { lookup } => {
+                samples[0] = f64::from(lookup[usize::from(to_u8(samples[0]))]);
+            },
+        }
+    }
+}
+
+
+/// The basis functions of the discrete cosine transform on 8 samples: `basis[x][u]` is the weight
+/// of frequency `u` at position `x`, scaled such that the transform is orthonormal.
+pub(crate) fn dct_basis() -> [[f64; 8]; 8] {
+    let mut basis = [[0.0; 8]; 8];
+    for (x, row) in basis.iter_mut().enumerate() {
+        for (u, weight) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5 / 2.0f64.sqrt() } else { 0.5 };
+            *weight = scale * (((2 * x + 1) * u) as f64 * PI / 16.0).cos();
+        }
+    }
+    basis
+}
+
+/// Converts the coefficients of a block (in row-major order) into samples.
+fn inverse_dct(basis: &[[f64; 8]; 8], coefficients: &[f64; 64]) -> [f64; 64] {
+    let mut rows = [0.0; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| basis[x][u] * coefficients[v * 8 + u]).sum();
+        }
+    }
+    let mut samples = [0.0; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            samples[y * 8 + x] = (0..8).map(|v| basis[y][v] * rows[v * 8 + x]).sum();
+        }
+    }
+    samples
+}
+
+/// Converts the samples of a block (in row-major order) into coefficients.
+fn forward_dct(basis: &[[f64; 8]; 8], samples: &[f64; 64]) -> [f64; 64] {
+    let mut rows = [0.0; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8).map(|x| basis[x][u] * samples[y * 8 + x]).sum();
+        }
+    }
+    let mut coefficients = [0.0; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coefficients[v * 8 + u] = (0..8).map(|y| basis[y][v] * rows[y * 8 + u]).sum();
+        }
+    }
+    coefficients
+}
+
+/// Reads a quantization table definition (as kept by [`SequentialScan`]) into its values in
+/// zigzag order.
+pub(crate) fn quantization_values(definition: &[u8]) -> [f64; 64] {
+    let mut values = [0.0; 64];
+    for (k, value) in values.iter_mut().enumerate() {
+        *value = if definition[0] >> 4 == 0 {
+            f64::from(definition[1 + k])
+        } else {
+            f64::from(u16::from_be_bytes([definition[1 + 2 * k], definition[2 + 2 * k]]))
+        };
+        // a zero would make the image undecodable anyway; avoid dividing by it
+        if *value == 0.0 {
+            *value = 1.0;
+        }
+    }
+    values
+}
+
+/// Converts the quantized coefficients of a block (in zigzag order) into samples (in row-major
+/// order) between 0 and 255.
+pub(crate) fn decode_block(basis: &[[f64; 8]; 8], coefficients: &[i32; 64], quantization: &[f64; 64]) -> [f64; 64] {
+    let mut dequantized = [0.0; 64];
+    for (k, &coefficient) in coefficients.iter().enumerate() {
+        dequantized[ZIGZAG[k]] = f64::from(coefficient) * quantization[k];
+    }
+    let mut samples = inverse_dct(basis, &dequantized);
+    for sample in &mut samples {
+        *sample += 128.0;
+    }
+    samples
+}
+
+/// Converts the samples of a block (in row-major order) into quantized coefficients (in zigzag
+/// order).
+pub(crate) fn encode_block(basis: &[[f64; 8]; 8], samples: &[f64; 64], quantization: &[f64; 64]) -> [i32; 64] {
+    let mut centered = [0.0; 64];
+    for (centered_sample, &sample) in centered.iter_mut().zip(samples.iter()) {
+        *centered_sample = sample.clamp(0.0, 255.0) - 128.0;
+    }
+    let transformed = forward_dct(basis, &centered);
+    let mut quantized = [0i32; 64];
+    for (k, value) in quantized.iter_mut().enumerate() {
+        *value = ((transformed[ZIGZAG[k]] / quantization[k]).round() as i32)
+            .clamp(-MAX_AC_COEFFICIENT, MAX_AC_COEFFICIENT);
+    }
+    // the DC coefficient is bounded by the sample range anyway
+    quantized[0] = (transformed[0] / quantization[0]).round() as i32;
+    quantized
+}
+
+
+/// Reassembles the quantized coefficients of each block from the elements of the entropy-coded
+/// data.
+pub(crate) struct BlockDecoder {
+    /// For each block of an MCU, in order, the index of its component within the scan.
+    block_components: Vec<usize>,
+    /// The DC coefficient of the most recent block of each component.
+    predictions: Vec<i32>,
+    /// The number of blocks started so far.
+    block_count: usize,
+    /// The quantized coefficients (in zigzag order) of the block being decoded.
+    coefficients: [i32; 64],
+    /// The index of the next coefficient of the block being decoded.
+    next_coefficient: usize,
+}
+impl BlockDecoder {
+    pub fn new(block_components: Vec<usize>) -> Self {
+        let component_count = block_components.iter().max().map_or(0, |&c| c + 1);
+        Self {
+            block_components,
+            predictions: vec![0; component_count],
+            block_count: 0,
+            coefficients: [0; 64],
+            next_coefficient: 64,
+        }
+    }
+
+    /// Processes an element of the entropy-coded data. Once the next block starts, returns the
+    /// index of the preceding block (counting from the start of the image) along with its
+    /// coefficients.
+    pub fn visit(&mut self, event: Event) -> Option<(usize, [i32; 64])> {
+        match event {
+            Event::Symbol { class: 0, symbol, extra_bits, .. } => {
+                let finished = self.finish();
+                let scan_index = self.block_components[self.block_count % self.block_components.len()];
+                self.predictions[scan_index] += decode_difference(u32::from(symbol), extra_bits);
+                self.coefficients = [0; 64];
+                self.coefficients[0] = self.predictions[scan_index];
+                self.next_coefficient = 1;
+                self.block_count += 1;
+                finished
+            },
+            Event::Symbol { symbol, extra_bits, .. } => {
+                let run = usize::from(symbol >> 4);
+                let size = u32::from(symbol & 0x0F);
+                if size == 0 {
+                    self.next_coefficient = if run == 15 { self.next_coefficient + 16 } else { 64 };
+                } else {
+                    self.next_coefficient += run;
+                    if self.next_coefficient < 64 {
+                        self.coefficients[self.next_coefficient] = decode_difference(size, extra_bits);
+                    }
+                    self.next_coefficient += 1;
+                }
+                None
+            },
+            Event::Restart => {
+                self.predictions.fill(0);
+                None
+            },
+        }
+    }
+
+    /// Returns the index and the coefficients of the block being decoded, if any. Called once the
+    /// entropy-coded data has ended to obtain the last block.
+    pub fn finish(&self) -> Option<(usize, [i32; 64])> {
+        if self.block_count == 0 {
+            None
+        } else {
+            Some((self.block_count - 1, self.coefficients))
+        }
+    }
+}
+
+
+/// Passes the symbols encoding the given quantized blocks (in zigzag order, in the order in which
+/// they are encoded) to `visit`.
+fn visit_blocks(scan: &SequentialScan<'_>, blocks: &[[i32; 64]], block_components: &[usize], restart_interval: usize, visit: &mut dyn FnMut(Event)) {
+    let mut predictions = vec![0i32; scan.scan_components.len()];
+    for (mcu_index, mcu_blocks) in blocks.chunks(block_components.len()).enumerate() {
+        if restart_interval > 0 && mcu_index > 0 && mcu_index % restart_interval == 0 {
+            visit(Event::Restart);
+            predictions.fill(0);
+        }
+        for (block, &scan_index) in mcu_blocks.iter().zip(block_components.iter()) {
+            let sc = scan.scan_components[scan_index];
+            let (size, bits) = encode_difference(block[0] - predictions[scan_index]);
+            predictions[scan_index] = block[0];
+            visit(Event::Symbol { class: 0, table: sc.dc_table, symbol: size, extra_bits: bits, extra_bit_count: u32::from(size) });
+
+            let mut run = 0;
+            for &coefficient in &block[1..] {
+                if coefficient == 0 {
+                    run += 1;
+                    continue;
+                }
+                while run >= 16 {
+                    // a run of 16 zeros
+                    visit(Event::Symbol { class: 1, table: sc.ac_table, symbol: 0xF0, extra_bits: 0, extra_bit_count: 0 });
+                    run -= 16;
+                }
+                let (size, bits) = encode_difference(coefficient);
+                visit(Event::Symbol { class: 1, table: sc.ac_table, symbol: (run << 4) | size, extra_bits: bits, extra_bit_count: u32::from(size) });
+                run = 0;
+            }
+            if run > 0 {
+                // end of block
+                visit(Event::Symbol { class: 1, table: sc.ac_table, symbol: 0x00, extra_bits: 0, extra_bit_count: 0 });
+            }
+        }
+    }
+}
+
+/// Encodes the given quantized blocks (in zigzag order, in the order in which they are encoded)
+/// with the components and tables of the given scan, using Huffman tables optimized for the
+/// blocks. Returns the tables along with the entropy-coded data.
+pub(crate) fn encode_blocks(
+    scan: &SequentialScan<'_>,
+    blocks: &[[i32; 64]],
+    block_components: &[usize],
+    restart_interval: usize,
+) -> Result<(TableDefinitions, Vec<u8>), optimize::Error> {
+    let tables = scan.optimal_tables(|frequencies| {
+        visit_blocks(scan, blocks, block_components, restart_interval, &mut |event| {
+            if let Event::Symbol { class, table, symbol, .. } = event {
+                frequencies[class][table][usize::from(symbol)] += 1;
+            }
+        });
+        Ok(())
+    })?;
+    let codes: [[Option<EncodingTable>; 4]; 2] = [0, 1].map(|class|
+        [0, 1, 2, 3].map(|table: usize| tables[class][table].as_ref().map(|t| t.codes()))
+    );
+    let mut writer = BitWriter::new();
+    let mut restart_number = 0;
+    visit_blocks(scan, blocks, block_components, restart_interval, &mut |event| {
+        match event {
+            Event::Symbol { class, table, symbol, extra_bits, extra_bit_count } => {
+                let (code, length) = codes[class][table].as_ref().unwrap()[usize::from(symbol)];
+                writer.write_bits(code, length);
+                writer.write_bits(extra_bits, extra_bit_count);
+            },
+            Event::Restart => {
+                writer.pad();
+                writer.data.push(0xFF);
+                writer.data.push(0xD0 + restart_number);
+                restart_number = (restart_number + 1) % 8;
+            },
+        }
+    });
+    writer.pad();
+    Ok((tables, writer.data))
+}
+
+
+/// A component of the scan and where its blocks lie within an MCU.
+struct ComponentLayout {
+    /// The number of blocks of the component across an MCU.
+    blocks_across: usize,
+    /// The number of blocks of the component down an MCU.
+    blocks_down: usize,
+    /// The quantization values in zigzag order.
+    quantization: [f64; 64],
+}
+
+/// Decodes the blocks of an image MCU by MCU and converts their colors.
+struct Converter<'a> {
+    transform: &'a ColorTransform,
+    basis: [[f64; 8]; 8],
+    components: Vec<ComponentLayout>,
+    /// For each block of an MCU, its component within the scan and its position (in blocks)
+    /// within the area of the MCU covered by the component.
+    block_positions: Vec<(usize, usize, usize)>,
+    /// The width and height of an MCU in pixels.
+    mcu_width: usize,
+    mcu_height: usize,
+    /// The decoded samples of the current MCU for each component.
+    samples: Vec<Vec<f64>>,
+    /// The quantized coefficients (in zigzag order) of all converted blocks, in the order in which
+    /// they are encoded.
+    converted_blocks: Vec<[i32; 64]>,
+    decoder: BlockDecoder,
+}
+impl<'a> Converter<'a> {
+    fn new(scan: &SequentialScan<'_>, transform: &'a ColorTransform) -> Self {
+        let interleaved = scan.scan_components.len() > 1;
+        let mut components = Vec::with_capacity(scan.scan_components.len());
+        let mut block_positions = Vec::new();
+        for (scan_index, sc) in scan.scan_components.iter().enumerate() {
+            let fc = scan.frame_components[sc.frame_index];
+            // a non-interleaved scan has one block per MCU
+            let (blocks_across, blocks_down) = if interleaved {
+                (fc.horizontal_sampling, fc.vertical_sampling)
+            } else {
+                (1, 1)
+            };
+            for block_down in 0..blocks_down {
+                for block_across in 0..blocks_across {
+                    block_positions.push((scan_index, block_across, block_down));
+                }
+            }
+            components.push(ComponentLayout {
+                blocks_across,
+                blocks_down,
+                quantization: quantization_values(scan.quantization_tables[fc.quantization_table].unwrap()),
+            });
+        }
+        let mcu_width = 8 * components.iter().map(|c| c.blocks_across).max().unwrap();
+        let mcu_height = 8 * components.iter().map(|c| c.blocks_down).max().unwrap();
+        let samples = components.iter()
+            .map(|c| vec![0.0; 64 * c.blocks_across * c.blocks_down])
+            .collect();
+        let decoder = BlockDecoder::new(block_positions.iter().map(|&(scan_index, _, _)| scan_index).collect());
+        Self {
+            transform,
+            basis: dct_basis(),
+            components,
+            block_positions,
+            mcu_width,
+            mcu_height,
+            samples,
+            converted_blocks: Vec::new(),
+            decoder,
+        }
+    }
+
+    /// Processes an element of the entropy-coded data.
+    fn visit(&mut self, event: Event) {
+        if let Some((block_index, coefficients)) = self.decoder.visit(event) {
+            self.finish_block(block_index, &coefficients);
+        }
+    }
+
+    /// Processes the last block once the entropy-coded data has ended.
+    fn finish(&mut self) {
+        if let Some((block_index, coefficients)) = self.decoder.finish() {
+            self.finish_block(block_index, &coefficients);
+        }
+    }
+
+    /// Transforms a decoded block into samples and, if it is the last block of an MCU, converts
+    /// the MCU.
+    fn finish_block(&mut self, block_index: usize, coefficients: &[i32; 64]) {
+        let block_offset = block_index % self.block_positions.len();
+        let (scan_index, block_across, block_down) = self.block_positions[block_offset];
+        let component = &self.components[scan_index];
+
+        let block_samples = decode_block(&self.basis, coefficients, &component.quantization);
+        let stride = 8 * component.blocks_across;
+        let samples = &mut self.samples[scan_index];
+        for y in 0..8 {
+            for x in 0..8 {
+                samples[(8 * block_down + y) * stride + 8 * block_across + x] = block_samples[y * 8 + x];
+            }
+        }
+
+        if block_offset == self.block_positions.len() - 1 {
+            self.convert_mcu();
+        }
+    }
+
+    /// Converts the colors of the decoded MCU and quantizes its blocks.
+    fn convert_mcu(&mut self) {
+        // each sample of a subsampled component covers several pixels; convert every pixel and
+        // average the results
+        let mut sums: Vec<Vec<f64>> = self.samples.iter().map(|s| vec![0.0; s.len()]).collect();
+        let mut counts: Vec<Vec<u32>> = self.samples.iter().map(|s| vec![0; s.len()]).collect();
+        let mut pixel = vec![0.0; self.components.len()];
+        for y in 0..self.mcu_height {
+            for x in 0..self.mcu_width {
+                let indexes: Vec<usize> = self.components.iter()
+                    .map(|c| {
+                        let sample_y = y * 8 * c.blocks_down / self.mcu_height;
+                        let sample_x = x * 8 * c.blocks_across / self.mcu_width;
+                        sample_y * 8 * c.blocks_across + sample_x
+                    })
+                    .collect();
+                for (scan_index, &index) in indexes.iter().enumerate() {
+                    pixel[scan_index] = self.samples[scan_index][index];
+                }
+                self.transform.apply(&mut pixel);
+                for (scan_index, &index) in indexes.iter().enumerate() {
+                    sums[scan_index][index] += pixel[scan_index];
+                    counts[scan_index][index] += 1;
+                }
+            }
+        }
+
+        for &(scan_index, block_across, block_down) in &self.block_positions {
+            let component = &self.components[scan_index];
+            let stride = 8 * component.blocks_across;
+            let mut block_samples = [0.0; 64];
+            for y in 0..8 {
+                for x in 0..8 {
+                    let index = (8 * block_down + y) * stride + 8 * block_across + x;
+                    block_samples[y * 8 + x] = sums[scan_index][index] / f64::from(counts[scan_index][index].max(1));
+                }
+            }
+            self.converted_blocks.push(encode_block(&self.basis, &block_samples, &component.quantization));
+        }
+    }
+}
+
+
+/// Converts the colors of the given image from its embedded ICC profile to sRGB.
+///
+/// Returns `None` if the image has no ICC profile or if its profile is equivalent to sRGB, in which
+/// case the image can be used as it is.
+pub fn convert_to_srgb(image: &Image) -> Result<Option<Image>, Error> {
+    let Some(profile) = Profile::from_image(image)? else {
+        return Ok(None);
+    };
+    if image.bit_depth != 8 {
+        return Err(Error::UnsupportedBitDepth(image.bit_depth));
+    }
+    let Some(transform) = ColorTransform::new(&profile, image)? else {
+        return Ok(None);
+    };
+    let scan = SequentialScan::parse(image)?;
+    if scan.scan_components.len() != scan.frame_components.len() {
+        return Err(Error::Scan(optimize::Error::MultipleScans));
+    }
+
+    let mut converter = Converter::new(&scan, &transform);
+    scan.walk(image, |event| converter.visit(event))?;
+    converter.finish();
+
+    let block_components: Vec<usize> = converter.block_positions.iter()
+        .map(|&(scan_index, _, _)| scan_index)
+        .collect();
+    let (tables, image_data) = encode_blocks(
+        &scan,
+        &converter.converted_blocks,
+        &block_components,
+        usize::from(scan.restart_interval),
+    )?;
+
+    let leading_blocks = replace_tables(
+        &image.leading_blocks,
+        scan.quantization_block(),
+        huffman_block(&tables),
+        |block| block.metadata_kind() != Some(MetadataKind::Icc),
+    );
+
+    Ok(Some(Image {
+        frame_type: image.frame_type,
+        bit_depth: image.bit_depth,
+        width: image.width,
+        height: image.height,
+        color_space: image.color_space,
+        density_unit: image.density_unit,
+        density_x: image.density_x,
+        density_y: image.density_y,
+        exif_orientation: image.exif_orientation,
+        adobe_transform: image.adobe_transform,
+        leading_blocks,
+        image_data,
+        trailing_blocks: image.trailing_blocks.clone(),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icc::tests::{curve_tag, icc_block, rgb_profile, srgb_curve_tag};
+
+    const SMALL_JPEG: &[u8] = include_bytes!("../tests/data/small.jpg");
+
+    /// Reads the test image and embeds the given ICC profile into it.
+    fn with_profile(profile: &[u8]) -> Image {
+        let mut image = Image::try_read(SMALL_JPEG).expect("failed to read image");
+        image.leading_blocks.insert(1, icc_block(1, 1, profile));
+        image
+    }
+
+    /// Writes the given image, reads it back and decodes the samples of each of its blocks.
+    fn block_samples(image: &Image) -> Vec<[f64; 64]> {
+        let mut data = Vec::new();
+        image.write(&mut data).expect("failed to write image");
+        let image = Image::try_read(&data[..]).expect("failed to read image back");
+
+        let scan = SequentialScan::parse(&image).expect("failed to parse scan");
+        let basis = dct_basis();
+        let block_components = scan.geometry.block_components.clone();
+        let mut decoder = BlockDecoder::new(block_components.clone());
+        let mut blocks = Vec::new();
+        scan.walk(&image, |event| blocks.extend(decoder.visit(event)))
+            .expect("failed to decode scan");
+        blocks.extend(decoder.finish());
+        blocks.iter()
+            .map(|(index, coefficients)| {
+                let sc = scan.scan_components[block_components[index % block_components.len()]];
+                let fc = scan.frame_components[sc.frame_index];
+                let quantization = quantization_values(scan.quantization_tables[fc.quantization_table].unwrap());
+                decode_block(&basis, coefficients, &quantization)
+            })
+            .collect()
+    }
+
+    /// The average difference between corresponding samples of the given blocks.
+    fn mean_difference(a: &[[f64; 64]], b: &[[f64; 64]]) -> f64 {
+        assert_eq!(a.len(), b.len());
+        let total: f64 = a.iter().zip(b.iter())
+            .flat_map(|(a_block, b_block)| a_block.iter().zip(b_block.iter()).map(|(a, b)| (a - b).abs()))
+            .sum();
+        total / (64 * a.len()) as f64
+    }
+
+    #[test]
+    fn dct_round_trip() {
+        let basis = dct_basis();
+        let samples: [f64; 64] = std::array::from_fn(|i| ((i * 37) % 256) as f64 - 128.0);
+        let restored = inverse_dct(&basis, &forward_dct(&basis, &samples));
+        for (restored_sample, sample) in restored.iter().zip(samples.iter()) {
+            assert!((restored_sample - sample).abs() < 1e-9);
+        }
+
+        // a flat block has only a DC coefficient, which is eight times the centered sample
+        let flat = forward_dct(&basis, &[10.0; 64]);
+        assert!((flat[0] - 80.0).abs() < 1e-9);
+        assert!(flat[1..].iter().all(|c| c.abs() < 1e-9));
+
+        // quantizing with 1 loses at most the rounding of each coefficient
+        let uncentered: [f64; 64] = std::array::from_fn(|i| samples[i] + 128.0);
+        let quantized = encode_block(&basis, &uncentered, &[1.0; 64]);
+        let decoded = decode_block(&basis, &quantized, &[1.0; 64]);
+        for (decoded_sample, sample) in decoded.iter().zip(uncentered.iter()) {
+            assert!((decoded_sample - sample).abs() < 2.0);
+        }
+    }
+
+    #[test]
+    fn srgb_profiles_left_alone() {
+        let image = Image::try_read(SMALL_JPEG).expect("failed to read image");
+        assert!(convert_to_srgb(&image).expect("failed to convert").is_none());
+
+        let parametric = with_profile(&rgb_profile(srgb_curve_tag()));
+        assert!(convert_to_srgb(&parametric).expect("failed to convert").is_none());
+
+        let samples: Vec<u16> = (0..1024)
+            .map(|i| (icc::srgb_decode(f64::from(i) / 1023.0) * 65535.0).round() as u16)
+            .collect();
+        let tabulated = with_profile(&rgb_profile(curve_tag(&samples)));
+        assert!(convert_to_srgb(&tabulated).expect("failed to convert").is_none());
+    }
+
+    #[test]
+    fn srgb_transform_is_identity() {
+        // the transform an sRGB profile would have if it were not skipped
+        let profile = Profile::parse(&rgb_profile(srgb_curve_tag())).expect("failed to parse profile");
+        let Profile::Rgb { curves, .. } = &profile else { unreachable!() };
+        let transform = ColorTransform::Rgb {
+            linear: Box::new([0, 1, 2].map(|c| std::array::from_fn(|v| curves[c].apply(v as f64 / 255.0)))),
+            matrix: profile.to_srgb_matrix().unwrap().unwrap(),
+            encoded: (0..ENCODING_STEPS)
+                .map(|step| to_u8(srgb_encode(step as f64 / (ENCODING_STEPS - 1) as f64) * 255.0))
+                .collect(),
+            ycbcr: true,
+        };
+        for ycbcr in [[0.0, 128.0, 128.0], [255.0, 128.0, 128.0], [90.0, 100.0, 170.0], [200.0, 150.0, 60.0]] {
+            let mut samples = ycbcr;
+            transform.apply(&mut samples);
+            for (converted, original) in samples.iter().zip(ycbcr.iter()) {
+                // YCbCr to 8-bit RGB and back rounds
+                assert!((converted - original).abs() < 2.0, "{:?} became {:?}", ycbcr, samples);
+            }
+        }
+    }
+
+    #[test]
+    fn near_srgb_profile_converted() {
+        // the sRGB curve, but with the brightest value a little darker, which is too far off for
+        // the conversion to be skipped but does not occur in the test image
+        let samples: Vec<u16> = (0..=255)
+            .map(|i| (icc::srgb_decode(f64::from(i) / 255.0) * 65535.0).round() as u16)
+            .map(|sample| sample.min(64000))
+            .collect();
+        let image = with_profile(&rgb_profile(curve_tag(&samples)));
+        let converted = convert_to_srgb(&image)
+            .expect("failed to convert")
+            .expect("conversion skipped");
+        assert!(!converted.has_icc_profile());
+        assert_eq!((converted.width, converted.height), (image.width, image.height));
+        assert_eq!(converted.color_space, image.color_space);
+        // the quantization tables are kept
+        let quantization_tables = |image: &Image| SequentialScan::parse(image).unwrap().quantization_tables.map(|t| t.map(|t| t.to_vec()));
+        assert_eq!(quantization_tables(&converted), quantization_tables(&image));
+
+        // what remains is the rounding of the colors and of the coefficients when encoding anew,
+        // along with the clamping of the colors of the noisy test image which lie outside of RGB
+        let difference = mean_difference(&block_samples(&converted), &block_samples(&image));
+        assert!(difference < 4.0, "samples differ by {} on average", difference);
+    }
+
+    #[test]
+    fn unsupported_images_rejected() {
+        let gray_profile = icc::tests::build_profile(b"GRAY", b"XYZ ", &[(b"kTRC", curve_tag(&[563]))]);
+        assert!(matches!(
+            convert_to_srgb(&with_profile(&gray_profile)),
+            Err(Error::ProfileMismatch { color_space: ColorSpace::Rgb }),
+        ));
+
+        let cmyk_profile = icc::tests::build_profile(b"CMYK", b"XYZ ", &[(b"A2B0", b"mft2\0\0\0\0".to_vec())]);
+        assert!(matches!(
+            convert_to_srgb(&with_profile(&cmyk_profile)),
+            Err(Error::Profile(icc::Error::UnsupportedColorSpace { .. })),
+        ));
+
+        let gamma_profile = rgb_profile(curve_tag(&[563]));
+        let mut twelve_bit = with_profile(&gamma_profile);
+        twelve_bit.bit_depth = 12;
+        assert!(matches!(convert_to_srgb(&twelve_bit), Err(Error::UnsupportedBitDepth(12))));
+
+        // the scan repeated before the end of the image
+        let mut multiple_scans = with_profile(&gamma_profile);
+        let scan_data = multiple_scans.image_data.clone();
+        let scan_header = multiple_scans.leading_blocks.iter().rfind(|b| b.kind() == 0xDA).unwrap().clone();
+        scan_header.write(&mut multiple_scans.image_data).unwrap();
+        multiple_scans.image_data.extend_from_slice(&scan_data);
+        assert!(matches!(convert_to_srgb(&multiple_scans), Err(Error::Scan(optimize::Error::MultipleScans))));
+    }
+}
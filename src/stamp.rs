@@ -0,0 +1,102 @@
+//! Text stamps (watermarks) drawn on top of each page.
+
+
+use crate::content::Operator;
+use crate::font::{encode_win_ansi, StandardFont};
+
+
+/// The font in which stamps are set.
+pub const STAMP_FONT: StandardFont = StandardFont::HelveticaBold;
+
+/// The distance between the stamp and the edge of the page, in points.
+const MARGIN_PT: f64 = 18.0;
+
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum StampPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stamp {
+    pub text: String,
+    pub position: StampPosition,
+    pub font_size: f64,
+    /// Counterclockwise rotation in degrees.
+    pub rotation_deg: f64,
+}
+impl Stamp {
+    /// Generates the content stream operators to draw the stamp on a page of the given size.
+    ///
+    /// `font_name` and `ext_g_state_name` are the resource names (without the leading slash) of
+    /// the stamp font and the graphics state providing the opacity.
+    pub fn operators(&self, page_width_pt: f64, page_height_pt: f64, font_name: &str, ext_g_state_name: &str) -> Vec<Operator> {
+        let encoded_text = encode_win_ansi(&self.text);
+        let text_width = STAMP_FONT.text_width(&encoded_text, self.font_size);
+        let text_height = f64::from(STAMP_FONT.cap_height()) * self.font_size / 1000.0;
+
+        let (sin, cos) = self.rotation_deg.to_radians().sin_cos();
+        let (origin_x, origin_y) = text_origin(
+            self.position, text_width, text_height, self.rotation_deg, page_width_pt, page_height_pt,
+        );
+
+        vec![
+            Operator::SaveState,
+            Operator::SetExtGState(ext_g_state_name.to_owned()),
+            Operator::SetFillRgb(1.0, 0.0, 0.0),
+            Operator::BeginText,
+            Operator::SetFont { name: font_name.to_owned(), size: self.font_size },
+            Operator::SetTextMatrix([cos, sin, -sin, cos, origin_x, origin_y]),
+            Operator::ShowText(encoded_text),
+            Operator::EndText,
+            Operator::RestoreState,
+        ]
+    }
+}
+
+
+/// Calculates where to put the origin (the bottom left corner) of a text of the given size, rotated
+/// counterclockwise by the given angle, such that it appears at the given position on a page of the
+/// given size.
+pub fn text_origin(position: StampPosition, text_width: f64, text_height: f64, rotation_deg: f64, page_width_pt: f64, page_height_pt: f64) -> (f64, f64) {
+    let (sin, cos) = rotation_deg.to_radians().sin_cos();
+
+    // half the extent of the rotated text's bounding box
+    let half_extent_x = (cos.abs() * text_width + sin.abs() * text_height) / 2.0;
+    let half_extent_y = (sin.abs() * text_width + cos.abs() * text_height) / 2.0;
+
+    // where the center of the text should end up
+    let center_x = match position {
+        StampPosition::TopLeft|StampPosition::Left|StampPosition::BottomLeft
+            => MARGIN_PT + half_extent_x,
+        StampPosition::Top|StampPosition::Center|StampPosition::Bottom
+            => page_width_pt / 2.0,
+        StampPosition::TopRight|StampPosition::Right|StampPosition::BottomRight
+            => page_width_pt - MARGIN_PT - half_extent_x,
+    };
+    let center_y = match position {
+        StampPosition::TopLeft|StampPosition::Top|StampPosition::TopRight
+            => page_height_pt - MARGIN_PT - half_extent_y,
+        StampPosition::Left|StampPosition::Center|StampPosition::Right
+            => page_height_pt / 2.0,
+        StampPosition::BottomLeft|StampPosition::Bottom|StampPosition::BottomRight
+            => MARGIN_PT + half_extent_y,
+    };
+
+    // the text origin is at the bottom left; rotate the offset to the center of the text
+    let origin_x = center_x - (cos * text_width / 2.0 - sin * text_height / 2.0);
+    let origin_y = center_y - (sin * text_width / 2.0 + cos * text_height / 2.0);
+
+    (origin_x, origin_y)
+}
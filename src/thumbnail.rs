@@ -0,0 +1,556 @@
+//! Decoding a low-resolution brightness preview of a JPEG image.
+//!
+//! Only the DC coefficients (the average value of each 8x8 block) of the first color component are
+//! decoded, which yields a thumbnail at 1/8 of the image's resolution. This is fast and sufficient
+//! for judging the content of a page, e.g. whether it is blank.
+
+
+use std::fmt;
+
+use crate::jpeg::Image;
+
+
+#[derive(Debug)]
+pub enum Error {
+    MissingBlock { kind: u8 },
+    BlockTooShort { kind: u8 },
+    UnsupportedCoding { sof_kind: u8 },
+    InvalidTable { kind: u8, table: u8 },
+    MissingTable { kind: u8, table: u8 },
+    InvalidHuffmanCode,
+    FirstComponentNotInScan,
+    FirstScanNotDc,
+    InvalidScanData,
+    ZeroDimension,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingBlock { kind }
+                => write!(f, "required block 0x{:02X} missing", kind),
+            Self::BlockTooShort { kind }
+                => write!(f, "block 0x{:02X} too short", kind),
+            Self::UnsupportedCoding { sof_kind }
+                => write!(f, "decoding images of frame type 0x{:02X} is not supported", sof_kind),
+            Self::InvalidTable { kind, table }
+                => write!(f, "invalid table {} in block 0x{:02X}", table, kind),
+            Self::MissingTable { kind, table }
+                => write!(f, "table {} referenced but not defined in a block 0x{:02X}", table, kind),
+            Self::InvalidHuffmanCode
+                => write!(f, "invalid Huffman code in image data"),
+            Self::FirstComponentNotInScan
+                => write!(f, "no scan contains the first component"),
+            Self::FirstScanNotDc
+                => write!(f, "no scan of progressive image contains the DC coefficients of the first component"),
+            Self::InvalidScanData
+                => write!(f, "blocks between scans are invalid"),
+            Self::ZeroDimension
+                => write!(f, "image has zero width or height"),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+/// A grayscale preview of an image with one value per 8x8 block of the first color component.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<u8>,
+}
+impl Thumbnail {
+    pub fn value(&self, x: usize, y: usize) -> u8 {
+        self.values[y * self.width + x]
+    }
+
+    /// Returns the share of blocks (between 0.0 and 1.0) whose brightness differs notably from
+    /// that of the background.
+    ///
+    /// The background brightness is assumed to be the median brightness.
+    pub fn ink_coverage(&self, min_difference: u8) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let mut sorted_values = self.values.clone();
+        sorted_values.sort_unstable();
+        let background = sorted_values[sorted_values.len() / 2];
+        let ink_blocks = self.values.iter()
+            .filter(|&&v| v.abs_diff(background) > min_difference)
+            .count();
+        (ink_blocks as f64) / (self.values.len() as f64)
+    }
+
+    /// Calculates a 256-bit perceptual difference hash.
+    ///
+    /// The thumbnail is scaled down to 17x16 values; each bit of the hash denotes whether a value
+    /// is brighter than its right neighbor. Similar images have hashes that differ in few bits.
+    pub fn difference_hash(&self) -> [u64; 4] {
+        const HASH_WIDTH: usize = 17;
+        const HASH_HEIGHT: usize = 16;
+
+        // scale down, averaging the values in each area
+        let mut scaled = [[0u64; HASH_WIDTH]; HASH_HEIGHT];
+        for (hash_y, row) in scaled.iter_mut().enumerate() {
+            let y_start = hash_y * self.height / HASH_HEIGHT;
+            let y_end = ((hash_y + 1) * self.height / HASH_HEIGHT).max(y_start + 1).min(self.height);
+            for (hash_x, cell) in row.iter_mut().enumerate() {
+                let x_start = hash_x * self.width / HASH_WIDTH;
+                let x_end = ((hash_x + 1) * self.width / HASH_WIDTH).max(x_start + 1).min(self.width);
+
+                let mut sum = 0;
+                let mut count = 0;
+                for y in y_start..y_end {
+                    for x in x_start..x_end {
+                        sum += u64::from(self.value(x, y));
+                        count += 1;
+                    }
+                }
+                *cell = sum.checked_div(count).unwrap_or(0);
+            }
+        }
+
+        let mut hash = [0u64; 4];
+        for (hash_y, row) in scaled.iter().enumerate() {
+            for hash_x in 0..HASH_WIDTH-1 {
+                if row[hash_x] > row[hash_x + 1] {
+                    let bit_index = hash_y * (HASH_WIDTH - 1) + hash_x;
+                    hash[bit_index / 64] |= 1 << (bit_index % 64);
+                }
+            }
+        }
+        hash
+    }
+}
+
+/// Counts the bits in which two difference hashes differ.
+pub fn hash_distance(one: &[u64; 4], other: &[u64; 4]) -> u32 {
+    one.iter()
+        .zip(other.iter())
+        .map(|(a, b)| (a ^ b).count_ones())
+        .sum()
+}
+
+
+pub(crate) struct HuffmanTable {
+    // canonical decoding tables as described in JPEG Annex F.2.2.3
+    max_code: [i32; 17],
+    value_offset: [i32; 17],
+    values: Vec<u8>,
+}
+impl HuffmanTable {
+    pub fn new(counts: &[u8], values: &[u8]) -> Self {
+        let mut max_code = [-1; 17];
+        let mut value_offset = [0; 17];
+        let mut code: i32 = 0;
+        let mut value_index: i32 = 0;
+        for length in 1..=16 {
+            let count = i32::from(counts[length - 1]);
+            if count > 0 {
+                value_offset[length] = value_index - code;
+                code += count;
+                value_index += count;
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        Self {
+            max_code,
+            value_offset,
+            values: values.to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FrameComponent {
+    id: u8,
+    horizontal_sampling: usize,
+    vertical_sampling: usize,
+    quantization_table: usize,
+}
+
+#[derive(Clone, Copy)]
+struct ScanComponent {
+    frame_index: usize,
+    dc_table: usize,
+    ac_table: usize,
+}
+
+
+/// Reads bits from entropy-coded data, removing stuffed zero bytes.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+    hit_marker: bool,
+}
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+            hit_marker: false,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.bit_count <= 24 {
+            let mut byte = 0x00;
+            if !self.hit_marker && self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xFF {
+                    let next_byte = self.data.get(self.pos + 1).copied().unwrap_or(0xD9);
+                    if next_byte == 0x00 {
+                        // stuffed byte
+                        self.pos += 2;
+                    } else {
+                        // a marker; pretend the data continues with zeroes
+                        self.hit_marker = true;
+                        byte = 0x00;
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.bit_buffer |= u32::from(byte) << (24 - self.bit_count);
+            self.bit_count += 8;
+        }
+    }
+
+    pub fn read_bit(&mut self) -> u32 {
+        self.read_bits(1)
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        self.fill();
+        let value = self.bit_buffer >> (32 - count);
+        self.bit_buffer <<= count;
+        self.bit_count -= count;
+        value
+    }
+
+    /// Reads a value of the given size and extends its sign (JPEG Annex F.2.2.1).
+    pub fn receive_extend(&mut self, size: u8) -> i32 {
+        if size == 0 {
+            return 0;
+        }
+        let size = u32::from(size).min(16);
+        let value = self.read_bits(size) as i32;
+        if value < (1 << (size - 1)) {
+            value - (1 << size) + 1
+        } else {
+            value
+        }
+    }
+
+    pub fn decode(&mut self, table: &HuffmanTable) -> Result<u8, Error> {
+        let mut code = self.read_bit() as i32;
+        for length in 1..=16 {
+            if code <= table.max_code[length] {
+                let index = usize::try_from(table.value_offset[length] + code)
+                    .map_err(|_| Error::InvalidHuffmanCode)?;
+                return table.values.get(index)
+                    .copied()
+                    .ok_or(Error::InvalidHuffmanCode);
+            }
+            code = (code << 1) | (self.read_bit() as i32);
+        }
+        Err(Error::InvalidHuffmanCode)
+    }
+
+    /// Skips to the next restart marker, discarding any remaining bits.
+    pub fn restart(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+        if self.hit_marker {
+            if let Some(0xD0..=0xD7) = self.data.get(self.pos + 1) {
+                self.pos += 2;
+                self.hit_marker = false;
+            }
+        } else {
+            // find the marker
+            while self.pos + 1 < self.data.len() {
+                if self.data[self.pos] == 0xFF && (0xD0..=0xD7).contains(&self.data[self.pos + 1]) {
+                    self.pos += 2;
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+    }
+}
+
+
+/// Decodes a thumbnail from the DC coefficients of the first color component.
+pub fn decode_thumbnail(image: &Image) -> Result<Thumbnail, Error> {
+    let mut quantization_tables: [Option<u16>; 4] = [None; 4];
+    let mut dc_tables: [Option<HuffmanTable>; 4] = [None, None, None, None];
+    let mut ac_tables: [Option<HuffmanTable>; 4] = [None, None, None, None];
+    let mut frame: Option<(u8, Vec<FrameComponent>)> = None;
+    let mut restart_interval = 0;
+    let mut scan: Option<(Vec<ScanComponent>, u8, &[u8])> = None;
+    let mut scan_seen = false;
+
+    // the first scan containing the DC coefficients of the first component is decoded; the blocks
+    // of the scans before it may define tables used by it
+    let scans = image.scans()
+        .map_err(|_| Error::InvalidScanData)?;
+    'scans: for (scan_index, image_scan) in scans.iter().enumerate() {
+        let blocks = if scan_index == 0 {
+            image.leading_blocks.as_slice()
+        } else {
+            image_scan.blocks.as_slice()
+        };
+        for block in blocks {
+            let kind = block.kind();
+            let data = block.data();
+            match kind {
+                0xDB => {
+                    // quantization tables; we only need the first (DC) value of each
+                    let mut rest = data;
+                    while !rest.is_empty() {
+                        let precision = rest[0] >> 4;
+                        let table = rest[0] & 0x0F;
+                        let table_length = if precision == 0 { 64 } else { 128 };
+                        if rest.len() < 1 + table_length {
+                            return Err(Error::BlockTooShort { kind });
+                        }
+                        if table > 3 {
+                            return Err(Error::InvalidTable { kind, table });
+                        }
+                        let dc_value = if precision == 0 {
+                            u16::from(rest[1])
+                        } else {
+                            u16::from_be_bytes([rest[1], rest[2]])
+                        };
+                        quantization_tables[usize::from(table)] = Some(dc_value);
+                        rest = &rest[1 + table_length..];
+                    }
+                },
+                0xC4 => {
+                    // Huffman tables
+                    let mut rest = data;
+                    while !rest.is_empty() {
+                        if rest.len() < 17 {
+                            return Err(Error::BlockTooShort { kind });
+                        }
+                        let class = rest[0] >> 4;
+                        let table = rest[0] & 0x0F;
+                        let counts = &rest[1..17];
+                        let value_count: usize = counts.iter().map(|&c| usize::from(c)).sum();
+                        if rest.len() < 17 + value_count {
+                            return Err(Error::BlockTooShort { kind });
+                        }
+                        if class > 1 || table > 3 {
+                            return Err(Error::InvalidTable { kind, table });
+                        }
+                        let huffman_table = HuffmanTable::new(counts, &rest[17..17 + value_count]);
+                        if class == 0 {
+                            dc_tables[usize::from(table)] = Some(huffman_table);
+                        } else {
+                            ac_tables[usize::from(table)] = Some(huffman_table);
+                        }
+                        rest = &rest[17 + value_count..];
+                    }
+                },
+                0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF => {
+                    // start of frame
+                    if data.len() < 6 {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let component_count = usize::from(data[5]);
+                    if data.len() < 6 + 3 * component_count {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let components = data[6..6 + 3 * component_count]
+                        .chunks(3)
+                        .map(|c| FrameComponent {
+                            id: c[0],
+                            horizontal_sampling: usize::from(c[1] >> 4).max(1),
+                            vertical_sampling: usize::from(c[1] & 0x0F).max(1),
+                            quantization_table: usize::from(c[2] & 0x03),
+                        })
+                        .collect();
+                    frame = Some((kind, components));
+                },
+                0xDD => {
+                    // restart interval
+                    if data.len() < 2 {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    restart_interval = u16::from_be_bytes([data[0], data[1]]);
+                },
+                0xDA => {
+                    // start of scan
+                    let (_, frame_components) = frame.as_ref()
+                        .ok_or(Error::MissingBlock { kind: 0xC0 })?;
+                    if data.is_empty() {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let component_count = usize::from(data[0]);
+                    if data.len() < 1 + 2 * component_count + 3 {
+                        return Err(Error::BlockTooShort { kind });
+                    }
+                    let mut scan_components = Vec::with_capacity(component_count);
+                    for c in data[1..1 + 2 * component_count].chunks(2) {
+                        // components not in the frame are skipped
+                        if let Some(frame_index) = frame_components.iter().position(|fc| fc.id == c[0]) {
+                            scan_components.push(ScanComponent {
+                                frame_index,
+                                dc_table: usize::from(c[1] >> 4) & 0x03,
+                                ac_table: usize::from(c[1] & 0x0F) & 0x03,
+                            });
+                        }
+                    }
+                    let spectral_start = data[1 + 2 * component_count];
+                    let approximation = data[1 + 2 * component_count + 2];
+                    scan_seen = true;
+
+                    let progressive = frame.as_ref().is_some_and(|(sof_kind, _)| *sof_kind == 0xC2);
+                    let has_first = scan_components.iter().any(|sc| sc.frame_index == 0);
+                    let first_dc = !progressive || (spectral_start == 0 && (approximation >> 4) == 0);
+                    if has_first && first_dc {
+                        scan = Some((scan_components, approximation, image_scan.data));
+                        break 'scans;
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    let (sof_kind, frame_components) = frame
+        .ok_or(Error::MissingBlock { kind: 0xC0 })?;
+    let progressive = match sof_kind {
+        0xC0|0xC1 => false,
+        0xC2 => true,
+        other => return Err(Error::UnsupportedCoding { sof_kind: other }),
+    };
+    let (scan_components, approximation, scan_data) = match scan {
+        Some(scan) => scan,
+        None if !scan_seen => return Err(Error::MissingBlock { kind: 0xDA }),
+        None if progressive => return Err(Error::FirstScanNotDc),
+        None => return Err(Error::FirstComponentNotInScan),
+    };
+    let point_transform = approximation & 0x0F;
+
+    if image.width == 0 || image.height == 0 || frame_components.is_empty() {
+        return Err(Error::ZeroDimension);
+    }
+    let max_horizontal = frame_components.iter().map(|c| c.horizontal_sampling).max().unwrap();
+    let max_vertical = frame_components.iter().map(|c| c.vertical_sampling).max().unwrap();
+    let first = frame_components[0];
+    let first_dc_quantization = quantization_tables[first.quantization_table]
+        .ok_or(Error::MissingTable { kind: 0xDB, table: first.quantization_table as u8 })?;
+
+    for sc in &scan_components {
+        if dc_tables[sc.dc_table].is_none() {
+            return Err(Error::MissingTable { kind: 0xC4, table: sc.dc_table as u8 });
+        }
+        if !progressive && ac_tables[sc.ac_table].is_none() {
+            return Err(Error::MissingTable { kind: 0xC4, table: sc.ac_table as u8 });
+        }
+    }
+
+    // size of the first component in blocks
+    let width_px = usize::from(image.width);
+    let height_px = usize::from(image.height);
+    let first_width = (width_px * first.horizontal_sampling).div_ceil(max_horizontal).div_ceil(8);
+    let first_height = (height_px * first.vertical_sampling).div_ceil(max_vertical).div_ceil(8);
+
+    // how the blocks are arranged into minimum coded units
+    let (mcus_across, mcus_down) = if scan_components.len() == 1 {
+        // non-interleaved: one block per MCU
+        let fc = frame_components[scan_components[0].frame_index];
+        (
+            (width_px * fc.horizontal_sampling).div_ceil(max_horizontal).div_ceil(8),
+            (height_px * fc.vertical_sampling).div_ceil(max_vertical).div_ceil(8),
+        )
+    } else {
+        (width_px.div_ceil(8 * max_horizontal), height_px.div_ceil(8 * max_vertical))
+    };
+
+    let mut dc_values = vec![0i32; first_width * first_height];
+    let mut predictions = vec![0i32; frame_components.len()];
+    let mut reader = BitReader::new(scan_data);
+    let mut mcus_until_restart = restart_interval;
+
+    for mcu_y in 0..mcus_down {
+        for mcu_x in 0..mcus_across {
+            if restart_interval > 0 {
+                if mcus_until_restart == 0 {
+                    reader.restart();
+                    predictions.iter_mut().for_each(|p| *p = 0);
+                    mcus_until_restart = restart_interval;
+                }
+                mcus_until_restart -= 1;
+            }
+
+            for sc in &scan_components {
+                let fc = frame_components[sc.frame_index];
+                let (blocks_across, blocks_down) = if scan_components.len() == 1 {
+                    (1, 1)
+                } else {
+                    (fc.horizontal_sampling, fc.vertical_sampling)
+                };
+                for block_y in 0..blocks_down {
+                    for block_x in 0..blocks_across {
+                        // DC coefficient
+                        let dc_table = dc_tables[sc.dc_table].as_ref().unwrap();
+                        let size = reader.decode(dc_table)?;
+                        predictions[sc.frame_index] += reader.receive_extend(size);
+                        let dc_value = predictions[sc.frame_index] << point_transform;
+
+                        if !progressive {
+                            // skip over the AC coefficients
+                            let ac_table = ac_tables[sc.ac_table].as_ref().unwrap();
+                            let mut k = 1;
+                            while k < 64 {
+                                let run_size = reader.decode(ac_table)?;
+                                let run = run_size >> 4;
+                                let size = run_size & 0x0F;
+                                if size == 0 {
+                                    if run != 15 {
+                                        // end of block
+                                        break;
+                                    }
+                                    k += 16;
+                                } else {
+                                    k += usize::from(run);
+                                    reader.read_bits(size.into());
+                                    k += 1;
+                                }
+                            }
+                        }
+
+                        if sc.frame_index == 0 {
+                            let x = mcu_x * blocks_across + block_x;
+                            let y = mcu_y * blocks_down + block_y;
+                            if x < first_width && y < first_height {
+                                dc_values[y * first_width + x] = dc_value;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // the DC coefficient is eight times the average sample value (which is shifted by 128)
+    let values = dc_values.iter()
+        .map(|&dc| (dc * i32::from(first_dc_quantization) / 8 + 128).clamp(0, 255) as u8)
+        .collect();
+    Ok(Thumbnail {
+        width: first_width,
+        height: first_height,
+        values,
+    })
+}
@@ -0,0 +1,360 @@
+//! Writing of JPEG images into multi-page TIFF files.
+//!
+//! Each image becomes one page (image file directory) with JPEG compression (compression scheme
+//! 7), its complete JPEG data stored unchanged as a single strip. The pages are written one at a
+//! time, so that only one image at a time has to be held in memory.
+
+
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::jpeg::{self, AdobeTransform, ColorSpace, Image};
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Jpeg(jpeg::Error),
+    UnsupportedColorSpace(ColorSpace),
+    UnsupportedSubsampling { horizontal: u8, vertical: u8 },
+    MissingFrame,
+    TooLarge,
+    NoPages,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+            Self::Jpeg(e)
+                => write!(f, "{}", e),
+            Self::UnsupportedColorSpace(color_space)
+                => write!(f, "color space {:?} cannot be stored in TIFF", color_space),
+            Self::UnsupportedSubsampling { horizontal, vertical }
+                => write!(f, "chroma subsampling of {}x{} cannot be stored in TIFF", horizontal, vertical),
+            Self::MissingFrame
+                => write!(f, "image has no start-of-frame block"),
+            Self::TooLarge
+                => write!(f, "TIFF file would exceed 4 GiB"),
+            Self::NoPages
+                => write!(f, "TIFF file has no pages"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Jpeg(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+impl From<jpeg::Error> for Error {
+    fn from(value: jpeg::Error) -> Self { Self::Jpeg(value) }
+}
+
+
+/// A fraction as stored in TIFF.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Rational {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+impl Rational {
+    /// Returns the fraction in lowest terms.
+    pub fn reduced(self) -> Self {
+        let (mut a, mut b) = (self.numerator, self.denominator);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        if a <= 1 {
+            return self;
+        }
+        Self {
+            numerator: self.numerator / a,
+            denominator: self.denominator / a,
+        }
+    }
+}
+
+/// A page of a TIFF file.
+#[derive(Clone, Debug)]
+pub struct Page<'a> {
+    pub image: &'a Image,
+    /// The color space of the image, which may differ from the one the image declares.
+    pub color_space: ColorSpace,
+    /// The horizontal and vertical resolution in pixels per inch.
+    pub resolution: (Rational, Rational),
+    /// How the image is to be rotated or mirrored when displayed, with the same values (1 to 8,
+    /// with 1 meaning upright) as the Exif orientation.
+    pub orientation: u16,
+    pub description: Option<String>,
+}
+
+
+/// The types of the values of a TIFF field.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum FieldType {
+    Ascii = 2,
+    Short = 3,
+    Long = 4,
+    Rational = 5,
+}
+
+/// A field of an image file directory along with its values, encoded in little-endian byte order.
+struct Field {
+    tag: u16,
+    field_type: FieldType,
+    count: u32,
+    data: Vec<u8>,
+}
+impl Field {
+    fn shorts(tag: u16, values: &[u16]) -> Self {
+        Self {
+            tag,
+            field_type: FieldType::Short,
+            count: u32::try_from(values.len()).unwrap(),
+            data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+
+    fn long(tag: u16, value: u32) -> Self {
+        Self {
+            tag,
+            field_type: FieldType::Long,
+            count: 1,
+            data: value.to_le_bytes().to_vec(),
+        }
+    }
+
+    fn rationals(tag: u16, values: &[Rational]) -> Self {
+        Self {
+            tag,
+            field_type: FieldType::Rational,
+            count: u32::try_from(values.len()).unwrap(),
+            data: values.iter()
+                .flat_map(|v| v.numerator.to_le_bytes().into_iter().chain(v.denominator.to_le_bytes()))
+                .collect(),
+        }
+    }
+
+    fn ascii(tag: u16, text: &str) -> Self {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        Self {
+            tag,
+            field_type: FieldType::Ascii,
+            count: u32::try_from(data.len()).unwrap(),
+            data,
+        }
+    }
+}
+
+mod tags {
+    pub const IMAGE_WIDTH: u16 = 256;
+    pub const IMAGE_LENGTH: u16 = 257;
+    pub const BITS_PER_SAMPLE: u16 = 258;
+    pub const COMPRESSION: u16 = 259;
+    pub const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+    pub const IMAGE_DESCRIPTION: u16 = 270;
+    pub const STRIP_OFFSETS: u16 = 273;
+    pub const ORIENTATION: u16 = 274;
+    pub const SAMPLES_PER_PIXEL: u16 = 277;
+    pub const ROWS_PER_STRIP: u16 = 278;
+    pub const STRIP_BYTE_COUNTS: u16 = 279;
+    pub const X_RESOLUTION: u16 = 282;
+    pub const Y_RESOLUTION: u16 = 283;
+    pub const PLANAR_CONFIGURATION: u16 = 284;
+    pub const RESOLUTION_UNIT: u16 = 296;
+    pub const INK_SET: u16 = 332;
+    pub const Y_CB_CR_SUB_SAMPLING: u16 = 530;
+    pub const REFERENCE_BLACK_WHITE: u16 = 532;
+}
+
+/// The photometric interpretation of the image data and, for YCbCr, how much the chroma
+/// components are subsampled horizontally and vertically.
+fn photometric_interpretation(image: &Image, color_space: ColorSpace) -> Result<(u16, Option<(u8, u8)>), Error> {
+    match color_space {
+        ColorSpace::Grayscale => Ok((1, None)),
+        // the decoder assumes YCbCr for three components unless told otherwise
+        ColorSpace::Rgb if matches!(image.adobe_transform, Some(AdobeTransform::Untransformed)) => Ok((2, None)),
+        ColorSpace::Rgb => Ok((6, Some(chroma_subsampling(image)?))),
+        ColorSpace::Cmyk => Ok((5, None)),
+        ColorSpace::Other(_) => Err(Error::UnsupportedColorSpace(color_space)),
+    }
+}
+
+/// The factors by which the chroma components are subsampled relative to the luma component.
+fn chroma_subsampling(image: &Image) -> Result<(u8, u8), Error> {
+    let frame = image.leading_blocks.iter()
+        .find(|block| matches!(block.kind(), 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF))
+        .ok_or(Error::MissingFrame)?;
+    // precision, height, width, component count, then three bytes per component
+    let data = frame.data();
+    if data.len() < 6 + 3 * 3 || data[5] != 3 {
+        return Err(Error::UnsupportedColorSpace(ColorSpace::Rgb));
+    }
+    let sampling = |component: usize| (data[6 + 3 * component + 1] >> 4, data[6 + 3 * component + 1] & 0x0F);
+    let (luma_horizontal, luma_vertical) = sampling(0);
+    let (chroma_horizontal, chroma_vertical) = sampling(1);
+    let unsupported = Error::UnsupportedSubsampling { horizontal: luma_horizontal, vertical: luma_vertical };
+    // both chroma components must be sampled alike, at a fraction of the luma component that TIFF
+    // can express
+    if sampling(2) != (chroma_horizontal, chroma_vertical) || chroma_horizontal == 0 || chroma_vertical == 0 {
+        return Err(unsupported);
+    }
+    if !luma_horizontal.is_multiple_of(chroma_horizontal) || !luma_vertical.is_multiple_of(chroma_vertical) {
+        return Err(unsupported);
+    }
+    let (horizontal, vertical) = (luma_horizontal / chroma_horizontal, luma_vertical / chroma_vertical);
+    if ![1, 2, 4].contains(&horizontal) || ![1, 2, 4].contains(&vertical) || vertical > horizontal {
+        return Err(unsupported);
+    }
+    Ok((horizontal, vertical))
+}
+
+
+/// Writes a multi-page TIFF file page by page.
+pub struct TiffWriter<W: Seek + Write> {
+    output: W,
+    start_pos: u64,
+    /// Where the offset of the next image file directory is to be filled in.
+    next_ifd_offset_pos: u64,
+    page_count: usize,
+}
+impl<W: Seek + Write> TiffWriter<W> {
+    /// Starts a TIFF file at the current position of the given writer by writing the header.
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        let start_pos = writer.stream_position()?;
+        // little-endian, the magic number, then the offset of the first image file directory
+        writer.write_all(b"II\x2A\x00")?;
+        writer.write_all(&[0; 4])?;
+        Ok(Self {
+            output: writer,
+            start_pos,
+            next_ifd_offset_pos: start_pos + 4,
+            page_count: 0,
+        })
+    }
+
+    /// The current offset from the start of the file, aligned to a word boundary as TIFF requires.
+    fn aligned_offset(&mut self) -> Result<u32, Error> {
+        let mut offset = self.output.stream_position()? - self.start_pos;
+        if !offset.is_multiple_of(2) {
+            self.output.write_all(&[0])?;
+            offset += 1;
+        }
+        u32::try_from(offset).map_err(|_| Error::TooLarge)
+    }
+
+    /// Appends a page to the file.
+    pub fn write_page(&mut self, page: &Page<'_>) -> Result<(), Error> {
+        let image = page.image;
+        let (photometric, subsampling) = photometric_interpretation(image, page.color_space)?;
+        let samples = match page.color_space {
+            ColorSpace::Grayscale => 1,
+            ColorSpace::Rgb => 3,
+            ColorSpace::Cmyk => 4,
+            ColorSpace::Other(_) => return Err(Error::UnsupportedColorSpace(page.color_space)),
+        };
+
+        // the whole JPEG data stream is a single strip
+        let strip_offset = self.aligned_offset()?;
+        image.write(&mut self.output)?;
+        let strip_length = u32::try_from(image.encoded_len()).map_err(|_| Error::TooLarge)?;
+
+        // ordered by tag, as TIFF requires
+        let mut fields = vec![
+            Field::long(tags::IMAGE_WIDTH, image.width.into()),
+            Field::long(tags::IMAGE_LENGTH, image.height.into()),
+            Field::shorts(tags::BITS_PER_SAMPLE, &vec![u16::from(image.bit_depth); samples]),
+            Field::shorts(tags::COMPRESSION, &[7]),
+            Field::shorts(tags::PHOTOMETRIC_INTERPRETATION, &[photometric]),
+        ];
+        if let Some(description) = &page.description {
+            fields.push(Field::ascii(tags::IMAGE_DESCRIPTION, description));
+        }
+        fields.push(Field::long(tags::STRIP_OFFSETS, strip_offset));
+        if page.orientation != 1 {
+            fields.push(Field::shorts(tags::ORIENTATION, &[page.orientation]));
+        }
+        fields.extend([
+            Field::shorts(tags::SAMPLES_PER_PIXEL, &[u16::try_from(samples).unwrap()]),
+            Field::long(tags::ROWS_PER_STRIP, image.height.into()),
+            Field::long(tags::STRIP_BYTE_COUNTS, strip_length),
+            Field::rationals(tags::X_RESOLUTION, &[page.resolution.0]),
+            Field::rationals(tags::Y_RESOLUTION, &[page.resolution.1]),
+            Field::shorts(tags::PLANAR_CONFIGURATION, &[1]),
+            // inches
+            Field::shorts(tags::RESOLUTION_UNIT, &[2]),
+        ]);
+        if matches!(page.color_space, ColorSpace::Cmyk) {
+            fields.push(Field::shorts(tags::INK_SET, &[1]));
+        }
+        if let Some((horizontal, vertical)) = subsampling {
+            fields.push(Field::shorts(tags::Y_CB_CR_SUB_SAMPLING, &[horizontal.into(), vertical.into()]));
+            let full_range = |black, white| [
+                Rational { numerator: black, denominator: 1 },
+                Rational { numerator: white, denominator: 1 },
+            ];
+            let reference: Vec<Rational> = [full_range(0, 255), full_range(128, 255), full_range(128, 255)]
+                .concat();
+            fields.push(Field::rationals(tags::REFERENCE_BLACK_WHITE, &reference));
+        }
+
+        // values that do not fit into their field are stored before the directory
+        let mut value_offsets = Vec::with_capacity(fields.len());
+        for field in &fields {
+            if field.data.len() > 4 {
+                let offset = self.aligned_offset()?;
+                self.output.write_all(&field.data)?;
+                value_offsets.push(Some(offset));
+            } else {
+                value_offsets.push(None);
+            }
+        }
+
+        let ifd_offset = self.aligned_offset()?;
+        self.output.write_all(&u16::try_from(fields.len()).unwrap().to_le_bytes())?;
+        for (field, value_offset) in fields.iter().zip(value_offsets) {
+            self.output.write_all(&field.tag.to_le_bytes())?;
+            self.output.write_all(&(field.field_type as u16).to_le_bytes())?;
+            self.output.write_all(&field.count.to_le_bytes())?;
+            match value_offset {
+                Some(offset) => self.output.write_all(&offset.to_le_bytes())?,
+                None => {
+                    let mut value = [0u8; 4];
+                    value[..field.data.len()].copy_from_slice(&field.data);
+                    self.output.write_all(&value)?;
+                },
+            }
+        }
+        let next_ifd_offset_pos = self.output.stream_position()?;
+        self.output.write_all(&[0; 4])?;
+        let end_pos = self.output.stream_position()?;
+        if end_pos - self.start_pos > u64::from(u32::MAX) {
+            return Err(Error::TooLarge);
+        }
+
+        // link the directory to the previous one (or the header)
+        self.output.seek(SeekFrom::Start(self.next_ifd_offset_pos))?;
+        self.output.write_all(&ifd_offset.to_le_bytes())?;
+        self.output.seek(SeekFrom::Start(end_pos))?;
+        self.next_ifd_offset_pos = next_ifd_offset_pos;
+        self.page_count += 1;
+        Ok(())
+    }
+
+    /// Completes the file, returning the writer, positioned at the end of the file.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.page_count == 0 {
+            return Err(Error::NoPages);
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
@@ -0,0 +1,212 @@
+//! Lossless splitting of JPEG images into tiles.
+//!
+//! Some PDF viewers fail to display very large images. Since the image data is divided into
+//! minimum coded units (MCUs), an image can be split along their boundaries without touching the
+//! pixels: the entropy-coded data is decoded into Huffman symbols, and the blocks of each tile are
+//! re-encoded using Huffman tables built for the tile. Only the DC coefficients, which are stored
+//! as differences to the preceding block, have to be recalculated.
+//!
+//! Only sequential images with Huffman coding and a single scan are supported.
+
+
+use crate::jpeg::{Block, Image, MetadataKind};
+use crate::optimize::{
+    huffman_block, replace_tables, BitWriter, EncodingTable, Error, Event, SequentialScan,
+};
+
+
+/// A part of an image.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Tile {
+    /// The horizontal position of the tile within the image, in pixels from the left.
+    pub left: u16,
+    /// The vertical position of the tile within the image, in pixels from the top.
+    pub top: u16,
+    pub image: Image,
+}
+
+
+/// The coefficients of an 8x8 block as decoded from the entropy-coded data.
+struct CodedBlock {
+    /// The DC coefficient (not the difference to the preceding block).
+    dc: i32,
+    /// The index of the first AC symbol of the block in the list of all AC symbols.
+    ac_start: usize,
+}
+
+/// A Huffman-coded AC symbol (run length and size) along with its additional bits.
+#[derive(Clone, Copy)]
+struct AcSymbol {
+    run_size: u8,
+    extra_bits: u16,
+}
+
+
+/// Returns the number of additional bits required to store the given DC difference, along with
+/// the bits themselves.
+pub(crate) fn encode_difference(difference: i32) -> (u8, u32) {
+    let size = 32 - difference.unsigned_abs().leading_zeros();
+    let bits = if difference < 0 {
+        // negative values are stored as the one's complement
+        (difference - 1) as u32
+    } else {
+        difference as u32
+    };
+    (size as u8, bits & ((1 << size) - 1))
+}
+
+/// Decodes a DC difference from its size and additional bits.
+pub(crate) fn decode_difference(size: u32, bits: u32) -> i32 {
+    if size == 0 {
+        0
+    } else if bits < (1 << (size - 1)) {
+        (bits as i32) - (1 << size) + 1
+    } else {
+        bits as i32
+    }
+}
+
+
+/// Splits an image into tiles of at most the given size.
+///
+/// The tiles are sized in multiples of the image's MCU size (usually 8 or 16 pixels); only the
+/// tiles at the right and bottom edge may be smaller. Returns the image as a single tile if it
+/// already fits.
+///
+/// Metadata blocks other than the JFIF header and the Adobe block (which influence how the image
+/// is decoded) are not copied into the tiles.
+pub fn split(image: &Image, max_width: u16, max_height: u16) -> Result<Vec<Tile>, Error> {
+    if image.width <= max_width && image.height <= max_height {
+        return Ok(vec![Tile {
+            left: 0,
+            top: 0,
+            image: image.clone(),
+        }]);
+    }
+
+    let scan = SequentialScan::parse(image)?;
+    let geometry = &scan.geometry;
+    let mcus_per_tile_across = (usize::from(max_width) / geometry.mcu_width_px).max(1);
+    let mcus_per_tile_down = (usize::from(max_height) / geometry.mcu_height_px).max(1);
+
+    // decode all blocks
+    let mut blocks = Vec::new();
+    let mut ac_symbols = Vec::new();
+    let mut predictions = vec![0i32; scan.scan_components.len()];
+    scan.walk(image, |event| match event {
+        Event::Symbol { class: 0, symbol, extra_bits, .. } => {
+            let scan_index = geometry.block_components[blocks.len() % geometry.block_components.len()];
+            predictions[scan_index] += decode_difference(u32::from(symbol), extra_bits);
+            blocks.push(CodedBlock {
+                dc: predictions[scan_index],
+                ac_start: ac_symbols.len(),
+            });
+        },
+        Event::Symbol { symbol, extra_bits, .. } => {
+            ac_symbols.push(AcSymbol {
+                run_size: symbol,
+                extra_bits: extra_bits as u16,
+            });
+        },
+        Event::Restart => {
+            predictions.fill(0);
+        },
+    })?;
+
+    let mut tiles = Vec::new();
+    for first_mcu_down in (0..geometry.mcus_down).step_by(mcus_per_tile_down) {
+        let last_mcu_down = (first_mcu_down + mcus_per_tile_down).min(geometry.mcus_down);
+        for first_mcu_across in (0..geometry.mcus_across).step_by(mcus_per_tile_across) {
+            let last_mcu_across = (first_mcu_across + mcus_per_tile_across).min(geometry.mcus_across);
+
+            // visits the symbols of the tile in the order in which they are encoded
+            let visit_tile = |visit: &mut dyn FnMut(usize, usize, u8, u32)| {
+                let mut predictions = vec![0i32; scan.scan_components.len()];
+                for mcu_down in first_mcu_down..last_mcu_down {
+                    for mcu_across in first_mcu_across..last_mcu_across {
+                        let first_block = (mcu_down * geometry.mcus_across + mcu_across) * geometry.block_components.len();
+                        for (block_offset, &scan_index) in geometry.block_components.iter().enumerate() {
+                            let sc = scan.scan_components[scan_index];
+                            let block_index = first_block + block_offset;
+                            let block = &blocks[block_index];
+                            let ac_end = blocks.get(block_index + 1)
+                                .map(|b| b.ac_start)
+                                .unwrap_or(ac_symbols.len());
+
+                            let (size, bits) = encode_difference(block.dc - predictions[scan_index]);
+                            predictions[scan_index] = block.dc;
+                            visit(0, sc.dc_table, size, bits);
+                            for ac_symbol in &ac_symbols[block.ac_start..ac_end] {
+                                visit(1, sc.ac_table, ac_symbol.run_size, u32::from(ac_symbol.extra_bits));
+                            }
+                        }
+                    }
+                }
+            };
+
+            let tables = scan.optimal_tables(|frequencies| {
+                visit_tile(&mut |class, table, symbol, _bits| {
+                    frequencies[class][table][usize::from(symbol)] += 1;
+                });
+                Ok(())
+            })?;
+            let codes: [[Option<EncodingTable>; 4]; 2] = [0, 1].map(|class|
+                [0, 1, 2, 3].map(|table: usize| tables[class][table].as_ref().map(|t| t.codes()))
+            );
+            let mut writer = BitWriter::new();
+            visit_tile(&mut |class, table, symbol, bits| {
+                let (code, length) = codes[class][table].as_ref().unwrap()[usize::from(symbol)];
+                writer.write_bits(code, length);
+                // the number of additional bits is the size, i.e. the DC symbol or the lower half of
+                // the AC symbol
+                writer.write_bits(bits, u32::from(symbol & 0x0F).min(16));
+            });
+            writer.pad();
+
+            let left = first_mcu_across * geometry.mcu_width_px;
+            let top = first_mcu_down * geometry.mcu_height_px;
+            let width = (last_mcu_across * geometry.mcu_width_px).min(usize::from(image.width)) - left;
+            let height = (last_mcu_down * geometry.mcu_height_px).min(usize::from(image.height)) - top;
+            let width = u16::try_from(width).unwrap();
+            let height = u16::try_from(height).unwrap();
+
+            // the tile is encoded without restart markers
+            let mut leading_blocks = replace_tables(
+                &image.leading_blocks,
+                scan.quantization_block(),
+                huffman_block(&tables),
+                |block| block.kind() != 0xDD && match block.metadata_kind() {
+                    None => true,
+                    Some(kind) => matches!(kind, MetadataKind::Jfif|MetadataKind::Adobe),
+                },
+            );
+            for block in &mut leading_blocks {
+                if let Block::Long { kind: 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF, data } = block {
+                    data[1..3].copy_from_slice(&height.to_be_bytes());
+                    data[3..5].copy_from_slice(&width.to_be_bytes());
+                }
+            }
+
+            tiles.push(Tile {
+                left: u16::try_from(left).unwrap(),
+                top: u16::try_from(top).unwrap(),
+                image: Image {
+                    frame_type: image.frame_type,
+                    bit_depth: image.bit_depth,
+                    width,
+                    height,
+                    color_space: image.color_space,
+                    density_unit: image.density_unit,
+                    density_x: image.density_x,
+                    density_y: image.density_y,
+                    exif_orientation: image.exif_orientation,
+                    adobe_transform: image.adobe_transform,
+                    leading_blocks,
+                    image_data: writer.data,
+                    trailing_blocks: image.trailing_blocks.clone(),
+                },
+            });
+        }
+    }
+    Ok(tiles)
+}
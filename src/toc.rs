@@ -0,0 +1,142 @@
+//! Table-of-contents pages listing the images of a merged document.
+
+
+use crate::content::Operator;
+use crate::font::{encode_win_ansi, StandardFont};
+
+
+/// The font in which the entries are set.
+pub const ENTRY_FONT: StandardFont = StandardFont::Helvetica;
+
+/// The font in which the title is set.
+pub const TITLE_FONT: StandardFont = StandardFont::HelveticaBold;
+
+const TITLE: &str = "Contents";
+const MARGIN_PT: f64 = 72.0;
+const TITLE_SIZE_PT: f64 = 18.0;
+const ENTRY_SIZE_PT: f64 = 11.0;
+const LINE_HEIGHT_PT: f64 = 16.0;
+/// The distance between the top margin and the baseline of the first entry on the first page.
+const TITLE_HEIGHT_PT: f64 = 40.0;
+/// The minimum distance between the title of an entry and its page number.
+const PAGE_NUMBER_GAP_PT: f64 = 12.0;
+/// The WinAnsi-encoded ellipsis, used to shorten titles that are too long.
+const ELLIPSIS: u8 = 0x85;
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TocEntry {
+    pub title: String,
+    /// The index of the page the entry refers to within the document, counted from 0.
+    pub page_index: usize,
+}
+
+/// A page of the table of contents.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TocPage {
+    pub entries: Vec<TocEntry>,
+    /// Whether this is the first page of the table of contents, which carries the title.
+    pub is_first: bool,
+}
+impl TocPage {
+    /// Distributes the given entries onto as many pages of the given height as necessary.
+    pub fn paginate(entries: Vec<TocEntry>, page_height_pt: f64) -> Vec<TocPage> {
+        let mut pages = Vec::new();
+        let mut remaining = entries.into_iter().peekable();
+        loop {
+            let is_first = pages.is_empty();
+            let capacity = Self::capacity(page_height_pt, is_first);
+            let entries: Vec<TocEntry> = remaining.by_ref().take(capacity).collect();
+            pages.push(TocPage { entries, is_first });
+            if remaining.peek().is_none() {
+                break;
+            }
+        }
+        pages
+    }
+
+    /// The number of entries that fit onto a page of the given height.
+    fn capacity(page_height_pt: f64, is_first: bool) -> usize {
+        let mut available = page_height_pt - 2.0 * MARGIN_PT;
+        if is_first {
+            available -= TITLE_HEIGHT_PT;
+        }
+        ((available / LINE_HEIGHT_PT).floor() as usize).max(1)
+    }
+
+    /// The y coordinate of the baseline of the entry with the given index on this page.
+    fn baseline_pt(&self, page_height_pt: f64, index: usize) -> f64 {
+        let first_baseline = if self.is_first {
+            page_height_pt - MARGIN_PT - TITLE_HEIGHT_PT
+        } else {
+            page_height_pt - MARGIN_PT - ENTRY_SIZE_PT
+        };
+        first_baseline - (index as f64) * LINE_HEIGHT_PT
+    }
+
+    /// Generates the content stream operators to draw this page.
+    ///
+    /// `entry_font_name` and `title_font_name` are the resource names (without the leading slash)
+    /// of [`ENTRY_FONT`] and [`TITLE_FONT`].
+    pub fn operators(&self, page_width_pt: f64, page_height_pt: f64, entry_font_name: &str, title_font_name: &str) -> Vec<Operator> {
+        let mut operators = vec![
+            Operator::BeginText,
+        ];
+        if self.is_first {
+            operators.push(Operator::SetFont { name: title_font_name.to_owned(), size: TITLE_SIZE_PT });
+            operators.push(Operator::SetTextMatrix([1.0, 0.0, 0.0, 1.0, MARGIN_PT, page_height_pt - MARGIN_PT - TITLE_SIZE_PT]));
+            operators.push(Operator::ShowText(encode_win_ansi(TITLE)));
+        }
+
+        operators.push(Operator::SetFont { name: entry_font_name.to_owned(), size: ENTRY_SIZE_PT });
+        let right_pt = page_width_pt - MARGIN_PT;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let baseline_pt = self.baseline_pt(page_height_pt, index);
+
+            let page_number = encode_win_ansi(&(entry.page_index + 1).to_string());
+            let page_number_width = ENTRY_FONT.text_width(&page_number, ENTRY_SIZE_PT);
+            let max_title_width = right_pt - MARGIN_PT - page_number_width - PAGE_NUMBER_GAP_PT;
+            let title = shorten_to_width(encode_win_ansi(&entry.title), max_title_width);
+
+            operators.push(Operator::SetTextMatrix([1.0, 0.0, 0.0, 1.0, MARGIN_PT, baseline_pt]));
+            operators.push(Operator::ShowText(title));
+            operators.push(Operator::SetTextMatrix([1.0, 0.0, 0.0, 1.0, right_pt - page_number_width, baseline_pt]));
+            operators.push(Operator::ShowText(page_number));
+        }
+
+        operators.push(Operator::EndText);
+        operators
+    }
+
+    /// Returns the clickable area of each entry as `(left, bottom, right, top)` in points from the
+    /// bottom left corner of the page.
+    pub fn entry_rects_pt(&self, page_width_pt: f64, page_height_pt: f64) -> Vec<(f64, f64, f64, f64)> {
+        // extend each line slightly below the baseline to cover descenders
+        let descent_pt = (LINE_HEIGHT_PT - ENTRY_SIZE_PT) / 2.0;
+        (0..self.entries.len())
+            .map(|index| {
+                let baseline_pt = self.baseline_pt(page_height_pt, index);
+                let bottom_pt = baseline_pt - descent_pt;
+                (MARGIN_PT, bottom_pt, page_width_pt - MARGIN_PT, bottom_pt + LINE_HEIGHT_PT)
+            })
+            .collect()
+    }
+}
+
+
+/// Shortens the WinAnsi-encoded text, marking the omission with an ellipsis, until it fits into
+/// the given width when set in the entry font.
+fn shorten_to_width(mut text: Vec<u8>, max_width_pt: f64) -> Vec<u8> {
+    if ENTRY_FONT.text_width(&text, ENTRY_SIZE_PT) <= max_width_pt {
+        return text;
+    }
+    while !text.is_empty() {
+        text.pop();
+        let mut shortened = text.clone();
+        shortened.push(ELLIPSIS);
+        if ENTRY_FONT.text_width(&shortened, ENTRY_SIZE_PT) <= max_width_pt {
+            return shortened;
+        }
+    }
+    vec![ELLIPSIS]
+}
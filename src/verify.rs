@@ -0,0 +1,470 @@
+//! Verification of the structure of written PDF documents.
+//!
+//! The written bytes are parsed again, just far enough to check that the cross-reference table
+//! points at the objects and links the free entries into a list, that the trailer has the right size, that each stream is as long as its
+//! `/Length` says, and that the data of each image is exactly the data that was to be embedded
+//! (encrypted, if the document is).
+//! The parser only understands the subset of PDF syntax that this crate writes.
+
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::pdf::{Document, FREE_LIST_HEAD_GENERATION, ObjectData, ObjectEncoding, PdfGeneration, PdfObjectId};
+
+
+#[derive(Debug)]
+pub enum Error {
+    NotPdf,
+    NoStartXref,
+    InvalidXref { offset: usize },
+    InvalidFreeList { id: PdfObjectId },
+    Syntax { offset: usize, message: &'static str },
+    MissingObject { id: PdfObjectId },
+    ObjectMismatch { id: PdfObjectId, offset: usize },
+    LengthMismatch { id: PdfObjectId, declared: usize },
+    SizeMismatch { declared: u64, expected: u64 },
+    ImageDataMismatch { id: PdfObjectId },
+    Io(io::Error),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPdf
+                => write!(f, "file does not start with a PDF header"),
+            Self::NoStartXref
+                => write!(f, "no startxref at the end of the file"),
+            Self::InvalidXref { offset }
+                => write!(f, "invalid cross-reference table at offset {}", offset),
+            Self::InvalidFreeList { id }
+                => write!(f, "free entry of object {} in the cross-reference table is not part of the list of free objects", id),
+            Self::Syntax { offset, message }
+                => write!(f, "syntax error at offset {}: {}", offset, message),
+            Self::MissingObject { id }
+                => write!(f, "object {} missing from the cross-reference table", id),
+            Self::ObjectMismatch { id, offset }
+                => write!(f, "cross-reference table points at offset {} for object {}, which does not start there", offset, id),
+            Self::LengthMismatch { id, declared }
+                => write!(f, "stream of object {} does not end after its declared length of {} bytes", id, declared),
+            Self::SizeMismatch { declared, expected }
+                => write!(f, "trailer declares size {}, expected {}", declared, expected),
+            Self::ImageDataMismatch { id }
+                => write!(f, "data of image object {} differs from the source image", id),
+            Self::Io(e)
+                => write!(f, "failed to read the file: {}", e),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+
+
+/// A value as far as the verification is concerned.
+#[derive(Clone, Debug, PartialEq)]
+enum Value<'a> {
+    Integer(i64),
+    Name(&'a [u8]),
+    Dictionary(Vec<(&'a [u8], Value<'a>)>),
+    /// Any other value (arrays, strings, references, etc.), whose content is not of interest.
+    Other,
+}
+impl<'a> Value<'a> {
+    fn get(&self, key: &[u8]) -> Option<&Value<'a>> {
+        match self {
+            Self::Dictionary(entries) => entries.iter()
+                .find(|(k, _v)| *k == key)
+                .map(|(_k, v)| v),
+            _ => None,
+        }
+    }
+}
+
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\0'|b'\t'|b'\n'|b'\x0C'|b'\r'|b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(b, b'('|b')'|b'<'|b'>'|b'['|b']'|b'{'|b'}'|b'/'|b'%')
+}
+
+/// Reads PDF syntax starting at a given position.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn error(&self, message: &'static str) -> Error {
+        Error::Syntax { offset: self.pos, message }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if is_whitespace(b) {
+                self.pos += 1;
+            } else if b == b'%' {
+                while self.peek().is_some_and(|b| b != b'\n' && b != b'\r') {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Reads a run of regular characters, e.g. a keyword or a number.
+    fn regular(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| !is_whitespace(b) && !is_delimiter(b)) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn integer(&mut self) -> Result<i64, Error> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let token = self.regular();
+        std::str::from_utf8(token).ok()
+            .and_then(|t| t.parse().ok())
+            .ok_or(Error::Syntax { offset: start, message: "integer expected" })
+    }
+
+    fn expect_keyword(&mut self, keyword: &[u8]) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.regular() == keyword {
+            Ok(())
+        } else {
+            Err(self.error("unexpected keyword"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("unexpected end of file")),
+            Some(b'/') => {
+                self.pos += 1;
+                Ok(Value::Name(self.regular()))
+            },
+            Some(b'(') => {
+                // literal string; parentheses may nest unless escaped
+                self.pos += 1;
+                let mut depth = 1;
+                while depth > 0 {
+                    match self.peek() {
+                        None => return Err(self.error("unterminated string")),
+                        Some(b'\\') => self.pos += 1,
+                        Some(b'(') => depth += 1,
+                        Some(b')') => depth -= 1,
+                        Some(_) => {},
+                    }
+                    self.pos += 1;
+                }
+                Ok(Value::Other)
+            },
+            Some(b'<') if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b'>') => {
+                            self.pos += 2;
+                            break;
+                        },
+                        Some(b'/') => {
+                            self.pos += 1;
+                            let key = self.regular();
+                            let value = self.value()?;
+                            entries.push((key, value));
+                        },
+                        _ => return Err(self.error("name expected as dictionary key")),
+                    }
+                }
+                Ok(Value::Dictionary(entries))
+            },
+            Some(b'<') => {
+                // hexadecimal string
+                while self.peek().is_some_and(|b| b != b'>') {
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                Ok(Value::Other)
+            },
+            Some(b'[') => {
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        break;
+                    }
+                    self.value()?;
+                }
+                Ok(Value::Other)
+            },
+            Some(_) => {
+                let start = self.pos;
+                let token = self.regular();
+                if token.is_empty() {
+                    return Err(self.error("unexpected delimiter"));
+                }
+                let integer = std::str::from_utf8(token).ok()
+                    .and_then(|t| t.parse::<i64>().ok());
+                let Some(integer) = integer else {
+                    // a real number, boolean or null
+                    return Ok(Value::Other);
+                };
+
+                // an indirect reference consists of two integers and R
+                let mut lookahead = Parser::new(self.data, self.pos);
+                let is_reference = lookahead.integer().is_ok() && lookahead.expect_keyword(b"R").is_ok();
+                if is_reference {
+                    self.pos = lookahead.pos;
+                    Ok(Value::Other)
+                } else {
+                    self.pos = start + token.len();
+                    Ok(Value::Integer(integer))
+                }
+            },
+        }
+    }
+}
+
+
+/// Finds the last occurrence of a byte sequence.
+fn rfind(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).rposition(|w| w == needle)
+}
+
+
+/// An entry of the cross-reference table.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum XrefEntry {
+    InUse { offset: usize, generation: PdfGeneration },
+    Free { next_free_id: PdfObjectId, generation: PdfGeneration },
+}
+impl XrefEntry {
+    fn offset(&self) -> Option<usize> {
+        match self {
+            Self::InUse { offset, .. } => Some(*offset),
+            Self::Free { .. } => None,
+        }
+    }
+}
+
+/// Checks that the free entries form a single list headed by object 0, which visits each free
+/// entry exactly once and leads back to object 0.
+fn check_free_list(entries: &[XrefEntry]) -> Result<(), Error> {
+    match entries.first() {
+        Some(XrefEntry::Free { generation: FREE_LIST_HEAD_GENERATION, .. }) => {},
+        _ => return Err(Error::InvalidFreeList { id: 0 }),
+    }
+    let free_count = entries.iter()
+        .filter(|e| matches!(e, XrefEntry::Free { .. }))
+        .count();
+    let mut id: PdfObjectId = 0;
+    for _ in 0..free_count {
+        let next_free_id = match usize::try_from(id).ok().and_then(|index| entries.get(index)) {
+            Some(XrefEntry::Free { next_free_id, .. }) => *next_free_id,
+            _ => return Err(Error::InvalidFreeList { id }),
+        };
+        id = next_free_id;
+    }
+    // after visiting every free entry once, the list must have come full circle
+    if id != 0 {
+        return Err(Error::InvalidFreeList { id });
+    }
+    Ok(())
+}
+
+/// Reads the cross-reference table of the given bytes, returning its entries, indexed by object
+/// ID, and a parser positioned after the table.
+fn read_xref(data: &[u8]) -> Result<(Vec<XrefEntry>, Parser<'_>), Error> {
+    if !data.starts_with(b"%PDF-") {
+        return Err(Error::NotPdf);
+    }
+
+    let xref_pos = usize::try_from(startxref(data)?)
+        .map_err(|_| Error::NoStartXref)?;
+    read_xref_at(data, xref_pos)
+}
+
+/// Returns the offset of the cross-reference table given after the last `startxref` keyword of
+/// the given bytes.
+fn startxref(data: &[u8]) -> Result<u64, Error> {
+    let startxref_pos = rfind(data, b"startxref")
+        .ok_or(Error::NoStartXref)?;
+    let mut parser = Parser::new(data, startxref_pos);
+    parser.expect_keyword(b"startxref")?;
+    u64::try_from(parser.integer()?)
+        .map_err(|_| Error::NoStartXref)
+}
+
+/// Reads the cross-reference table starting at the given offset of the given bytes, returning its
+/// entries, indexed by object ID, and a parser positioned after the table.
+fn read_xref_at(data: &[u8], xref_pos: usize) -> Result<(Vec<XrefEntry>, Parser<'_>), Error> {
+    let mut parser = Parser::new(data, xref_pos);
+    parser.expect_keyword(b"xref")
+        .map_err(|_| Error::InvalidXref { offset: xref_pos })?;
+    let first_id = parser.integer()?;
+    let entry_count = parser.integer()?;
+    if first_id != 0 || entry_count < 1 {
+        return Err(Error::InvalidXref { offset: xref_pos });
+    }
+    parser.skip_whitespace();
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        let entry = data.get(parser.pos..parser.pos + 20)
+            .ok_or(Error::InvalidXref { offset: parser.pos })?;
+        let number = std::str::from_utf8(&entry[0..10]).ok()
+            .and_then(|o| o.parse::<u64>().ok())
+            .ok_or(Error::InvalidXref { offset: parser.pos })?;
+        let generation = std::str::from_utf8(&entry[11..16]).ok()
+            .and_then(|g| g.parse::<PdfGeneration>().ok())
+            .ok_or(Error::InvalidXref { offset: parser.pos })?;
+        entries.push(match entry[17] {
+            b'n' => XrefEntry::InUse {
+                offset: usize::try_from(number).map_err(|_| Error::InvalidXref { offset: parser.pos })?,
+                generation,
+            },
+            b'f' => XrefEntry::Free { next_free_id: number, generation },
+            _ => return Err(Error::InvalidXref { offset: parser.pos }),
+        });
+        parser.pos += 20;
+    }
+    check_free_list(&entries)?;
+    Ok((entries, parser))
+}
+
+/// Reads the byte offset of each object in the given bytes from the cross-reference table.
+pub fn object_offsets(data: &[u8]) -> Result<BTreeMap<PdfObjectId, usize>, Error> {
+    let (entries, _parser) = read_xref(data)?;
+    Ok(in_use_offsets(entries))
+}
+
+/// Reads the byte offset of each object of the document in the given reader from the
+/// cross-reference table, which only requires reading the end of the document.
+///
+/// The document has to start at the beginning of the reader.
+pub fn read_object_offsets<R: Read + Seek>(mut reader: R) -> Result<BTreeMap<PdfObjectId, usize>, Error> {
+    // startxref, the offset of the table and the end-of-file marker
+    const TAIL_LEN: u64 = 64;
+
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(length.saturating_sub(TAIL_LEN)))?;
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail)?;
+    let xref_pos = startxref(&tail)?;
+    if xref_pos >= length {
+        return Err(Error::NoStartXref);
+    }
+
+    // the table and the trailer
+    reader.seek(SeekFrom::Start(xref_pos))?;
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest)?;
+    let (entries, _parser) = read_xref_at(&rest, 0)?;
+    Ok(in_use_offsets(entries))
+}
+
+fn in_use_offsets(entries: Vec<XrefEntry>) -> BTreeMap<PdfObjectId, usize> {
+    entries.into_iter()
+        .enumerate()
+        .filter_map(|(id, entry)| Some((u64::try_from(id).unwrap(), entry.offset()?)))
+        .collect()
+}
+
+/// Checks that the given bytes are a faithful rendition of the given document.
+pub fn verify(document: &Document, data: &[u8]) -> Result<(), Error> {
+    // the cross-reference table
+    let (entries, mut parser) = read_xref(data)?;
+
+    // the trailer
+    parser.expect_keyword(b"trailer")?;
+    let trailer = parser.value()?;
+    let declared_size = match trailer.get(b"Size") {
+        Some(Value::Integer(size)) => u64::try_from(*size).unwrap_or(0),
+        _ => return Err(parser.error("trailer without size")),
+    };
+    let expected_size = document.objects.keys().max().map_or(1, |id| id + 1);
+    if declared_size != expected_size || declared_size != u64::try_from(entries.len()).unwrap() {
+        return Err(Error::SizeMismatch { declared: declared_size, expected: expected_size });
+    }
+
+    // the objects
+    for (&id, object) in &document.objects {
+        let (offset, generation) = match usize::try_from(id).ok().and_then(|index| entries.get(index)) {
+            Some(&XrefEntry::InUse { offset, generation }) => (offset, generation),
+            _ => return Err(Error::MissingObject { id }),
+        };
+        let header = format!("{} {} obj", id, generation);
+        let header_ok = data.get(offset..)
+            .is_some_and(|rest| rest.starts_with(header.as_bytes()));
+        if !header_ok {
+            return Err(Error::ObjectMismatch { id, offset });
+        }
+        let mut parser = Parser::new(data, offset + header.len());
+
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if !data[parser.pos..].starts_with(b"stream") {
+            parser.expect_keyword(b"endobj")?;
+            continue;
+        }
+
+        // the stream data starts after the end of the line and is followed by endstream
+        parser.pos += b"stream".len();
+        if data[parser.pos..].starts_with(b"\r\n") {
+            parser.pos += 2;
+        } else if data[parser.pos..].starts_with(b"\n") {
+            parser.pos += 1;
+        } else {
+            return Err(parser.error("end of line expected after stream"));
+        }
+        let length = match value.get(b"Length") {
+            Some(Value::Integer(length)) => usize::try_from(*length)
+                .map_err(|_| parser.error("negative stream length"))?,
+            _ => return Err(parser.error("stream without length")),
+        };
+        let stream_data = data.get(parser.pos..parser.pos + length)
+            .ok_or(Error::LengthMismatch { id, declared: length })?;
+        let mut end_parser = Parser::new(data, parser.pos + length);
+        end_parser.skip_whitespace();
+        if !data[end_parser.pos..].starts_with(b"endstream") {
+            return Err(Error::LengthMismatch { id, declared: length });
+        }
+
+        if let ObjectData::ImageXObject(image) = object {
+            debug_assert_eq!(value.get(b"Subtype"), Some(&Value::Name(b"Image")));
+            let expected_data = image.stream_data(document.ascii_encoding)
+                .map_err(|_| Error::ImageDataMismatch { id })?;
+            let expected_data = ObjectEncoding::new(document.compression, document.ascii_encoding, document.encryption.as_ref(), id)
+                .encrypt_stream_data(expected_data);
+            if stream_data != expected_data.as_slice() {
+                return Err(Error::ImageDataMismatch { id });
+            }
+        }
+    }
+
+    Ok(())
+}
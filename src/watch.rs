@@ -0,0 +1,123 @@
+//! Monitoring of a directory for arriving images, e.g. a folder into which a network scanner
+//! deposits its scans.
+//!
+//! The directory is polled at regular intervals. Since an image may still be in the process of
+//! being written when it is first seen, it is only handed out for conversion once its size and
+//! modification time have remained unchanged for a while.
+
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::batch::Job;
+use crate::fetch::JPEG_EXTENSIONS;
+
+
+/// The size and modification time of a file, which change while it is being written.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct FileState {
+    size: u64,
+    modified: SystemTime,
+}
+impl FileState {
+    fn of(metadata: &fs::Metadata) -> Result<Self, io::Error> {
+        Ok(Self {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+
+/// Keeps track of the images in a directory.
+pub struct Watcher {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    settle_time: Duration,
+    /// Images that have been seen but not handed out yet, along with their state and the time at
+    /// which that state was first observed.
+    pending: BTreeMap<PathBuf, (FileState, Instant)>,
+    /// Images that have been handed out, along with their state at that time. If the state
+    /// changes (i.e. the image is replaced), the image is handed out again.
+    handed_out: BTreeMap<PathBuf, FileState>,
+}
+impl Watcher {
+    /// Creates a watcher for images in the given directory which are to be converted into PDF
+    /// files in the given output directory. Images are handed out once they have remained
+    /// unchanged for the given time.
+    pub fn new(input_dir: &Path, output_dir: &Path, settle_time: Duration) -> Self {
+        Self {
+            input_dir: input_dir.to_owned(),
+            output_dir: output_dir.to_owned(),
+            settle_time,
+            pending: BTreeMap::new(),
+            handed_out: BTreeMap::new(),
+        }
+    }
+
+    /// Scans the directory and returns the jobs for the images that are ready to be converted, in
+    /// the order of their paths.
+    ///
+    /// Images whose PDF file is at least as new as the image itself are considered converted by
+    /// a previous run and are not handed out.
+    pub fn poll(&mut self) -> Result<Vec<Job>, io::Error> {
+        let now = Instant::now();
+        let mut seen = BTreeMap::new();
+        for entry in fs::read_dir(&self.input_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_jpeg = path.extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .is_some_and(|ext| JPEG_EXTENSIONS.contains(&ext.as_str()));
+            if !is_jpeg {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                // the file may have been removed in the meantime
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            seen.insert(path, FileState::of(&metadata)?);
+        }
+
+        // forget about images that have disappeared
+        self.pending.retain(|path, _| seen.contains_key(path));
+        self.handed_out.retain(|path, _| seen.contains_key(path));
+
+        let mut ready = Vec::new();
+        for (path, state) in seen {
+            if self.handed_out.get(&path) == Some(&state) {
+                continue;
+            }
+            let since = match self.pending.get(&path) {
+                Some(&(pending_state, since)) if pending_state == state => since,
+                _ => {
+                    // new or changed since the last scan; start waiting anew
+                    self.pending.insert(path, (state, now));
+                    continue;
+                },
+            };
+            if now.duration_since(since) < self.settle_time {
+                continue;
+            }
+
+            self.pending.remove(&path);
+            self.handed_out.insert(path.clone(), state);
+            let job = Job::for_input(&path, Some(&self.output_dir));
+            let is_converted = fs::metadata(&job.output_path)
+                .and_then(|output_metadata| output_metadata.modified())
+                .is_ok_and(|output_modified| output_modified >= state.modified);
+            if !is_converted {
+                ready.push(job);
+            }
+        }
+        Ok(ready)
+    }
+}
@@ -0,0 +1,31 @@
+use jpeg2pdf::ConvertError;
+
+
+#[test]
+fn zero_density_is_an_error() {
+    let jpeg_data = include_bytes!("data/zero-density.jpg");
+    let result = jpeg2pdf::convert_bytes(jpeg_data);
+    assert!(matches!(result, Err(ConvertError::ZeroDensity)), "unexpected result: {:?}", result.map(|pdf| pdf.len()));
+}
+
+#[test]
+fn zero_density_is_an_error_within_limits() {
+    let jpeg_data = include_bytes!("data/zero-density.jpg");
+    let image = jpeg2pdf::jpeg::Image::try_read_with_limits(&jpeg_data[..], &jpeg2pdf::jpeg::Limits::default())
+        .expect("failed to read image");
+    assert!(matches!(jpeg2pdf::resample::resample_to_square(&image), Err(jpeg2pdf::resample::Error::ZeroDensity)));
+    assert!(matches!(jpeg2pdf::convert_image(image), Err(ConvertError::ZeroDensity)));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn convert_async_matches_convert_bytes() {
+    let jpeg_data = include_bytes!("data/small.jpg");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("failed to start runtime");
+    let mut pdf_data = Vec::new();
+    runtime.block_on(jpeg2pdf::convert_async(&jpeg_data[..], &mut pdf_data))
+        .expect("failed to convert image");
+    assert_eq!(pdf_data, jpeg2pdf::convert_bytes(jpeg_data).expect("failed to convert image"));
+}
@@ -0,0 +1,62 @@
+use jpeg2pdf::deflate::zlib_compress;
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+
+/// Pseudo-random bytes (xorshift), which do not compress.
+fn noise(length: usize, mut state: u32) -> Vec<u8> {
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+fn assert_round_trip(data: &[u8]) {
+    let compressed = zlib_compress(data);
+    let decompressed = decompress_to_vec_zlib(&compressed)
+        .expect("reference inflater rejected the output");
+    assert!(decompressed == data, "round trip of {} bytes changed the data", data.len());
+}
+
+
+#[test]
+fn empty() {
+    assert_round_trip(b"");
+}
+
+#[test]
+fn longest_match() {
+    // a literal followed by runs long enough for matches of the maximum length of 258 bytes
+    let mut data = b"x".to_vec();
+    data.extend(std::iter::repeat_n(b'a', 3 * 258 + 1));
+    assert_round_trip(&data);
+    assert!(zlib_compress(&data).len() < 32);
+}
+
+#[test]
+fn farthest_distance() {
+    // a block repeated exactly 32768 bytes (the window size) later
+    let block = noise(512, 1);
+    let mut data = block.clone();
+    data.extend(noise(32768 - block.len(), 2));
+    data.extend(&block);
+    assert_round_trip(&data);
+}
+
+#[test]
+fn incompressible() {
+    let data = noise(100_000, 3);
+    assert_round_trip(&data);
+    // stored blocks add a few bytes per 64 KiB at most
+    assert!(zlib_compress(&data).len() < data.len() + 64);
+}
+
+#[test]
+fn content_stream() {
+    let data = b"q 595 0 0 842 0 0 cm /Im0 Do Q\n".repeat(100);
+    assert_round_trip(&data);
+    assert!(zlib_compress(&data).len() < data.len() / 10);
+}
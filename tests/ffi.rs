@@ -0,0 +1,75 @@
+#![cfg(feature = "ffi")]
+
+
+use std::ptr;
+
+use jpeg2pdf::ffi::{
+    jpeg2pdf_convert, jpeg2pdf_free, Options, JPEG2PDF_ERROR_INVALID_ARGUMENT,
+    JPEG2PDF_ERROR_INVALID_JPEG, JPEG2PDF_OK,
+};
+
+
+#[test]
+fn convert_matches_convert_bytes() {
+    let jpeg_data = include_bytes!("data/small.jpg");
+    let mut pdf_data: *mut u8 = ptr::null_mut();
+    let mut pdf_len: usize = 0;
+    let result = unsafe {
+        jpeg2pdf_convert(jpeg_data.as_ptr(), jpeg_data.len(), &mut pdf_data, &mut pdf_len, ptr::null())
+    };
+    assert_eq!(result, JPEG2PDF_OK);
+    assert!(!pdf_data.is_null());
+    let pdf = unsafe { std::slice::from_raw_parts(pdf_data, pdf_len) }.to_vec();
+    unsafe { jpeg2pdf_free(pdf_data, pdf_len) };
+    assert_eq!(pdf, jpeg2pdf::convert_bytes(jpeg_data).expect("failed to convert image"));
+}
+
+#[test]
+fn convert_with_density() {
+    let jpeg_data = include_bytes!("data/small.jpg");
+    let options = Options { dpi_x: 2, dpi_y: 2 };
+    let mut pdf_data: *mut u8 = ptr::null_mut();
+    let mut pdf_len: usize = 0;
+    let result = unsafe {
+        jpeg2pdf_convert(jpeg_data.as_ptr(), jpeg_data.len(), &mut pdf_data, &mut pdf_len, &options)
+    };
+    assert_eq!(result, JPEG2PDF_OK);
+    let pdf = unsafe { std::slice::from_raw_parts(pdf_data, pdf_len) }.to_vec();
+    unsafe { jpeg2pdf_free(pdf_data, pdf_len) };
+    // 16x16 pixels at 2 dpi
+    let media_box = b"/MediaBox [ 0 0 576 576 ]";
+    assert!(pdf.windows(media_box.len()).any(|w| w == media_box), "page not sized at 2 dpi");
+}
+
+#[test]
+fn null_pointers_are_rejected() {
+    let jpeg_data = include_bytes!("data/small.jpg");
+    let mut pdf_data: *mut u8 = ptr::null_mut();
+    let mut pdf_len: usize = 0;
+    let result = unsafe {
+        jpeg2pdf_convert(ptr::null(), jpeg_data.len(), &mut pdf_data, &mut pdf_len, ptr::null())
+    };
+    assert_eq!(result, JPEG2PDF_ERROR_INVALID_ARGUMENT);
+    let result = unsafe {
+        jpeg2pdf_convert(jpeg_data.as_ptr(), jpeg_data.len(), ptr::null_mut(), &mut pdf_len, ptr::null())
+    };
+    assert_eq!(result, JPEG2PDF_ERROR_INVALID_ARGUMENT);
+    assert!(pdf_data.is_null());
+    assert_eq!(pdf_len, 0);
+
+    // releasing nothing does nothing
+    unsafe { jpeg2pdf_free(ptr::null_mut(), 0) };
+}
+
+#[test]
+fn invalid_jpeg_is_rejected() {
+    let not_jpeg = b"%PDF-1.4 this is not a JPEG image";
+    let mut pdf_data: *mut u8 = ptr::null_mut();
+    let mut pdf_len: usize = 0;
+    let result = unsafe {
+        jpeg2pdf_convert(not_jpeg.as_ptr(), not_jpeg.len(), &mut pdf_data, &mut pdf_len, ptr::null())
+    };
+    assert_eq!(result, JPEG2PDF_ERROR_INVALID_JPEG);
+    assert!(pdf_data.is_null());
+    assert_eq!(pdf_len, 0);
+}
@@ -0,0 +1,119 @@
+use jpeg2pdf::inflate::{inflate, Error};
+use miniz_oxide::deflate::compress_to_vec;
+
+
+/// Assembles Deflate data bit by bit.
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    bit_count: usize,
+}
+impl Bits {
+    /// Appends the lowest `count` bits of `value`, least significant bit first (as for header
+    /// fields and extra bits).
+    fn value(mut self, value: u32, count: usize) -> Self {
+        for i in 0..count {
+            if self.bit_count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if value & (1 << i) != 0 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit_count % 8);
+            }
+            self.bit_count += 1;
+        }
+        self
+    }
+
+    /// Appends a Huffman code of the given length, most significant bit first.
+    fn code(self, code: u32, length: usize) -> Self {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.value(reversed, length)
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// The start of a final block using the fixed Huffman codes.
+fn fixed_block() -> Bits {
+    Bits::default()
+        .value(1, 1)
+        .value(0b01, 2)
+}
+
+
+#[test]
+fn round_trip() {
+    let data = b"jpeg2pdf jpeg2pdf jpeg2pdf jpeg2pdf".repeat(50);
+    let compressed = compress_to_vec(&data, 6);
+    assert_eq!(inflate(&compressed, data.len()), Ok(data));
+}
+
+#[test]
+fn stored_block() {
+    let mut compressed = vec![0x01, 0x03, 0x00, 0xFC, 0xFF];
+    compressed.extend_from_slice(b"abc");
+    assert_eq!(inflate(&compressed, 3), Ok(b"abc".to_vec()));
+}
+
+#[test]
+fn empty_fixed_block() {
+    // end of block (symbol 256) straight away
+    let compressed = fixed_block()
+        .code(0, 7)
+        .finish();
+    assert_eq!(inflate(&compressed, 0), Ok(Vec::new()));
+}
+
+#[test]
+fn invalid_block_type() {
+    let compressed = Bits::default()
+        .value(1, 1)
+        .value(0b11, 2)
+        .value(0, 5)
+        .finish();
+    assert_eq!(inflate(&compressed, 1024), Err(Error::InvalidData));
+}
+
+#[test]
+fn over_subscribed_code_lengths() {
+    // a dynamic block whose code length code has 19 codes of length 1
+    let mut bits = Bits::default()
+        .value(1, 1)
+        .value(0b10, 2)
+        .value(0, 5)
+        .value(0, 5)
+        .value(15, 4);
+    for _ in 0..19 {
+        bits = bits.value(1, 3);
+    }
+    let compressed = bits.value(0, 16).finish();
+    assert_eq!(inflate(&compressed, 1024), Err(Error::InvalidData));
+}
+
+#[test]
+fn distance_before_start() {
+    // a match of length 3 at distance 1 before anything has been output
+    let compressed = fixed_block()
+        .code(1, 7)
+        .code(0, 5)
+        .code(0, 7)
+        .finish();
+    assert_eq!(inflate(&compressed, 1024), Err(Error::InvalidData));
+}
+
+#[test]
+fn truncated() {
+    let data = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+    let compressed = compress_to_vec(&data, 6);
+    assert_eq!(inflate(&compressed[..compressed.len() / 2], data.len()), Err(Error::UnexpectedEnd));
+    assert_eq!(inflate(&[], data.len()), Err(Error::UnexpectedEnd));
+}
+
+#[test]
+fn longer_than_expected() {
+    let data = vec![0u8; 100_000];
+    let compressed = compress_to_vec(&data, 6);
+    assert_eq!(inflate(&compressed, 1000), Err(Error::TooLong { max_length: 1000 }));
+}
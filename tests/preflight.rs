@@ -0,0 +1,16 @@
+use jpeg2pdf::lint::Severity;
+use jpeg2pdf::preflight::{Check, Preflight};
+
+
+#[test]
+fn zero_density_is_reported() {
+    let jpeg_data = include_bytes!("data/zero-density.jpg");
+    let image = jpeg2pdf::jpeg::Image::try_read(&jpeg_data[..])
+        .expect("failed to read image");
+    let mut preflight = Preflight::new(&[]);
+    let findings = preflight.check_image(&image);
+    assert!(
+        findings.iter().any(|finding| finding.check == Check::PageSize && finding.severity == Severity::Error && finding.target.is_none()),
+        "unexpected findings: {:?}", findings,
+    );
+}
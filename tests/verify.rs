@@ -0,0 +1,26 @@
+use std::io::Cursor;
+
+
+#[test]
+fn object_offsets_from_reader() {
+    let pdf_data = jpeg2pdf::convert_bytes(include_bytes!("data/small.jpg"))
+        .expect("failed to convert image");
+    let expected = jpeg2pdf::verify::object_offsets(&pdf_data)
+        .expect("failed to read object offsets");
+    assert!(!expected.is_empty());
+
+    let offsets = jpeg2pdf::verify::read_object_offsets(Cursor::new(&pdf_data))
+        .expect("failed to read object offsets");
+    assert_eq!(offsets, expected);
+}
+
+#[test]
+fn object_offsets_from_truncated_reader() {
+    let pdf_data = jpeg2pdf::convert_bytes(include_bytes!("data/small.jpg"))
+        .expect("failed to convert image");
+    let truncated = &pdf_data[..pdf_data.len() - 40];
+    assert!(matches!(
+        jpeg2pdf::verify::read_object_offsets(Cursor::new(truncated)),
+        Err(jpeg2pdf::verify::Error::NoStartXref),
+    ));
+}